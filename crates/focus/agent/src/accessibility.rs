@@ -37,10 +37,10 @@ pub fn get_pids_for_bundle(bundle_id: &str) -> color_eyre::eyre::Result<Vec<i32>
     let mut pids = Vec::new();
 
     for line in stdout.lines() {
-        if let Some(pid_str) = line.strip_prefix("\"pid\"=") {
-            if let Ok(pid) = pid_str.trim().parse::<i32>() {
-                pids.push(pid);
-            }
+        if let Some(pid_str) = line.strip_prefix("\"pid\"=")
+            && let Ok(pid) = pid_str.trim().parse::<i32>()
+        {
+            pids.push(pid);
         }
     }
 
@@ -1,10 +1,9 @@
 //! App enforcement via SIGKILL.
 
-use color_eyre::eyre::WrapErr as _;
 use std::collections::HashSet;
 
 use crate::accessibility;
-use crate::policy::{AppPolicy, FocusPolicy};
+use crate::policy::FocusPolicy;
 
 /// App enforcer that kills disallowed apps.
 pub struct AppEnforcer {
@@ -31,10 +30,10 @@ impl AppEnforcer {
         // Get frontmost app
         let frontmost = accessibility::get_frontmost_app()?;
 
-        if let Some((bundle_id, _name)) = frontmost {
-            if !policy.apps.is_allowed(&bundle_id) {
-                self.kill_app(&bundle_id)?;
-            }
+        if let Some((bundle_id, _name)) = frontmost
+            && !policy.apps.is_allowed(&bundle_id)
+        {
+            self.kill_app(&bundle_id)?;
         }
 
         Ok(())
@@ -107,7 +107,7 @@ fn resolve_domains(domains: &[String]) -> color_eyre::eyre::Result<Vec<String>>
             for line in stdout.lines() {
                 let line = line.trim();
                 // Check if it looks like an IP
-                if line.chars().next().map_or(false, |c| c.is_ascii_digit()) {
+                if line.chars().next().is_some_and(|c| c.is_ascii_digit()) {
                     ips.push(line.to_string());
                 }
             }
@@ -102,7 +102,7 @@ impl AppPolicy {
             "com.apple.notificationcenterui",
         ];
 
-        if SYSTEM_ESSENTIALS.iter().any(|&s| bundle_id == s) {
+        if SYSTEM_ESSENTIALS.contains(&bundle_id) {
             return true;
         }
 
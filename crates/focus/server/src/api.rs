@@ -1,5 +1,7 @@
 //! Focus-specific API endpoints.
 
+use std::sync::Arc;
+
 use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
@@ -8,40 +10,68 @@ use axum::{Json, Router};
 use serde::{Deserialize, Serialize};
 
 use focus_agent::policy::FocusPolicy;
+use mdm_http::{EnqueueState, PushResponse};
+use mdm_push::PushProvider;
 use mdm_storage::CommandStore;
 
 /// Create the focus API router.
-pub fn focus_router<S>(store: S) -> Router
+///
+/// Policy changes are pushed to the device when `pusher` is set.
+pub fn focus_router<S, P>(store: S, pusher: Option<Arc<P>>) -> Router
 where
     S: CommandStore + Clone + 'static,
+    P: PushProvider + 'static,
 {
     Router::new()
-        .route("/api/focus/policy/:device_id", post(set_policy::<S>))
-        .route("/api/focus/policy/:device_id", get(get_policy))
-        .route("/api/focus/status/:device_id", get(get_status))
-        .with_state(store)
+        .route("/api/focus/policy/{device_id}", post(set_policy::<S, P>))
+        .route("/api/focus/policy/{device_id}", get(get_policy))
+        .route("/api/focus/status/{device_id}", get(get_status))
+        .with_state(EnqueueState { store, pusher })
 }
 
 /// Set focus policy request.
 #[derive(Debug, Deserialize)]
 pub struct SetPolicyRequest {
     pub policy: FocusPolicy,
+    /// Leave the command for the device's next check-in.
+    #[serde(default, alias = "nopush")]
+    pub no_push: bool,
+    /// Seconds after which the policy expires if the device hasn't answered.
+    #[serde(default)]
+    pub ttl: Option<u64>,
 }
 
 /// Set focus policy response.
 #[derive(Debug, Serialize)]
 pub struct SetPolicyResponse {
     pub command_uuid: String,
+    /// Older unanswered policies cancelled in favour of this one.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub superseded: Vec<String>,
+    /// Outcome of the push sent after enqueueing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push: Option<PushResponse>,
+}
+
+impl SetPolicyResponse {
+    fn error() -> Json<Self> {
+        Json(Self {
+            command_uuid: String::new(),
+            superseded: Vec::new(),
+            push: None,
+        })
+    }
 }
 
 /// Set a focus policy for a device.
-pub async fn set_policy<S>(
-    State(store): State<S>,
+pub async fn set_policy<S, P>(
+    State(state): State<EnqueueState<S, P>>,
     Path(device_id): Path<String>,
     Json(request): Json<SetPolicyRequest>,
 ) -> impl IntoResponse
 where
     S: CommandStore,
+    P: PushProvider,
 {
     tracing::info!(device_id = %device_id, "setting focus policy");
 
@@ -57,49 +87,82 @@ where
             tracing::error!(error = %e, "failed to serialize policy");
             return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(SetPolicyResponse {
-                    command_uuid: String::new(),
-                }),
+                SetPolicyResponse::error(),
             );
         }
     }
 
-    // Serialize command
-    let command_bytes = match mdm_core::serialize_command(&command) {
-        Ok(bytes) => bytes,
+    let expires_at = match mdm_http::expires_at(request.ttl) {
+        Ok(expires_at) => expires_at,
         Err(e) => {
-            tracing::error!(error = %e, "failed to serialize command");
-            return (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(SetPolicyResponse {
-                    command_uuid: String::new(),
-                }),
-            );
+            tracing::warn!(error = %e, "rejected focus policy");
+            return (StatusCode::BAD_REQUEST, SetPolicyResponse::error());
         }
     };
 
-    // Enqueue command
     let enroll_id = mdm_core::EnrollId {
         enroll_type: mdm_core::EnrollType::Device,
         id: device_id,
         parent_id: None,
     };
 
-    match store.enqueue_command(&enroll_id, &command_bytes) {
-        Ok(uuid) => (
-            StatusCode::OK,
-            Json(SetPolicyResponse { command_uuid: uuid }),
-        ),
+    // Enqueue command
+    let uuid = match state
+        .store
+        .enqueue_command(&enroll_id, &command, expires_at)
+        .await
+    {
+        Ok(uuid) => uuid,
         Err(e) => {
             tracing::error!(error = %e, "failed to enqueue command");
-            (
+            return (
                 StatusCode::INTERNAL_SERVER_ERROR,
-                Json(SetPolicyResponse {
-                    command_uuid: String::new(),
-                }),
-            )
+                SetPolicyResponse::error(),
+            );
         }
+    };
+
+    // An older policy answered after this one would overwrite it, so cancel
+    // any the device hasn't answered yet. This runs after enqueueing so a
+    // failure leaves the device with a policy to apply, and the caller still
+    // gets its UUID.
+    let superseded = match state
+        .store
+        .cancel_request_type(&enroll_id, &command.command.request_type, Some(&uuid))
+        .await
+    {
+        Ok(superseded) => superseded,
+        Err(e) => {
+            tracing::warn!(
+                device_id = %enroll_id.id,
+                error = %e,
+                "failed to cancel superseded policies"
+            );
+            Vec::new()
+        }
+    };
+    if !superseded.is_empty() {
+        tracing::info!(
+            device_id = %enroll_id.id,
+            superseded = ?superseded,
+            "cancelled superseded policies"
+        );
     }
+
+    let push = mdm_http::push_after_enqueue(
+        state.pusher.as_deref(),
+        &[enroll_id.id.as_str()],
+        request.no_push,
+    )
+    .await;
+    (
+        StatusCode::OK,
+        Json(SetPolicyResponse {
+            command_uuid: uuid,
+            superseded,
+            push,
+        }),
+    )
 }
 
 /// Get current policy for a device.
@@ -130,3 +193,104 @@ pub async fn get_status(Path(device_id): Path<String>) -> impl IntoResponse {
         policy_applied: false,
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use axum::body::Body;
+    use axum::http::Request;
+    use mdm_core::{CommandState, PushResult};
+    use mdm_storage::{CommandFilter, InMemoryStorage};
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    /// Records, for each push, how many policies the device had queued.
+    struct MockProvider {
+        store: InMemoryStorage,
+        pushed: Mutex<Vec<(String, usize)>>,
+    }
+
+    impl PushProvider for MockProvider {
+        async fn push_by_id(&self, ids: &[&str]) -> Vec<PushResult> {
+            let mut results = Vec::new();
+            for id in ids {
+                let filter = CommandFilter {
+                    state: Some(CommandState::Pending),
+                    ..CommandFilter::default()
+                };
+                let queued = self
+                    .store
+                    .list_commands(&device(id), &filter)
+                    .await
+                    .unwrap()
+                    .len();
+                self.pushed.lock().unwrap().push((id.to_string(), queued));
+                results.push(PushResult::success(id.to_string(), "apns-1".to_string()));
+            }
+            results
+        }
+    }
+
+    fn device(id: &str) -> mdm_core::EnrollId {
+        mdm_core::EnrollId {
+            enroll_type: mdm_core::EnrollType::Device,
+            id: id.to_string(),
+            parent_id: None,
+        }
+    }
+
+    async fn set(app: &Router, body: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/api/focus/policy/dev-1")
+            .header("content-type", "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_set_policy() {
+        let store = InMemoryStorage::new();
+        let pusher = Arc::new(MockProvider {
+            store: store.clone(),
+            pushed: Mutex::new(Vec::new()),
+        });
+        let app = focus_router(store.clone(), Some(pusher.clone()));
+        let policy = serde_json::json!({
+            "schedule": {"periods": [{"start": "09:00", "end": "17:00"}]},
+            "apps": {"mode": "blocklist", "apps": ["com.example.game"]},
+            "websites": {"mode": "blocklist", "domains": ["example.com"]},
+        });
+
+        // The device is pushed once the policy is queued
+        let (status, first) = set(&app, serde_json::json!({"policy": policy})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(first["push"]["status"]["dev-1"]["push_result"], "apns-1");
+        assert_eq!(*pusher.pushed.lock().unwrap(), [("dev-1".to_string(), 1)]);
+
+        // A newer policy supersedes the unanswered one, and may skip the push
+        let (status, second) =
+            set(&app, serde_json::json!({"policy": policy, "nopush": true})).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(second.get("push").is_none());
+        assert_eq!(pusher.pushed.lock().unwrap().len(), 1);
+        assert_eq!(
+            second["superseded"],
+            serde_json::json!([first["command_uuid"]])
+        );
+
+        let state = |uuid: &serde_json::Value| {
+            let store = store.clone();
+            let uuid = uuid.as_str().unwrap().to_string();
+            async move { store.get_command(&uuid).await.unwrap()[0].state }
+        };
+        assert_eq!(state(&first["command_uuid"]).await, CommandState::Cancelled);
+        assert_eq!(state(&second["command_uuid"]).await, CommandState::Pending);
+    }
+}
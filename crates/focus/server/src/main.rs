@@ -23,22 +23,92 @@ async fn main() -> color_eyre::eyre::Result<()> {
     let database_url =
         std::env::var("DATABASE_URL").unwrap_or_else(|_| "sqlite:focus.db".to_string());
 
-    let storage =
-        mdm_storage::SqliteStorage::new(&database_url).wrap_err("failed to initialize storage")?;
-
-    storage
-        .run_migrations()
-        .wrap_err("failed to run migrations")?;
+    if database_url == "memory:" {
+        tracing::warn!("using in-memory storage; nothing survives a restart");
+        serve(mdm_storage::InMemoryStorage::new()).await
+    } else if database_url.starts_with("postgres://") || database_url.starts_with("postgresql://") {
+        let storage = mdm_storage::PostgresStorage::new(&database_url)
+            .wrap_err("failed to initialize storage")?;
+        storage
+            .run_migrations()
+            .wrap_err("failed to run migrations")?;
+        serve(storage).await
+    } else {
+        let pool_size = std::env::var("SQLITE_POOL_SIZE")
+            .ok()
+            .map(|n| n.parse())
+            .transpose()
+            .wrap_err("SQLITE_POOL_SIZE must be a number")?
+            .unwrap_or(1);
+        let storage = mdm_storage::SqliteStorage::with_pool_size(&database_url, pool_size)
+            .wrap_err("failed to initialize storage")?;
+        storage
+            .run_migrations()
+            .wrap_err("failed to run migrations")?;
+        serve(storage).await
+    }
+}
 
+/// Build the routers on `storage` and serve until shutdown.
+async fn serve<S>(storage: S) -> color_eyre::eyre::Result<()>
+where
+    S: mdm_storage::AllStorage + Clone + 'static,
+{
     // Create MDM service
     let service = mdm_service::NanoMdm::new(storage.clone());
 
-    // Build router
-    let app = Router::new()
-        .merge(mdm_http::mdm_router(service))
-        .merge(mdm_http::api_router(storage.clone()))
-        .merge(focus_server::api::focus_router(storage))
-        .layer(TraceLayer::new_for_http());
+    // Optionally require identity certificates issued by a trusted CA
+    let mut mdm_router = mdm_http::mdm_router(service);
+    if let Some(verifier) = load_cert_verifier()? {
+        mdm_router = mdm_router.layer(axum::middleware::from_fn_with_state(
+            std::sync::Arc::new(verifier),
+            mdm_http::verify_cert_middleware,
+        ));
+    }
+
+    // Warn before the push certificate expires
+    let warn_days: u64 = std::env::var("PUSH_CERT_WARN_DAYS")
+        .ok()
+        .map(|days| days.parse())
+        .transpose()
+        .wrap_err("PUSH_CERT_WARN_DAYS must be a number of days")?
+        .unwrap_or(30);
+    tokio::spawn(mdm_push::watch_push_cert_expiry(
+        storage.clone(),
+        std::time::Duration::from_secs(warn_days * 24 * 60 * 60),
+        std::time::Duration::from_secs(6 * 60 * 60),
+    ));
+
+    // Build router; the API and focus routers share one push provider, whose
+    // APNs clients are built from the certificates uploaded to /v1/pushcert
+    let endpoint: mdm_push::Endpoint = std::env::var("APNS_ENDPOINT")
+        .map(|endpoint| endpoint.parse())
+        .unwrap_or(Ok(mdm_push::Endpoint::Production))
+        .wrap_err("invalid APNS_ENDPOINT")?;
+    let push_concurrency = std::env::var("APNS_CONCURRENCY")
+        .ok()
+        .map(|n| n.parse())
+        .transpose()
+        .wrap_err("APNS_CONCURRENCY must be a number")?
+        .unwrap_or(mdm_push::DEFAULT_PUSH_CONCURRENCY);
+    tracing::info!(apns = %endpoint.base_url(), push_concurrency, "APNs endpoint");
+    let pusher = Some(std::sync::Arc::new(
+        mdm_push::ApnsProvider::new(storage.clone())
+            .with_endpoint(endpoint)
+            .with_concurrency(push_concurrency),
+    ));
+    let mut app = Router::new()
+        .merge(mdm_router)
+        .merge(mdm_http::api_router(storage.clone(), pusher.clone()))
+        .merge(focus_server::api::focus_router(storage.clone(), pusher));
+    let (scep, challenges) = load_scep_server()?.unzip();
+    if let Some(config) = load_enroll_config(challenges)? {
+        app = app.merge(mdm_http::enroll_router(storage, config));
+    }
+    if let Some(scep) = scep {
+        app = app.merge(mdm_http::scep_router(std::sync::Arc::new(scep)));
+    }
+    let app = app.layer(TraceLayer::new_for_http());
 
     // Start server
     let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
@@ -52,3 +122,141 @@ async fn main() -> color_eyre::eyre::Result<()> {
 
     Ok(())
 }
+
+/// Build a certificate verifier from `MDM_CA_BUNDLE` (PEM) and `MDM_CRL_PATH`.
+///
+/// Returns `None` when no CA bundle is configured.
+fn load_cert_verifier() -> color_eyre::eyre::Result<Option<mdm_crypto::SignatureVerifier>> {
+    let Ok(bundle_path) = std::env::var("MDM_CA_BUNDLE") else {
+        return Ok(None);
+    };
+
+    let bundle = std::fs::read_to_string(&bundle_path)
+        .wrap_err_with(|| format!("failed to read CA bundle {}", bundle_path))?;
+    let mut verifier = mdm_crypto::SignatureVerifier::from_pem_bundle(&bundle)
+        .wrap_err("failed to load CA bundle")?;
+
+    if let Ok(crl_path) = std::env::var("MDM_CRL_PATH") {
+        verifier = verifier
+            .load_crls(std::path::Path::new(&crl_path))
+            .wrap_err("failed to load CRLs")?;
+    }
+
+    tracing::info!(ca_bundle = %bundle_path, "identity certificate verification enabled");
+
+    Ok(Some(verifier))
+}
+
+/// How long a challenge minted into an enrollment profile stays valid.
+const SCEP_CHALLENGE_TTL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+
+/// Build the SCEP server from `SCEP_CA_CERT` and `SCEP_CA_KEY`.
+///
+/// The server accepts the single-use challenges minted into enrollment
+/// profiles, and also `SCEP_CHALLENGE` when set, for clients enrolled out of
+/// band. Returns `None` when no SCEP CA is configured.
+fn load_scep_server() -> color_eyre::eyre::Result<
+    Option<(
+        mdm_crypto::ScepServer,
+        std::sync::Arc<mdm_crypto::OneTimeChallenges>,
+    )>,
+> {
+    use mdm_crypto::SigningPolicy as _;
+
+    let (Ok(cert_path), Ok(key_path)) =
+        (std::env::var("SCEP_CA_CERT"), std::env::var("SCEP_CA_KEY"))
+    else {
+        return Ok(None);
+    };
+
+    let ca = mdm_crypto::CertificateAuthority::load(
+        std::path::Path::new(&cert_path),
+        std::path::Path::new(&key_path),
+    )
+    .wrap_err("failed to load SCEP CA")?;
+
+    let challenges = std::sync::Arc::new(mdm_crypto::OneTimeChallenges::new(SCEP_CHALLENGE_TTL));
+    let fixed = std::env::var("SCEP_CHALLENGE")
+        .ok()
+        .map(mdm_crypto::StaticChallenge::new);
+    let minted = challenges.clone();
+    let policy = move |request: &mdm_crypto::CsrRequest<'_>| {
+        minted.authorize(request).or_else(|e| match &fixed {
+            Some(fixed) => fixed.authorize(request),
+            None => Err(e),
+        })
+    };
+
+    tracing::info!(ca_cert = %cert_path, "SCEP enabled at /scep");
+
+    Ok(Some((mdm_crypto::ScepServer::new(ca, policy), challenges)))
+}
+
+/// Build the enrollment profile settings from `MDM_SERVER_URL`.
+///
+/// The identity payload uses ACME when `ACME_DIRECTORY_URL` is set, otherwise
+/// SCEP at `SCEP_URL` (defaulting to the built-in `/scep` when enabled, with a
+/// fresh single-use challenge per profile). The profile is signed when
+/// `PROFILE_SIGNING_CERT` and `PROFILE_SIGNING_KEY` are set. Returns `None`
+/// when no server URL is configured.
+fn load_enroll_config(
+    challenges: Option<std::sync::Arc<mdm_crypto::OneTimeChallenges>>,
+) -> color_eyre::eyre::Result<Option<mdm_http::EnrollConfig>> {
+    let Ok(base_url) = std::env::var("MDM_SERVER_URL") else {
+        return Ok(None);
+    };
+    let base_url = base_url.trim_end_matches('/').to_string();
+    let subject = std::env::var("MDM_IDENTITY_SUBJECT").unwrap_or_else(|_| "moonstone".into());
+
+    let mut minted = None;
+    let identity = if let Ok(directory_url) = std::env::var("ACME_DIRECTORY_URL") {
+        mdm_core::IdentityPayload::Acme {
+            directory_url,
+            client_identifier: std::env::var("ACME_CLIENT_ID")
+                .wrap_err("ACME_CLIENT_ID must be set when ACME_DIRECTORY_URL is")?,
+            subject,
+        }
+    } else {
+        let url = match (std::env::var("SCEP_URL"), challenges) {
+            (Ok(url), _) => url,
+            (Err(_), Some(challenges)) => {
+                minted = Some(challenges);
+                format!("{base_url}/scep")
+            }
+            (Err(_), None) => {
+                tracing::warn!(
+                    "MDM_SERVER_URL set without SCEP or ACME; enrollment profile disabled"
+                );
+                return Ok(None);
+            }
+        };
+        mdm_core::IdentityPayload::Scep {
+            url,
+            challenge: None,
+            subject,
+        }
+    };
+
+    let mut config = mdm_http::EnrollConfig::new(&base_url, identity);
+    if let Some(challenges) = minted {
+        config = config.with_challenges(challenges);
+    }
+    if let Ok(organization) = std::env::var("MDM_ORGANIZATION") {
+        config = config.with_organization(organization);
+    }
+    if let (Ok(cert_path), Ok(key_path)) = (
+        std::env::var("PROFILE_SIGNING_CERT"),
+        std::env::var("PROFILE_SIGNING_KEY"),
+    ) {
+        let signer = mdm_crypto::CmsSigner::load(
+            std::path::Path::new(&cert_path),
+            std::path::Path::new(&key_path),
+        )
+        .wrap_err("failed to load profile signing certificate")?;
+        config = config.with_signer(signer);
+    }
+
+    tracing::info!(server_url = %base_url, "enrollment profile enabled at /v1/enroll/profile");
+
+    Ok(Some(config))
+}
@@ -80,14 +80,14 @@ pub struct TokenUpdate {
     pub topic: String,
 
     /// Push token (hex-encoded).
-    #[serde(with = "hex_bytes")]
+    #[serde(with = "data_bytes")]
     pub token: Vec<u8>,
 
     /// Push magic string.
     pub push_magic: String,
 
     /// Unlock token (optional).
-    #[serde(default)]
+    #[serde(default, with = "data_bytes::option")]
     pub unlock_token: Option<Vec<u8>>,
 
     /// Awaiting configuration (DEP).
@@ -181,8 +181,8 @@ pub struct DeclarativeManagement {
     #[serde(default)]
     pub endpoint: Option<String>,
 
-    /// DDM data payload.
-    #[serde(default)]
+    /// DDM data payload, JSON for the `status` endpoint.
+    #[serde(default, with = "data_bytes::option")]
     pub data: Option<Vec<u8>>,
 
     /// Raw message for storage.
@@ -214,9 +214,41 @@ pub struct GetTokenResponse {
     pub token_data: Vec<u8>,
 }
 
-/// Helper module for hex-encoded bytes in plist.
-mod hex_bytes {
-    use serde::{Deserialize, Deserializer, Serializer};
+/// Plist `<data>` fields.
+///
+/// Check-in messages are internally tagged, so serde buffers their fields and
+/// hands data over as bytes, which `Vec<u8>` itself doesn't accept.
+mod data_bytes {
+    use serde::{Deserializer, Serializer};
+
+    struct BytesVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.write_str("plist data")
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+            Ok(v.to_vec())
+        }
+
+        fn visit_byte_buf<E: serde::de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+            Ok(v)
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(
+            self,
+            mut seq: A,
+        ) -> Result<Self::Value, A::Error> {
+            let mut bytes = Vec::new();
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
 
     pub fn serialize<S>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -229,14 +261,86 @@ mod hex_bytes {
     where
         D: Deserializer<'de>,
     {
-        // Plist Data type deserializes as bytes directly
-        Vec::<u8>::deserialize(deserializer)
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+
+    /// Optional plist `<data>` fields.
+    pub mod option {
+        use serde::{Deserializer, Serializer};
+
+        struct OptionVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for OptionVisitor {
+            type Value = Option<Vec<u8>>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str("optional plist data")
+            }
+
+            fn visit_none<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_unit<E: serde::de::Error>(self) -> Result<Self::Value, E> {
+                Ok(None)
+            }
+
+            fn visit_some<D: Deserializer<'de>>(
+                self,
+                deserializer: D,
+            ) -> Result<Self::Value, D::Error> {
+                super::deserialize(deserializer).map(Some)
+            }
+        }
+
+        pub fn serialize<S>(bytes: &Option<Vec<u8>>, serializer: S) -> Result<S::Ok, S::Error>
+        where
+            S: Serializer,
+        {
+            match bytes {
+                Some(bytes) => serializer.serialize_some(&serde_bytes(bytes)),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D>(deserializer: D) -> Result<Option<Vec<u8>>, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            deserializer.deserialize_option(OptionVisitor)
+        }
+
+        /// Serialize as data rather than an array of integers.
+        fn serde_bytes(bytes: &[u8]) -> impl serde::Serialize + '_ {
+            struct Bytes<'a>(&'a [u8]);
+
+            impl serde::Serialize for Bytes<'_> {
+                fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                    serializer.serialize_bytes(self.0)
+                }
+            }
+
+            Bytes(bytes)
+        }
     }
 }
 
-/// Parse a check-in message from plist bytes.
+/// Parse a check-in message from plist bytes, keeping them as the raw message.
 pub fn parse_checkin(data: &[u8]) -> color_eyre::eyre::Result<CheckinMessage> {
     use color_eyre::eyre::WrapErr as _;
 
-    plist::from_bytes(data).wrap_err("failed to parse check-in message")
+    let mut msg: CheckinMessage =
+        plist::from_bytes(data).wrap_err("failed to parse check-in message")?;
+    let raw = match &mut msg {
+        CheckinMessage::Authenticate(msg) => &mut msg.raw,
+        CheckinMessage::TokenUpdate(msg) => &mut msg.raw,
+        CheckinMessage::CheckOut(msg) => &mut msg.raw,
+        CheckinMessage::UserAuthenticate(msg) => &mut msg.raw,
+        CheckinMessage::SetBootstrapToken(msg) => &mut msg.raw,
+        CheckinMessage::GetBootstrapToken(msg) => &mut msg.raw,
+        CheckinMessage::DeclarativeManagement(msg) => &mut msg.raw,
+        CheckinMessage::GetToken(msg) => &mut msg.raw,
+    };
+    *raw = data.to_vec();
+    Ok(msg)
 }
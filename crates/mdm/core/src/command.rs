@@ -6,7 +6,9 @@ use crate::Enrollment;
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct Command {
-    /// Unique command identifier.
+    /// Unique command identifier. Commands stored by earlier releases use
+    /// the `CommandUuid` key.
+    #[serde(rename = "CommandUUID", alias = "CommandUuid", default)]
     pub command_uuid: String,
 
     /// Command payload.
@@ -33,7 +35,8 @@ pub struct CommandResults {
     #[serde(flatten)]
     pub enrollment: Enrollment,
 
-    /// Command UUID being reported.
+    /// Command UUID being reported. Empty for Idle reports, which have none.
+    #[serde(rename = "CommandUUID", default)]
     pub command_uuid: String,
 
     /// Status of command execution.
@@ -75,8 +78,84 @@ impl std::fmt::Display for CommandStatus {
     }
 }
 
+/// Lifecycle state of a queued command.
+///
+/// Pending → Sent → Acknowledged / Error / NotNow. NotNow commands are sent
+/// again after the device next reports Idle. Commands that expire or are
+/// cancelled before the device answers them are never sent again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum CommandState {
+    /// Queued and not yet sent.
+    Pending,
+    /// Sent to the device, awaiting results.
+    Sent,
+    /// Device executed the command.
+    Acknowledged,
+    /// Device reported an error or a malformed command.
+    Error,
+    /// Device was busy; retried after its next Idle.
+    NotNow,
+    /// Expired before the device answered it.
+    Expired,
+    /// Cancelled before the device answered it.
+    Cancelled,
+}
+
+impl CommandState {
+    /// All states, in lifecycle order.
+    pub const ALL: [Self; 7] = [
+        Self::Pending,
+        Self::Sent,
+        Self::Acknowledged,
+        Self::Error,
+        Self::NotNow,
+        Self::Expired,
+        Self::Cancelled,
+    ];
+
+    /// The state after the device reports `status`, or `None` for Idle.
+    pub fn from_status(status: CommandStatus) -> Option<Self> {
+        match status {
+            CommandStatus::Acknowledged => Some(Self::Acknowledged),
+            CommandStatus::Error | CommandStatus::CommandFormatError => Some(Self::Error),
+            CommandStatus::NotNow => Some(Self::NotNow),
+            CommandStatus::Idle => None,
+        }
+    }
+
+    /// The state's name, as stored.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Pending => "Pending",
+            Self::Sent => "Sent",
+            Self::Acknowledged => "Acknowledged",
+            Self::Error => "Error",
+            Self::NotNow => "NotNow",
+            Self::Expired => "Expired",
+            Self::Cancelled => "Cancelled",
+        }
+    }
+}
+
+impl std::fmt::Display for CommandState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for CommandState {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|state| state.as_str() == s)
+            .ok_or_else(|| color_eyre::eyre::eyre!("unknown command state {s:?}"))
+    }
+}
+
 /// Error chain item from device.
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct ErrorChainItem {
     /// Error code.
@@ -105,11 +184,35 @@ pub struct QueuedCommand {
     pub created_at: chrono::DateTime<chrono::Utc>,
 }
 
-/// Parse command results from plist bytes.
+/// Stored command with its lifecycle state and results.
+#[derive(Debug, Clone)]
+pub struct CommandRecord {
+    /// Enrollment the command was queued for.
+    pub enrollment_id: String,
+    /// Command UUID.
+    pub uuid: String,
+    /// Request type, empty for commands queued by older releases.
+    pub request_type: String,
+    /// Lifecycle state.
+    pub state: CommandState,
+    /// Raw command plist.
+    pub command: Vec<u8>,
+    /// Raw results plist of the latest report.
+    pub result: Option<Vec<u8>>,
+    /// When the command was queued.
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// When the command expires if unanswered.
+    pub expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Parse command results from plist bytes, keeping them as the raw message.
 pub fn parse_command_results(data: &[u8]) -> color_eyre::eyre::Result<CommandResults> {
     use color_eyre::eyre::WrapErr as _;
 
-    plist::from_bytes(data).wrap_err("failed to parse command results")
+    let mut results: CommandResults =
+        plist::from_bytes(data).wrap_err("failed to parse command results")?;
+    results.raw = data.to_vec();
+    Ok(results)
 }
 
 /// Create a new command with generated UUID.
@@ -138,3 +241,27 @@ pub fn to_plist_value<T: serde::Serialize>(value: &T) -> color_eyre::eyre::Resul
 
     plist::to_value(value).wrap_err("failed to convert to plist value")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_idle_without_command_uuid() {
+        let idle = br#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN"
+    "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Status</key>
+	<string>Idle</string>
+	<key>UDID</key>
+	<string>00008103-001A2B3C4D5E6F70</string>
+</dict>
+</plist>
+"#;
+        let results = parse_command_results(idle).unwrap();
+        assert_eq!(results.status, CommandStatus::Idle);
+        assert!(results.command_uuid.is_empty());
+    }
+}
@@ -0,0 +1,496 @@
+//! Declarative Device Management (DDM).
+//!
+//! Devices fetch declarations over the DeclarativeManagement check-in. The
+//! message's `Endpoint` names what the device wants; responses are JSON.
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::WrapErr as _;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::push::hex_encode;
+
+/// Category of a declaration, from the second component of its `Type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DeclarationKind {
+    Activation,
+    Asset,
+    Configuration,
+    Management,
+}
+
+impl DeclarationKind {
+    pub const ALL: [Self; 4] = [
+        Self::Activation,
+        Self::Asset,
+        Self::Configuration,
+        Self::Management,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Activation => "activation",
+            Self::Asset => "asset",
+            Self::Configuration => "configuration",
+            Self::Management => "management",
+        }
+    }
+
+    /// Kind of a declaration type, e.g. "com.apple.configuration.passcode.settings".
+    pub fn from_type(declaration_type: &str) -> Option<Self> {
+        let kind = declaration_type
+            .strip_prefix("com.apple.")?
+            .split('.')
+            .next()?;
+        Self::ALL.into_iter().find(|k| k.as_str() == kind)
+    }
+}
+
+impl std::fmt::Display for DeclarationKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A declaration as served to devices.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Declaration {
+    /// e.g. "com.apple.configuration.passcode.settings".
+    #[serde(rename = "Type")]
+    pub declaration_type: String,
+    pub identifier: String,
+    /// Changes whenever the declaration does. See [`Declaration::content_token`].
+    #[serde(default)]
+    pub server_token: String,
+    #[serde(default)]
+    pub payload: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Declaration {
+    /// Parse a declaration from JSON and set its server token from its content.
+    pub fn parse(data: &[u8]) -> color_eyre::eyre::Result<Self> {
+        let mut declaration: Self =
+            serde_json::from_slice(data).wrap_err("failed to parse declaration")?;
+        if declaration.identifier.is_empty() {
+            color_eyre::eyre::bail!("declaration has no Identifier");
+        }
+        if declaration.kind().is_none() {
+            color_eyre::eyre::bail!("unknown declaration type: {}", declaration.declaration_type);
+        }
+        declaration.server_token = declaration.content_token();
+        Ok(declaration)
+    }
+
+    pub fn kind(&self) -> Option<DeclarationKind> {
+        DeclarationKind::from_type(&self.declaration_type)
+    }
+
+    /// SHA-256 of the type and payload, as hex.
+    pub fn content_token(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.declaration_type.as_bytes());
+        hasher.update([0]);
+        hasher.update(serde_json::to_vec(&self.payload).unwrap_or_default());
+        hex_encode(&hasher.finalize())
+    }
+}
+
+/// A stored declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclarationRecord {
+    pub declaration: Declaration,
+    /// Last time the declaration's content changed.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Token over the identifiers and server tokens of a set of declarations.
+///
+/// Changes whenever a declaration is added to, removed from, or changed in
+/// the set, which is what tells the device to fetch `declaration-items`.
+pub fn declarations_token<'a>(declarations: impl IntoIterator<Item = &'a Declaration>) -> String {
+    let mut items: Vec<_> = declarations
+        .into_iter()
+        .map(|d| (d.identifier.as_str(), d.server_token.as_str()))
+        .collect();
+    items.sort_unstable();
+
+    let mut hasher = Sha256::new();
+    for (identifier, server_token) in items {
+        hasher.update(identifier.as_bytes());
+        hasher.update([0]);
+        hasher.update(server_token.as_bytes());
+        hasher.update([0]);
+    }
+    hex_encode(&hasher.finalize())
+}
+
+/// A DeclarativeManagement check-in endpoint.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DdmEndpoint {
+    Tokens,
+    DeclarationItems,
+    Declaration {
+        kind: DeclarationKind,
+        identifier: String,
+    },
+    Status,
+}
+
+impl std::str::FromStr for DdmEndpoint {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> color_eyre::eyre::Result<Self> {
+        match s {
+            "tokens" => return Ok(Self::Tokens),
+            "declaration-items" => return Ok(Self::DeclarationItems),
+            "status" => return Ok(Self::Status),
+            _ => {}
+        }
+        let Some((kind, identifier)) = s
+            .strip_prefix("declaration/")
+            .and_then(|rest| rest.split_once('/'))
+        else {
+            color_eyre::eyre::bail!("unknown DDM endpoint: {s}");
+        };
+        let Some(kind) = DeclarationKind::ALL
+            .into_iter()
+            .find(|k| k.as_str() == kind)
+        else {
+            color_eyre::eyre::bail!("unknown declaration kind: {kind}");
+        };
+        if identifier.is_empty() {
+            color_eyre::eyre::bail!("DDM endpoint has no declaration identifier: {s}");
+        }
+        Ok(Self::Declaration {
+            kind,
+            identifier: identifier.to_string(),
+        })
+    }
+}
+
+/// Response to the `tokens` endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TokensResponse {
+    pub sync_tokens: SyncTokens,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SyncTokens {
+    pub declarations_token: String,
+    /// Latest change to the assigned declarations. Removals change the token
+    /// but not the timestamp.
+    pub timestamp: DateTime<Utc>,
+}
+
+impl TokensResponse {
+    /// Tokens for an enrollment's assigned declarations.
+    pub fn new(assigned: &[DeclarationRecord]) -> Self {
+        Self {
+            sync_tokens: SyncTokens {
+                declarations_token: declarations_token(assigned.iter().map(|r| &r.declaration)),
+                timestamp: assigned
+                    .iter()
+                    .map(|r| r.updated_at)
+                    .max()
+                    .unwrap_or(DateTime::UNIX_EPOCH),
+            },
+        }
+    }
+}
+
+/// Response to the `declaration-items` endpoint.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeclarationItemsResponse {
+    pub declarations: DeclarationItems,
+    pub declarations_token: String,
+}
+
+/// Assigned declarations, by kind.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeclarationItems {
+    pub activations: Vec<DeclarationItem>,
+    pub assets: Vec<DeclarationItem>,
+    pub configurations: Vec<DeclarationItem>,
+    pub management: Vec<DeclarationItem>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeclarationItem {
+    pub identifier: String,
+    pub server_token: String,
+}
+
+impl DeclarationItemsResponse {
+    /// Manifest of an enrollment's assigned declarations.
+    pub fn new(assigned: &[DeclarationRecord]) -> Self {
+        let mut declarations = DeclarationItems::default();
+        for declaration in assigned.iter().map(|r| &r.declaration) {
+            let items = match declaration.kind() {
+                Some(DeclarationKind::Activation) => &mut declarations.activations,
+                Some(DeclarationKind::Asset) => &mut declarations.assets,
+                Some(DeclarationKind::Configuration) => &mut declarations.configurations,
+                Some(DeclarationKind::Management) => &mut declarations.management,
+                None => continue,
+            };
+            items.push(DeclarationItem {
+                identifier: declaration.identifier.clone(),
+                server_token: declaration.server_token.clone(),
+            });
+        }
+        Self {
+            declarations,
+            declarations_token: declarations_token(assigned.iter().map(|r| &r.declaration)),
+        }
+    }
+}
+
+/// Payload of the `status` endpoint.
+///
+/// Status items are nested objects keyed by the components of the item name,
+/// e.g. `device.operating-system.version` is under `device`,
+/// `operating-system`, `version`. Unless `FullReport` is set, only changed
+/// items are included.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatusReport {
+    #[serde(default)]
+    pub status_items: serde_json::Map<String, serde_json::Value>,
+    #[serde(default)]
+    pub errors: Vec<StatusError>,
+    #[serde(default)]
+    pub full_report: bool,
+}
+
+/// A status item the device failed to report.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StatusError {
+    pub status_item: String,
+    #[serde(default)]
+    pub reasons: Vec<serde_json::Value>,
+}
+
+/// State of one declaration, from the `management.declarations` status item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct DeclarationStatus {
+    pub identifier: String,
+    pub active: bool,
+    /// "valid", "invalid" or "unknown".
+    pub valid: String,
+    /// Server token of the declaration the device has.
+    pub server_token: String,
+    /// Why the declaration is inactive or invalid.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<serde_json::Value>,
+}
+
+/// A stored declaration status.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeclarationStatusRecord {
+    pub status: DeclarationStatus,
+    /// When the device reported it.
+    pub updated_at: DateTime<Utc>,
+}
+
+/// Status items whose values are objects rather than further items.
+const OBJECT_STATUS_ITEMS: &[&str] = &[
+    "management.client-capabilities",
+    "management.declarations",
+    "softwareupdate.failure-reason",
+    "softwareupdate.install-reason",
+    "softwareupdate.pending-version",
+];
+
+/// One status item from a report, e.g. `device.operating-system.version`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StatusItem {
+    pub item: String,
+    pub value: serde_json::Value,
+}
+
+/// The current value of a status item for an enrollment.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusItemRecord {
+    pub item: String,
+    pub value: serde_json::Value,
+    /// When the device first reported this value.
+    pub changed_at: DateTime<Utc>,
+    /// When the device last reported this value.
+    pub reported_at: DateTime<Utc>,
+}
+
+/// A value a status item changed to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatusItemChange {
+    pub value: serde_json::Value,
+    /// When the device first reported this value.
+    pub reported_at: DateTime<Utc>,
+}
+
+impl StatusReport {
+    pub fn parse(data: &[u8]) -> color_eyre::eyre::Result<Self> {
+        serde_json::from_slice(data).wrap_err("failed to parse DDM status report")
+    }
+
+    /// The report's status items, by name.
+    ///
+    /// Nested objects are split into their items, except for items whose
+    /// value is itself an object, like `management.client-capabilities`.
+    pub fn items(&self) -> Vec<StatusItem> {
+        fn collect(
+            prefix: &str,
+            object: &serde_json::Map<String, serde_json::Value>,
+            items: &mut Vec<StatusItem>,
+        ) {
+            for (key, value) in object {
+                let item = if prefix.is_empty() {
+                    key.clone()
+                } else {
+                    format!("{prefix}.{key}")
+                };
+                match value {
+                    serde_json::Value::Object(object)
+                        if !OBJECT_STATUS_ITEMS.contains(&item.as_str()) =>
+                    {
+                        collect(&item, object, items);
+                    }
+                    _ => items.push(StatusItem {
+                        item,
+                        value: value.clone(),
+                    }),
+                }
+            }
+        }
+
+        let mut items = Vec::new();
+        collect("", &self.status_items, &mut items);
+        items.sort_by(|a, b| a.item.cmp(&b.item));
+        items
+    }
+
+    /// Declaration states, if the report includes `management.declarations`.
+    pub fn declarations(&self) -> color_eyre::eyre::Result<Option<Vec<DeclarationStatus>>> {
+        let Some(item) = self
+            .status_items
+            .get("management")
+            .and_then(|management| management.get("declarations"))
+        else {
+            return Ok(None);
+        };
+        let by_kind: std::collections::BTreeMap<String, Vec<DeclarationStatus>> =
+            serde_json::from_value(item.clone())
+                .wrap_err("failed to parse management.declarations")?;
+        Ok(Some(by_kind.into_values().flatten().collect()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATUS: &str = include_str!("../testdata/ddm_status.json");
+
+    fn declaration(identifier: &str, declaration_type: &str) -> Declaration {
+        let json = format!(
+            r#"{{"Type": "{declaration_type}", "Identifier": "{identifier}", "Payload": {{}}}}"#
+        );
+        Declaration::parse(json.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(
+            "tokens".parse::<DdmEndpoint>().unwrap(),
+            DdmEndpoint::Tokens
+        );
+        assert_eq!(
+            "declaration/configuration/com.example.passcode"
+                .parse::<DdmEndpoint>()
+                .unwrap(),
+            DdmEndpoint::Declaration {
+                kind: DeclarationKind::Configuration,
+                identifier: "com.example.passcode".to_string(),
+            }
+        );
+        assert!("declaration/profile/x".parse::<DdmEndpoint>().is_err());
+        assert!("declaration/asset/".parse::<DdmEndpoint>().is_err());
+        assert!("bogus".parse::<DdmEndpoint>().is_err());
+    }
+
+    #[test]
+    fn test_tokens_follow_assigned_set() {
+        let activation = declaration("act", "com.apple.activation.simple");
+        let passcode = declaration("passcode", "com.apple.configuration.passcode.settings");
+        assert_eq!(activation.kind(), Some(DeclarationKind::Activation));
+
+        let one = declarations_token([&activation]);
+        let both = declarations_token([&activation, &passcode]);
+        assert_ne!(one, both);
+        assert_eq!(both, declarations_token([&passcode, &activation]));
+
+        let mut changed = passcode.clone();
+        changed
+            .payload
+            .insert("MinimumLength".to_string(), serde_json::json!(6));
+        changed.server_token = changed.content_token();
+        assert_ne!(changed.server_token, passcode.server_token);
+        assert_ne!(both, declarations_token([&activation, &changed]));
+
+        let record = |declaration: Declaration| DeclarationRecord {
+            declaration,
+            updated_at: DateTime::UNIX_EPOCH,
+        };
+        let items = DeclarationItemsResponse::new(&[record(activation), record(passcode)]);
+        assert_eq!(items.declarations.activations[0].identifier, "act");
+        assert_eq!(items.declarations.configurations[0].identifier, "passcode");
+        assert_eq!(items.declarations_token, both);
+
+        assert!(Declaration::parse(br#"{"Type": "com.apple.bogus", "Identifier": "x"}"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_status_report() {
+        let report = StatusReport::parse(STATUS.as_bytes()).unwrap();
+        assert!(report.full_report);
+        let declarations = report.declarations().unwrap().unwrap();
+        assert_eq!(declarations.len(), 2);
+        let passcode = declarations
+            .iter()
+            .find(|d| d.identifier == "com.example.passcode")
+            .unwrap();
+        assert!(!passcode.active);
+        assert_eq!(passcode.valid, "invalid");
+        assert_eq!(passcode.reasons.len(), 1);
+
+        let items = report.items();
+        let item = |name: &str| {
+            items
+                .iter()
+                .find(|item| item.item == name)
+                .map(|item| &item.value)
+        };
+        assert_eq!(
+            item("device.operating-system.version"),
+            Some(&serde_json::json!("14.4.1"))
+        );
+        assert_eq!(
+            item("softwareupdate.install-state"),
+            Some(&serde_json::json!("downloading"))
+        );
+        assert_eq!(
+            item("softwareupdate.pending-version").unwrap()["os-version"],
+            "14.5"
+        );
+        assert!(item("management.client-capabilities").unwrap()["supported-versions"].is_array());
+        assert!(item("management.declarations").is_some());
+        assert!(item("management.client-capabilities.supported-versions").is_none());
+    }
+}
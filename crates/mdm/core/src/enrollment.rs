@@ -15,6 +15,45 @@ pub enum EnrollType {
     SharedIpad,
 }
 
+impl EnrollType {
+    /// All enrollment types.
+    pub const ALL: [Self; 5] = [
+        Self::Device,
+        Self::User,
+        Self::UserEnrollmentDevice,
+        Self::UserEnrollment,
+        Self::SharedIpad,
+    ];
+
+    /// The type's name, as stored.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Device => "Device",
+            Self::User => "User",
+            Self::UserEnrollmentDevice => "UserEnrollmentDevice",
+            Self::UserEnrollment => "UserEnrollment",
+            Self::SharedIpad => "SharedIpad",
+        }
+    }
+}
+
+impl std::fmt::Display for EnrollType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl std::str::FromStr for EnrollType {
+    type Err = color_eyre::eyre::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|enroll_type| enroll_type.as_str() == s)
+            .ok_or_else(|| color_eyre::eyre::eyre!("unknown enrollment type {s:?}"))
+    }
+}
+
 /// Raw enrollment data from check-in messages.
 #[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 #[serde(rename_all = "PascalCase")]
@@ -102,6 +141,26 @@ impl Enrollment {
     }
 }
 
+/// Stored enrollment.
+#[derive(Debug, Clone)]
+pub struct EnrollmentRecord {
+    pub id: String,
+    pub enroll_type: EnrollType,
+    /// Device enrollment of a user channel.
+    pub parent_id: Option<String>,
+    pub topic: String,
+    pub disabled: bool,
+    /// Raw Authenticate message, if one was stored.
+    pub authenticate_raw: Option<Vec<u8>>,
+    /// Raw TokenUpdate message, if one was stored.
+    pub token_update_raw: Option<Vec<u8>>,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    /// Last Authenticate or TokenUpdate.
+    pub updated_at: chrono::DateTime<chrono::Utc>,
+    /// When APNs first rejected the push token, until the next TokenUpdate.
+    pub push_invalid_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -130,4 +189,17 @@ mod tests {
         assert_eq!(id.id, "ABC123:user-456");
         assert_eq!(id.parent_id.as_deref(), Some("ABC123"));
     }
+
+    #[test]
+    fn test_enroll_type_names() {
+        for enroll_type in EnrollType::ALL {
+            // Stored names predate as_str and came from Debug
+            assert_eq!(enroll_type.as_str(), format!("{enroll_type:?}"));
+            assert_eq!(
+                enroll_type.as_str().parse::<EnrollType>().unwrap(),
+                enroll_type
+            );
+        }
+        assert!("Bogus".parse::<EnrollType>().is_err());
+    }
 }
@@ -0,0 +1,152 @@
+//! Device inventory.
+
+use crate::{Authenticate, EnrollId, QueryResponses, SecurityInfoReport};
+
+/// What the server last heard about a device's hardware, OS and security.
+#[derive(Debug, Clone, Default, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct DeviceInventory {
+    /// Device enrollment ID.
+    pub device_id: String,
+    pub serial_number: Option<String>,
+    pub device_name: Option<String>,
+    /// Model identifier, e.g. "MacBookPro18,3".
+    pub model: Option<String>,
+    /// Marketing name, e.g. "MacBook Pro".
+    pub model_name: Option<String>,
+    pub product_name: Option<String>,
+    pub os_version: Option<String>,
+    pub build_version: Option<String>,
+    /// FileVault is on.
+    pub filevault_enabled: Option<bool>,
+    /// System Integrity Protection is on.
+    pub sip_enabled: Option<bool>,
+    /// Last check-in or command report from the device or its user channels.
+    pub last_checkin_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+/// Properties reported by one message. `None` leaves the stored value as is.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct InventoryUpdate {
+    pub serial_number: Option<String>,
+    pub device_name: Option<String>,
+    pub model: Option<String>,
+    pub model_name: Option<String>,
+    pub product_name: Option<String>,
+    pub os_version: Option<String>,
+    pub build_version: Option<String>,
+    pub filevault_enabled: Option<bool>,
+    pub sip_enabled: Option<bool>,
+    pub last_checkin_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl InventoryUpdate {
+    /// An update that only records a check-in at `at`.
+    pub fn checkin(at: chrono::DateTime<chrono::Utc>) -> Self {
+        Self {
+            last_checkin_at: Some(at),
+            ..Default::default()
+        }
+    }
+
+    /// Whether the update changes nothing.
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+impl DeviceInventory {
+    /// Overwrite the properties `update` reports.
+    pub fn apply(&mut self, update: &InventoryUpdate) {
+        fn set<T: Clone>(field: &mut Option<T>, value: &Option<T>) {
+            if value.is_some() {
+                field.clone_from(value);
+            }
+        }
+
+        set(&mut self.serial_number, &update.serial_number);
+        set(&mut self.device_name, &update.device_name);
+        set(&mut self.model, &update.model);
+        set(&mut self.model_name, &update.model_name);
+        set(&mut self.product_name, &update.product_name);
+        set(&mut self.os_version, &update.os_version);
+        set(&mut self.build_version, &update.build_version);
+        set(&mut self.filevault_enabled, &update.filevault_enabled);
+        set(&mut self.sip_enabled, &update.sip_enabled);
+        set(&mut self.last_checkin_at, &update.last_checkin_at);
+    }
+}
+
+impl From<&Authenticate> for InventoryUpdate {
+    fn from(msg: &Authenticate) -> Self {
+        Self {
+            serial_number: msg.serial_number.clone(),
+            device_name: msg.device_name.clone(),
+            model: msg.model.clone(),
+            model_name: msg.model_name.clone(),
+            product_name: msg.product_name.clone(),
+            os_version: msg.os_version.clone(),
+            build_version: msg.build_version.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&QueryResponses> for InventoryUpdate {
+    fn from(info: &QueryResponses) -> Self {
+        Self {
+            serial_number: info.serial_number.clone(),
+            device_name: info.device_name.clone(),
+            model: info.model.clone(),
+            model_name: info.model_name.clone(),
+            product_name: info.product_name.clone(),
+            os_version: info.os_version.clone(),
+            build_version: info.build_version.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&SecurityInfoReport> for InventoryUpdate {
+    fn from(info: &SecurityInfoReport) -> Self {
+        Self {
+            filevault_enabled: info.fde_enabled,
+            sip_enabled: info.system_integrity_protection_enabled,
+            ..Default::default()
+        }
+    }
+}
+
+impl EnrollId {
+    /// ID of the device enrollment, which is the parent for user channels.
+    pub fn device_id(&self) -> &str {
+        self.parent_id.as_deref().unwrap_or(&self.id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_keeps_unreported() {
+        let mut inventory = DeviceInventory {
+            device_id: "dev-1".to_string(),
+            ..Default::default()
+        };
+        inventory.apply(&InventoryUpdate {
+            os_version: Some("14.4".to_string()),
+            filevault_enabled: Some(false),
+            ..Default::default()
+        });
+        inventory.apply(&InventoryUpdate {
+            os_version: Some("14.4.1".to_string()),
+            model: Some("MacBookPro18,3".to_string()),
+            ..Default::default()
+        });
+
+        assert_eq!(inventory.os_version.as_deref(), Some("14.4.1"));
+        assert_eq!(inventory.model.as_deref(), Some("MacBookPro18,3"));
+        assert_eq!(inventory.filevault_enabled, Some(false));
+        assert!(InventoryUpdate::default().is_empty());
+    }
+}
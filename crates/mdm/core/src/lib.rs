@@ -5,12 +5,20 @@
 
 mod checkin;
 mod command;
+mod ddm;
 mod enrollment;
+mod inventory;
+mod profile;
 mod push;
 mod request;
+mod requests;
 
 pub use checkin::*;
 pub use command::*;
+pub use ddm::*;
 pub use enrollment::*;
+pub use inventory::*;
+pub use profile::*;
 pub use push::*;
 pub use request::*;
+pub use requests::*;
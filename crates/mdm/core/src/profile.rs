@@ -0,0 +1,335 @@
+//! Enrollment profile (.mobileconfig) generation.
+
+/// Default MDM access rights (all rights).
+pub const DEFAULT_ACCESS_RIGHTS: u32 = 8191;
+
+/// Content type for configuration profiles.
+pub const PROFILE_CONTENT_TYPE: &str = "application/x-apple-aspen-config";
+
+/// Identity certificate payload the device uses to authenticate to the server.
+#[derive(Debug, Clone)]
+pub enum IdentityPayload {
+    /// Request the identity from a SCEP server.
+    Scep {
+        url: String,
+        challenge: Option<String>,
+        /// Certificate subject common name.
+        subject: String,
+    },
+    /// Request a hardware-bound identity from an ACME server.
+    Acme {
+        directory_url: String,
+        client_identifier: String,
+        /// Certificate subject common name.
+        subject: String,
+    },
+}
+
+/// An MDM enrollment profile.
+#[derive(Debug, Clone)]
+pub struct EnrollmentProfile {
+    /// Reverse-DNS prefix for payload identifiers.
+    pub identifier: String,
+    pub display_name: String,
+    pub organization: Option<String>,
+    /// `/mdm/command` URL.
+    pub server_url: String,
+    /// `/mdm/checkin` URL.
+    pub check_in_url: String,
+    /// APNs topic from the push certificate.
+    pub topic: String,
+    pub access_rights: u32,
+    pub sign_message: bool,
+    pub identity: IdentityPayload,
+}
+
+impl EnrollmentProfile {
+    /// Create a profile for the MDM server at `base_url`.
+    ///
+    /// The server and check-in URLs point at the MDM router's endpoints.
+    pub fn new(base_url: &str, topic: impl Into<String>, identity: IdentityPayload) -> Self {
+        let base_url = base_url.trim_end_matches('/');
+        Self {
+            identifier: "com.moonstone.mdm".to_string(),
+            display_name: "MDM Enrollment".to_string(),
+            organization: None,
+            server_url: format!("{base_url}/mdm/command"),
+            check_in_url: format!("{base_url}/mdm/checkin"),
+            topic: topic.into(),
+            access_rights: DEFAULT_ACCESS_RIGHTS,
+            sign_message: true,
+            identity,
+        }
+    }
+
+    pub fn with_identifier(mut self, identifier: impl Into<String>) -> Self {
+        self.identifier = identifier.into();
+        self
+    }
+
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn with_access_rights(mut self, access_rights: u32) -> Self {
+        self.access_rights = access_rights;
+        self
+    }
+
+    /// Serialize the profile to an XML plist.
+    pub fn to_xml(&self) -> color_eyre::eyre::Result<Vec<u8>> {
+        use color_eyre::eyre::WrapErr as _;
+
+        let identity_uuid = new_uuid();
+        let identity = match &self.identity {
+            IdentityPayload::Scep {
+                url,
+                challenge,
+                subject,
+            } => Payload::Scep(ScepPayload {
+                header: PayloadHeader::new(
+                    "com.apple.security.scep",
+                    format!("{}.scep", self.identifier),
+                    identity_uuid.clone(),
+                    "SCEP Identity",
+                ),
+                payload_content: ScepContent {
+                    url: url.clone(),
+                    challenge: challenge.clone(),
+                    subject: subject_name(subject),
+                    keysize: 2048,
+                    key_type: "RSA",
+                    // Signing and encryption
+                    key_usage: 5,
+                },
+            }),
+            IdentityPayload::Acme {
+                directory_url,
+                client_identifier,
+                subject,
+            } => Payload::Acme(AcmePayload {
+                header: PayloadHeader::new(
+                    "com.apple.security.acme",
+                    format!("{}.acme", self.identifier),
+                    identity_uuid.clone(),
+                    "ACME Identity",
+                ),
+                directory_url: directory_url.clone(),
+                client_identifier: client_identifier.clone(),
+                subject: subject_name(subject),
+                key_size: 384,
+                key_type: "ECSECPrimeRandom",
+                hardware_bound: true,
+                attest: true,
+            }),
+        };
+
+        let mdm = Payload::Mdm(MdmPayload {
+            header: PayloadHeader::new(
+                "com.apple.mdm",
+                format!("{}.mdm", self.identifier),
+                new_uuid(),
+                "MDM",
+            ),
+            identity_certificate_uuid: identity_uuid,
+            topic: self.topic.clone(),
+            server_url: self.server_url.clone(),
+            check_in_url: self.check_in_url.clone(),
+            check_out_when_removed: true,
+            access_rights: self.access_rights,
+            sign_message: self.sign_message,
+            server_capabilities: vec!["com.apple.mdm.per-user-connections"],
+        });
+
+        let profile = Profile {
+            payload_type: "Configuration",
+            payload_version: 1,
+            payload_identifier: self.identifier.clone(),
+            payload_uuid: new_uuid(),
+            payload_display_name: self.display_name.clone(),
+            payload_organization: self.organization.clone(),
+            payload_scope: "System",
+            payload_content: vec![identity, mdm],
+        };
+
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &profile).wrap_err("failed to serialize profile")?;
+        Ok(buf)
+    }
+}
+
+fn new_uuid() -> String {
+    uuid::Uuid::new_v4().to_string().to_uppercase()
+}
+
+/// Subject as the nested RDN arrays profiles expect: `[[["CN", name]]]`.
+fn subject_name(common_name: &str) -> Vec<Vec<[String; 2]>> {
+    vec![vec![["CN".to_string(), common_name.to_string()]]]
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct Profile {
+    payload_type: &'static str,
+    payload_version: u32,
+    payload_identifier: String,
+    #[serde(rename = "PayloadUUID")]
+    payload_uuid: String,
+    payload_display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    payload_organization: Option<String>,
+    payload_scope: &'static str,
+    payload_content: Vec<Payload>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Payload {
+    Mdm(MdmPayload),
+    Scep(ScepPayload),
+    Acme(AcmePayload),
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PayloadHeader {
+    payload_type: &'static str,
+    payload_version: u32,
+    payload_identifier: String,
+    #[serde(rename = "PayloadUUID")]
+    payload_uuid: String,
+    payload_display_name: &'static str,
+}
+
+impl PayloadHeader {
+    fn new(
+        payload_type: &'static str,
+        payload_identifier: String,
+        payload_uuid: String,
+        payload_display_name: &'static str,
+    ) -> Self {
+        Self {
+            payload_type,
+            payload_version: 1,
+            payload_identifier,
+            payload_uuid,
+            payload_display_name,
+        }
+    }
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct MdmPayload {
+    #[serde(flatten)]
+    header: PayloadHeader,
+    #[serde(rename = "IdentityCertificateUUID")]
+    identity_certificate_uuid: String,
+    topic: String,
+    #[serde(rename = "ServerURL")]
+    server_url: String,
+    #[serde(rename = "CheckInURL")]
+    check_in_url: String,
+    check_out_when_removed: bool,
+    access_rights: u32,
+    sign_message: bool,
+    server_capabilities: Vec<&'static str>,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ScepPayload {
+    #[serde(flatten)]
+    header: PayloadHeader,
+    payload_content: ScepContent,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct ScepContent {
+    #[serde(rename = "URL")]
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    challenge: Option<String>,
+    subject: Vec<Vec<[String; 2]>>,
+    keysize: u32,
+    #[serde(rename = "Key Type")]
+    key_type: &'static str,
+    #[serde(rename = "Key Usage")]
+    key_usage: u32,
+}
+
+#[derive(serde::Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct AcmePayload {
+    #[serde(flatten)]
+    header: PayloadHeader,
+    #[serde(rename = "DirectoryURL")]
+    directory_url: String,
+    client_identifier: String,
+    subject: Vec<Vec<[String; 2]>>,
+    key_size: u32,
+    key_type: &'static str,
+    hardware_bound: bool,
+    attest: bool,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scep_profile() {
+        let profile = EnrollmentProfile::new(
+            "https://mdm.example.com/",
+            "com.apple.mgmt.External.1234",
+            IdentityPayload::Scep {
+                url: "https://mdm.example.com/scep".to_string(),
+                challenge: Some("secret".to_string()),
+                subject: "device".to_string(),
+            },
+        );
+
+        let xml = profile.to_xml().unwrap();
+        let value = plist::Value::from_reader_xml(xml.as_slice()).unwrap();
+        let root = value.as_dictionary().unwrap();
+        assert_eq!(root["PayloadType"].as_string(), Some("Configuration"));
+
+        let payloads = root["PayloadContent"].as_array().unwrap();
+        let scep = payloads[0].as_dictionary().unwrap();
+        let mdm = payloads[1].as_dictionary().unwrap();
+
+        assert_eq!(
+            scep["PayloadType"].as_string(),
+            Some("com.apple.security.scep")
+        );
+        assert_eq!(
+            scep["PayloadContent"].as_dictionary().unwrap()["Challenge"].as_string(),
+            Some("secret")
+        );
+
+        assert_eq!(mdm["PayloadType"].as_string(), Some("com.apple.mdm"));
+        assert_eq!(
+            mdm["ServerURL"].as_string(),
+            Some("https://mdm.example.com/mdm/command")
+        );
+        assert_eq!(
+            mdm["CheckInURL"].as_string(),
+            Some("https://mdm.example.com/mdm/checkin")
+        );
+        assert_eq!(
+            mdm["Topic"].as_string(),
+            Some("com.apple.mgmt.External.1234")
+        );
+        assert_eq!(
+            mdm["IdentityCertificateUUID"].as_string(),
+            scep["PayloadUUID"].as_string()
+        );
+        assert_eq!(
+            mdm["AccessRights"].as_unsigned_integer(),
+            Some(u64::from(DEFAULT_ACCESS_RIGHTS))
+        );
+        assert_eq!(mdm["SignMessage"].as_boolean(), Some(true));
+    }
+}
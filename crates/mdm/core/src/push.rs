@@ -3,6 +3,8 @@
 /// Push notification info for a device.
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct PushInfo {
+    /// Enrollment ID the push info belongs to.
+    pub enrollment_id: String,
     /// APNs push token (raw bytes).
     pub token: Vec<u8>,
     /// Push magic string.
@@ -18,6 +20,42 @@ impl PushInfo {
     }
 }
 
+/// Why a push failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PushErrorKind {
+    /// APNs 410 `Unregistered`: the device token is no longer active.
+    Unregistered,
+    /// APNs `BadDeviceToken`: the token is malformed or for the other
+    /// environment.
+    BadDeviceToken,
+    /// APNs `DeviceTokenNotForTopic`.
+    DeviceTokenNotForTopic,
+    /// The push certificate was rejected or is missing.
+    Certificate,
+    /// APNs 429 `TooManyRequests`.
+    TooManyRequests,
+    /// APNs 5xx; retry later.
+    ServerError,
+    /// Connection or timeout error before APNs answered.
+    Transport,
+    /// No push token is stored for the enrollment.
+    NoPushInfo,
+    /// Any other failure.
+    Other,
+}
+
+impl PushErrorKind {
+    /// Whether the device token will never work again.
+    ///
+    /// Only `Unregistered` qualifies: `BadDeviceToken` and
+    /// `DeviceTokenNotForTopic` also follow from a wrong APNs environment or
+    /// push certificate, which would otherwise invalidate every token.
+    pub fn is_invalid_token(self) -> bool {
+        self == Self::Unregistered
+    }
+}
+
 /// Result of a push notification attempt.
 #[derive(Debug, Clone)]
 pub struct PushResult {
@@ -27,6 +65,8 @@ pub struct PushResult {
     pub apns_id: Option<String>,
     /// Error (if failed).
     pub error: Option<String>,
+    /// Error classification (if failed).
+    pub error_kind: Option<PushErrorKind>,
 }
 
 impl PushResult {
@@ -36,15 +76,26 @@ impl PushResult {
             enrollment_id,
             apns_id: Some(apns_id),
             error: None,
+            error_kind: None,
         }
     }
 
     /// Create a failed push result.
     pub fn failure(enrollment_id: String, error: impl std::fmt::Display) -> Self {
+        Self::failure_kind(enrollment_id, PushErrorKind::Other, error)
+    }
+
+    /// Create a failed push result with a known cause.
+    pub fn failure_kind(
+        enrollment_id: String,
+        kind: PushErrorKind,
+        error: impl std::fmt::Display,
+    ) -> Self {
         Self {
             enrollment_id,
             apns_id: None,
             error: Some(error.to_string()),
+            error_kind: Some(kind),
         }
     }
 
@@ -54,6 +105,15 @@ impl PushResult {
     }
 }
 
-fn hex_encode(bytes: &[u8]) -> String {
+/// Stored push certificate topic and expiry.
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PushCertSummary {
+    /// APNs topic.
+    pub topic: String,
+    /// Certificate expiry, if known.
+    pub not_after: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+pub(crate) fn hex_encode(bytes: &[u8]) -> String {
     bytes.iter().map(|b| format!("{:02x}", b)).collect()
 }
@@ -0,0 +1,798 @@
+//! Typed MDM command requests and their responses.
+//!
+//! Each request serializes to the fields of a command's `Command` dictionary
+//! and names the type its `CommandResults` payload parses into. Responses only
+//! cover the payload; status and error chain stay on [`CommandResults`].
+
+use color_eyre::eyre::WrapErr as _;
+use serde::{Deserialize, Serialize};
+
+use crate::{Command, CommandPayload, CommandResults, CommandStatus, ErrorChainItem};
+
+/// A typed MDM command request.
+pub trait CommandRequest: Serialize {
+    /// `RequestType` sent to the device.
+    const REQUEST_TYPE: &'static str;
+
+    /// Payload of the device's results.
+    type Response: serde::de::DeserializeOwned;
+}
+
+/// Create a command with a generated UUID from a typed request.
+pub fn command_from_request<R: CommandRequest>(request: &R) -> color_eyre::eyre::Result<Command> {
+    let plist::Value::Dictionary(dict) = crate::to_plist_value(request)? else {
+        color_eyre::eyre::bail!("{} request is not a dictionary", R::REQUEST_TYPE);
+    };
+    Ok(Command {
+        command_uuid: uuid::Uuid::new_v4().to_string(),
+        command: CommandPayload {
+            request_type: R::REQUEST_TYPE.to_string(),
+            data: dict.into_iter().collect(),
+        },
+    })
+}
+
+/// Parse the payload of a command's results from plist bytes.
+///
+/// Results with a status other than Acknowledged usually carry no payload and
+/// fail to parse for requests whose response has required fields.
+pub fn parse_command_response<R: CommandRequest>(
+    data: &[u8],
+) -> color_eyre::eyre::Result<R::Response> {
+    plist::from_bytes(data)
+        .wrap_err_with(|| format!("failed to parse {} response", R::REQUEST_TYPE))
+}
+
+impl CommandResults {
+    /// Parse the payload of these results as the response to `R`.
+    pub fn response<R: CommandRequest>(&self) -> color_eyre::eyre::Result<R::Response> {
+        parse_command_response::<R>(&self.raw)
+    }
+}
+
+/// Response to commands whose results carry nothing beyond their status.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EmptyResponse {}
+
+/// Query device properties.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceInformation {
+    /// Query keys, e.g. "OSVersion".
+    pub queries: Vec<String>,
+}
+
+impl DeviceInformation {
+    /// Queries for every property in [`QueryResponses`].
+    pub fn common() -> Self {
+        let queries = [
+            "AvailableDeviceCapacity",
+            "BluetoothMAC",
+            "BuildVersion",
+            "DeviceCapacity",
+            "DeviceName",
+            "EthernetMAC",
+            "HostName",
+            "IsActivationLockEnabled",
+            "IsSupervised",
+            "LocalHostName",
+            "Model",
+            "ModelName",
+            "ModelNumber",
+            "OSVersion",
+            "ProductName",
+            "SerialNumber",
+            "UDID",
+            "WiFiMAC",
+        ];
+        Self {
+            queries: queries.into_iter().map(String::from).collect(),
+        }
+    }
+}
+
+impl CommandRequest for DeviceInformation {
+    const REQUEST_TYPE: &'static str = "DeviceInformation";
+    type Response = DeviceInformationResponse;
+}
+
+/// DeviceInformation results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceInformationResponse {
+    pub query_responses: QueryResponses,
+}
+
+/// Commonly queried device properties. Properties the device didn't report,
+/// or that weren't queried, are `None`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct QueryResponses {
+    /// Free storage in GB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub available_device_capacity: Option<f64>,
+    #[serde(
+        default,
+        rename = "BluetoothMAC",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub bluetooth_mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub build_version: Option<String>,
+    /// Total storage in GB.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_capacity: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(
+        default,
+        rename = "EthernetMAC",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ethernet_mac: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_activation_lock_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_supervised: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub local_host_name: Option<String>,
+    /// Model identifier, e.g. "MacBookPro18,3".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// Marketing name, e.g. "MacBook Pro".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model_number: Option<String>,
+    #[serde(default, rename = "OSVersion", skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    #[serde(default, rename = "UDID", skip_serializing_if = "Option::is_none")]
+    pub udid: Option<String>,
+    #[serde(default, rename = "WiFiMAC", skip_serializing_if = "Option::is_none")]
+    pub wifi_mac: Option<String>,
+}
+
+/// Query the device's security state.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SecurityInfo {}
+
+impl CommandRequest for SecurityInfo {
+    const REQUEST_TYPE: &'static str = "SecurityInfo";
+    type Response = SecurityInfoResponse;
+}
+
+/// SecurityInfo results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecurityInfoResponse {
+    pub security_info: SecurityInfoReport,
+}
+
+/// Security state reported by SecurityInfo. Most keys are platform-specific.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SecurityInfoReport {
+    /// FileVault is on (macOS).
+    #[serde(
+        default,
+        rename = "FDE_Enabled",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fde_enabled: Option<bool>,
+    #[serde(
+        default,
+        rename = "FDE_HasPersonalRecoveryKey",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fde_has_personal_recovery_key: Option<bool>,
+    #[serde(
+        default,
+        rename = "FDE_HasInstitutionalRecoveryKey",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fde_has_institutional_recovery_key: Option<bool>,
+    /// SIP is on (macOS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub system_integrity_protection_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub authenticated_root_volume_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub is_recovery_lock_enabled: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remote_desktop_enabled: Option<bool>,
+    /// Passcode is set (iOS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub passcode_present: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub firewall_settings: Option<FirewallSettings>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub management_status: Option<ManagementStatus>,
+}
+
+/// Application firewall state (macOS).
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct FirewallSettings {
+    #[serde(default)]
+    pub firewall_enabled: bool,
+    #[serde(default)]
+    pub block_all_incoming: bool,
+    #[serde(default)]
+    pub stealth_mode: bool,
+}
+
+/// How the device was enrolled.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ManagementStatus {
+    #[serde(default, rename = "EnrolledViaDEP")]
+    pub enrolled_via_dep: bool,
+    #[serde(default)]
+    pub is_user_enrollment: bool,
+    #[serde(default)]
+    pub user_approved_enrollment: bool,
+    #[serde(default)]
+    pub is_activation_lock_manageable: bool,
+}
+
+/// List installed configuration profiles.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProfileList {
+    /// Only list profiles installed by MDM.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_only: Option<bool>,
+}
+
+impl CommandRequest for ProfileList {
+    const REQUEST_TYPE: &'static str = "ProfileList";
+    type Response = ProfileListResponse;
+}
+
+/// ProfileList results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProfileListResponse {
+    pub profile_list: Vec<InstalledProfile>,
+}
+
+/// An installed configuration profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstalledProfile {
+    pub payload_identifier: String,
+    #[serde(rename = "PayloadUUID")]
+    pub payload_uuid: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_organization: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_version: Option<u64>,
+    #[serde(default)]
+    pub payload_removal_disallowed: bool,
+    #[serde(default)]
+    pub has_removal_passcode: bool,
+    #[serde(default)]
+    pub is_encrypted: bool,
+    #[serde(default)]
+    pub is_managed: bool,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub payload_content: Vec<InstalledPayload>,
+    /// DER certificates that signed the profile.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signer_certificates: Vec<plist::Data>,
+}
+
+/// A payload of an installed profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstalledPayload {
+    pub payload_type: String,
+    pub payload_identifier: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub payload_description: Option<String>,
+}
+
+/// Install a configuration profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstallProfile {
+    /// The `.mobileconfig`, signed or unsigned.
+    pub payload: plist::Data,
+}
+
+impl InstallProfile {
+    pub fn new(payload: impl Into<Vec<u8>>) -> Self {
+        Self {
+            payload: plist::Data::new(payload.into()),
+        }
+    }
+}
+
+impl CommandRequest for InstallProfile {
+    const REQUEST_TYPE: &'static str = "InstallProfile";
+    type Response = EmptyResponse;
+}
+
+/// Remove a configuration profile.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RemoveProfile {
+    /// `PayloadIdentifier` of the profile.
+    pub identifier: String,
+}
+
+impl CommandRequest for RemoveProfile {
+    const REQUEST_TYPE: &'static str = "RemoveProfile";
+    type Response = EmptyResponse;
+}
+
+/// List installed applications.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstalledApplicationList {
+    /// Only these bundle identifiers.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifiers: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub managed_apps_only: Option<bool>,
+}
+
+impl CommandRequest for InstalledApplicationList {
+    const REQUEST_TYPE: &'static str = "InstalledApplicationList";
+    type Response = InstalledApplicationListResponse;
+}
+
+/// InstalledApplicationList results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstalledApplicationListResponse {
+    pub installed_application_list: Vec<InstalledApplication>,
+}
+
+/// An installed application.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstalledApplication {
+    /// Bundle identifier.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub short_version: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// Size in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub installing: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub has_update_available: Option<bool>,
+}
+
+/// Install an App Store or enterprise application.
+///
+/// Set exactly one of `itunes_store_id`, `identifier` or `manifest_url`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstallApplication {
+    #[serde(
+        default,
+        rename = "iTunesStoreID",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub itunes_store_id: Option<u64>,
+    /// Bundle identifier of an App Store app.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// URL of an enterprise app's manifest.
+    #[serde(
+        default,
+        rename = "ManifestURL",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub manifest_url: Option<String>,
+    /// 1 removes the app with the MDM profile, 4 prevents backing up its data.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub management_flags: Option<u32>,
+}
+
+impl CommandRequest for InstallApplication {
+    const REQUEST_TYPE: &'static str = "InstallApplication";
+    type Response = InstallApplicationResponse;
+}
+
+/// InstallApplication results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct InstallApplicationResponse {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub identifier: Option<String>,
+    /// Installation state, e.g. "Queued" or "Managed".
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub state: Option<String>,
+}
+
+/// Restart the device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct RestartDevice {
+    /// Let the user postpone the restart (macOS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notify_user: Option<bool>,
+}
+
+impl CommandRequest for RestartDevice {
+    const REQUEST_TYPE: &'static str = "RestartDevice";
+    type Response = EmptyResponse;
+}
+
+/// Shut down the device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ShutDownDevice {}
+
+impl CommandRequest for ShutDownDevice {
+    const REQUEST_TYPE: &'static str = "ShutDownDevice";
+    type Response = EmptyResponse;
+}
+
+/// Lock the device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeviceLock {
+    /// Six-digit unlock PIN, required on macOS.
+    #[serde(default, rename = "PIN", skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    /// Shown on the lock screen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+    /// Shown on the lock screen.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phone_number: Option<String>,
+}
+
+impl CommandRequest for DeviceLock {
+    const REQUEST_TYPE: &'static str = "DeviceLock";
+    type Response = EmptyResponse;
+}
+
+/// Erase the device.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct EraseDevice {
+    /// Six-digit Find My PIN, required on older macOS.
+    #[serde(default, rename = "PIN", skip_serializing_if = "Option::is_none")]
+    pub pin: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preserve_data_plan: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disallow_proximity_setup: Option<bool>,
+}
+
+impl CommandRequest for EraseDevice {
+    const REQUEST_TYPE: &'static str = "EraseDevice";
+    type Response = EmptyResponse;
+}
+
+/// Change device settings.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Settings {
+    pub settings: Vec<Setting>,
+}
+
+/// A setting to change, tagged by its `Item`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "Item", rename_all_fields = "PascalCase")]
+pub enum Setting {
+    DeviceName {
+        device_name: String,
+    },
+    HostName {
+        host_name: String,
+    },
+    /// IANA time zone, e.g. "Europe/Berlin".
+    TimeZone {
+        time_zone: String,
+    },
+    Bluetooth {
+        enabled: bool,
+    },
+    DataRoaming {
+        enabled: bool,
+    },
+    VoiceRoaming {
+        enabled: bool,
+    },
+    PersonalHotspot {
+        enabled: bool,
+    },
+}
+
+impl CommandRequest for Settings {
+    const REQUEST_TYPE: &'static str = "Settings";
+    type Response = SettingsResponse;
+}
+
+/// Settings results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SettingsResponse {
+    /// One result per setting, in request order.
+    #[serde(default)]
+    pub settings: Vec<SettingResult>,
+}
+
+/// Result of changing one setting.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SettingResult {
+    pub item: String,
+    pub status: CommandStatus,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_chain: Vec<ErrorChainItem>,
+}
+
+/// Schedule OS updates.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScheduleOSUpdate {
+    pub updates: Vec<OSUpdate>,
+}
+
+/// An update to schedule. Without a product key or version, applies to all
+/// available updates.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct OSUpdate {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_version: Option<String>,
+    pub install_action: InstallAction,
+    /// Deferrals allowed before a forced install (macOS).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_user_deferrals: Option<u32>,
+}
+
+/// What ScheduleOSUpdate does with an update.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum InstallAction {
+    /// Download or install, whichever the device decides.
+    #[default]
+    Default,
+    DownloadOnly,
+    #[serde(rename = "InstallASAP")]
+    InstallAsap,
+    NotifyOnly,
+    InstallLater,
+    InstallForceRestart,
+}
+
+impl CommandRequest for ScheduleOSUpdate {
+    const REQUEST_TYPE: &'static str = "ScheduleOSUpdate";
+    type Response = ScheduleOSUpdateResponse;
+}
+
+/// ScheduleOSUpdate results.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ScheduleOSUpdateResponse {
+    #[serde(default)]
+    pub update_results: Vec<OSUpdateResult>,
+}
+
+/// State of a scheduled update.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct OSUpdateResult {
+    pub product_key: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub product_version: Option<String>,
+    pub install_action: InstallAction,
+    /// Progress, e.g. "Downloading" or "Installing".
+    pub status: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_chain: Vec<ErrorChainItem>,
+}
+
+/// Tell the device to synchronize declarations.
+///
+/// Named apart from the [`DeclarativeManagement`](crate::DeclarativeManagement)
+/// check-in message.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct DeclarativeManagementCommand {
+    /// JSON tokens response, which saves the device fetching `tokens`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<plist::Data>,
+}
+
+impl CommandRequest for DeclarativeManagementCommand {
+    const REQUEST_TYPE: &'static str = "DeclarativeManagement";
+    type Response = EmptyResponse;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parse captured results, then check they survive a serialize/parse cycle.
+    fn round_trip<R>(data: &str) -> R::Response
+    where
+        R: CommandRequest,
+        R::Response: Serialize + PartialEq + std::fmt::Debug,
+    {
+        let results = crate::parse_command_results(data.as_bytes()).unwrap();
+        assert_eq!(results.status, CommandStatus::Acknowledged);
+        let response = results.response::<R>().unwrap();
+
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &response).unwrap();
+        assert_eq!(parse_command_response::<R>(&buf).unwrap(), response);
+        response
+    }
+
+    fn command_dict<R: CommandRequest>(request: &R) -> plist::Dictionary {
+        let command = command_from_request(request).unwrap();
+        let bytes = crate::serialize_command(&command).unwrap();
+        let value: plist::Value = plist::from_bytes(&bytes).unwrap();
+        let dict = value.into_dictionary().unwrap();
+        assert!(!dict["CommandUUID"].as_string().unwrap().is_empty());
+        dict["Command"].as_dictionary().unwrap().clone()
+    }
+
+    #[test]
+    fn test_request_keys() {
+        let dict = command_dict(&DeviceInformation {
+            queries: vec!["OSVersion".to_string()],
+        });
+        assert_eq!(dict["RequestType"].as_string(), Some("DeviceInformation"));
+        assert_eq!(dict["Queries"].as_array().unwrap().len(), 1);
+
+        let dict = command_dict(&DeviceLock {
+            pin: Some("123456".to_string()),
+            ..Default::default()
+        });
+        assert_eq!(dict["PIN"].as_string(), Some("123456"));
+        assert!(!dict.contains_key("Message"));
+
+        let dict = command_dict(&InstallProfile::new(b"profile".to_vec()));
+        assert_eq!(dict["Payload"].as_data(), Some(&b"profile"[..]));
+
+        let dict = command_dict(&InstallApplication {
+            itunes_store_id: Some(361285480),
+            management_flags: Some(1),
+            ..Default::default()
+        });
+        assert_eq!(dict["iTunesStoreID"].as_unsigned_integer(), Some(361285480));
+
+        let dict = command_dict(&Settings {
+            settings: vec![Setting::TimeZone {
+                time_zone: "Europe/Berlin".to_string(),
+            }],
+        });
+        let setting = dict["Settings"].as_array().unwrap()[0]
+            .as_dictionary()
+            .unwrap();
+        assert_eq!(setting["Item"].as_string(), Some("TimeZone"));
+        assert_eq!(setting["TimeZone"].as_string(), Some("Europe/Berlin"));
+
+        let dict = command_dict(&ScheduleOSUpdate {
+            updates: vec![OSUpdate {
+                install_action: InstallAction::InstallAsap,
+                ..Default::default()
+            }],
+        });
+        let update = dict["Updates"].as_array().unwrap()[0]
+            .as_dictionary()
+            .unwrap();
+        assert_eq!(update["InstallAction"].as_string(), Some("InstallASAP"));
+
+        let dict = command_dict(&ShutDownDevice {});
+        assert_eq!(dict.len(), 1);
+    }
+
+    #[test]
+    fn test_device_information_response() {
+        let response =
+            round_trip::<DeviceInformation>(include_str!("../testdata/device_information.plist"));
+        let info = response.query_responses;
+        assert_eq!(info.os_version.as_deref(), Some("14.4.1"));
+        assert_eq!(info.build_version.as_deref(), Some("23E224"));
+        assert_eq!(info.model.as_deref(), Some("MacBookPro18,3"));
+        assert_eq!(info.wifi_mac.as_deref(), Some("f0:18:98:aa:bb:ce"));
+        assert_eq!(info.is_supervised, Some(true));
+        assert!(info.host_name.is_none());
+    }
+
+    #[test]
+    fn test_security_info_response() {
+        let response = round_trip::<SecurityInfo>(include_str!("../testdata/security_info.plist"));
+        let info = response.security_info;
+        assert_eq!(info.fde_enabled, Some(true));
+        assert_eq!(info.system_integrity_protection_enabled, Some(true));
+        assert!(info.firewall_settings.unwrap().firewall_enabled);
+        assert!(info.management_status.unwrap().enrolled_via_dep);
+    }
+
+    #[test]
+    fn test_profile_list_response() {
+        let response = round_trip::<ProfileList>(include_str!("../testdata/profile_list.plist"));
+        let profile = &response.profile_list[0];
+        assert_eq!(profile.payload_identifier, "com.example.wifi");
+        assert_eq!(profile.payload_uuid, "3F1A9C2E-5B7D-4E8F-A1B2-C3D4E5F60718");
+        assert!(profile.payload_removal_disallowed);
+        assert_eq!(
+            profile.payload_content[0].payload_type,
+            "com.apple.wifi.managed"
+        );
+        assert_eq!(profile.signer_certificates.len(), 1);
+    }
+
+    #[test]
+    fn test_installed_application_list_response() {
+        let response = round_trip::<InstalledApplicationList>(include_str!(
+            "../testdata/installed_application_list.plist"
+        ));
+        let apps = response.installed_application_list;
+        assert_eq!(apps.len(), 2);
+        assert_eq!(apps[0].identifier.as_deref(), Some("com.apple.Keynote"));
+        assert_eq!(apps[0].bundle_size, Some(154632192));
+        assert_eq!(apps[1].installing, Some(true));
+    }
+
+    #[test]
+    fn test_install_application_response() {
+        let response =
+            round_trip::<InstallApplication>(include_str!("../testdata/install_application.plist"));
+        assert_eq!(response.identifier.as_deref(), Some("com.apple.Keynote"));
+        assert_eq!(response.state.as_deref(), Some("Queued"));
+    }
+
+    #[test]
+    fn test_settings_response() {
+        let response = round_trip::<Settings>(include_str!("../testdata/settings.plist"));
+        assert_eq!(response.settings[0].item, "DeviceName");
+        assert_eq!(response.settings[0].status, CommandStatus::Acknowledged);
+        assert_eq!(response.settings[1].status, CommandStatus::Error);
+        assert_eq!(response.settings[1].error_chain[0].error_code, 12078);
+    }
+
+    #[test]
+    fn test_schedule_os_update_response() {
+        let response =
+            round_trip::<ScheduleOSUpdate>(include_str!("../testdata/schedule_os_update.plist"));
+        let update = &response.update_results[0];
+        assert_eq!(update.install_action, InstallAction::InstallAsap);
+        assert_eq!(update.product_version.as_deref(), Some("14.4.1"));
+        assert_eq!(update.status, "Installing");
+    }
+
+    #[test]
+    fn test_empty_responses() {
+        let data = include_str!("../testdata/acknowledged.plist");
+        round_trip::<InstallProfile>(data);
+        round_trip::<RemoveProfile>(data);
+        round_trip::<RestartDevice>(data);
+        round_trip::<ShutDownDevice>(data);
+        round_trip::<DeviceLock>(data);
+        round_trip::<EraseDevice>(data);
+
+        // Typed payloads need the device to have answered with one
+        assert!(parse_command_response::<DeviceInformation>(data.as_bytes()).is_err());
+    }
+}
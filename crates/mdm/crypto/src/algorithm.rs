@@ -0,0 +1,154 @@
+//! Digest and public-key signature algorithms.
+
+use const_oid::ObjectIdentifier;
+use const_oid::db::rfc5912;
+use der::Encode as _;
+use x509_cert::spki::SubjectPublicKeyInfoOwned;
+
+use crate::SignatureError;
+
+/// Digest algorithms accepted in device signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    /// Look up a digest algorithm by OID.
+    pub(crate) fn from_oid(oid: &ObjectIdentifier) -> Result<Self, SignatureError> {
+        match *oid {
+            rfc5912::ID_SHA_1 => Ok(Self::Sha1),
+            rfc5912::ID_SHA_256 => Ok(Self::Sha256),
+            rfc5912::ID_SHA_384 => Ok(Self::Sha384),
+            rfc5912::ID_SHA_512 => Ok(Self::Sha512),
+            _ => Err(SignatureError::UnsupportedAlgorithm(oid.to_string())),
+        }
+    }
+
+    /// The algorithm's OID.
+    pub(crate) fn oid(self) -> ObjectIdentifier {
+        match self {
+            Self::Sha1 => rfc5912::ID_SHA_1,
+            Self::Sha256 => rfc5912::ID_SHA_256,
+            Self::Sha384 => rfc5912::ID_SHA_384,
+            Self::Sha512 => rfc5912::ID_SHA_512,
+        }
+    }
+
+    /// Hash `data` with this algorithm.
+    pub(crate) fn digest(self, data: &[u8]) -> Vec<u8> {
+        use sha2::Digest as _;
+
+        match self {
+            Self::Sha1 => sha1::Sha1::digest(data).to_vec(),
+            Self::Sha256 => sha2::Sha256::digest(data).to_vec(),
+            Self::Sha384 => sha2::Sha384::digest(data).to_vec(),
+            Self::Sha512 => sha2::Sha512::digest(data).to_vec(),
+        }
+    }
+}
+
+/// Resolve the digest used by a signature algorithm.
+///
+/// Bare `rsaEncryption` and `id-ecPublicKey` (both seen in CMS signer infos)
+/// don't name a digest, so `fallback` is used for them.
+pub(crate) fn signature_digest(
+    signature_alg: &ObjectIdentifier,
+    fallback: DigestAlgorithm,
+) -> Result<DigestAlgorithm, SignatureError> {
+    match *signature_alg {
+        rfc5912::RSA_ENCRYPTION | rfc5912::ID_EC_PUBLIC_KEY => Ok(fallback),
+        rfc5912::SHA_1_WITH_RSA_ENCRYPTION => Ok(DigestAlgorithm::Sha1),
+        rfc5912::SHA_256_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_256 => {
+            Ok(DigestAlgorithm::Sha256)
+        }
+        rfc5912::SHA_384_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_384 => {
+            Ok(DigestAlgorithm::Sha384)
+        }
+        rfc5912::SHA_512_WITH_RSA_ENCRYPTION | rfc5912::ECDSA_WITH_SHA_512 => {
+            Ok(DigestAlgorithm::Sha512)
+        }
+        _ => Err(SignatureError::UnsupportedAlgorithm(
+            signature_alg.to_string(),
+        )),
+    }
+}
+
+/// Verify `signature` over a message whose `digest_alg` hash is `prehash`.
+///
+/// Supports RSA PKCS#1 v1.5 and ECDSA on P-256/P-384, which covers the
+/// identity keys issued by SCEP and ACME.
+pub(crate) fn verify_prehashed(
+    spki: &SubjectPublicKeyInfoOwned,
+    digest_alg: DigestAlgorithm,
+    prehash: &[u8],
+    signature: &[u8],
+) -> Result<(), SignatureError> {
+    use p256::ecdsa::signature::hazmat::PrehashVerifier as _;
+    use p256::pkcs8::DecodePublicKey as _;
+
+    let spki_der = spki
+        .to_der()
+        .map_err(|e| SignatureError::Malformed(format!("public key: {e}")))?;
+
+    match spki.algorithm.oid {
+        rfc5912::RSA_ENCRYPTION => {
+            let key = rsa::RsaPublicKey::from_public_key_der(&spki_der)
+                .map_err(|e| SignatureError::Malformed(format!("RSA public key: {e}")))?;
+            let scheme = match digest_alg {
+                DigestAlgorithm::Sha1 => rsa::Pkcs1v15Sign::new::<sha1::Sha1>(),
+                DigestAlgorithm::Sha256 => rsa::Pkcs1v15Sign::new::<sha2::Sha256>(),
+                DigestAlgorithm::Sha384 => rsa::Pkcs1v15Sign::new::<sha2::Sha384>(),
+                DigestAlgorithm::Sha512 => rsa::Pkcs1v15Sign::new::<sha2::Sha512>(),
+            };
+            key.verify(scheme, prehash, signature)
+                .map_err(|_| SignatureError::BadSignature)
+        }
+        rfc5912::ID_EC_PUBLIC_KEY => {
+            let curve = spki
+                .algorithm
+                .parameters
+                .as_ref()
+                .and_then(|p| p.decode_as::<ObjectIdentifier>().ok());
+
+            match curve {
+                Some(rfc5912::SECP_256_R_1) => {
+                    let key = p256::ecdsa::VerifyingKey::from_public_key_der(&spki_der)
+                        .map_err(|e| SignatureError::Malformed(format!("P-256 public key: {e}")))?;
+                    let sig = p256::ecdsa::Signature::from_der(signature)
+                        .map_err(|_| SignatureError::BadSignature)?;
+                    key.verify_prehash(prehash, &sig)
+                        .map_err(|_| SignatureError::BadSignature)
+                }
+                Some(rfc5912::SECP_384_R_1) => {
+                    let key = p384::ecdsa::VerifyingKey::from_public_key_der(&spki_der)
+                        .map_err(|e| SignatureError::Malformed(format!("P-384 public key: {e}")))?;
+                    let sig = p384::ecdsa::Signature::from_der(signature)
+                        .map_err(|_| SignatureError::BadSignature)?;
+                    key.verify_prehash(prehash, &sig)
+                        .map_err(|_| SignatureError::BadSignature)
+                }
+                other => Err(SignatureError::UnsupportedAlgorithm(format!(
+                    "EC curve {}",
+                    other.map(|oid| oid.to_string()).unwrap_or_default()
+                ))),
+            }
+        }
+        other => Err(SignatureError::UnsupportedAlgorithm(other.to_string())),
+    }
+}
+
+/// Verify `signature` over `message` using a combined signature algorithm
+/// such as `sha256WithRSAEncryption` or `ecdsa-with-SHA256`.
+pub(crate) fn verify_signature(
+    spki: &SubjectPublicKeyInfoOwned,
+    signature_alg: &ObjectIdentifier,
+    message: &[u8],
+    signature: &[u8],
+) -> Result<(), SignatureError> {
+    let digest_alg = signature_digest(signature_alg, DigestAlgorithm::Sha256)?;
+    verify_prehashed(spki, digest_alg, &digest_alg.digest(message), signature)
+}
@@ -0,0 +1,171 @@
+//! Certificate authority for issuing device identity certificates.
+
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use color_eyre::eyre::WrapErr as _;
+use der::{Decode as _, Encode as _};
+use rsa::RsaPrivateKey;
+use rsa::rand_core::{OsRng, RngCore as _};
+use x509_cert::Certificate;
+use x509_cert::builder::{Builder as _, CertificateBuilder, Profile};
+use x509_cert::ext::pkix::ExtendedKeyUsage;
+use x509_cert::request::CertReq;
+use x509_cert::serial_number::SerialNumber;
+use x509_cert::time::{Time, Validity};
+
+use crate::{algorithm, signer};
+
+/// Default lifetime of issued certificates.
+const DEFAULT_VALIDITY: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// How far `notBefore` is backdated to tolerate device clock skew.
+const CLOCK_SKEW: Duration = Duration::from_secs(5 * 60);
+
+/// An RSA certificate authority that signs device certificate requests.
+#[derive(Clone)]
+pub struct CertificateAuthority {
+    cert: Certificate,
+    cert_der: Vec<u8>,
+    key: RsaPrivateKey,
+    validity: Duration,
+}
+
+/// Leaves out the private key.
+impl std::fmt::Debug for CertificateAuthority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CertificateAuthority")
+            .field("subject", &self.cert.tbs_certificate.subject.to_string())
+            .field("validity", &self.validity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl CertificateAuthority {
+    /// Create a CA from a PEM certificate and a PEM RSA private key
+    /// (PKCS#8 or PKCS#1).
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> color_eyre::eyre::Result<Self> {
+        let cert = pem::parse(cert_pem).wrap_err("failed to parse CA certificate PEM")?;
+        if cert.tag() != "CERTIFICATE" {
+            color_eyre::eyre::bail!("expected CERTIFICATE PEM, got {}", cert.tag());
+        }
+
+        let key = signer::rsa_key_from_pem(key_pem).wrap_err("failed to load CA key")?;
+
+        Self::new(cert.contents(), key)
+    }
+
+    /// Load a CA from PEM files on disk.
+    pub fn load(cert_path: &Path, key_path: &Path) -> color_eyre::eyre::Result<Self> {
+        let cert = std::fs::read_to_string(cert_path)
+            .wrap_err_with(|| format!("failed to read {}", cert_path.display()))?;
+        let key = std::fs::read_to_string(key_path)
+            .wrap_err_with(|| format!("failed to read {}", key_path.display()))?;
+        Self::from_pem(&cert, &key)
+    }
+
+    /// Create a CA from a DER certificate and its private key.
+    pub fn new(cert_der: &[u8], key: RsaPrivateKey) -> color_eyre::eyre::Result<Self> {
+        let cert = Certificate::from_der(cert_der).wrap_err("failed to parse CA certificate")?;
+        signer::check_key_matches(&cert, &key).wrap_err("invalid CA key")?;
+
+        Ok(Self {
+            cert,
+            cert_der: cert_der.to_vec(),
+            key,
+            validity: DEFAULT_VALIDITY,
+        })
+    }
+
+    /// Set the lifetime of issued certificates.
+    pub fn with_validity(mut self, validity: Duration) -> Self {
+        self.validity = validity;
+        self
+    }
+
+    /// DER-encoded CA certificate.
+    pub fn cert_der(&self) -> &[u8] {
+        &self.cert_der
+    }
+
+    pub(crate) fn cert(&self) -> &Certificate {
+        &self.cert
+    }
+
+    pub(crate) fn key(&self) -> &RsaPrivateKey {
+        &self.key
+    }
+
+    /// Whether `cert` carries a valid signature from this CA.
+    pub(crate) fn issued(&self, cert: &Certificate) -> bool {
+        if cert.tbs_certificate.issuer != self.cert.tbs_certificate.subject {
+            return false;
+        }
+
+        let Ok(tbs) = cert.tbs_certificate.to_der() else {
+            return false;
+        };
+        algorithm::verify_signature(
+            &self.cert.tbs_certificate.subject_public_key_info,
+            &cert.signature_algorithm.oid,
+            &tbs,
+            cert.signature.raw_bytes(),
+        )
+        .is_ok()
+    }
+
+    /// Issue a client-auth certificate for `csr`.
+    ///
+    /// The CSR's self-signature is checked first. Returns the DER-encoded
+    /// certificate.
+    pub fn issue(&self, csr: &CertReq) -> color_eyre::eyre::Result<Vec<u8>> {
+        let info = csr.info.to_der().wrap_err("failed to encode CSR")?;
+        algorithm::verify_signature(
+            &csr.info.public_key,
+            &csr.algorithm.oid,
+            &info,
+            csr.signature.raw_bytes(),
+        )
+        .wrap_err("CSR signature verification failed")?;
+
+        let now = SystemTime::now();
+        let validity = Validity {
+            not_before: Time::try_from(now - CLOCK_SKEW)?,
+            not_after: Time::try_from(now + self.validity)?,
+        };
+
+        let signer = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(self.key.clone());
+        let mut builder = CertificateBuilder::new(
+            Profile::Leaf {
+                issuer: self.cert.tbs_certificate.subject.clone(),
+                enable_key_agreement: false,
+                enable_key_encipherment: true,
+            },
+            random_serial()?,
+            validity,
+            csr.info.subject.clone(),
+            csr.info.public_key.clone(),
+            &signer,
+        )
+        .map_err(|e| color_eyre::eyre::eyre!("failed to prepare certificate: {e}"))?;
+        builder
+            .add_extension(&ExtendedKeyUsage(vec![
+                const_oid::db::rfc5280::ID_KP_CLIENT_AUTH,
+            ]))
+            .map_err(|e| color_eyre::eyre::eyre!("failed to add extended key usage: {e}"))?;
+
+        let cert = builder
+            .build::<rsa::pkcs1v15::Signature>()
+            .map_err(|e| color_eyre::eyre::eyre!("failed to sign certificate: {e}"))?;
+        Ok(cert.to_der()?)
+    }
+}
+
+/// A random positive 128-bit serial number.
+fn random_serial() -> color_eyre::eyre::Result<SerialNumber> {
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    bytes[0] &= 0x7f;
+    bytes[0] |= 0x01;
+    Ok(SerialNumber::new(&bytes)?)
+}
@@ -14,10 +14,10 @@ pub fn extract_topic_from_cert(cert_der: &[u8]) -> color_eyre::eyre::Result<Stri
     for rdn in cert.subject().iter() {
         for attr in rdn.iter() {
             // Try to get the value as a string
-            if let Ok(value) = attr.attr_value().as_str() {
-                if value.starts_with("com.apple.mgmt.") {
-                    return Ok(value.to_string());
-                }
+            if let Ok(value) = attr.attr_value().as_str()
+                && value.starts_with("com.apple.mgmt.")
+            {
+                return Ok(value.to_string());
             }
         }
     }
@@ -25,6 +25,46 @@ pub fn extract_topic_from_cert(cert_der: &[u8]) -> color_eyre::eyre::Result<Stri
     color_eyre::eyre::bail!("no APNs topic found in certificate")
 }
 
+/// Topic, names, and validity window of an APNs push certificate.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PushCertInfo {
+    pub topic: String,
+    pub subject: String,
+    pub issuer: String,
+    pub not_before: chrono::DateTime<chrono::Utc>,
+    pub not_after: chrono::DateTime<chrono::Utc>,
+}
+
+/// Parse the topic, subject, issuer, and validity of a push certificate.
+pub fn parse_push_cert(cert_der: &[u8]) -> color_eyre::eyre::Result<PushCertInfo> {
+    let (_, cert) =
+        X509Certificate::from_der(cert_der).wrap_err("failed to parse certificate DER")?;
+
+    let time = |t: ASN1Time| {
+        chrono::DateTime::from_timestamp(t.timestamp(), 0)
+            .ok_or_else(|| color_eyre::eyre::eyre!("certificate time out of range: {t}"))
+    };
+
+    Ok(PushCertInfo {
+        topic: extract_topic_from_cert(cert_der)?,
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        not_before: time(cert.validity().not_before)?,
+        not_after: time(cert.validity().not_after)?,
+    })
+}
+
+/// Check that a PEM RSA private key (PKCS#8 or PKCS#1) belongs to a
+/// certificate.
+pub fn verify_cert_key(cert_der: &[u8], key_pem: &str) -> color_eyre::eyre::Result<()> {
+    use der::Decode as _;
+
+    let cert =
+        x509_cert::Certificate::from_der(cert_der).wrap_err("failed to parse certificate DER")?;
+    let key = crate::signer::rsa_key_from_pem(key_pem)?;
+    crate::signer::check_key_matches(&cert, &key)
+}
+
 /// Parse a certificate from PEM format.
 pub fn parse_pem_cert(pem_str: &str) -> color_eyre::eyre::Result<Vec<u8>> {
     let pem_data =
@@ -62,12 +102,18 @@ pub fn extract_pem_header(header: &str) -> color_eyre::eyre::Result<Vec<u8>> {
     parse_pem_cert(&pem_str)
 }
 
-/// Compute a simple hash of certificate DER for identification.
-///
-/// Note: This is a simplified implementation. In production, use a proper SHA-256.
+/// Compute the SHA-256 fingerprint of certificate DER for identification.
 pub fn cert_hash(cert_der: &[u8]) -> [u8; 32] {
-    // Simple XOR-based hash for identification (not cryptographically secure)
-    // In production, use ring::digest::digest or sha2 crate
+    use sha2::Digest as _;
+
+    sha2::Sha256::digest(cert_der).into()
+}
+
+/// Compute the legacy XOR-folded certificate hash.
+///
+/// Not collision resistant. Only used to recognize `cert_auth` rows written
+/// before the switch to SHA-256 so they can be re-hashed.
+pub fn legacy_cert_hash(cert_der: &[u8]) -> [u8; 32] {
     let mut result = [0u8; 32];
     for (i, &byte) in cert_der.iter().enumerate() {
         result[i % 32] ^= byte.wrapping_add((i / 32) as u8);
@@ -86,4 +132,29 @@ mod tests {
         let result = extract_rfc9440_cert(header).unwrap();
         assert_eq!(result, b"test");
     }
+
+    #[test]
+    fn test_parse_push_cert() {
+        let cert = parse_pem_cert(include_str!("../testdata/push.pem")).unwrap();
+        let info = parse_push_cert(&cert).unwrap();
+
+        assert_eq!(info.topic, "com.apple.mgmt.External.moonstone-test");
+        assert!(info.subject.contains("CN=APSP:moonstone-test"));
+        assert!(info.issuer.contains("CN=Moonstone Test CA"));
+        assert_eq!(info.not_after.to_rfc3339(), "2035-01-01T00:00:00+00:00");
+
+        verify_cert_key(&cert, include_str!("../testdata/push.key")).unwrap();
+        assert!(verify_cert_key(&cert, include_str!("../testdata/device-rsa.key")).is_err());
+    }
+
+    #[test]
+    fn test_cert_hash_is_sha256() {
+        let expected = [
+            0xba, 0x78, 0x16, 0xbf, 0x8f, 0x01, 0xcf, 0xea, 0x41, 0x41, 0x40, 0xde, 0x5d, 0xae,
+            0x22, 0x23, 0xb0, 0x03, 0x61, 0xa3, 0x96, 0x17, 0x7a, 0x9c, 0xb4, 0x10, 0xff, 0x61,
+            0xf2, 0x00, 0x15, 0xad,
+        ];
+        assert_eq!(cert_hash(b"abc"), expected);
+        assert_ne!(cert_hash(b"abc"), legacy_cert_hash(b"abc"));
+    }
 }
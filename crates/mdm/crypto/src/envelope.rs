@@ -0,0 +1,240 @@
+//! CMS EnvelopedData with RSA key transport, as used by SCEP.
+
+use cbc::cipher::block_padding::Pkcs7;
+use cms::cert::IssuerAndSerialNumber;
+use cms::content_info::{CmsVersion, ContentInfo};
+use cms::enveloped_data::{
+    EncryptedContentInfo, EnvelopedData, KeyTransRecipientInfo, RecipientIdentifier, RecipientInfo,
+    RecipientInfos,
+};
+use const_oid::ObjectIdentifier;
+use const_oid::db::{rfc5911, rfc5912};
+use der::asn1::OctetString;
+use der::{Any, Decode as _, Encode as _, Tag};
+use rsa::rand_core::{OsRng, RngCore as _};
+use rsa::{Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey};
+use x509_cert::Certificate;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+
+use crate::SignatureError;
+
+/// CBC-mode content encryption ciphers accepted in SCEP envelopes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ContentCipher {
+    Aes128,
+    Aes192,
+    Aes256,
+    DesEde3,
+}
+
+impl ContentCipher {
+    fn from_oid(oid: &ObjectIdentifier) -> Result<Self, SignatureError> {
+        match *oid {
+            rfc5911::ID_AES_128_CBC => Ok(Self::Aes128),
+            rfc5911::ID_AES_192_CBC => Ok(Self::Aes192),
+            rfc5911::ID_AES_256_CBC => Ok(Self::Aes256),
+            rfc5911::DES_EDE_3_CBC => Ok(Self::DesEde3),
+            _ => Err(SignatureError::UnsupportedAlgorithm(oid.to_string())),
+        }
+    }
+
+    fn oid(self) -> ObjectIdentifier {
+        match self {
+            Self::Aes128 => rfc5911::ID_AES_128_CBC,
+            Self::Aes192 => rfc5911::ID_AES_192_CBC,
+            Self::Aes256 => rfc5911::ID_AES_256_CBC,
+            Self::DesEde3 => rfc5911::DES_EDE_3_CBC,
+        }
+    }
+
+    fn key_len(self) -> usize {
+        match self {
+            Self::Aes128 => 16,
+            Self::Aes192 | Self::DesEde3 => 24,
+            Self::Aes256 => 32,
+        }
+    }
+
+    fn iv_len(self) -> usize {
+        match self {
+            Self::DesEde3 => 8,
+            _ => 16,
+        }
+    }
+
+    fn encrypt(self, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, SignatureError> {
+        fn run<C: cbc::cipher::BlockEncryptMut + cbc::cipher::KeyIvInit>(
+            key: &[u8],
+            iv: &[u8],
+            data: &[u8],
+        ) -> Result<Vec<u8>, SignatureError> {
+            let cipher = C::new_from_slices(key, iv)
+                .map_err(|_| SignatureError::Malformed("invalid key or IV length".into()))?;
+            Ok(cipher.encrypt_padded_vec_mut::<Pkcs7>(data))
+        }
+
+        match self {
+            Self::Aes128 => run::<cbc::Encryptor<aes::Aes128>>(key, iv, data),
+            Self::Aes192 => run::<cbc::Encryptor<aes::Aes192>>(key, iv, data),
+            Self::Aes256 => run::<cbc::Encryptor<aes::Aes256>>(key, iv, data),
+            Self::DesEde3 => run::<cbc::Encryptor<des::TdesEde3>>(key, iv, data),
+        }
+    }
+
+    fn decrypt(self, key: &[u8], iv: &[u8], data: &[u8]) -> Result<Vec<u8>, SignatureError> {
+        fn run<C: cbc::cipher::BlockDecryptMut + cbc::cipher::KeyIvInit>(
+            key: &[u8],
+            iv: &[u8],
+            data: &[u8],
+        ) -> Result<Vec<u8>, SignatureError> {
+            let cipher = C::new_from_slices(key, iv)
+                .map_err(|_| SignatureError::Malformed("invalid key or IV length".into()))?;
+            cipher
+                .decrypt_padded_vec_mut::<Pkcs7>(data)
+                .map_err(|_| SignatureError::Malformed("bad content padding".into()))
+        }
+
+        match self {
+            Self::Aes128 => run::<cbc::Decryptor<aes::Aes128>>(key, iv, data),
+            Self::Aes192 => run::<cbc::Decryptor<aes::Aes192>>(key, iv, data),
+            Self::Aes256 => run::<cbc::Decryptor<aes::Aes256>>(key, iv, data),
+            Self::DesEde3 => run::<cbc::Decryptor<des::TdesEde3>>(key, iv, data),
+        }
+    }
+}
+
+/// Decrypt a DER-encoded ContentInfo wrapping an EnvelopedData.
+///
+/// Uses the first key-transport recipient; SCEP envelopes have exactly one.
+/// Returns the plaintext and the cipher it was encrypted with.
+pub(crate) fn decrypt(
+    cms_der: &[u8],
+    key: &RsaPrivateKey,
+) -> Result<(Vec<u8>, ContentCipher), SignatureError> {
+    let content_info = ContentInfo::from_der(cms_der)?;
+    if content_info.content_type != rfc5911::ID_ENVELOPED_DATA {
+        return Err(SignatureError::Malformed(format!(
+            "expected EnvelopedData, got content type {}",
+            content_info.content_type
+        )));
+    }
+    let enveloped = content_info.content.decode_as::<EnvelopedData>()?;
+
+    let recipient = enveloped
+        .recip_infos
+        .0
+        .iter()
+        .find_map(|info| match info {
+            RecipientInfo::Ktri(ktri) => Some(ktri),
+            _ => None,
+        })
+        .ok_or_else(|| SignatureError::Malformed("no key transport recipient".into()))?;
+    if recipient.key_enc_alg.oid != rfc5912::RSA_ENCRYPTION {
+        return Err(SignatureError::UnsupportedAlgorithm(
+            recipient.key_enc_alg.oid.to_string(),
+        ));
+    }
+
+    let content_key = key
+        .decrypt(Pkcs1v15Encrypt, recipient.enc_key.as_bytes())
+        .map_err(|_| SignatureError::Malformed("failed to decrypt content key".into()))?;
+
+    let encrypted = &enveloped.encrypted_content;
+    let cipher = ContentCipher::from_oid(&encrypted.content_enc_alg.oid)?;
+    let iv = encrypted
+        .content_enc_alg
+        .parameters
+        .as_ref()
+        .ok_or_else(|| SignatureError::Malformed("missing cipher IV".into()))?
+        .decode_as::<OctetString>()?;
+    let ciphertext = encrypted
+        .encrypted_content
+        .as_ref()
+        .ok_or_else(|| SignatureError::Malformed("missing encrypted content".into()))?;
+
+    let plaintext = cipher.decrypt(&content_key, iv.as_bytes(), ciphertext.as_bytes())?;
+    Ok((plaintext, cipher))
+}
+
+/// Encrypt `content` for `recipient`, returning a DER-encoded ContentInfo
+/// wrapping an EnvelopedData.
+pub(crate) fn encrypt(
+    content: &[u8],
+    recipient: &Certificate,
+    cipher: ContentCipher,
+) -> Result<Vec<u8>, SignatureError> {
+    use rsa::pkcs8::DecodePublicKey as _;
+
+    let spki = recipient.tbs_certificate.subject_public_key_info.to_der()?;
+    let public_key = RsaPublicKey::from_public_key_der(&spki)
+        .map_err(|e| SignatureError::Malformed(format!("recipient RSA public key: {e}")))?;
+
+    let mut content_key = vec![0u8; cipher.key_len()];
+    let mut iv = vec![0u8; cipher.iv_len()];
+    OsRng.fill_bytes(&mut content_key);
+    OsRng.fill_bytes(&mut iv);
+
+    let ciphertext = cipher.encrypt(&content_key, &iv, content)?;
+    let enc_key = public_key
+        .encrypt(&mut OsRng, Pkcs1v15Encrypt, &content_key)
+        .map_err(|e| SignatureError::Malformed(format!("failed to encrypt content key: {e}")))?;
+
+    let recipient_info = RecipientInfo::Ktri(KeyTransRecipientInfo {
+        version: CmsVersion::V0,
+        rid: RecipientIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+            issuer: recipient.tbs_certificate.issuer.clone(),
+            serial_number: recipient.tbs_certificate.serial_number.clone(),
+        }),
+        key_enc_alg: AlgorithmIdentifierOwned {
+            oid: rfc5912::RSA_ENCRYPTION,
+            parameters: Some(Any::null()),
+        },
+        enc_key: OctetString::new(enc_key)?,
+    });
+
+    let enveloped = EnvelopedData {
+        version: CmsVersion::V0,
+        originator_info: None,
+        recip_infos: RecipientInfos::try_from(vec![recipient_info])?,
+        encrypted_content: EncryptedContentInfo {
+            content_type: rfc5911::ID_DATA,
+            content_enc_alg: AlgorithmIdentifierOwned {
+                oid: cipher.oid(),
+                parameters: Some(Any::new(Tag::OctetString, iv)?),
+            },
+            encrypted_content: Some(OctetString::new(ciphertext)?),
+        },
+        unprotected_attrs: None,
+    };
+
+    let content_info = ContentInfo {
+        content_type: rfc5911::ID_ENVELOPED_DATA,
+        content: Any::encode_from(&enveloped)?,
+    };
+    Ok(content_info.to_der()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        use rsa::pkcs8::DecodePrivateKey as _;
+
+        let cert = Certificate::from_der(include_bytes!("../testdata/device-rsa.der")).unwrap();
+        let key =
+            RsaPrivateKey::from_pkcs8_pem(include_str!("../testdata/device-rsa.key")).unwrap();
+
+        for cipher in [
+            ContentCipher::Aes128,
+            ContentCipher::Aes256,
+            ContentCipher::DesEde3,
+        ] {
+            let envelope = encrypt(b"hello scep", &cert, cipher).unwrap();
+            let (plaintext, used) = decrypt(&envelope, &key).unwrap();
+            assert_eq!(plaintext, b"hello scep");
+            assert_eq!(used, cipher);
+        }
+    }
+}
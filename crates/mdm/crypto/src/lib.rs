@@ -1,9 +1,20 @@
 //! MDM Crypto Utilities
 //!
-//! Certificate parsing, PKCS7 verification, and related crypto operations.
+//! Certificate parsing, CMS signature verification, SCEP issuance, and related
+//! crypto operations.
 
+mod algorithm;
+mod ca;
 mod cert;
+mod envelope;
+mod scep;
 mod signature;
+mod signer;
+mod verifier;
 
+pub use ca::*;
 pub use cert::*;
+pub use scep::*;
 pub use signature::*;
+pub use signer::*;
+pub use verifier::*;
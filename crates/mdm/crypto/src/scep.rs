@@ -0,0 +1,658 @@
+//! SCEP (RFC 8894) server for issuing device identity certificates.
+//!
+//! Devices enroll by sending a PKCSReq: a CMS SignedData, signed with a
+//! temporary self-signed certificate, whose content is an EnvelopedData
+//! (encrypted to the CA) holding a PKCS#10 CSR. The CertRep reply mirrors
+//! that shape, with the issued certificate encrypted back to the requester.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use cms::builder::SignedDataBuilder;
+use cms::cert::CertificateChoices;
+use cms::signed_data::{EncapsulatedContentInfo, SignedData, SignerInfo};
+use const_oid::db::rfc5911;
+use const_oid::{AssociatedOid as _, ObjectIdentifier};
+use der::asn1::{OctetString, SetOfVec};
+use der::{Any, Decode as _, Encode as _, Tag, Tagged as _};
+use rsa::RsaPrivateKey;
+use rsa::rand_core::{OsRng, RngCore as _};
+use x509_cert::Certificate;
+use x509_cert::attr::Attribute;
+use x509_cert::request::CertReq;
+use x509_cert::request::attributes::ChallengePassword;
+
+use crate::algorithm::DigestAlgorithm;
+use crate::envelope::{self, ContentCipher};
+use crate::signature::{parse_signed_data, verify_signed_data};
+use crate::signer;
+use crate::{CertificateAuthority, SignatureError};
+
+/// Capabilities advertised by GetCACaps.
+pub const SCEP_CA_CAPS: &str =
+    "POSTPKIOperation\nSHA-1\nSHA-256\nAES\nDES3\nSCEPStandard\nRenewal\n";
+
+/// SCEP signed attribute OIDs (id-attributes under the VeriSign arc).
+mod attr {
+    use const_oid::ObjectIdentifier;
+
+    pub const MESSAGE_TYPE: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.2");
+    pub const PKI_STATUS: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.3");
+    pub const FAIL_INFO: ObjectIdentifier = ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.4");
+    pub const SENDER_NONCE: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.5");
+    pub const RECIPIENT_NONCE: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.6");
+    pub const TRANSACTION_ID: ObjectIdentifier =
+        ObjectIdentifier::new_unwrap("2.16.840.1.113733.1.9.7");
+}
+
+/// SCEP pkiMessage types.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    CertRep,
+    RenewalReq,
+    PkcsReq,
+    CertPoll,
+    GetCert,
+    GetCrl,
+}
+
+impl MessageType {
+    fn code(self) -> &'static str {
+        match self {
+            Self::CertRep => "3",
+            Self::RenewalReq => "17",
+            Self::PkcsReq => "19",
+            Self::CertPoll => "20",
+            Self::GetCert => "21",
+            Self::GetCrl => "22",
+        }
+    }
+
+    fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "3" => Some(Self::CertRep),
+            "17" => Some(Self::RenewalReq),
+            "19" => Some(Self::PkcsReq),
+            "20" => Some(Self::CertPoll),
+            "21" => Some(Self::GetCert),
+            "22" => Some(Self::GetCrl),
+            _ => None,
+        }
+    }
+}
+
+/// Reason codes sent in a failed CertRep.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FailInfo {
+    /// Unrecognized or unsupported algorithm.
+    BadAlg,
+    /// Integrity check (signature or decryption) failed.
+    BadMessageCheck,
+    /// Transaction not permitted or supported.
+    BadRequest,
+}
+
+impl FailInfo {
+    fn code(self) -> &'static str {
+        match self {
+            Self::BadAlg => "0",
+            Self::BadMessageCheck => "1",
+            Self::BadRequest => "2",
+        }
+    }
+}
+
+/// A decrypted certificate request awaiting a signing decision.
+#[derive(Debug)]
+pub struct CsrRequest<'a> {
+    pub message_type: MessageType,
+    pub transaction_id: &'a str,
+    /// The CSR's challengePassword attribute, if any.
+    pub challenge_password: Option<&'a str>,
+    pub csr: &'a CertReq,
+    /// Certificate that signed the pkiMessage.
+    pub signer: &'a Certificate,
+    /// Whether `signer` was issued by this CA (i.e. this is a renewal by an
+    /// already-enrolled device).
+    pub signed_by_ca: bool,
+}
+
+/// Decides whether a certificate request may be signed.
+pub trait SigningPolicy: Send + Sync {
+    /// Return an error to reject the request.
+    fn authorize(&self, request: &CsrRequest<'_>) -> color_eyre::eyre::Result<()>;
+}
+
+impl<F> SigningPolicy for F
+where
+    F: Fn(&CsrRequest<'_>) -> color_eyre::eyre::Result<()> + Send + Sync,
+{
+    fn authorize(&self, request: &CsrRequest<'_>) -> color_eyre::eyre::Result<()> {
+        self(request)
+    }
+}
+
+/// Requires a fixed challenge password, except for renewals signed by a
+/// certificate this CA issued.
+#[derive(Clone)]
+pub struct StaticChallenge {
+    password: String,
+}
+
+/// Leaves out the password.
+impl std::fmt::Debug for StaticChallenge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("StaticChallenge").finish_non_exhaustive()
+    }
+}
+
+impl StaticChallenge {
+    pub fn new(password: impl Into<String>) -> Self {
+        Self {
+            password: password.into(),
+        }
+    }
+}
+
+impl SigningPolicy for StaticChallenge {
+    fn authorize(&self, request: &CsrRequest<'_>) -> color_eyre::eyre::Result<()> {
+        if request.message_type == MessageType::RenewalReq && request.signed_by_ca {
+            return Ok(());
+        }
+
+        match request.challenge_password {
+            Some(password) if challenge_matches(password, &self.password) => Ok(()),
+            Some(_) => color_eyre::eyre::bail!("incorrect challenge password"),
+            None => color_eyre::eyre::bail!("missing challenge password"),
+        }
+    }
+}
+
+/// Compare challenge passwords in constant time.
+///
+/// Both are hashed first so the time taken doesn't depend on their lengths.
+fn challenge_matches(given: &str, expected: &str) -> bool {
+    use sha2::Digest as _;
+    use subtle::ConstantTimeEq as _;
+
+    let given: [u8; 32] = sha2::Sha256::digest(given).into();
+    let expected: [u8; 32] = sha2::Sha256::digest(expected).into();
+    given[..].ct_eq(&expected[..]).into()
+}
+
+/// Single-use challenge passwords, minted per enrollment profile.
+///
+/// Each challenge authorizes one request and expires after `ttl` if unused.
+/// Renewals signed by a certificate this CA issued need no challenge.
+pub struct OneTimeChallenges {
+    ttl: Duration,
+    issued: Mutex<HashMap<String, Instant>>,
+}
+
+impl OneTimeChallenges {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            issued: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Issue a new random challenge.
+    pub fn mint(&self) -> String {
+        use base64::Engine as _;
+
+        let mut bytes = [0u8; 18];
+        OsRng.fill_bytes(&mut bytes);
+        let challenge = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+
+        let now = Instant::now();
+        let mut issued = self.issued.lock().unwrap_or_else(|e| e.into_inner());
+        issued.retain(|_, expires| *expires > now);
+        issued.insert(challenge.clone(), now + self.ttl);
+        challenge
+    }
+
+    /// Consume `challenge`, returning whether it was issued and unexpired.
+    pub fn redeem(&self, challenge: &str) -> bool {
+        let mut issued = self.issued.lock().unwrap_or_else(|e| e.into_inner());
+        issued
+            .remove(challenge)
+            .is_some_and(|expires| expires > Instant::now())
+    }
+}
+
+impl std::fmt::Debug for OneTimeChallenges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("OneTimeChallenges")
+            .field("ttl", &self.ttl)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SigningPolicy for OneTimeChallenges {
+    fn authorize(&self, request: &CsrRequest<'_>) -> color_eyre::eyre::Result<()> {
+        if request.message_type == MessageType::RenewalReq && request.signed_by_ca {
+            return Ok(());
+        }
+
+        match request.challenge_password {
+            Some(password) if self.redeem(password) => Ok(()),
+            Some(_) => color_eyre::eyre::bail!("unknown, used, or expired challenge password"),
+            None => color_eyre::eyre::bail!("missing challenge password"),
+        }
+    }
+}
+
+/// SCEP server backed by a [`CertificateAuthority`].
+pub struct ScepServer {
+    ca: CertificateAuthority,
+    policy: Box<dyn SigningPolicy>,
+}
+
+impl ScepServer {
+    pub fn new(ca: CertificateAuthority, policy: impl SigningPolicy + 'static) -> Self {
+        Self {
+            ca,
+            policy: Box::new(policy),
+        }
+    }
+
+    /// Response body for GetCACaps.
+    pub fn ca_caps(&self) -> &'static str {
+        SCEP_CA_CAPS
+    }
+
+    /// Response body for GetCACert (the DER CA certificate).
+    pub fn ca_cert(&self) -> &[u8] {
+        self.ca.cert_der()
+    }
+
+    /// Handle a PKIOperation and return the DER CertRep.
+    ///
+    /// Errors are returned only when the message itself can't be
+    /// authenticated; rejected requests produce a FAILURE CertRep.
+    pub fn pki_operation(&self, message: &[u8]) -> color_eyre::eyre::Result<Vec<u8>> {
+        let signed_data = parse_signed_data(message)?;
+        let content = encapsulated_content(&signed_data)?;
+        let signer_cert = verify_signed_data(&signed_data, &content)?;
+        let signer_info = signed_data
+            .signer_infos
+            .0
+            .iter()
+            .next()
+            .ok_or_else(|| color_eyre::eyre::eyre!("pkiMessage has no signer"))?;
+
+        let message_type = signed_attr_str(signer_info, attr::MESSAGE_TYPE)
+            .ok_or_else(|| color_eyre::eyre::eyre!("pkiMessage missing messageType"))?;
+        let transaction_id = signed_attr_str(signer_info, attr::TRANSACTION_ID)
+            .ok_or_else(|| color_eyre::eyre::eyre!("pkiMessage missing transactionID"))?;
+        let sender_nonce = signed_attr_octets(signer_info, attr::SENDER_NONCE)
+            .ok_or_else(|| color_eyre::eyre::eyre!("pkiMessage missing senderNonce"))?;
+        let digest = DigestAlgorithm::from_oid(&signer_info.digest_alg.oid)?;
+
+        let outcome = match MessageType::from_code(&message_type) {
+            Some(message_type @ (MessageType::PkcsReq | MessageType::RenewalReq)) => {
+                self.handle_request(message_type, &transaction_id, &content, &signer_cert)
+            }
+            _ => Err((
+                FailInfo::BadRequest,
+                color_eyre::eyre::eyre!("unsupported messageType {message_type}"),
+            )),
+        };
+
+        let mut attrs = vec![
+            attribute(attr::MESSAGE_TYPE, printable(MessageType::CertRep.code())?)?,
+            attribute(attr::TRANSACTION_ID, printable(&transaction_id)?)?,
+            attribute(
+                attr::RECIPIENT_NONCE,
+                Any::new(Tag::OctetString, sender_nonce)?,
+            )?,
+            attribute(attr::SENDER_NONCE, Any::new(Tag::OctetString, nonce())?)?,
+        ];
+
+        let envelope = match outcome {
+            Ok(envelope) => {
+                tracing::info!(%transaction_id, "SCEP certificate issued");
+                attrs.push(attribute(attr::PKI_STATUS, printable("0")?)?);
+                Some(envelope)
+            }
+            Err((fail_info, e)) => {
+                tracing::warn!(%transaction_id, ?fail_info, "SCEP request rejected: {e:#}");
+                attrs.push(attribute(attr::PKI_STATUS, printable("2")?)?);
+                attrs.push(attribute(attr::FAIL_INFO, printable(fail_info.code())?)?);
+                None
+            }
+        };
+
+        sign_pki_message(
+            self.ca.cert(),
+            self.ca.key(),
+            digest,
+            attrs,
+            envelope.as_deref(),
+        )
+    }
+
+    /// Decrypt, authorize, and sign a PKCSReq or RenewalReq. Returns the
+    /// pkcsPKIEnvelope for the CertRep.
+    fn handle_request(
+        &self,
+        message_type: MessageType,
+        transaction_id: &str,
+        content: &[u8],
+        signer: &Certificate,
+    ) -> Result<Vec<u8>, (FailInfo, color_eyre::Report)> {
+        let (csr_der, cipher) = envelope::decrypt(content, self.ca.key()).map_err(|e| {
+            let fail_info = match e {
+                SignatureError::UnsupportedAlgorithm(_) => FailInfo::BadAlg,
+                _ => FailInfo::BadMessageCheck,
+            };
+            (fail_info, color_eyre::eyre::eyre!(e))
+        })?;
+        let csr = CertReq::from_der(&csr_der).map_err(|e| {
+            (
+                FailInfo::BadRequest,
+                color_eyre::eyre::eyre!("invalid CSR: {e}"),
+            )
+        })?;
+
+        let challenge_password = challenge_password(&csr);
+        let request = CsrRequest {
+            message_type,
+            transaction_id,
+            challenge_password: challenge_password.as_deref(),
+            csr: &csr,
+            signer,
+            signed_by_ca: self.ca.issued(signer),
+        };
+        self.policy
+            .authorize(&request)
+            .map_err(|e| (FailInfo::BadRequest, e))?;
+
+        let cert_der = self
+            .ca
+            .issue(&csr)
+            .map_err(|e| (FailInfo::BadMessageCheck, e))?;
+
+        certs_only_envelope(&cert_der, signer, cipher).map_err(|e| (FailInfo::BadRequest, e))
+    }
+}
+
+/// Wrap `cert_der` in a degenerate certs-only SignedData and encrypt it to
+/// `recipient`.
+fn certs_only_envelope(
+    cert_der: &[u8],
+    recipient: &Certificate,
+    cipher: ContentCipher,
+) -> color_eyre::eyre::Result<Vec<u8>> {
+    let cert = Certificate::from_der(cert_der)?;
+    let content = EncapsulatedContentInfo {
+        econtent_type: rfc5911::ID_DATA,
+        econtent: None,
+    };
+    let certs_only = SignedDataBuilder::new(&content)
+        .add_certificate(CertificateChoices::Certificate(cert))
+        .map_err(signer::cms_error)?
+        .build()
+        .map_err(signer::cms_error)?
+        .to_der()?;
+
+    Ok(envelope::encrypt(&certs_only, recipient, cipher)?)
+}
+
+/// Sign a pkiMessage with `attrs` over `content`.
+///
+/// Without content (a failed CertRep) the signature covers an empty
+/// detached message, so the message-digest attribute is still present.
+pub(crate) fn sign_pki_message(
+    cert: &Certificate,
+    key: &RsaPrivateKey,
+    digest: DigestAlgorithm,
+    attrs: Vec<Attribute>,
+    content: Option<&[u8]>,
+) -> color_eyre::eyre::Result<Vec<u8>> {
+    signer::sign_rsa(cert, &[], key, digest, attrs, content)
+}
+
+/// The encapsulated content bytes, or empty when the content is absent.
+pub(crate) fn encapsulated_content(signed_data: &SignedData) -> Result<Vec<u8>, SignatureError> {
+    match &signed_data.encap_content_info.econtent {
+        Some(econtent) => Ok(econtent.decode_as::<OctetString>()?.into_bytes()),
+        None => Ok(Vec::new()),
+    }
+}
+
+fn signed_attr(signer: &SignerInfo, oid: ObjectIdentifier) -> Option<&Any> {
+    signer
+        .signed_attrs
+        .iter()
+        .flat_map(|attrs| attrs.iter())
+        .find(|attr| attr.oid == oid)
+        .and_then(|attr| attr.values.iter().next())
+}
+
+/// A string-valued signed attribute. Clients disagree on the string type,
+/// so the tag is ignored.
+pub(crate) fn signed_attr_str(signer: &SignerInfo, oid: ObjectIdentifier) -> Option<String> {
+    let value = signed_attr(signer, oid)?;
+    String::from_utf8(value.value().to_vec()).ok()
+}
+
+pub(crate) fn signed_attr_octets(signer: &SignerInfo, oid: ObjectIdentifier) -> Option<Vec<u8>> {
+    let value = signed_attr(signer, oid)?;
+    (value.tag() == Tag::OctetString).then(|| value.value().to_vec())
+}
+
+/// The CSR's challengePassword attribute.
+fn challenge_password(csr: &CertReq) -> Option<String> {
+    csr.info
+        .attributes
+        .iter()
+        .find(|attr| attr.oid == ChallengePassword::OID)
+        .and_then(|attr| attr.values.iter().next())
+        .and_then(|value| String::from_utf8(value.value().to_vec()).ok())
+}
+
+pub(crate) fn attribute(oid: ObjectIdentifier, value: Any) -> Result<Attribute, SignatureError> {
+    Ok(Attribute {
+        oid,
+        values: SetOfVec::try_from(vec![value])?,
+    })
+}
+
+pub(crate) fn printable(value: &str) -> Result<Any, SignatureError> {
+    Ok(Any::new(Tag::PrintableString, value.as_bytes())?)
+}
+
+/// A fresh 16-byte senderNonce.
+pub(crate) fn nonce() -> Vec<u8> {
+    let mut nonce = vec![0u8; 16];
+    OsRng.fill_bytes(&mut nonce);
+    nonce
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr as _;
+
+    use rsa::pkcs8::DecodePrivateKey as _;
+    use x509_cert::builder::{Builder as _, RequestBuilder};
+    use x509_cert::ext::pkix::name::DirectoryString;
+    use x509_cert::name::Name;
+    use x509_cert::request::attributes::ChallengePassword;
+
+    use super::*;
+    use crate::SignatureVerifier;
+
+    const CA_CERT: &str = include_str!("../testdata/ca.pem");
+    const CA_KEY: &str = include_str!("../testdata/ca.key");
+    const DEVICE_CERT: &[u8] = include_bytes!("../testdata/device-rsa.der");
+    const DEVICE_KEY: &str = include_str!("../testdata/device-rsa.key");
+
+    fn server() -> ScepServer {
+        let ca = CertificateAuthority::from_pem(CA_CERT, CA_KEY).unwrap();
+        ScepServer::new(ca, StaticChallenge::new("secret"))
+    }
+
+    /// Build a PKCSReq the way a device would, returning it with its nonce.
+    fn pkcs_req(challenge: &str) -> (Vec<u8>, Vec<u8>) {
+        let key = RsaPrivateKey::from_pkcs8_pem(DEVICE_KEY).unwrap();
+        let device = Certificate::from_der(DEVICE_CERT).unwrap();
+        let ca = Certificate::from_der(&pem::parse(CA_CERT).unwrap().into_contents()).unwrap();
+
+        let csr_signer = rsa::pkcs1v15::SigningKey::<sha2::Sha256>::new(key.clone());
+        let subject = Name::from_str("CN=scep-test-device").unwrap();
+        let mut csr = RequestBuilder::new(subject, &csr_signer).unwrap();
+        csr.add_attribute(&ChallengePassword(DirectoryString::Utf8String(
+            challenge.into(),
+        )))
+        .unwrap();
+        let csr = csr.build::<rsa::pkcs1v15::Signature>().unwrap();
+
+        let envelope =
+            envelope::encrypt(&csr.to_der().unwrap(), &ca, ContentCipher::Aes128).unwrap();
+        let nonce = nonce();
+        let attrs = vec![
+            attribute(attr::MESSAGE_TYPE, printable("19").unwrap()).unwrap(),
+            attribute(attr::TRANSACTION_ID, printable("tx-1").unwrap()).unwrap(),
+            attribute(
+                attr::SENDER_NONCE,
+                Any::new(Tag::OctetString, nonce.clone()).unwrap(),
+            )
+            .unwrap(),
+        ];
+        let message = sign_pki_message(
+            &device,
+            &key,
+            DigestAlgorithm::Sha256,
+            attrs,
+            Some(&envelope),
+        )
+        .unwrap();
+
+        (message, nonce)
+    }
+
+    /// Verify a CertRep, returning its signer info and content.
+    fn parse_cert_rep(response: &[u8]) -> (SignerInfo, Vec<u8>) {
+        let signed_data = parse_signed_data(response).unwrap();
+        let content = encapsulated_content(&signed_data).unwrap();
+        let signer = verify_signed_data(&signed_data, &content).unwrap();
+        assert_eq!(signer.to_der().unwrap(), server().ca_cert());
+
+        let signer_info = signed_data.signer_infos.0.iter().next().unwrap().clone();
+        (signer_info, content)
+    }
+
+    #[test]
+    fn test_pkcs_req_issues_certificate() {
+        let (message, nonce) = pkcs_req("secret");
+        let response = server().pki_operation(&message).unwrap();
+
+        let (signer_info, content) = parse_cert_rep(&response);
+        assert_eq!(
+            signed_attr_str(&signer_info, attr::PKI_STATUS).as_deref(),
+            Some("0")
+        );
+        assert_eq!(
+            signed_attr_str(&signer_info, attr::TRANSACTION_ID).as_deref(),
+            Some("tx-1")
+        );
+        assert_eq!(
+            signed_attr_octets(&signer_info, attr::RECIPIENT_NONCE),
+            Some(nonce)
+        );
+
+        let key = RsaPrivateKey::from_pkcs8_pem(DEVICE_KEY).unwrap();
+        let (certs_only, _) = envelope::decrypt(&content, &key).unwrap();
+        let certs_only = parse_signed_data(&certs_only).unwrap();
+        let Some(CertificateChoices::Certificate(issued)) =
+            certs_only.certificates.unwrap().0.into_vec().pop()
+        else {
+            panic!("no certificate in CertRep");
+        };
+
+        assert_eq!(
+            issued.tbs_certificate.subject.to_string(),
+            "CN=scep-test-device"
+        );
+        SignatureVerifier::from_pem_bundle(CA_CERT)
+            .unwrap()
+            .verify_cert(&issued.to_der().unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn test_wrong_challenge_fails() {
+        let (message, _) = pkcs_req("wrong");
+        let response = server().pki_operation(&message).unwrap();
+
+        let (signer_info, content) = parse_cert_rep(&response);
+        assert!(content.is_empty());
+        assert_eq!(
+            signed_attr_str(&signer_info, attr::PKI_STATUS).as_deref(),
+            Some("2")
+        );
+        assert_eq!(
+            signed_attr_str(&signer_info, attr::FAIL_INFO).as_deref(),
+            Some(FailInfo::BadRequest.code())
+        );
+    }
+
+    #[test]
+    fn test_challenge_matches() {
+        assert!(challenge_matches("secret", "secret"));
+        assert!(!challenge_matches("secre", "secret"));
+        assert!(!challenge_matches("secret2", "secret"));
+        assert!(!challenge_matches("", "secret"));
+    }
+
+    #[test]
+    fn test_one_time_challenge_single_use() {
+        let ca = CertificateAuthority::from_pem(CA_CERT, CA_KEY).unwrap();
+        let challenges = std::sync::Arc::new(OneTimeChallenges::new(Duration::from_secs(60)));
+        let policy = challenges.clone();
+        let server = ScepServer::new(ca, move |request: &CsrRequest<'_>| {
+            policy.authorize(request)
+        });
+        let status = |challenge: &str| {
+            let (message, _) = pkcs_req(challenge);
+            let (signer_info, _) = parse_cert_rep(&server.pki_operation(&message).unwrap());
+            signed_attr_str(&signer_info, attr::PKI_STATUS)
+        };
+
+        let challenge = challenges.mint();
+        assert_eq!(status(&challenge).as_deref(), Some("0"));
+        assert_eq!(status(&challenge).as_deref(), Some("2"));
+        assert_eq!(status("secret").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn test_one_time_challenge_expires() {
+        let challenges = OneTimeChallenges::new(Duration::ZERO);
+        let challenge = challenges.mint();
+        assert!(!challenges.redeem(&challenge));
+    }
+
+    #[test]
+    fn test_debug_redacts_secrets() {
+        let ca = CertificateAuthority::from_pem(CA_CERT, CA_KEY).unwrap();
+        let debug = format!("{ca:?}");
+        assert!(
+            debug.contains("subject") && !debug.contains("key"),
+            "{debug}"
+        );
+        let debug = format!("{:?}", StaticChallenge::new("secret"));
+        assert!(!debug.contains("secret"), "{debug}");
+    }
+
+    #[test]
+    fn test_unsigned_message_rejected() {
+        assert!(server().pki_operation(b"not cms").is_err());
+    }
+}
@@ -1,58 +1,281 @@
 //! MDM signature verification.
 
-use color_eyre::eyre::WrapErr as _;
+use cms::cert::IssuerAndSerialNumber;
+use cms::content_info::ContentInfo;
+use cms::signed_data::{SignedData, SignerIdentifier, SignerInfo};
+use const_oid::db::{rfc5280, rfc5911};
+use der::{Decode as _, Encode as _};
+use x509_cert::Certificate;
+use x509_cert::ext::pkix::SubjectKeyIdentifier;
+
+use crate::algorithm::{self, DigestAlgorithm};
+
+/// Error verifying a CMS signature.
+#[derive(Debug)]
+pub enum SignatureError {
+    /// The signature is not valid base64.
+    Base64(base64::DecodeError),
+    /// The CMS structure could not be parsed.
+    Malformed(String),
+    /// The signature uses a digest or key algorithm we don't support.
+    UnsupportedAlgorithm(String),
+    /// No certificate in the SignedData matches the signer.
+    MissingSignerCert,
+    /// The signed message digest doesn't match the content.
+    DigestMismatch,
+    /// The signature doesn't verify against the signer's public key.
+    BadSignature,
+}
+
+impl std::fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Base64(e) => write!(f, "invalid signature base64: {e}"),
+            Self::Malformed(e) => write!(f, "malformed CMS signature: {e}"),
+            Self::UnsupportedAlgorithm(alg) => write!(f, "unsupported algorithm: {alg}"),
+            Self::MissingSignerCert => write!(f, "signer certificate not found"),
+            Self::DigestMismatch => write!(f, "message digest does not match content"),
+            Self::BadSignature => write!(f, "signature verification failed"),
+        }
+    }
+}
+
+impl std::error::Error for SignatureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Base64(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<der::Error> for SignatureError {
+    fn from(e: der::Error) -> Self {
+        Self::Malformed(e.to_string())
+    }
+}
 
 /// Verify the Mdm-Signature header and extract the signing certificate.
 ///
-/// The Mdm-Signature header contains a base64-encoded PKCS#7 detached signature
-/// over the request body.
-///
-/// Note: Full CMS verification is complex. This is a placeholder implementation.
+/// The Mdm-Signature header contains a base64-encoded CMS detached signature
+/// over the request body. Returns the DER-encoded signer certificate.
 pub fn verify_mdm_signature(
     signature_header: &str,
-    _body: &[u8],
-) -> color_eyre::eyre::Result<Vec<u8>> {
+    body: &[u8],
+) -> Result<Vec<u8>, SignatureError> {
     use base64::Engine as _;
 
-    // Decode the base64 signature
-    let signature_der = base64::engine::general_purpose::STANDARD
-        .decode(signature_header)
-        .wrap_err("failed to decode Mdm-Signature base64")?;
+    let cms_der = base64::engine::general_purpose::STANDARD
+        .decode(signature_header.trim())
+        .map_err(SignatureError::Base64)?;
 
-    // In production, we would:
-    // 1. Parse CMS SignedData structure
-    // 2. Extract the signing certificate
-    // 3. Verify the signature over the body
-    // 4. Validate the certificate chain
+    verify_detached_signature(&cms_der, body)
+}
 
-    tracing::warn!("MDM signature verification is not fully implemented");
+/// Verify a DER-encoded CMS SignedData over detached `content`.
+///
+/// Returns the DER-encoded signer certificate.
+pub fn verify_detached_signature(
+    cms_der: &[u8],
+    content: &[u8],
+) -> Result<Vec<u8>, SignatureError> {
+    let signed_data = parse_signed_data(cms_der)?;
+    let cert = verify_signed_data(&signed_data, content)?;
+    Ok(cert.to_der()?)
+}
+
+/// Parse a DER-encoded ContentInfo wrapping a SignedData.
+pub(crate) fn parse_signed_data(cms_der: &[u8]) -> Result<SignedData, SignatureError> {
+    let content_info = ContentInfo::from_der(cms_der)?;
+
+    if content_info.content_type != rfc5911::ID_SIGNED_DATA {
+        return Err(SignatureError::Malformed(format!(
+            "expected SignedData, got content type {}",
+            content_info.content_type
+        )));
+    }
 
-    // For now, just return the raw signature data
-    // A full implementation would use the cms crate properly
-    Ok(signature_der)
+    Ok(content_info.content.decode_as::<SignedData>()?)
 }
 
-/// Signature verifier using a trusted CA.
-pub struct SignatureVerifier {
-    /// Trusted CA certificate (DER-encoded).
-    #[allow(dead_code)]
-    ca_cert: Vec<u8>,
+/// Verify the first signer of `signed_data` over `content`.
+///
+/// Returns the signer certificate.
+pub(crate) fn verify_signed_data(
+    signed_data: &SignedData,
+    content: &[u8],
+) -> Result<Certificate, SignatureError> {
+    let signer = signed_data
+        .signer_infos
+        .0
+        .iter()
+        .next()
+        .ok_or_else(|| SignatureError::Malformed("no signer infos".into()))?;
+
+    let cert = find_signer_cert(signed_data, &signer.sid)?;
+
+    let digest_alg = DigestAlgorithm::from_oid(&signer.digest_alg.oid)?;
+    let content_digest = digest_alg.digest(content);
+
+    // With signed attributes, the signature covers their DER encoding (as a
+    // SET OF) and the message-digest attribute carries the content hash.
+    let signed_bytes = match &signer.signed_attrs {
+        Some(attrs) => {
+            if message_digest(signer)? != content_digest {
+                return Err(SignatureError::DigestMismatch);
+            }
+            attrs.to_der()?
+        }
+        None => content.to_vec(),
+    };
+
+    let sig_digest = algorithm::signature_digest(&signer.signature_algorithm.oid, digest_alg)?;
+    algorithm::verify_prehashed(
+        &cert.tbs_certificate.subject_public_key_info,
+        sig_digest,
+        &sig_digest.digest(&signed_bytes),
+        signer.signature.as_bytes(),
+    )?;
+
+    Ok(cert)
 }
 
-impl SignatureVerifier {
-    /// Create a new verifier with a trusted CA.
-    pub fn new(ca_cert_der: Vec<u8>) -> Self {
-        Self {
-            ca_cert: ca_cert_der,
+/// Find the certificate identified by `sid` in the SignedData certificate set.
+fn find_signer_cert(
+    signed_data: &SignedData,
+    sid: &SignerIdentifier,
+) -> Result<Certificate, SignatureError> {
+    let certs = signed_data
+        .certificates
+        .iter()
+        .flat_map(|set| set.0.iter())
+        .filter_map(|choice| match choice {
+            cms::cert::CertificateChoices::Certificate(cert) => Some(cert),
+            _ => None,
+        });
+
+    for cert in certs {
+        let matches = match sid {
+            SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+                issuer,
+                serial_number,
+            }) => {
+                cert.tbs_certificate.issuer == *issuer
+                    && cert.tbs_certificate.serial_number == *serial_number
+            }
+            SignerIdentifier::SubjectKeyIdentifier(ski) => {
+                cert.tbs_certificate.extensions.iter().flatten().any(|ext| {
+                    // The extension value is a DER OCTET STRING holding the key ID
+                    ext.extn_id == rfc5280::ID_CE_SUBJECT_KEY_IDENTIFIER
+                        && SubjectKeyIdentifier::from_der(ext.extn_value.as_bytes())
+                            .is_ok_and(|cert_ski| cert_ski == *ski)
+                })
+            }
+        };
+
+        if matches {
+            return Ok(cert.clone());
         }
     }
 
-    /// Verify that a certificate was signed by the trusted CA.
-    ///
-    /// Note: This is a placeholder. Full implementation needs proper X.509 verification.
-    pub fn verify_cert(&self, _cert_der: &[u8]) -> color_eyre::eyre::Result<()> {
-        // In production, use x509-parser's verify_signature or webpki
-        tracing::warn!("certificate verification is not fully implemented");
-        Ok(())
+    Err(SignatureError::MissingSignerCert)
+}
+
+/// Extract the message-digest signed attribute.
+fn message_digest(signer: &SignerInfo) -> Result<Vec<u8>, SignatureError> {
+    let attr = signer
+        .signed_attrs
+        .iter()
+        .flat_map(|attrs| attrs.iter())
+        .find(|attr| attr.oid == rfc5911::ID_MESSAGE_DIGEST)
+        .ok_or_else(|| SignatureError::Malformed("missing message-digest attribute".into()))?;
+
+    let value = attr
+        .values
+        .iter()
+        .next()
+        .ok_or_else(|| SignatureError::Malformed("empty message-digest attribute".into()))?;
+
+    Ok(value.decode_as::<der::asn1::OctetString>()?.into_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = include_bytes!("../testdata/checkin.plist");
+
+    fn header(p7s: &[u8]) -> String {
+        use base64::Engine as _;
+        base64::engine::general_purpose::STANDARD.encode(p7s)
+    }
+
+    #[test]
+    fn test_verify_rsa_signature() {
+        let p7s = include_bytes!("../testdata/checkin-rsa.p7s");
+        let cert = verify_mdm_signature(&header(p7s), BODY).unwrap();
+        assert_eq!(cert, include_bytes!("../testdata/device-rsa.der"));
+    }
+
+    #[test]
+    fn test_verify_ec_signature() {
+        let p7s = include_bytes!("../testdata/checkin-ec.p7s");
+        let cert = verify_mdm_signature(&header(p7s), BODY).unwrap();
+        assert_eq!(cert, include_bytes!("../testdata/device-ec.der"));
+    }
+
+    #[test]
+    fn test_tampered_body() {
+        let p7s = include_bytes!("../testdata/checkin-rsa.p7s");
+        let mut body = BODY.to_vec();
+        body[0] ^= 1;
+
+        let err = verify_mdm_signature(&header(p7s), &body).unwrap_err();
+        assert!(matches!(err, SignatureError::DigestMismatch), "{err}");
+    }
+
+    #[test]
+    fn test_tampered_signature() {
+        // No unsigned attributes, so the signature value ends the structure.
+        let mut p7s = include_bytes!("../testdata/checkin-ec.p7s").to_vec();
+        let last = p7s.len() - 1;
+        p7s[last] ^= 1;
+
+        let err = verify_mdm_signature(&header(&p7s), BODY).unwrap_err();
+        assert!(matches!(err, SignatureError::BadSignature), "{err}");
+    }
+
+    #[test]
+    fn test_signer_cert_by_subject_key_id() {
+        let signed_data = parse_signed_data(include_bytes!("../testdata/checkin-rsa.p7s")).unwrap();
+        // Subject key identifier of device-rsa.der
+        let key_id = [
+            0x44, 0x40, 0x9e, 0xc4, 0x0c, 0x01, 0xdb, 0x37, 0x6f, 0x7f, 0xe5, 0xd3, 0xe6, 0xde,
+            0xe9, 0xb2, 0x0f, 0x6f, 0x17, 0x6a,
+        ];
+        let sid = |key_id: &[u8]| {
+            SignerIdentifier::SubjectKeyIdentifier(SubjectKeyIdentifier(
+                der::asn1::OctetString::new(key_id).unwrap(),
+            ))
+        };
+
+        let cert = find_signer_cert(&signed_data, &sid(&key_id)).unwrap();
+        assert_eq!(
+            cert.to_der().unwrap(),
+            include_bytes!("../testdata/device-rsa.der")
+        );
+        // A trailing part of the key ID isn't a match
+        assert!(matches!(
+            find_signer_cert(&signed_data, &sid(&key_id[4..])),
+            Err(SignatureError::MissingSignerCert)
+        ));
+    }
+
+    #[test]
+    fn test_missing_signer_cert() {
+        let p7s = include_bytes!("../testdata/checkin-nocerts.p7s");
+
+        let err = verify_mdm_signature(&header(p7s), BODY).unwrap_err();
+        assert!(matches!(err, SignatureError::MissingSignerCert), "{err}");
     }
 }
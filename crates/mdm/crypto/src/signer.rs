@@ -0,0 +1,241 @@
+//! CMS signing with an RSA key.
+
+use std::path::Path;
+
+use cms::builder::{SignedDataBuilder, SignerInfoBuilder};
+use cms::cert::{CertificateChoices, IssuerAndSerialNumber};
+use cms::signed_data::{EncapsulatedContentInfo, SignerIdentifier};
+use color_eyre::eyre::WrapErr as _;
+use const_oid::db::rfc5911;
+use der::{Any, Decode as _, Encode as _, Tag};
+use rsa::RsaPrivateKey;
+use x509_cert::Certificate;
+use x509_cert::attr::Attribute;
+use x509_cert::spki::AlgorithmIdentifierOwned;
+
+use crate::algorithm::DigestAlgorithm;
+
+/// Signs content as attached CMS SignedData, e.g. signed `.mobileconfig`
+/// profiles.
+#[derive(Clone)]
+pub struct CmsSigner {
+    cert: Certificate,
+    chain: Vec<Certificate>,
+    key: RsaPrivateKey,
+}
+
+/// Leaves out the private key.
+impl std::fmt::Debug for CmsSigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CmsSigner")
+            .field("subject", &self.cert.tbs_certificate.subject.to_string())
+            .field("chain", &self.chain.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl CmsSigner {
+    /// Create a signer from a PEM bundle (signing certificate first, then
+    /// any intermediates) and a PEM RSA private key.
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> color_eyre::eyre::Result<Self> {
+        let mut certs = pem::parse_many(cert_pem)
+            .wrap_err("failed to parse signing certificate PEM")?
+            .into_iter()
+            .filter(|pem| pem.tag() == "CERTIFICATE")
+            .map(|pem| Certificate::from_der(pem.contents()))
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("failed to parse signing certificate")?;
+        if certs.is_empty() {
+            color_eyre::eyre::bail!("no certificate in signing certificate PEM");
+        }
+        let cert = certs.remove(0);
+
+        Ok(Self {
+            cert,
+            chain: certs,
+            key: rsa_key_from_pem(key_pem)?,
+        })
+    }
+
+    /// Load a signer from PEM files on disk.
+    pub fn load(cert_path: &Path, key_path: &Path) -> color_eyre::eyre::Result<Self> {
+        let cert = std::fs::read_to_string(cert_path)
+            .wrap_err_with(|| format!("failed to read {}", cert_path.display()))?;
+        let key = std::fs::read_to_string(key_path)
+            .wrap_err_with(|| format!("failed to read {}", key_path.display()))?;
+        Self::from_pem(&cert, &key)
+    }
+
+    /// Sign `content`, returning a DER ContentInfo with the content attached.
+    pub fn sign(&self, content: &[u8]) -> color_eyre::eyre::Result<Vec<u8>> {
+        sign_rsa(
+            &self.cert,
+            &self.chain,
+            &self.key,
+            DigestAlgorithm::Sha256,
+            Vec::new(),
+            Some(content),
+        )
+    }
+}
+
+/// Parse a PEM RSA private key (PKCS#8 or PKCS#1).
+pub(crate) fn rsa_key_from_pem(key_pem: &str) -> color_eyre::eyre::Result<RsaPrivateKey> {
+    use rsa::pkcs1::DecodeRsaPrivateKey as _;
+    use rsa::pkcs8::DecodePrivateKey as _;
+
+    let key = pem::parse(key_pem).wrap_err("failed to parse private key PEM")?;
+    match key.tag() {
+        "PRIVATE KEY" => RsaPrivateKey::from_pkcs8_der(key.contents())
+            .map_err(|e| color_eyre::eyre::eyre!("invalid PKCS#8 private key: {e}")),
+        "RSA PRIVATE KEY" => RsaPrivateKey::from_pkcs1_der(key.contents())
+            .map_err(|e| color_eyre::eyre::eyre!("invalid PKCS#1 private key: {e}")),
+        other => color_eyre::eyre::bail!("unsupported private key PEM type: {other}"),
+    }
+}
+
+/// Fail unless `key` is the private half of `cert`'s public key.
+pub(crate) fn check_key_matches(
+    cert: &Certificate,
+    key: &RsaPrivateKey,
+) -> color_eyre::eyre::Result<()> {
+    use rsa::pkcs8::EncodePublicKey as _;
+
+    let cert_spki = cert
+        .tbs_certificate
+        .subject_public_key_info
+        .to_der()
+        .wrap_err("failed to encode certificate public key")?;
+    let key_spki = key
+        .to_public_key()
+        .to_public_key_der()
+        .map_err(|e| color_eyre::eyre::eyre!("failed to encode public key: {e}"))?;
+    if cert_spki != key_spki.as_bytes() {
+        color_eyre::eyre::bail!("private key does not match certificate");
+    }
+    Ok(())
+}
+
+/// Sign `content` with `key`, adding `attrs` to the signed attributes.
+///
+/// Without content the signature covers an empty detached message, so the
+/// message-digest attribute is still present.
+pub(crate) fn sign_rsa(
+    cert: &Certificate,
+    chain: &[Certificate],
+    key: &RsaPrivateKey,
+    digest: DigestAlgorithm,
+    attrs: Vec<Attribute>,
+    content: Option<&[u8]>,
+) -> color_eyre::eyre::Result<Vec<u8>> {
+    match digest {
+        DigestAlgorithm::Sha1 => sign_with::<sha1::Sha1>(cert, chain, key, digest, attrs, content),
+        DigestAlgorithm::Sha256 => {
+            sign_with::<sha2::Sha256>(cert, chain, key, digest, attrs, content)
+        }
+        DigestAlgorithm::Sha384 => {
+            sign_with::<sha2::Sha384>(cert, chain, key, digest, attrs, content)
+        }
+        DigestAlgorithm::Sha512 => {
+            sign_with::<sha2::Sha512>(cert, chain, key, digest, attrs, content)
+        }
+    }
+}
+
+fn sign_with<D>(
+    cert: &Certificate,
+    chain: &[Certificate],
+    key: &RsaPrivateKey,
+    digest: DigestAlgorithm,
+    attrs: Vec<Attribute>,
+    content: Option<&[u8]>,
+) -> color_eyre::eyre::Result<Vec<u8>>
+where
+    D: sha2::Digest + const_oid::AssociatedOid + rsa::pkcs1v15::RsaSignatureAssociatedOid,
+{
+    let signer = rsa::pkcs1v15::SigningKey::<D>::new(key.clone());
+    let digest_alg = AlgorithmIdentifierOwned {
+        oid: digest.oid(),
+        parameters: None,
+    };
+    let sid = SignerIdentifier::IssuerAndSerialNumber(IssuerAndSerialNumber {
+        issuer: cert.tbs_certificate.issuer.clone(),
+        serial_number: cert.tbs_certificate.serial_number.clone(),
+    });
+
+    let econtent = content
+        .map(|content| Any::new(Tag::OctetString, content))
+        .transpose()?;
+    let external_digest = econtent.is_none().then(|| digest.digest(&[]));
+    let encapsulated = EncapsulatedContentInfo {
+        econtent_type: rfc5911::ID_DATA,
+        econtent,
+    };
+
+    let mut signer_info = SignerInfoBuilder::new(
+        &signer,
+        sid,
+        digest_alg.clone(),
+        &encapsulated,
+        external_digest.as_deref(),
+    )
+    .map_err(cms_error)?;
+    for attr in attrs {
+        signer_info.add_signed_attribute(attr).map_err(cms_error)?;
+    }
+
+    let mut builder = SignedDataBuilder::new(&encapsulated);
+    builder
+        .add_digest_algorithm(digest_alg)
+        .map_err(cms_error)?
+        .add_certificate(CertificateChoices::Certificate(cert.clone()))
+        .map_err(cms_error)?;
+    for cert in chain {
+        builder
+            .add_certificate(CertificateChoices::Certificate(cert.clone()))
+            .map_err(cms_error)?;
+    }
+    let content_info = builder
+        .add_signer_info::<_, rsa::pkcs1v15::Signature>(signer_info)
+        .map_err(cms_error)?
+        .build()
+        .map_err(cms_error)?;
+    Ok(content_info.to_der()?)
+}
+
+pub(crate) fn cms_error(e: cms::builder::Error) -> color_eyre::Report {
+    color_eyre::eyre::eyre!("failed to build CMS message: {e}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scep::encapsulated_content;
+    use crate::signature::{parse_signed_data, verify_signed_data};
+
+    #[test]
+    fn test_sign_attached() {
+        let signer = CmsSigner::from_pem(
+            include_str!("../testdata/device-rsa.pem"),
+            include_str!("../testdata/device-rsa.key"),
+        )
+        .unwrap();
+
+        let signed = signer.sign(b"<plist/>").unwrap();
+        let signed_data = parse_signed_data(&signed).unwrap();
+        let content = encapsulated_content(&signed_data).unwrap();
+        assert_eq!(content, b"<plist/>");
+
+        let cert = verify_signed_data(&signed_data, &content).unwrap();
+        assert_eq!(
+            cert.to_der().unwrap(),
+            include_bytes!("../testdata/device-rsa.der")
+        );
+
+        let debug = format!("{signer:?}");
+        assert!(
+            debug.contains("subject") && !debug.contains("key"),
+            "{debug}"
+        );
+    }
+}
@@ -0,0 +1,425 @@
+//! Device identity certificate chain validation.
+
+use std::time::SystemTime;
+
+use color_eyre::eyre::WrapErr as _;
+use const_oid::db::rfc5280;
+use der::{Decode as _, Encode as _};
+use x509_cert::Certificate;
+use x509_cert::crl::CertificateList;
+use x509_cert::ext::pkix::{BasicConstraints, ExtendedKeyUsage, KeyUsage};
+
+use crate::algorithm;
+
+/// Maximum number of issuers followed when building a chain.
+const MAX_CHAIN_DEPTH: usize = 8;
+
+/// Signature verifier using a pool of trusted CAs.
+///
+/// Validates device identity certificates (from a SCEP CA, an ACME CA, or an
+/// intermediate bundle) up to one of the trust anchors, checking validity
+/// windows, key usage, extended key usage, and any configured CRLs.
+#[derive(Debug, Clone, Default)]
+pub struct SignatureVerifier {
+    /// Trust anchors.
+    anchors: Vec<Certificate>,
+    /// Untrusted intermediates available for chain building.
+    intermediates: Vec<Certificate>,
+    /// Certificate revocation lists.
+    crls: Vec<CertificateList>,
+}
+
+impl SignatureVerifier {
+    /// Create a new verifier with a trusted CA.
+    pub fn new(ca_cert_der: &[u8]) -> color_eyre::eyre::Result<Self> {
+        Self::default().with_ca(ca_cert_der)
+    }
+
+    /// Create a verifier trusting every certificate in a PEM bundle.
+    pub fn from_pem_bundle(pem_bundle: &str) -> color_eyre::eyre::Result<Self> {
+        let mut verifier = Self::default();
+        for der in parse_pem_bundle(pem_bundle, "CERTIFICATE")? {
+            verifier = verifier.with_ca(&der)?;
+        }
+
+        if verifier.anchors.is_empty() {
+            color_eyre::eyre::bail!("no certificates in CA bundle");
+        }
+
+        Ok(verifier)
+    }
+
+    /// Add a trusted CA certificate.
+    pub fn with_ca(mut self, ca_cert_der: &[u8]) -> color_eyre::eyre::Result<Self> {
+        let cert = Certificate::from_der(ca_cert_der).wrap_err("failed to parse CA certificate")?;
+        self.anchors.push(cert);
+        Ok(self)
+    }
+
+    /// Add an intermediate certificate used for chain building.
+    ///
+    /// Intermediates are not trusted on their own; a chain through them must
+    /// still end at a trusted CA.
+    pub fn with_intermediate(mut self, cert_der: &[u8]) -> color_eyre::eyre::Result<Self> {
+        let cert =
+            Certificate::from_der(cert_der).wrap_err("failed to parse intermediate certificate")?;
+        self.intermediates.push(cert);
+        Ok(self)
+    }
+
+    /// Add a certificate revocation list (DER or PEM).
+    pub fn with_crl(mut self, crl: &[u8]) -> color_eyre::eyre::Result<Self> {
+        let ders = match std::str::from_utf8(crl) {
+            Ok(pem_str) if pem_str.contains("-----BEGIN") => parse_pem_bundle(pem_str, "X509 CRL")?,
+            _ => vec![crl.to_vec()],
+        };
+
+        for der in ders {
+            let list = CertificateList::from_der(&der).wrap_err("failed to parse CRL")?;
+            self.crls.push(list);
+        }
+
+        Ok(self)
+    }
+
+    /// Load CRLs from a file, or from every file in a directory.
+    pub fn load_crls(mut self, path: &std::path::Path) -> color_eyre::eyre::Result<Self> {
+        let files = if path.is_dir() {
+            let mut files = Vec::new();
+            for entry in std::fs::read_dir(path)
+                .wrap_err_with(|| format!("failed to read CRL directory {}", path.display()))?
+            {
+                let entry = entry.wrap_err("failed to read CRL directory entry")?;
+                if entry.file_type().is_ok_and(|t| t.is_file()) {
+                    files.push(entry.path());
+                }
+            }
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        for file in files {
+            let data = std::fs::read(&file)
+                .wrap_err_with(|| format!("failed to read CRL {}", file.display()))?;
+            self = self
+                .with_crl(&data)
+                .wrap_err_with(|| format!("invalid CRL {}", file.display()))?;
+        }
+
+        Ok(self)
+    }
+
+    /// Verify that a certificate chains to a trusted CA.
+    pub fn verify_cert(&self, cert_der: &[u8]) -> color_eyre::eyre::Result<()> {
+        self.verify_cert_at(cert_der, SystemTime::now())
+    }
+
+    /// Verify that a certificate chains to a trusted CA at time `now`.
+    pub fn verify_cert_at(&self, cert_der: &[u8], now: SystemTime) -> color_eyre::eyre::Result<()> {
+        let leaf = Certificate::from_der(cert_der).wrap_err("failed to parse certificate")?;
+
+        check_validity(&leaf, now)?;
+        check_leaf_usage(&leaf)?;
+
+        let mut current = leaf;
+
+        // `cas_below` counts the CA certificates between the issuer and the leaf.
+        for cas_below in 0..MAX_CHAIN_DEPTH {
+            let (issuer, trusted) = self.find_issuer(&current).ok_or_else(|| {
+                color_eyre::eyre::eyre!("certificate is not issued by a trusted CA")
+            })?;
+
+            check_validity(issuer, now)?;
+            check_ca(issuer, trusted, cas_below)?;
+            self.check_revocation(&current, issuer, now)?;
+
+            if trusted {
+                return Ok(());
+            }
+
+            current = issuer.clone();
+        }
+
+        color_eyre::eyre::bail!("certificate chain exceeds {MAX_CHAIN_DEPTH} certificates")
+    }
+
+    /// Find the certificate that issued `cert`, preferring trust anchors.
+    fn find_issuer(&self, cert: &Certificate) -> Option<(&Certificate, bool)> {
+        let anchors = self.anchors.iter().map(|c| (c, true));
+        let intermediates = self.intermediates.iter().map(|c| (c, false));
+
+        anchors
+            .chain(intermediates)
+            .find(|(candidate, _)| is_issued_by(cert, candidate))
+    }
+
+    /// Check `cert` against any CRLs published by `issuer`.
+    fn check_revocation(
+        &self,
+        cert: &Certificate,
+        issuer: &Certificate,
+        now: SystemTime,
+    ) -> color_eyre::eyre::Result<()> {
+        let crls = self
+            .crls
+            .iter()
+            .filter(|crl| crl.tbs_cert_list.issuer == issuer.tbs_certificate.subject);
+
+        for crl in crls {
+            let tbs = crl
+                .tbs_cert_list
+                .to_der()
+                .wrap_err("failed to encode CRL")?;
+            algorithm::verify_signature(
+                &issuer.tbs_certificate.subject_public_key_info,
+                &crl.signature_algorithm.oid,
+                &tbs,
+                crl.signature.raw_bytes(),
+            )
+            .wrap_err("CRL signature verification failed")?;
+
+            if let Some(next_update) = crl.tbs_cert_list.next_update
+                && next_update.to_system_time() < now
+            {
+                tracing::warn!(
+                    issuer = %issuer.tbs_certificate.subject,
+                    "CRL is past its next update"
+                );
+            }
+
+            let revoked = crl
+                .tbs_cert_list
+                .revoked_certificates
+                .iter()
+                .flatten()
+                .any(|entry| entry.serial_number == cert.tbs_certificate.serial_number);
+
+            if revoked {
+                color_eyre::eyre::bail!(
+                    "certificate {} has been revoked",
+                    cert.tbs_certificate.subject
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check whether `cert` was signed by `issuer`.
+fn is_issued_by(cert: &Certificate, issuer: &Certificate) -> bool {
+    if cert.tbs_certificate.issuer != issuer.tbs_certificate.subject {
+        return false;
+    }
+
+    let Ok(tbs) = cert.tbs_certificate.to_der() else {
+        return false;
+    };
+
+    algorithm::verify_signature(
+        &issuer.tbs_certificate.subject_public_key_info,
+        &cert.signature_algorithm.oid,
+        &tbs,
+        cert.signature.raw_bytes(),
+    )
+    .is_ok()
+}
+
+/// Check that `now` falls within the certificate's validity window.
+fn check_validity(cert: &Certificate, now: SystemTime) -> color_eyre::eyre::Result<()> {
+    let validity = &cert.tbs_certificate.validity;
+
+    if now < validity.not_before.to_system_time() {
+        color_eyre::eyre::bail!(
+            "certificate {} is not valid before {}",
+            cert.tbs_certificate.subject,
+            validity.not_before
+        );
+    }
+
+    if now > validity.not_after.to_system_time() {
+        color_eyre::eyre::bail!(
+            "certificate {} expired at {}",
+            cert.tbs_certificate.subject,
+            validity.not_after
+        );
+    }
+
+    Ok(())
+}
+
+/// Check that a device certificate may be used for signing and client auth.
+fn check_leaf_usage(cert: &Certificate) -> color_eyre::eyre::Result<()> {
+    let tbs = &cert.tbs_certificate;
+
+    if let Some((_, key_usage)) = tbs
+        .get::<KeyUsage>()
+        .wrap_err("invalid key usage extension")?
+        && !key_usage.digital_signature()
+    {
+        color_eyre::eyre::bail!("certificate key usage does not allow digital signatures");
+    }
+
+    if let Some((_, eku)) = tbs
+        .get::<ExtendedKeyUsage>()
+        .wrap_err("invalid extended key usage extension")?
+        && !eku.0.iter().any(|oid| {
+            *oid == rfc5280::ID_KP_CLIENT_AUTH || *oid == rfc5280::ANY_EXTENDED_KEY_USAGE
+        })
+    {
+        color_eyre::eyre::bail!("certificate extended key usage does not allow client auth");
+    }
+
+    Ok(())
+}
+
+/// Check that `cert` may act as a CA with `cas_below` CA certificates
+/// between it and the leaf.
+///
+/// Trust anchors are trusted explicitly, so a missing basic constraints
+/// extension is only an error for intermediates.
+fn check_ca(cert: &Certificate, trusted: bool, cas_below: usize) -> color_eyre::eyre::Result<()> {
+    let tbs = &cert.tbs_certificate;
+
+    match tbs
+        .get::<BasicConstraints>()
+        .wrap_err("invalid basic constraints extension")?
+    {
+        Some((_, constraints)) => {
+            if !constraints.ca {
+                color_eyre::eyre::bail!("issuer {} is not a CA", tbs.subject);
+            }
+            if let Some(path_len) = constraints.path_len_constraint
+                && cas_below > usize::from(path_len)
+            {
+                color_eyre::eyre::bail!("issuer {} path length exceeded", tbs.subject);
+            }
+        }
+        None if !trusted => {
+            color_eyre::eyre::bail!("intermediate {} has no basic constraints", tbs.subject);
+        }
+        None => {}
+    }
+
+    if let Some((_, key_usage)) = tbs
+        .get::<KeyUsage>()
+        .wrap_err("invalid key usage extension")?
+        && !key_usage.key_cert_sign()
+    {
+        color_eyre::eyre::bail!(
+            "issuer {} key usage does not allow signing certificates",
+            tbs.subject
+        );
+    }
+
+    Ok(())
+}
+
+/// Parse every PEM block with the given tag.
+fn parse_pem_bundle(pem_bundle: &str, tag: &str) -> color_eyre::eyre::Result<Vec<Vec<u8>>> {
+    let blocks = ::pem::parse_many(pem_bundle)
+        .map_err(|e| color_eyre::eyre::eyre!("failed to parse PEM: {}", e))?;
+
+    Ok(blocks
+        .into_iter()
+        .filter(|block| block.tag() == tag)
+        .map(::pem::Pem::into_contents)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CA: &[u8] = include_bytes!("../testdata/ca.der");
+    const INTERMEDIATE: &[u8] = include_bytes!("../testdata/intermediate.der");
+    const CRL: &[u8] = include_bytes!("../testdata/ca.crl");
+
+    #[test]
+    fn test_valid_chain() {
+        let verifier = SignatureVerifier::new(CA).unwrap();
+        verifier
+            .verify_cert(include_bytes!("../testdata/device-rsa.der"))
+            .unwrap();
+        verifier
+            .verify_cert(include_bytes!("../testdata/device-ec.der"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_intermediate_chain() {
+        let device = include_bytes!("../testdata/device-intermediate.der");
+
+        // Root alone can't build the chain.
+        let root_only = SignatureVerifier::new(CA).unwrap();
+        assert!(root_only.verify_cert(device).is_err());
+
+        let with_intermediate = root_only.with_intermediate(INTERMEDIATE).unwrap();
+        with_intermediate.verify_cert(device).unwrap();
+
+        // An intermediate bundle can also be trusted directly.
+        let intermediate_anchor = SignatureVerifier::new(INTERMEDIATE).unwrap();
+        intermediate_anchor.verify_cert(device).unwrap();
+    }
+
+    #[test]
+    fn test_pem_bundle() {
+        let bundle = [
+            include_str!("../testdata/ca.pem"),
+            include_str!("../testdata/intermediate.pem"),
+        ]
+        .concat();
+
+        let verifier = SignatureVerifier::from_pem_bundle(&bundle).unwrap();
+        verifier
+            .verify_cert(include_bytes!("../testdata/device-intermediate.der"))
+            .unwrap();
+    }
+
+    #[test]
+    fn test_untrusted_ca() {
+        // Same issuer name as the trusted CA, different key.
+        let verifier = SignatureVerifier::new(CA).unwrap();
+        let err = verifier
+            .verify_cert(include_bytes!("../testdata/device-other-ca.der"))
+            .unwrap_err();
+        assert!(err.to_string().contains("trusted CA"), "{err}");
+    }
+
+    #[test]
+    fn test_expired() {
+        let verifier = SignatureVerifier::new(CA).unwrap();
+        let err = verifier
+            .verify_cert(include_bytes!("../testdata/device-expired.der"))
+            .unwrap_err();
+        assert!(err.to_string().contains("expired"), "{err}");
+    }
+
+    #[test]
+    fn test_wrong_extended_key_usage() {
+        let verifier = SignatureVerifier::new(CA).unwrap();
+        let err = verifier
+            .verify_cert(include_bytes!("../testdata/device-server-eku.der"))
+            .unwrap_err();
+        assert!(err.to_string().contains("extended key usage"), "{err}");
+    }
+
+    #[test]
+    fn test_revoked() {
+        let device = include_bytes!("../testdata/device-revoked.der");
+
+        let verifier = SignatureVerifier::new(CA).unwrap();
+        verifier.verify_cert(device).unwrap();
+
+        let verifier = verifier.with_crl(CRL).unwrap();
+        let err = verifier.verify_cert(device).unwrap_err();
+        assert!(err.to_string().contains("revoked"), "{err}");
+
+        // Other certificates from the same CA are unaffected.
+        verifier
+            .verify_cert(include_bytes!("../testdata/device-rsa.der"))
+            .unwrap();
+    }
+}
@@ -0,0 +1,121 @@
+#!/usr/bin/env bash
+# Regenerates the test vectors in this directory from a throwaway local CA.
+#
+# Usage: ./gen.sh (run from this directory)
+set -euo pipefail
+
+days=36500
+
+cat > ext.cnf <<'CNF'
+[device]
+basicConstraints = critical, CA:FALSE
+keyUsage = critical, digitalSignature, keyEncipherment
+extendedKeyUsage = clientAuth
+subjectKeyIdentifier = hash
+authorityKeyIdentifier = keyid
+
+[server]
+basicConstraints = critical, CA:FALSE
+keyUsage = critical, digitalSignature, keyEncipherment
+extendedKeyUsage = serverAuth
+subjectKeyIdentifier = hash
+authorityKeyIdentifier = keyid
+
+[intermediate]
+basicConstraints = critical, CA:TRUE, pathlen:0
+keyUsage = critical, keyCertSign, cRLSign
+subjectKeyIdentifier = hash
+authorityKeyIdentifier = keyid
+
+[ca]
+default_ca = test_ca
+
+[test_ca]
+database = index.txt
+default_md = sha256
+default_crl_days = 36500
+crlnumber = crlnumber
+crl_extensions = crl_ext
+
+[crl_ext]
+authorityKeyIdentifier = keyid:always
+CNF
+
+# Test CA
+openssl req -x509 -newkey rsa:2048 -nodes -keyout ca.key -out ca.pem -days "$days" \
+    -subj "/O=Moonstone Test/CN=Moonstone Test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign"
+openssl x509 -in ca.pem -outform DER -out ca.der
+
+# issue <name> <issuer> <extensions> [validity args] -- [key args]
+issue() {
+    local name=$1 issuer=$2 extensions=$3
+    shift 3
+    local validity=()
+    while [ "$1" != "--" ]; do
+        validity+=("$1")
+        shift
+    done
+    shift
+    openssl req -new "$@" -nodes -keyout "$name.key" -out "$name.csr" \
+        -subj "/O=Moonstone Test/CN=$name"
+    openssl x509 -req -in "$name.csr" -CA "$issuer.pem" -CAkey "$issuer.key" \
+        -set_serial "0x$(openssl rand -hex 8)" "${validity[@]}" \
+        -extfile ext.cnf -extensions "$extensions" -out "$name.pem"
+    openssl x509 -in "$name.pem" -outform DER -out "$name.der"
+    rm "$name.csr"
+}
+
+# Device identities (RSA and P-256)
+issue device-rsa ca device -days "$days" -- -newkey rsa:2048
+issue device-ec ca device -days "$days" -- -newkey ec -pkeyopt ec_paramgen_curve:P-256
+
+# Chain validation cases
+issue intermediate ca intermediate -days "$days" -- -newkey rsa:2048
+issue device-intermediate intermediate device -days "$days" -- -newkey ec -pkeyopt ec_paramgen_curve:P-256
+issue device-expired ca device -not_before 20000101000000Z -not_after 20010101000000Z -- -newkey rsa:2048
+issue device-server-eku ca server -days "$days" -- -newkey rsa:2048
+issue device-revoked ca device -days "$days" -- -newkey rsa:2048
+
+# Unrelated CA and a device it issued
+openssl req -x509 -newkey rsa:2048 -nodes -keyout other-ca.key -out other-ca.pem -days "$days" \
+    -subj "/O=Moonstone Test/CN=Moonstone Test CA" \
+    -addext "basicConstraints=critical,CA:TRUE" \
+    -addext "keyUsage=critical,keyCertSign,cRLSign"
+issue device-other-ca other-ca device -days "$days" -- -newkey rsa:2048
+rm other-ca.key other-ca.pem
+
+# CRL revoking device-revoked
+touch index.txt
+echo 01 > crlnumber
+openssl ca -config ext.cnf -keyfile ca.key -cert ca.pem -revoke device-revoked.pem
+openssl ca -config ext.cnf -keyfile ca.key -cert ca.pem -gencrl -out ca.crl.pem
+openssl crl -in ca.crl.pem -outform DER -out ca.crl
+rm -f index.txt* crlnumber* ca.crl.pem
+
+# APNs push certificate with its topic in the subject UID
+openssl req -new -newkey rsa:2048 -nodes -keyout push.key -out push.csr \
+    -subj "/UID=com.apple.mgmt.External.moonstone-test/CN=APSP:moonstone-test/C=US"
+openssl x509 -req -in push.csr -CA ca.pem -CAkey ca.key \
+    -set_serial "0x$(openssl rand -hex 8)" -not_before 20250101000000Z -not_after 20350101000000Z \
+    -extfile ext.cnf -extensions device -out push.pem
+rm push.csr
+# The same identity as PKCS#12, modern and as exported by Keychain Access
+openssl pkcs12 -export -inkey push.key -in push.pem -passout pass:moonstone -out push.p12
+openssl pkcs12 -export -legacy -inkey push.key -in push.pem -passout pass:moonstone \
+    -out push-legacy.p12
+
+# Detached Mdm-Signature payloads over checkin.plist
+openssl cms -sign -binary -in checkin.plist -outform DER -md sha256 \
+    -signer device-rsa.pem -inkey device-rsa.key -out checkin-rsa.p7s
+openssl cms -sign -binary -in checkin.plist -outform DER -md sha256 \
+    -signer device-ec.pem -inkey device-ec.key -out checkin-ec.p7s
+openssl cms -sign -binary -in checkin.plist -outform DER -md sha256 -nocerts \
+    -signer device-rsa.pem -inkey device-rsa.key -out checkin-nocerts.p7s
+
+rm ext.cnf intermediate.key device-intermediate.key device-expired.key device-server-eku.key \
+    device-revoked.key device-other-ca.key
+
+# scep-pkcsreq.der is a PKCSReq from device-rsa with challenge "secret". OpenSSL
+# can't build SCEP messages; regenerate it from pkcs_req() in src/scep.rs tests.
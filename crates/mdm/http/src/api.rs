@@ -1,47 +1,86 @@
 //! REST API handlers.
 
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
 use axum::Json;
 use axum::body::Bytes;
-use axum::extract::{Path, State};
+use axum::extract::{Path, Query, State};
 use axum::http::StatusCode;
 use axum::response::IntoResponse;
 use color_eyre::eyre::WrapErr as _;
 use serde::{Deserialize, Serialize};
 
-use mdm_storage::{CommandStore, PushCertStore};
+use mdm_core::{PushCertSummary, PushErrorKind, PushResult};
+use mdm_push::PushProvider;
+use mdm_storage::{CommandStore, DuplicateCommand, PushCertStore};
 
 /// Push certificate response.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PushCertResponse {
     pub topic: String,
+    /// RFC 3339 expiry of the certificate.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub not_after: Option<String>,
 }
 
+impl From<PushCertSummary> for PushCertResponse {
+    fn from(summary: PushCertSummary) -> Self {
+        Self {
+            topic: summary.topic,
+            not_after: summary.not_after.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
 /// Store a push certificate.
+///
+/// The body is the PEM certificate followed by its PEM private key.
 pub async fn store_push_cert<S>(State(store): State<S>, body: Bytes) -> impl IntoResponse
 where
     S: PushCertStore,
 {
-    match store_push_cert_inner(&store, &body) {
-        Ok(response) => (StatusCode::OK, Json(response)),
+    let empty = || {
+        Json(PushCertResponse {
+            topic: String::new(),
+            not_after: None,
+        })
+    };
+
+    let (info, cert_pem, key_pem) = match parse_push_cert_body(&body) {
+        Ok(parsed) => parsed,
         Err(e) => {
-            tracing::error!(error = %e, "failed to store push cert");
+            tracing::warn!(error = %e, "rejected push cert");
+            return (StatusCode::BAD_REQUEST, empty());
+        }
+    };
+
+    match store
+        .store_push_cert(&info.topic, &cert_pem, &key_pem)
+        .await
+    {
+        Ok(()) => {
+            tracing::info!(topic = %info.topic, not_after = %info.not_after, "stored push cert");
             (
-                StatusCode::INTERNAL_SERVER_ERROR,
+                StatusCode::OK,
                 Json(PushCertResponse {
-                    topic: String::new(),
-                    not_after: None,
+                    topic: info.topic,
+                    not_after: Some(info.not_after.to_rfc3339()),
                 }),
             )
         }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to store push cert");
+            (StatusCode::INTERNAL_SERVER_ERROR, empty())
+        }
     }
 }
 
-fn store_push_cert_inner<S: PushCertStore>(
-    store: &S,
+/// Split the body into cert and key PEM, parse the cert, and check the key
+/// belongs to it.
+fn parse_push_cert_body(
     body: &[u8],
-) -> color_eyre::eyre::Result<PushCertResponse> {
+) -> color_eyre::eyre::Result<(mdm_crypto::PushCertInfo, String, String)> {
     let body_str = std::str::from_utf8(body).wrap_err("body is not valid UTF-8")?;
 
     // Body should be PEM cert + key concatenated
@@ -53,41 +92,160 @@ fn store_push_cert_inner<S: PushCertStore>(
     let cert_pem = format!("{}-----END CERTIFICATE-----", parts[0]);
     let key_pem = parts[1].trim().to_string();
 
-    // Extract topic from cert
     let cert_der = mdm_crypto::parse_pem_cert(&cert_pem)?;
-    let topic = mdm_crypto::extract_topic_from_cert(&cert_der)?;
+    let info = mdm_crypto::parse_push_cert(&cert_der)?;
+    mdm_crypto::verify_cert_key(&cert_der, &key_pem)?;
 
-    store.store_push_cert(&topic, &cert_pem, &key_pem)?;
-
-    Ok(PushCertResponse {
-        topic,
-        not_after: None,
-    })
+    Ok((info, cert_pem, key_pem))
 }
 
-/// Get push certificate info.
+/// List stored push certificates with their expiry.
 pub async fn get_push_cert<S>(State(store): State<S>) -> impl IntoResponse
 where
     S: PushCertStore,
 {
-    // This would need a topic parameter in practice
-    (StatusCode::NOT_IMPLEMENTED, "not implemented")
+    match store.list_push_certs().await {
+        Ok(certs) => Json(
+            certs
+                .into_iter()
+                .map(PushCertResponse::from)
+                .collect::<Vec<_>>(),
+        )
+        .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to list push certs");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Per-enrollment push status.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct PushStatus {
+    /// APNs ID of the delivered notification.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_result: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_error_kind: Option<PushErrorKind>,
+}
+
+/// Push response keyed by enrollment ID.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct PushResponse {
+    pub status: BTreeMap<String, PushStatus>,
+}
+
+impl From<Vec<PushResult>> for PushResponse {
+    fn from(results: Vec<PushResult>) -> Self {
+        let status = results
+            .into_iter()
+            .map(|result| {
+                (
+                    result.enrollment_id,
+                    PushStatus {
+                        push_result: result.apns_id,
+                        push_error: result.error,
+                        push_error_kind: result.error_kind,
+                    },
+                )
+            })
+            .collect();
+        Self { status }
+    }
 }
 
 /// Push notifications to devices.
-pub async fn push_handler(Path(ids): Path<String>) -> impl IntoResponse {
-    // TODO: Implement push
-    tracing::info!(ids = %ids, "push requested");
-    StatusCode::NOT_IMPLEMENTED
+///
+/// `ids` is a comma-separated list of enrollment IDs.
+pub async fn push_handler<P>(
+    State(pusher): State<Option<Arc<P>>>,
+    Path(ids): Path<String>,
+) -> impl IntoResponse
+where
+    P: PushProvider,
+{
+    let Some(pusher) = pusher else {
+        return (StatusCode::SERVICE_UNAVAILABLE, "push not configured").into_response();
+    };
+
+    let ids = parse_ids(&ids);
+    if ids.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no enrollment IDs").into_response();
+    }
+
+    Json(PushResponse::from(pusher.push_by_id(&ids).await)).into_response()
 }
 
-/// Enqueue command request.
+/// Push to freshly enqueued enrollments unless `no_push` is set.
+///
+/// Returns `None` when nothing was pushed.
+pub async fn push_after_enqueue<P>(
+    pusher: Option<&P>,
+    ids: &[&str],
+    no_push: bool,
+) -> Option<PushResponse>
+where
+    P: PushProvider,
+{
+    if no_push {
+        return None;
+    }
+    let Some(pusher) = pusher else {
+        tracing::debug!("push not configured, devices will pick up commands at next check-in");
+        return None;
+    };
+
+    let response = PushResponse::from(pusher.push_by_id(ids).await);
+    for (id, status) in &response.status {
+        if let Some(error) = &status.push_error {
+            tracing::warn!(enrollment_id = %id, error = %error, "push after enqueue failed");
+        }
+    }
+    Some(response)
+}
+
+/// Split a comma-separated ID list, dropping blanks and duplicates.
+pub(crate) fn parse_ids(ids: &str) -> Vec<&str> {
+    let mut parsed: Vec<&str> = Vec::new();
+    for id in ids.split(',').map(str::trim).filter(|id| !id.is_empty()) {
+        if !parsed.contains(&id) {
+            parsed.push(id);
+        }
+    }
+    parsed
+}
+
+/// Enqueue command query parameters.
 #[derive(Debug, Deserialize)]
 pub struct EnqueueRequest {
     #[serde(default)]
     pub command: Option<String>,
-    #[serde(default)]
+    /// Skip the push that normally follows an enqueue.
+    #[serde(default, alias = "nopush")]
     pub no_push: bool,
+    /// Seconds after which an unanswered command expires instead of being
+    /// sent.
+    #[serde(default)]
+    pub ttl: Option<u64>,
+}
+
+/// Expiry for a command enqueued now with a TTL in seconds.
+///
+/// Fails if the TTL is too large to represent as a timestamp.
+pub fn expires_at(
+    ttl: Option<u64>,
+) -> color_eyre::eyre::Result<Option<chrono::DateTime<chrono::Utc>>> {
+    let Some(ttl) = ttl else {
+        return Ok(None);
+    };
+    i64::try_from(ttl)
+        .ok()
+        .and_then(chrono::TimeDelta::try_seconds)
+        .and_then(|ttl| chrono::Utc::now().checked_add_signed(ttl))
+        .map(Some)
+        .ok_or_else(|| color_eyre::eyre::eyre!("ttl {ttl} is out of range"))
 }
 
 /// Enqueue command response.
@@ -95,56 +253,375 @@ pub struct EnqueueRequest {
 pub struct EnqueueResponse {
     pub command_uuid: String,
     pub request_type: String,
+    /// Outcome of the push sent after enqueueing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push: Option<PushResponse>,
 }
 
-/// Enqueue a command for devices.
-pub async fn enqueue_handler<S>(
-    State(store): State<S>,
+impl EnqueueResponse {
+    fn error() -> Json<Self> {
+        Json(Self {
+            command_uuid: String::new(),
+            request_type: String::new(),
+            push: None,
+        })
+    }
+}
+
+/// State for handlers that enqueue commands and push.
+#[derive(Debug)]
+pub struct EnqueueState<S, P> {
+    pub store: S,
+    pub pusher: Option<Arc<P>>,
+}
+
+impl<S: Clone, P> Clone for EnqueueState<S, P> {
+    fn clone(&self) -> Self {
+        Self {
+            store: self.store.clone(),
+            pusher: self.pusher.clone(),
+        }
+    }
+}
+
+/// Enqueue a command for devices, then push to them unless `no_push` is set.
+///
+/// The command is queued for every device or for none of them.
+pub async fn enqueue_handler<S, P>(
+    State(state): State<EnqueueState<S, P>>,
     Path(ids): Path<String>,
+    Query(request): Query<EnqueueRequest>,
     body: Bytes,
 ) -> impl IntoResponse
 where
     S: CommandStore,
+    P: PushProvider,
 {
-    match enqueue_inner(&store, &ids, &body) {
-        Ok(response) => (StatusCode::OK, Json(response)),
+    let ids = parse_ids(&ids);
+    let (cmd, expires_at) =
+        match parse_enqueue(&ids, &body).and_then(|cmd| Ok((cmd, expires_at(request.ttl)?))) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                tracing::warn!(error = %e, "rejected command");
+                return (StatusCode::BAD_REQUEST, EnqueueResponse::error());
+            }
+        };
+
+    match enqueue_inner(&state.store, &ids, cmd, expires_at).await {
+        Ok(mut response) => {
+            response.push =
+                push_after_enqueue(state.pusher.as_deref(), &ids, request.no_push).await;
+            (StatusCode::OK, Json(response))
+        }
         Err(e) => {
             tracing::error!(error = %e, "failed to enqueue command");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                Json(EnqueueResponse {
-                    command_uuid: String::new(),
-                    request_type: String::new(),
-                }),
-            )
+            let status = if e.chain().any(|e| e.is::<DuplicateCommand>()) {
+                StatusCode::CONFLICT
+            } else {
+                StatusCode::INTERNAL_SERVER_ERROR
+            };
+            (status, EnqueueResponse::error())
         }
     }
 }
 
-fn enqueue_inner<S: CommandStore>(
-    store: &S,
-    ids: &str,
-    body: &[u8],
-) -> color_eyre::eyre::Result<EnqueueResponse> {
-    // Parse command from body (raw plist)
+/// Parse the command to enqueue for `ids`.
+fn parse_enqueue(ids: &[&str], body: &[u8]) -> color_eyre::eyre::Result<mdm_core::Command> {
+    if ids.is_empty() {
+        color_eyre::eyre::bail!("no enrollment IDs");
+    }
     let cmd: mdm_core::Command =
         plist::from_bytes(body).wrap_err("failed to parse command plist")?;
+    if cmd.command_uuid.is_empty() {
+        color_eyre::eyre::bail!("command has no CommandUUID");
+    }
+    Ok(cmd)
+}
 
-    // Enqueue for each ID
-    for id_str in ids.split(',') {
-        let id = mdm_core::EnrollId {
+async fn enqueue_inner<S: CommandStore>(
+    store: &S,
+    ids: &[&str],
+    cmd: mdm_core::Command,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+) -> color_eyre::eyre::Result<EnqueueResponse> {
+    let ids: Vec<mdm_core::EnrollId> = ids
+        .iter()
+        .map(|id| mdm_core::EnrollId {
             enroll_type: mdm_core::EnrollType::Device,
-            id: id_str.trim().to_string(),
+            id: id.to_string(),
             parent_id: None,
-        };
+        })
+        .collect();
 
-        store
-            .enqueue_command(&id, body)
-            .wrap_err_with(|| format!("failed to enqueue for {}", id_str))?;
-    }
+    // Enqueue for every ID under the UUID the device will report back
+    store
+        .enqueue_commands(&ids, &cmd, expires_at)
+        .await
+        .wrap_err("failed to enqueue command")?;
 
     Ok(EnqueueResponse {
         command_uuid: cmd.command_uuid,
         request_type: cmd.command.request_type,
+        push: None,
     })
 }
+
+/// Cancel command response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CancelResponse {
+    pub command_uuid: String,
+    /// Enrollments whose unanswered command was cancelled.
+    pub cancelled: Vec<String>,
+}
+
+/// Cancel a command the devices haven't answered yet.
+///
+/// `ids` is a comma-separated list of enrollment IDs. Devices that already
+/// answered the command, or never had it, are left out of the response.
+pub async fn cancel_handler<S>(
+    State(store): State<S>,
+    Path((ids, command_uuid)): Path<(String, String)>,
+) -> impl IntoResponse
+where
+    S: CommandStore,
+{
+    let ids = parse_ids(&ids);
+    if ids.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no enrollment IDs").into_response();
+    }
+
+    let mut cancelled = Vec::new();
+    for id_str in ids {
+        let id = mdm_core::EnrollId {
+            enroll_type: mdm_core::EnrollType::Device,
+            id: id_str.to_string(),
+            parent_id: None,
+        };
+        match store.cancel_command(&id, &command_uuid).await {
+            Ok(true) => cancelled.push(id.id),
+            Ok(false) => {}
+            Err(e) => {
+                tracing::error!(error = %e, enrollment_id = %id.id, "failed to cancel command");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    tracing::info!(command_uuid = %command_uuid, cancelled = cancelled.len(), "cancelled command");
+    Json(CancelResponse {
+        command_uuid,
+        cancelled,
+    })
+    .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use mdm_storage::InMemoryStorage;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    /// Provider that succeeds for `ok-*` IDs and fails the rest.
+    struct MockProvider;
+
+    impl PushProvider for MockProvider {
+        async fn push_by_id(&self, ids: &[&str]) -> Vec<PushResult> {
+            ids.iter()
+                .map(|id| match id.strip_prefix("ok-") {
+                    Some(n) => PushResult::success(id.to_string(), format!("apns-{n}")),
+                    None => PushResult::failure(id.to_string(), "no push info"),
+                })
+                .collect()
+        }
+    }
+
+    async fn push(pusher: Option<Arc<MockProvider>>, ids: &str) -> (StatusCode, Bytes) {
+        let app = axum::Router::new()
+            .route(
+                "/v1/push/{ids}",
+                axum::routing::post(push_handler::<MockProvider>),
+            )
+            .with_state(pusher);
+        let response = app
+            .oneshot(
+                Request::post(format!("/v1/push/{ids}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body)
+    }
+
+    #[tokio::test]
+    async fn test_push_handler() {
+        let (status, body) = push(Some(Arc::new(MockProvider)), "ok-1,%20ok-1,missing,").await;
+        assert_eq!(status, StatusCode::OK);
+
+        let response: PushResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.status.len(), 2);
+        assert_eq!(
+            response.status["ok-1"].push_result.as_deref(),
+            Some("apns-1")
+        );
+        assert_eq!(
+            response.status["missing"].push_error.as_deref(),
+            Some("no push info")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_push_not_configured() {
+        let (status, _) = push(None, "ok-1").await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    fn enqueue_app(store: &InMemoryStorage) -> axum::Router {
+        axum::Router::new()
+            .route(
+                "/v1/enqueue/{ids}",
+                axum::routing::post(enqueue_handler::<InMemoryStorage, MockProvider>),
+            )
+            .with_state(EnqueueState {
+                store: store.clone(),
+                pusher: Some(Arc::new(MockProvider)),
+            })
+    }
+
+    async fn post_command(store: &InMemoryStorage, uri: &str, body: Vec<u8>) -> StatusCode {
+        let request = Request::post(uri).body(Body::from(body)).unwrap();
+        enqueue_app(store).oneshot(request).await.unwrap().status()
+    }
+
+    async fn enqueue(store: &InMemoryStorage, uri: &str) -> serde_json::Value {
+        let command =
+            mdm_core::serialize_command(&mdm_core::new_command("DeviceInformation")).unwrap();
+        let response = enqueue_app(store)
+            .oneshot(Request::post(uri).body(Body::from(command)).unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_pushes() {
+        let store = InMemoryStorage::new();
+        let response = enqueue(&store, "/v1/enqueue/ok-1,ok-2").await;
+        assert_eq!(response["request_type"], "DeviceInformation");
+        assert_eq!(response["push"]["status"]["ok-2"]["push_result"], "apns-2");
+
+        let id = mdm_core::EnrollId {
+            enroll_type: mdm_core::EnrollType::Device,
+            id: "ok-2".to_string(),
+            parent_id: None,
+        };
+        assert!(store.next_command(&id, false).await.unwrap().is_some());
+
+        let response = enqueue(&store, "/v1/enqueue/ok-1?no_push=true").await;
+        assert!(response.get("push").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_enqueue_rejected() {
+        let store = InMemoryStorage::new();
+        let command = mdm_core::new_command("DeviceLock");
+        let body = mdm_core::serialize_command(&command).unwrap();
+        let bad = |uri, body| post_command(&store, uri, body);
+
+        assert_eq!(
+            bad("/v1/enqueue/dev-1", b"not a plist".to_vec()).await,
+            StatusCode::BAD_REQUEST
+        );
+        let mut no_uuid = command.clone();
+        no_uuid.command_uuid.clear();
+        let no_uuid = mdm_core::serialize_command(&no_uuid).unwrap();
+        assert_eq!(
+            bad("/v1/enqueue/dev-1", no_uuid).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            bad("/v1/enqueue/%20,", body.clone()).await,
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(
+            bad("/v1/enqueue/dev-1?ttl=18446744073709551615", body.clone()).await,
+            StatusCode::BAD_REQUEST
+        );
+
+        // A duplicate for one device leaves the others without the command
+        assert_eq!(
+            bad("/v1/enqueue/dev-2?no_push=true", body.clone()).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            bad("/v1/enqueue/dev-1,dev-2,dev-3", body.clone()).await,
+            StatusCode::CONFLICT
+        );
+        let records = store.get_command(&command.command_uuid).await.unwrap();
+        let queued: Vec<&str> = records.iter().map(|r| r.enrollment_id.as_str()).collect();
+        assert_eq!(queued, ["dev-2"]);
+
+        // Nothing left behind blocks a retry for the others
+        assert_eq!(
+            bad("/v1/enqueue/dev-1,dev-3?no_push=true", body).await,
+            StatusCode::OK
+        );
+    }
+
+    #[test]
+    fn test_expires_at() {
+        assert_eq!(expires_at(None).unwrap(), None);
+        let expiry = expires_at(Some(60)).unwrap().unwrap();
+        assert!(expiry > chrono::Utc::now());
+        assert!(expires_at(Some(i64::MAX as u64)).is_err());
+        assert!(expires_at(Some(u64::MAX)).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let store = InMemoryStorage::new();
+        let ids = ["dev-1", "dev-2"].map(|id| mdm_core::EnrollId {
+            enroll_type: mdm_core::EnrollType::Device,
+            id: id.to_string(),
+            parent_id: None,
+        });
+        let command = mdm_core::new_command("DeviceLock");
+        let uuid = store
+            .enqueue_command(&ids[0], &command, None)
+            .await
+            .unwrap();
+
+        let app = axum::Router::new()
+            .route(
+                "/v1/cancel/{ids}/{command_uuid}",
+                axum::routing::post(cancel_handler::<InMemoryStorage>),
+            )
+            .with_state(store.clone());
+        let response = app
+            .oneshot(
+                Request::post(format!("/v1/cancel/dev-1,dev-2/{uuid}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let response: CancelResponse = serde_json::from_slice(&body).unwrap();
+        assert_eq!(response.cancelled, ["dev-1"]);
+        assert!(store.next_command(&ids[0], false).await.unwrap().is_none());
+    }
+}
@@ -0,0 +1,335 @@
+//! Command history handlers.
+
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+
+use mdm_core::{CommandRecord, CommandState, CommandStatus, ErrorChainItem};
+use mdm_storage::{CommandFilter, CommandStore};
+
+use crate::Page;
+
+/// Page size when the request doesn't give one.
+const DEFAULT_LIMIT: u32 = 50;
+
+/// Largest page a request may ask for.
+const MAX_LIMIT: u32 = 500;
+
+/// One enrollment's copy of a command, with its decoded results.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandRecordResponse {
+    pub enrollment_id: String,
+    pub command_uuid: String,
+    pub request_type: String,
+    pub state: CommandState,
+    /// Status of the latest report.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<CommandStatus>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub error_chain: Vec<ErrorChainItem>,
+    /// The latest report as JSON.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response: Option<serde_json::Value>,
+    /// RFC 3339 time the command was queued.
+    pub created_at: String,
+    /// RFC 3339 time the command expires if unanswered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<String>,
+}
+
+impl From<&CommandRecord> for CommandRecordResponse {
+    fn from(record: &CommandRecord) -> Self {
+        let results = record.result.as_deref().filter(|raw| !raw.is_empty());
+        let parsed = results.and_then(|raw| match mdm_core::parse_command_results(raw) {
+            Ok(parsed) => Some(parsed),
+            Err(e) => {
+                tracing::warn!(
+                    error = %e,
+                    command_uuid = %record.uuid,
+                    "unreadable command results"
+                );
+                None
+            }
+        });
+        let response = results
+            .and_then(|raw| plist::Value::from_reader(std::io::Cursor::new(raw)).ok())
+            .map(plist_to_json);
+
+        Self {
+            enrollment_id: record.enrollment_id.clone(),
+            command_uuid: record.uuid.clone(),
+            request_type: record.request_type.clone(),
+            state: record.state,
+            status: parsed.as_ref().map(|parsed| parsed.status),
+            error_chain: parsed.map(|parsed| parsed.error_chain).unwrap_or_default(),
+            response,
+            created_at: record.created_at.to_rfc3339(),
+            expires_at: record.expires_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+/// Command lookup response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandResponse {
+    pub command_uuid: String,
+    pub request_type: String,
+    /// The command as JSON.
+    pub command: serde_json::Value,
+    /// Each enrollment the command was queued for.
+    pub enrollments: Vec<CommandRecordResponse>,
+}
+
+/// Look up a command and its results for every enrollment it was queued for.
+pub async fn get_command<S>(State(store): State<S>, Path(uuid): Path<String>) -> impl IntoResponse
+where
+    S: CommandStore,
+{
+    let records = match store.get_command(&uuid).await {
+        Ok(records) => records,
+        Err(e) => {
+            tracing::error!(error = %e, command_uuid = %uuid, "failed to get command");
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+    let Some(first) = records.first() else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let command = plist::Value::from_reader(std::io::Cursor::new(&first.command))
+        .map(plist_to_json)
+        .unwrap_or(serde_json::Value::Null);
+    Json(CommandResponse {
+        command_uuid: first.uuid.clone(),
+        request_type: first.request_type.clone(),
+        command,
+        enrollments: records.iter().map(CommandRecordResponse::from).collect(),
+    })
+    .into_response()
+}
+
+/// Command history query parameters.
+#[derive(Debug, Deserialize)]
+pub struct CommandListRequest {
+    /// Only commands in this lifecycle state.
+    #[serde(default)]
+    pub status: Option<CommandState>,
+    #[serde(default)]
+    pub request_type: Option<String>,
+}
+
+/// Command history response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CommandListResponse {
+    pub commands: Vec<CommandRecordResponse>,
+    /// Offset of the next page, if there may be one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<u32>,
+}
+
+/// List an enrollment's commands, newest first.
+pub async fn list_commands<S>(
+    State(store): State<S>,
+    Path(id): Path<String>,
+    Query(request): Query<CommandListRequest>,
+    Query(page): Query<Page>,
+) -> impl IntoResponse
+where
+    S: CommandStore,
+{
+    let id = mdm_core::EnrollId {
+        enroll_type: mdm_core::EnrollType::Device,
+        id,
+        parent_id: None,
+    };
+    let limit = page.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    let filter = CommandFilter {
+        state: request.status,
+        request_type: request.request_type,
+        limit: Some(limit),
+        offset: page.offset,
+    };
+
+    match store.list_commands(&id, &filter).await {
+        Ok(records) => {
+            let next_offset = page.next_offset(limit, records.len());
+            Json(CommandListResponse {
+                commands: records.iter().map(CommandRecordResponse::from).collect(),
+                next_offset,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!(error = %e, enrollment_id = %id.id, "failed to list commands");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Convert a plist to JSON, with data as base64 and dates as RFC 3339.
+pub fn plist_to_json(value: plist::Value) -> serde_json::Value {
+    use serde_json::Value as Json;
+
+    match value {
+        plist::Value::Array(items) => Json::Array(items.into_iter().map(plist_to_json).collect()),
+        plist::Value::Dictionary(dict) => Json::Object(
+            dict.into_iter()
+                .map(|(key, value)| (key, plist_to_json(value)))
+                .collect(),
+        ),
+        plist::Value::Boolean(b) => Json::Bool(b),
+        plist::Value::Data(data) => {
+            Json::String(base64::engine::general_purpose::STANDARD.encode(data))
+        }
+        plist::Value::Date(date) => Json::String(date.to_xml_format()),
+        plist::Value::Real(real) => {
+            serde_json::Number::from_f64(real).map_or(Json::Null, Json::Number)
+        }
+        plist::Value::Integer(int) => match (int.as_signed(), int.as_unsigned()) {
+            (Some(signed), _) => Json::from(signed),
+            (None, Some(unsigned)) => Json::from(unsigned),
+            (None, None) => Json::Null,
+        },
+        plist::Value::String(s) => Json::String(s),
+        plist::Value::Uid(uid) => Json::from(uid.get()),
+        _ => Json::Null,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use mdm_core::{EnrollId, EnrollType};
+    use mdm_storage::InMemoryStorage;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const INSTALL_PROFILE_ERROR: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN"
+    "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>CommandUUID</key>
+	<string>UUID</string>
+	<key>ErrorChain</key>
+	<array>
+		<dict>
+			<key>ErrorCode</key>
+			<integer>4001</integer>
+			<key>ErrorDomain</key>
+			<string>MCProfileErrorDomain</string>
+			<key>LocalizedDescription</key>
+			<string>The profile “Wi-Fi” could not be installed.</string>
+			<key>USEnglishDescription</key>
+			<string>The profile “Wi-Fi” could not be installed.</string>
+		</dict>
+	</array>
+	<key>Status</key>
+	<string>Error</string>
+	<key>UDID</key>
+	<string>dev-1</string>
+</dict>
+</plist>
+"#;
+
+    async fn get(store: &InMemoryStorage, uri: &str) -> (StatusCode, serde_json::Value) {
+        let app = axum::Router::new()
+            .route(
+                "/v1/commands/{uuid}",
+                axum::routing::get(get_command::<InMemoryStorage>),
+            )
+            .route(
+                "/v1/enrollments/{id}/commands",
+                axum::routing::get(list_commands::<InMemoryStorage>),
+            )
+            .with_state(store.clone());
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_command_history() {
+        let store = InMemoryStorage::new();
+        let id = EnrollId {
+            enroll_type: EnrollType::Device,
+            id: "dev-1".to_string(),
+            parent_id: None,
+        };
+        let mut install = mdm_core::new_command("InstallProfile");
+        install.command_uuid = "UUID".to_string();
+        install.command.data.insert(
+            "Payload".to_string(),
+            plist::Value::Data(b"profile".to_vec()),
+        );
+        store.enqueue_command(&id, &install, None).await.unwrap();
+        let info = mdm_core::new_command("DeviceInformation");
+        store.enqueue_command(&id, &info, None).await.unwrap();
+
+        store.next_command(&id, false).await.unwrap();
+        let results = mdm_core::parse_command_results(INSTALL_PROFILE_ERROR.as_bytes()).unwrap();
+        store.store_result(&id, &results).await.unwrap();
+
+        let (status, body) = get(&store, "/v1/commands/UUID").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["request_type"], "InstallProfile");
+        assert_eq!(body["command"]["Command"]["Payload"], "cHJvZmlsZQ==");
+        let record = &body["enrollments"][0];
+        assert_eq!(record["enrollment_id"], "dev-1");
+        assert_eq!(record["state"], "Error");
+        assert_eq!(record["status"], "Error");
+        assert_eq!(record["error_chain"][0]["ErrorCode"], 4001);
+        assert_eq!(
+            record["response"]["ErrorChain"][0]["ErrorDomain"],
+            "MCProfileErrorDomain"
+        );
+
+        let (status, _) = get(&store, "/v1/commands/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = get(&store, "/v1/enrollments/dev-1/commands?limit=1").await;
+        assert_eq!(body["commands"][0]["request_type"], "DeviceInformation");
+        assert_eq!(body["commands"][0]["state"], "Pending");
+        assert_eq!(body["next_offset"], 1);
+
+        let (_, body) = get(
+            &store,
+            "/v1/enrollments/dev-1/commands?status=Error&request_type=InstallProfile",
+        )
+        .await;
+        assert_eq!(body["commands"].as_array().unwrap().len(), 1);
+        assert!(body.get("next_offset").is_none());
+
+        let (status, _) = get(&store, "/v1/enrollments/dev-1/commands?status=Bogus").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_results_without_raw() {
+        let record = CommandRecord {
+            enrollment_id: "dev-1".to_string(),
+            uuid: "UUID".to_string(),
+            request_type: String::new(),
+            state: CommandState::Acknowledged,
+            command: Vec::new(),
+            // Stored by releases that didn't keep the raw results
+            result: Some(Vec::new()),
+            created_at: chrono::Utc::now(),
+            expires_at: None,
+        };
+        let response = CommandRecordResponse::from(&record);
+        assert!(response.status.is_none());
+        assert!(response.response.is_none());
+    }
+}
@@ -0,0 +1,472 @@
+//! Declarative Device Management handlers.
+
+use std::collections::BTreeMap;
+
+use axum::Json;
+use axum::body::Bytes;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+use mdm_core::{
+    Declaration, DeclarationRecord, DeclarationStatusRecord, DeclarativeManagementCommand,
+    EnrollId, EnrollType, StatusItemChange, StatusItemRecord, TokensResponse,
+};
+use mdm_push::PushProvider;
+use mdm_storage::{
+    CommandStore, DeclarationStore, StatusHistoryFilter, StatusItemFilter, StatusItemStore,
+    UnknownDeclaration,
+};
+
+use crate::Page;
+use crate::api::{EnqueueState, PushResponse, parse_ids, push_after_enqueue};
+
+/// History page size when the request doesn't give one.
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Largest history page a request may ask for.
+const MAX_LIMIT: u32 = 1000;
+
+/// A declaration with its last change.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeclarationResponse {
+    pub declaration: Declaration,
+    /// RFC 3339 time the declaration's content last changed.
+    pub updated_at: String,
+}
+
+impl From<DeclarationRecord> for DeclarationResponse {
+    fn from(record: DeclarationRecord) -> Self {
+        Self {
+            declaration: record.declaration,
+            updated_at: record.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Declaration list response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeclarationListResponse {
+    pub declarations: Vec<DeclarationResponse>,
+}
+
+/// Enrollment IDs the API is given carry no type; storage keys on the ID.
+fn enroll_id(id: String) -> EnrollId {
+    EnrollId {
+        enroll_type: EnrollType::Device,
+        id,
+        parent_id: None,
+    }
+}
+
+/// Create or replace a declaration from its JSON.
+///
+/// The server token is computed from the content; any in the body is ignored.
+pub async fn store_declaration<S>(State(store): State<S>, body: Bytes) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    let declaration = match Declaration::parse(&body) {
+        Ok(declaration) => declaration,
+        Err(e) => return (StatusCode::BAD_REQUEST, format!("{e:#}")).into_response(),
+    };
+    let identifier = declaration.identifier.clone();
+    let stored = match store.store_declaration(&declaration).await {
+        Ok(()) => store.get_declaration(&identifier).await,
+        Err(e) => Err(e),
+    };
+
+    match stored {
+        Ok(Some(record)) => Json(DeclarationResponse::from(record)).into_response(),
+        Ok(None) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, identifier = %identifier, "failed to store declaration");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// List declarations, by identifier.
+pub async fn list_declarations<S>(State(store): State<S>) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    match store.list_declarations().await {
+        Ok(records) => Json(DeclarationListResponse {
+            declarations: records.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to list declarations");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Look up a declaration.
+pub async fn get_declaration<S>(
+    State(store): State<S>,
+    Path(identifier): Path<String>,
+) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    match store.get_declaration(&identifier).await {
+        Ok(Some(record)) => Json(DeclarationResponse::from(record)).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, identifier = %identifier, "failed to get declaration");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Delete a declaration and unassign it from every enrollment.
+pub async fn delete_declaration<S>(
+    State(store): State<S>,
+    Path(identifier): Path<String>,
+) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    match store.delete_declaration(&identifier).await {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(error = %e, identifier = %identifier, "failed to delete declaration");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Assign a declaration to an enrollment.
+pub async fn assign_declaration<S>(
+    State(store): State<S>,
+    Path((id, identifier)): Path<(String, String)>,
+) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    match store.assign_declaration(&enroll_id(id), &identifier).await {
+        Ok(_) => StatusCode::NO_CONTENT,
+        Err(e) if e.chain().any(|e| e.is::<UnknownDeclaration>()) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(error = %e, identifier = %identifier, "failed to assign declaration");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// Remove a declaration from an enrollment.
+pub async fn unassign_declaration<S>(
+    State(store): State<S>,
+    Path((id, identifier)): Path<(String, String)>,
+) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    match store
+        .unassign_declaration(&enroll_id(id), &identifier)
+        .await
+    {
+        Ok(true) => StatusCode::NO_CONTENT,
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            tracing::error!(error = %e, identifier = %identifier, "failed to unassign declaration");
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+/// A declaration state the device reported.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeclarationStatusResponse {
+    pub identifier: String,
+    pub active: bool,
+    /// "valid", "invalid" or "unknown".
+    pub valid: String,
+    /// Server token of the declaration the device has.
+    pub server_token: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reasons: Vec<serde_json::Value>,
+    /// RFC 3339 time of the status report.
+    pub updated_at: String,
+}
+
+impl From<DeclarationStatusRecord> for DeclarationStatusResponse {
+    fn from(record: DeclarationStatusRecord) -> Self {
+        Self {
+            identifier: record.status.identifier,
+            active: record.status.active,
+            valid: record.status.valid,
+            server_token: record.status.server_token,
+            reasons: record.status.reasons,
+            updated_at: record.updated_at.to_rfc3339(),
+        }
+    }
+}
+
+/// An enrollment's declarations response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrollmentDeclarationsResponse {
+    /// Token the device gets from the `tokens` endpoint.
+    pub declarations_token: String,
+    /// Assigned declarations.
+    pub declarations: Vec<DeclarationResponse>,
+    /// States from the device's latest status report, which may lag behind
+    /// the assigned declarations.
+    pub statuses: Vec<DeclarationStatusResponse>,
+}
+
+/// Get an enrollment's assigned declarations and their reported states.
+pub async fn enrollment_declarations<S>(
+    State(store): State<S>,
+    Path(id): Path<String>,
+) -> impl IntoResponse
+where
+    S: DeclarationStore,
+{
+    let id = enroll_id(id);
+    let result = async {
+        let assigned = store.assigned_declarations(&id).await?;
+        let statuses = store.declaration_statuses(&id).await?;
+        color_eyre::eyre::Ok((assigned, statuses))
+    }
+    .await;
+
+    match result {
+        Ok((assigned, statuses)) => Json(EnrollmentDeclarationsResponse {
+            declarations_token: TokensResponse::new(&assigned)
+                .sync_tokens
+                .declarations_token,
+            declarations: assigned.into_iter().map(Into::into).collect(),
+            statuses: statuses.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, enrollment_id = %id.id, "failed to get declarations");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Sync query parameters.
+#[derive(Debug, Deserialize)]
+pub struct SyncRequest {
+    /// Skip the push that normally follows an enqueue.
+    #[serde(default, alias = "nopush")]
+    pub no_push: bool,
+}
+
+/// Sync response.
+#[derive(Debug, Serialize)]
+pub struct SyncResponse {
+    /// UUID of the DeclarativeManagement command, by enrollment ID.
+    pub command_uuids: BTreeMap<String, String>,
+    /// Outcome of the push sent after enqueueing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push: Option<PushResponse>,
+}
+
+/// Tell devices to synchronize their declarations.
+///
+/// Enqueues a DeclarativeManagement command carrying each enrollment's
+/// current tokens, then pushes unless `no_push` is set.
+pub async fn sync_handler<S, P>(
+    State(state): State<EnqueueState<S, P>>,
+    Path(ids): Path<String>,
+    Query(request): Query<SyncRequest>,
+) -> impl IntoResponse
+where
+    S: CommandStore + DeclarationStore,
+    P: PushProvider,
+{
+    let ids = parse_ids(&ids);
+    if ids.is_empty() {
+        return (StatusCode::BAD_REQUEST, "no enrollment IDs").into_response();
+    }
+
+    let mut command_uuids = BTreeMap::new();
+    for id in &ids {
+        match enqueue_sync(&state.store, id).await {
+            Ok(uuid) => {
+                command_uuids.insert(id.to_string(), uuid);
+            }
+            Err(e) => {
+                tracing::error!(error = %e, enrollment_id = %id, "failed to enqueue DDM sync");
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    let push = push_after_enqueue(state.pusher.as_deref(), &ids, request.no_push).await;
+    Json(SyncResponse {
+        command_uuids,
+        push,
+    })
+    .into_response()
+}
+
+async fn enqueue_sync<S>(store: &S, id: &str) -> color_eyre::eyre::Result<String>
+where
+    S: CommandStore + DeclarationStore,
+{
+    let id = enroll_id(id.to_string());
+    let assigned = store.assigned_declarations(&id).await?;
+    let tokens = serde_json::to_vec(&TokensResponse::new(&assigned))?;
+    let command = mdm_core::command_from_request(&DeclarativeManagementCommand {
+        data: Some(tokens.into()),
+    })?;
+    store.enqueue_command(&id, &command, None).await
+}
+
+/// The latest value of a status item.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusItemResponse {
+    /// e.g. "device.operating-system.version".
+    pub item: String,
+    pub value: serde_json::Value,
+    /// RFC 3339 time the device first reported this value.
+    pub changed_at: String,
+    /// RFC 3339 time the device last reported this value.
+    pub reported_at: String,
+}
+
+impl From<StatusItemRecord> for StatusItemResponse {
+    fn from(record: StatusItemRecord) -> Self {
+        Self {
+            item: record.item,
+            value: record.value,
+            changed_at: record.changed_at.to_rfc3339(),
+            reported_at: record.reported_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Status items query parameters.
+#[derive(Debug, Deserialize)]
+pub struct StatusItemsRequest {
+    /// Only this item and the items under it, e.g. "softwareupdate".
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+/// Status items response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusItemsResponse {
+    pub items: Vec<StatusItemResponse>,
+}
+
+/// Get the latest value of an enrollment's DDM status items, by item.
+pub async fn status_items<S>(
+    State(store): State<S>,
+    Path(id): Path<String>,
+    Query(request): Query<StatusItemsRequest>,
+) -> impl IntoResponse
+where
+    S: StatusItemStore,
+{
+    let id = enroll_id(id);
+    let filter = StatusItemFilter {
+        prefix: request.prefix,
+    };
+
+    match store.status_items(&id, &filter).await {
+        Ok(records) => Json(StatusItemsResponse {
+            items: records.into_iter().map(Into::into).collect(),
+        })
+        .into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, enrollment_id = %id.id, "failed to get status items");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// A value a status item changed to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusItemChangeResponse {
+    pub value: serde_json::Value,
+    /// RFC 3339 time the device first reported this value.
+    pub reported_at: String,
+}
+
+impl From<StatusItemChange> for StatusItemChangeResponse {
+    fn from(change: StatusItemChange) -> Self {
+        Self {
+            value: change.value,
+            reported_at: change.reported_at.to_rfc3339(),
+        }
+    }
+}
+
+/// Status item response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct StatusItemHistoryResponse {
+    #[serde(flatten)]
+    pub latest: StatusItemResponse,
+    /// Values the item changed to, newest first.
+    pub history: Vec<StatusItemChangeResponse>,
+    /// Offset of the next history page, if there may be one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<u32>,
+}
+
+/// Get the latest value of one of an enrollment's DDM status items and the
+/// values it changed to.
+pub async fn status_item_history<S>(
+    State(store): State<S>,
+    Path((id, item)): Path<(String, String)>,
+    Query(page): Query<Page>,
+) -> impl IntoResponse
+where
+    S: StatusItemStore,
+{
+    let id = enroll_id(id);
+    let limit = page.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    let filter = StatusHistoryFilter {
+        limit: Some(limit),
+        offset: page.offset,
+    };
+    let result = async {
+        let latest = store
+            .status_items(
+                &id,
+                &StatusItemFilter {
+                    prefix: Some(item.clone()),
+                },
+            )
+            .await?
+            .into_iter()
+            .find(|record| record.item == item);
+        let Some(latest) = latest else {
+            return Ok(None);
+        };
+        let history = store.status_item_history(&id, &item, &filter).await?;
+        color_eyre::eyre::Ok(Some((latest, history)))
+    }
+    .await;
+
+    match result {
+        Ok(Some((latest, history))) => {
+            let next_offset = page.next_offset(limit, history.len());
+            Json(StatusItemHistoryResponse {
+                latest: latest.into(),
+                history: history.into_iter().map(Into::into).collect(),
+                next_offset,
+            })
+            .into_response()
+        }
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(
+                error = %e,
+                enrollment_id = %id.id,
+                item = %item,
+                "failed to get status item"
+            );
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Device inventory handlers.
+
+use axum::Json;
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+use mdm_core::DeviceInventory;
+use mdm_storage::{InventoryFilter, InventoryStore};
+
+use crate::Page;
+
+/// Page size when the request doesn't give one.
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Largest page a request may ask for.
+const MAX_LIMIT: u32 = 1000;
+
+/// Device list query parameters. Each given parameter must match exactly.
+#[derive(Debug, Deserialize)]
+pub struct DeviceListRequest {
+    #[serde(default)]
+    pub serial_number: Option<String>,
+    /// Model identifier, e.g. "MacBookPro18,3".
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub os_version: Option<String>,
+    #[serde(default)]
+    pub filevault_enabled: Option<bool>,
+    #[serde(default)]
+    pub sip_enabled: Option<bool>,
+}
+
+/// Device list response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeviceListResponse {
+    pub devices: Vec<DeviceInventory>,
+    /// Offset of the next page, if there may be one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<u32>,
+}
+
+/// List device inventory, by device ID.
+pub async fn list_devices<S>(
+    State(store): State<S>,
+    Query(request): Query<DeviceListRequest>,
+    Query(page): Query<Page>,
+) -> impl IntoResponse
+where
+    S: InventoryStore,
+{
+    let limit = page.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    let filter = InventoryFilter {
+        serial_number: request.serial_number,
+        model: request.model,
+        os_version: request.os_version,
+        filevault_enabled: request.filevault_enabled,
+        sip_enabled: request.sip_enabled,
+        limit: Some(limit),
+        offset: page.offset,
+    };
+
+    match store.list_inventory(&filter).await {
+        Ok(devices) => {
+            let next_offset = page.next_offset(limit, devices.len());
+            Json(DeviceListResponse {
+                devices,
+                next_offset,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to list devices");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use mdm_core::InventoryUpdate;
+    use mdm_storage::InMemoryStorage;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    async fn get(store: &InMemoryStorage, uri: &str) -> (StatusCode, serde_json::Value) {
+        let app = axum::Router::new()
+            .route(
+                "/v1/devices",
+                axum::routing::get(list_devices::<InMemoryStorage>),
+            )
+            .with_state(store.clone());
+        let response = app
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    #[tokio::test]
+    async fn test_list_devices() {
+        let store = InMemoryStorage::new();
+        for (id, os_version, filevault) in [
+            ("dev-1", "14.4.1", true),
+            ("dev-2", "14.4.1", false),
+            ("dev-3", "13.6", true),
+        ] {
+            let update = InventoryUpdate {
+                os_version: Some(os_version.to_string()),
+                filevault_enabled: Some(filevault),
+                ..Default::default()
+            };
+            store.update_inventory(id, &update).await.unwrap();
+        }
+
+        let (status, body) = get(&store, "/v1/devices").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["devices"].as_array().unwrap().len(), 3);
+        assert!(body.get("next_offset").is_none());
+
+        let (_, body) = get(
+            &store,
+            "/v1/devices?os_version=14.4.1&filevault_enabled=false",
+        )
+        .await;
+        assert_eq!(body["devices"][0]["device_id"], "dev-2");
+        assert_eq!(body["devices"].as_array().unwrap().len(), 1);
+
+        let (_, body) = get(&store, "/v1/devices?limit=2").await;
+        assert_eq!(body["devices"][1]["device_id"], "dev-2");
+        assert_eq!(body["next_offset"], 2);
+
+        let (status, _) = get(&store, "/v1/devices?sip_enabled=maybe").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
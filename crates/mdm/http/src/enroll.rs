@@ -0,0 +1,136 @@
+//! Enrollment profile endpoint.
+
+use std::sync::Arc;
+
+use axum::extract::{Query, State};
+use axum::http::{StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use color_eyre::eyre::WrapErr as _;
+use serde::Deserialize;
+
+use mdm_core::{EnrollmentProfile, IdentityPayload, PROFILE_CONTENT_TYPE};
+use mdm_storage::PushCertStore;
+
+/// Settings used to build the enrollment profile.
+#[derive(Debug, Clone)]
+pub struct EnrollConfig {
+    /// Public base URL of the MDM server.
+    pub base_url: String,
+    pub identity: IdentityPayload,
+    pub organization: Option<String>,
+    /// CMS-sign the profile when set.
+    pub signer: Option<mdm_crypto::CmsSigner>,
+    /// Mint a fresh SCEP challenge into each profile when set.
+    pub challenges: Option<Arc<mdm_crypto::OneTimeChallenges>>,
+}
+
+impl EnrollConfig {
+    pub fn new(base_url: impl Into<String>, identity: IdentityPayload) -> Self {
+        Self {
+            base_url: base_url.into(),
+            identity,
+            organization: None,
+            signer: None,
+            challenges: None,
+        }
+    }
+
+    pub fn with_organization(mut self, organization: impl Into<String>) -> Self {
+        self.organization = Some(organization.into());
+        self
+    }
+
+    pub fn with_signer(mut self, signer: mdm_crypto::CmsSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    pub fn with_challenges(mut self, challenges: Arc<mdm_crypto::OneTimeChallenges>) -> Self {
+        self.challenges = Some(challenges);
+        self
+    }
+}
+
+/// State for the enrollment router.
+#[derive(Debug, Clone)]
+pub struct EnrollState<S> {
+    pub store: S,
+    pub config: Arc<EnrollConfig>,
+}
+
+/// Enrollment profile query parameters.
+#[derive(Debug, Deserialize)]
+pub struct ProfileQuery {
+    /// Push topic to use when more than one push certificate is stored.
+    pub topic: Option<String>,
+}
+
+/// Serve the enrollment `.mobileconfig`.
+pub async fn enroll_profile_handler<S>(
+    State(state): State<EnrollState<S>>,
+    Query(query): Query<ProfileQuery>,
+) -> Response
+where
+    S: PushCertStore,
+{
+    match build_profile(&state, query.topic.as_deref()).await {
+        Ok(Some(profile)) => (
+            [
+                (header::CONTENT_TYPE, PROFILE_CONTENT_TYPE),
+                (
+                    header::CONTENT_DISPOSITION,
+                    "attachment; filename=\"enroll.mobileconfig\"",
+                ),
+            ],
+            profile,
+        )
+            .into_response(),
+        Ok(None) => (StatusCode::NOT_FOUND, "no push certificate for topic").into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, "failed to build enrollment profile");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Build the (optionally signed) profile, or `None` if there is no push
+/// certificate to take the topic from.
+async fn build_profile<S: PushCertStore>(
+    state: &EnrollState<S>,
+    topic: Option<&str>,
+) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+    let topics = state.store.list_push_topics().await?;
+    let topic = match topic {
+        Some(topic) => topics.into_iter().find(|t| t == topic),
+        None => {
+            if topics.len() > 1 {
+                tracing::warn!(topic = %topics[0], "multiple push certificates, using first topic");
+            }
+            topics.into_iter().next()
+        }
+    };
+    let Some(topic) = topic else {
+        return Ok(None);
+    };
+
+    let config = &state.config;
+    let mut identity = config.identity.clone();
+    if let (IdentityPayload::Scep { challenge, .. }, Some(challenges)) =
+        (&mut identity, &config.challenges)
+    {
+        *challenge = Some(challenges.mint());
+    }
+    let mut profile = EnrollmentProfile::new(&config.base_url, topic, identity);
+    if let Some(organization) = &config.organization {
+        profile = profile.with_organization(organization);
+    }
+    let profile = profile.to_xml()?;
+
+    match &config.signer {
+        Some(signer) => signer
+            .sign(&profile)
+            .wrap_err("failed to sign profile")
+            .map(Some),
+        None => Ok(Some(profile)),
+    }
+}
@@ -0,0 +1,262 @@
+//! Enrollment listing and lookup handlers.
+
+use axum::Json;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::response::IntoResponse;
+use serde::{Deserialize, Serialize};
+
+use mdm_core::{CheckinMessage, EnrollId, EnrollType, EnrollmentRecord};
+use mdm_storage::{CheckinStore, CommandStore, EnrollmentFilter, InventoryStore};
+
+use crate::Page;
+
+/// Page size when the request doesn't give one.
+const DEFAULT_LIMIT: u32 = 100;
+
+/// Largest page a request may ask for.
+const MAX_LIMIT: u32 = 1000;
+
+/// An enrollment as listed.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrollmentSummary {
+    pub id: String,
+    pub enroll_type: EnrollType,
+    /// Device enrollment of a user channel.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub parent_id: Option<String>,
+    pub topic: String,
+    pub disabled: bool,
+    /// RFC 3339 time the enrollment was created.
+    pub created_at: String,
+    /// RFC 3339 time of the last Authenticate or TokenUpdate.
+    pub updated_at: String,
+    /// RFC 3339 time APNs first rejected the push token.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push_invalid_at: Option<String>,
+}
+
+impl From<&EnrollmentRecord> for EnrollmentSummary {
+    fn from(record: &EnrollmentRecord) -> Self {
+        Self {
+            id: record.id.clone(),
+            enroll_type: record.enroll_type,
+            parent_id: record.parent_id.clone(),
+            topic: record.topic.clone(),
+            disabled: record.disabled,
+            created_at: record.created_at.to_rfc3339(),
+            updated_at: record.updated_at.to_rfc3339(),
+            push_invalid_at: record.push_invalid_at.map(|t| t.to_rfc3339()),
+        }
+    }
+}
+
+/// Device properties from the stored Authenticate.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct AuthenticateInfo {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial_number: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub device_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub product_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub os_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_version: Option<String>,
+}
+
+/// Fields from the stored TokenUpdate.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TokenUpdateInfo {
+    /// APNs push token as hex.
+    pub token: String,
+    pub awaiting_configuration: bool,
+    pub has_unlock_token: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_short_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub user_long_name: Option<String>,
+}
+
+/// Enrollment lookup response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrollmentResponse {
+    #[serde(flatten)]
+    pub enrollment: EnrollmentSummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authenticate: Option<AuthenticateInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_update: Option<TokenUpdateInfo>,
+    /// Commands the enrollment has yet to answer.
+    pub queue_depth: u64,
+    /// RFC 3339 time of the last check-in or command report from the device.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen_at: Option<String>,
+    /// User channels of a device enrollment.
+    pub children: Vec<EnrollmentSummary>,
+}
+
+/// Parse a stored check-in message, if one was stored.
+///
+/// Releases before raw messages were kept stored them empty.
+fn stored_message(id: &str, raw: Option<&[u8]>) -> Option<CheckinMessage> {
+    let raw = raw.filter(|raw| !raw.is_empty())?;
+    match mdm_core::parse_checkin(raw) {
+        Ok(msg) => Some(msg),
+        Err(e) => {
+            tracing::warn!(error = %e, enrollment_id = %id, "unreadable stored check-in message");
+            None
+        }
+    }
+}
+
+fn authenticate_info(record: &EnrollmentRecord) -> Option<AuthenticateInfo> {
+    let Some(CheckinMessage::Authenticate(msg)) =
+        stored_message(&record.id, record.authenticate_raw.as_deref())
+    else {
+        return None;
+    };
+    Some(AuthenticateInfo {
+        serial_number: msg.serial_number,
+        device_name: msg.device_name,
+        model: msg.model,
+        model_name: msg.model_name,
+        product_name: msg.product_name,
+        os_version: msg.os_version,
+        build_version: msg.build_version,
+    })
+}
+
+fn token_update_info(record: &EnrollmentRecord) -> Option<TokenUpdateInfo> {
+    let Some(CheckinMessage::TokenUpdate(msg)) =
+        stored_message(&record.id, record.token_update_raw.as_deref())
+    else {
+        return None;
+    };
+    Some(TokenUpdateInfo {
+        token: msg.token.iter().map(|b| format!("{b:02x}")).collect(),
+        awaiting_configuration: msg.awaiting_configuration,
+        has_unlock_token: msg.unlock_token.is_some(),
+        user_short_name: msg.enrollment.user_short_name,
+        user_long_name: msg.enrollment.user_long_name,
+    })
+}
+
+/// Look up an enrollment.
+pub async fn get_enrollment<S>(State(store): State<S>, Path(id): Path<String>) -> impl IntoResponse
+where
+    S: CheckinStore + CommandStore + InventoryStore,
+{
+    match enrollment_response(&store, &id).await {
+        Ok(Some(response)) => Json(response).into_response(),
+        Ok(None) => StatusCode::NOT_FOUND.into_response(),
+        Err(e) => {
+            tracing::error!(error = %e, enrollment_id = %id, "failed to get enrollment");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn enrollment_response<S>(
+    store: &S,
+    id: &str,
+) -> color_eyre::eyre::Result<Option<EnrollmentResponse>>
+where
+    S: CheckinStore + CommandStore + InventoryStore,
+{
+    let Some(record) = store.get_enrollment(id).await? else {
+        return Ok(None);
+    };
+    let enroll_id = EnrollId {
+        enroll_type: record.enroll_type,
+        id: record.id.clone(),
+        parent_id: record.parent_id.clone(),
+    };
+
+    let queue_depth = store.queue_depth(&enroll_id).await?;
+    let last_checkin_at = store
+        .get_inventory(enroll_id.device_id())
+        .await?
+        .and_then(|inventory| inventory.last_checkin_at);
+    let filter = EnrollmentFilter {
+        parent_id: Some(record.id.clone()),
+        ..Default::default()
+    };
+    let children = store.list_enrollments(&filter).await?;
+
+    Ok(Some(EnrollmentResponse {
+        authenticate: authenticate_info(&record),
+        token_update: token_update_info(&record),
+        queue_depth,
+        last_seen_at: last_checkin_at
+            .into_iter()
+            .chain([record.updated_at])
+            .max()
+            .map(|t| t.to_rfc3339()),
+        children: children.iter().map(EnrollmentSummary::from).collect(),
+        enrollment: EnrollmentSummary::from(&record),
+    }))
+}
+
+/// Enrollment list query parameters. Each given parameter must match exactly.
+#[derive(Debug, Deserialize)]
+pub struct EnrollmentListRequest {
+    #[serde(default)]
+    pub enroll_type: Option<EnrollType>,
+    #[serde(default)]
+    pub disabled: Option<bool>,
+    #[serde(default)]
+    pub topic: Option<String>,
+    /// Only user channels of this device.
+    #[serde(default)]
+    pub parent_id: Option<String>,
+}
+
+/// Enrollment list response.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct EnrollmentListResponse {
+    pub enrollments: Vec<EnrollmentSummary>,
+    /// Offset of the next page, if there may be one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<u32>,
+}
+
+/// List enrollments, by ID.
+pub async fn list_enrollments<S>(
+    State(store): State<S>,
+    Query(request): Query<EnrollmentListRequest>,
+    Query(page): Query<Page>,
+) -> impl IntoResponse
+where
+    S: CheckinStore,
+{
+    let limit = page.limit(DEFAULT_LIMIT, MAX_LIMIT);
+    let filter = EnrollmentFilter {
+        enroll_type: request.enroll_type,
+        disabled: request.disabled,
+        topic: request.topic,
+        parent_id: request.parent_id,
+        limit: Some(limit),
+        offset: page.offset,
+    };
+
+    match store.list_enrollments(&filter).await {
+        Ok(records) => {
+            let next_offset = page.next_offset(limit, records.len());
+            Json(EnrollmentListResponse {
+                enrollments: records.iter().map(EnrollmentSummary::from).collect(),
+                next_offset,
+            })
+            .into_response()
+        }
+        Err(e) => {
+            tracing::error!(error = %e, "failed to list enrollments");
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
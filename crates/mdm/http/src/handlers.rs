@@ -1,13 +1,16 @@
 //! MDM HTTP handlers.
 
+use axum::Extension;
 use axum::body::Bytes;
 use axum::extract::State;
-use axum::http::{HeaderMap, StatusCode};
-use axum::response::IntoResponse;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
 use color_eyre::eyre::WrapErr as _;
 
 use mdm_core::{CheckinMessage, Request, parse_checkin, parse_command_results};
-use mdm_service::{Checkin, CommandAndReportResults};
+use mdm_service::{Checkin, CommandAndReportResults, DeclarationNotFound};
+
+use crate::VerifiedCertificate;
 
 /// Serialize a value to XML plist bytes.
 fn to_plist_xml<T: serde::Serialize>(value: &T) -> color_eyre::eyre::Result<Vec<u8>> {
@@ -16,38 +19,44 @@ fn to_plist_xml<T: serde::Serialize>(value: &T) -> color_eyre::eyre::Result<Vec<
     Ok(buf)
 }
 
-/// Content type for MDM check-in messages.
-#[allow(dead_code)]
-const CHECKIN_CONTENT_TYPE: &str = "application/x-apple-aspen-mdm-checkin";
+/// Content type for the JSON bodies of DeclarativeManagement responses.
+const DDM_CONTENT_TYPE: &str = "application/json";
 
 /// Handle MDM check-in requests.
 pub async fn checkin_handler<S>(
     State(service): State<S>,
+    verified: Option<Extension<VerifiedCertificate>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse
 where
     S: Checkin,
 {
-    match handle_checkin_inner(&service, &headers, &body).await {
-        Ok(response) => (StatusCode::OK, response),
+    let verified = verified.map(|Extension(cert)| cert);
+    match handle_checkin_inner(&service, verified, &headers, &body).await {
+        Ok(response) => response,
+        Err(e) if e.chain().any(|e| e.is::<DeclarationNotFound>()) => {
+            tracing::warn!(error = %e, "check-in for unknown declaration");
+            (StatusCode::NOT_FOUND, Vec::new()).into_response()
+        }
         Err(e) => {
             tracing::error!(error = %e, "check-in handler error");
-            (StatusCode::INTERNAL_SERVER_ERROR, Vec::new())
+            (StatusCode::INTERNAL_SERVER_ERROR, Vec::new()).into_response()
         }
     }
 }
 
 async fn handle_checkin_inner<S: Checkin>(
     service: &S,
+    verified: Option<VerifiedCertificate>,
     headers: &HeaderMap,
     body: &[u8],
-) -> color_eyre::eyre::Result<Vec<u8>> {
-    // Extract certificate from headers
-    let cert = extract_certificate(headers)?;
+) -> color_eyre::eyre::Result<Response> {
+    let cert = request_certificate(verified, headers, body)?;
 
     // Parse check-in message
     let msg = parse_checkin(body)?;
+    let is_ddm = matches!(msg, CheckinMessage::DeclarativeManagement(_));
 
     // Build request context
     let mut req = Request::new();
@@ -109,19 +118,26 @@ async fn handle_checkin_inner<S: Checkin>(
         }
     };
 
-    Ok(response.unwrap_or_default())
+    match response {
+        Some(body) if is_ddm => {
+            Ok(([(header::CONTENT_TYPE, DDM_CONTENT_TYPE)], body).into_response())
+        }
+        response => Ok((StatusCode::OK, response.unwrap_or_default()).into_response()),
+    }
 }
 
 /// Handle MDM command/report results requests.
 pub async fn command_handler<S>(
     State(service): State<S>,
+    verified: Option<Extension<VerifiedCertificate>>,
     headers: HeaderMap,
     body: Bytes,
 ) -> impl IntoResponse
 where
     S: CommandAndReportResults,
 {
-    match handle_command_inner(&service, &headers, &body).await {
+    let verified = verified.map(|Extension(cert)| cert);
+    match handle_command_inner(&service, verified, &headers, &body).await {
         Ok(response) => (StatusCode::OK, response),
         Err(e) => {
             tracing::error!(error = %e, "command handler error");
@@ -132,11 +148,11 @@ where
 
 async fn handle_command_inner<S: CommandAndReportResults>(
     service: &S,
+    verified: Option<VerifiedCertificate>,
     headers: &HeaderMap,
     body: &[u8],
 ) -> color_eyre::eyre::Result<Vec<u8>> {
-    // Extract certificate
-    let cert = extract_certificate(headers)?;
+    let cert = request_certificate(verified, headers, body)?;
 
     // Parse command results
     let results = parse_command_results(body)?;
@@ -162,14 +178,35 @@ async fn handle_command_inner<S: CommandAndReportResults>(
     }
 }
 
+/// Identity certificate of a request.
+///
+/// Uses the certificate the verification middleware already checked, if it
+/// ran, and extracts it from the headers otherwise.
+fn request_certificate(
+    verified: Option<VerifiedCertificate>,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+    match verified {
+        Some(VerifiedCertificate(cert)) => Ok(Some(cert)),
+        None => extract_certificate(headers, body),
+    }
+}
+
 /// Extract certificate from request headers.
-fn extract_certificate(headers: &HeaderMap) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+///
+/// The Mdm-Signature header is verified against `body` before its signer
+/// certificate is trusted.
+pub(crate) fn extract_certificate(
+    headers: &HeaderMap,
+    body: &[u8],
+) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
     // Try Mdm-Signature header first
     if let Some(sig) = headers.get("Mdm-Signature") {
-        let _sig_str = sig.to_str().wrap_err("invalid Mdm-Signature header")?;
-        // Note: Would need body for full verification
-        // For now just return None
-        return Ok(None);
+        let sig_str = sig.to_str().wrap_err("invalid Mdm-Signature header")?;
+        let cert = mdm_crypto::verify_mdm_signature(sig_str, body)
+            .wrap_err("Mdm-Signature verification failed")?;
+        return Ok(Some(cert));
     }
 
     // Try certificate header (RFC 9440 or PEM)
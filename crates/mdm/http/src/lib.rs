@@ -3,12 +3,26 @@
 //! Axum handlers for MDM check-in and command endpoints.
 
 mod api;
+mod commands;
+mod ddm;
+mod devices;
+mod enroll;
+mod enrollments;
 mod handlers;
 mod middleware;
+mod page;
+mod scep;
 
 pub use api::*;
+pub use commands::*;
+pub use ddm::*;
+pub use devices::*;
+pub use enroll::*;
+pub use enrollments::*;
 pub use handlers::*;
 pub use middleware::*;
+pub use page::*;
+pub use scep::*;
 
 use axum::Router;
 
@@ -26,16 +40,560 @@ where
 }
 
 /// Create the API router.
-pub fn api_router<St>(store: St) -> Router
+///
+/// `/v1/push/{ids}` responds 503 when no push provider is given, and
+/// enqueued commands are left for the next check-in.
+pub fn api_router<St, P>(store: St, pusher: Option<std::sync::Arc<P>>) -> Router
 where
     St: mdm_storage::AllStorage + Clone + 'static,
+    P: mdm_push::PushProvider + 'static,
 {
     use axum::routing::{get, post, put};
 
+    let push = Router::new()
+        .route("/v1/push/{ids}", post(api::push_handler::<P>))
+        .with_state(pusher.clone());
+    let enqueue = Router::new()
+        .route("/v1/enqueue/{ids}", post(api::enqueue_handler::<St, P>))
+        .route("/v1/ddm/sync/{ids}", post(ddm::sync_handler::<St, P>))
+        .with_state(EnqueueState {
+            store: store.clone(),
+            pusher,
+        });
+
     Router::new()
         .route("/v1/pushcert", put(api::store_push_cert::<St>))
         .route("/v1/pushcert", get(api::get_push_cert::<St>))
-        .route("/v1/push/:ids", post(api::push_handler))
-        .route("/v1/enqueue/:ids", post(api::enqueue_handler::<St>))
+        .route(
+            "/v1/cancel/{ids}/{command_uuid}",
+            post(api::cancel_handler::<St>),
+        )
+        .route("/v1/commands/{uuid}", get(commands::get_command::<St>))
+        .route("/v1/enrollments", get(enrollments::list_enrollments::<St>))
+        .route(
+            "/v1/enrollments/{id}",
+            get(enrollments::get_enrollment::<St>),
+        )
+        .route(
+            "/v1/enrollments/{id}/commands",
+            get(commands::list_commands::<St>),
+        )
+        .route("/v1/devices", get(devices::list_devices::<St>))
+        .route(
+            "/v1/ddm/declarations",
+            get(ddm::list_declarations::<St>).put(ddm::store_declaration::<St>),
+        )
+        .route(
+            "/v1/ddm/declarations/{identifier}",
+            get(ddm::get_declaration::<St>).delete(ddm::delete_declaration::<St>),
+        )
+        .route(
+            "/v1/ddm/enrollments/{id}/declarations",
+            get(ddm::enrollment_declarations::<St>),
+        )
+        .route(
+            "/v1/ddm/enrollments/{id}/declarations/{identifier}",
+            put(ddm::assign_declaration::<St>).delete(ddm::unassign_declaration::<St>),
+        )
+        .route(
+            "/v1/ddm/enrollments/{id}/status",
+            get(ddm::status_items::<St>),
+        )
+        .route(
+            "/v1/ddm/enrollments/{id}/status/{item}",
+            get(ddm::status_item_history::<St>),
+        )
         .with_state(store)
+        .merge(push)
+        .merge(enqueue)
+}
+
+/// Create the enrollment profile router.
+pub fn enroll_router<St>(store: St, config: EnrollConfig) -> Router
+where
+    St: mdm_storage::PushCertStore + Clone + 'static,
+{
+    use axum::routing::get;
+
+    Router::new()
+        .route(
+            "/v1/enroll/profile",
+            get(enroll::enroll_profile_handler::<St>),
+        )
+        .with_state(EnrollState {
+            store,
+            config: std::sync::Arc::new(config),
+        })
+}
+
+/// Create the SCEP router.
+pub fn scep_router(server: std::sync::Arc<mdm_crypto::ScepServer>) -> Router {
+    use axum::routing::get;
+
+    Router::new()
+        .route("/scep", get(scep::scep_handler).post(scep::scep_handler))
+        .with_state(server)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::{Request, StatusCode};
+    use mdm_storage::{InMemoryStorage, PushCertStore as _};
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    async fn get(app: &Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let response = app
+            .clone()
+            .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, serde_json::from_slice(&body).unwrap_or_default())
+    }
+
+    async fn send(app: &Router, uri: &str, body: Vec<u8>) -> (StatusCode, Vec<u8>) {
+        let response = app
+            .clone()
+            .oneshot(Request::post(uri).body(Body::from(body)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str, body: &str) -> StatusCode {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        app.clone().oneshot(request).await.unwrap().status()
+    }
+
+    fn plist(pairs: &[(&str, plist::Value)]) -> Vec<u8> {
+        let dict: plist::Dictionary = pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.clone()))
+            .collect();
+        let mut buf = Vec::new();
+        plist::to_writer_xml(&mut buf, &dict).unwrap();
+        buf
+    }
+
+    async fn enroll(app: &Router, udid: &str) {
+        for message_type in ["Authenticate", "TokenUpdate"] {
+            let body = plist(&[
+                ("MessageType", message_type.into()),
+                ("UDID", udid.into()),
+                ("Topic", "com.apple.mgmt.test".into()),
+                ("Token", plist::Value::Data(vec![0xaa])),
+                ("PushMagic", "magic".into()),
+                ("SerialNumber", format!("serial-{udid}").into()),
+            ]);
+            assert_eq!(send(app, "/mdm/checkin", body).await.0, StatusCode::OK);
+        }
+    }
+
+    /// Report `status` for `uuid` and return the UUID of the next command.
+    ///
+    /// Idle reports leave out CommandUUID, as devices do.
+    async fn report(app: &Router, udid: &str, uuid: &str, status: &str) -> Option<String> {
+        let mut pairs = vec![("UDID", udid.into()), ("Status", status.into())];
+        if status != "Idle" {
+            pairs.push(("CommandUUID", uuid.into()));
+        }
+        let body = plist(&pairs);
+        let (code, body) = send(app, "/mdm/command", body).await;
+        assert_eq!(code, StatusCode::OK);
+        if body.is_empty() {
+            return None;
+        }
+        let next: mdm_core::Command = plist::from_bytes(&body).unwrap();
+        Some(next.command_uuid)
+    }
+
+    #[tokio::test]
+    async fn test_results_match_enqueued_command() {
+        let store = InMemoryStorage::new();
+        let service = mdm_service::NanoMdm::new(store.clone());
+        let app = mdm_router(service).merge(
+            api_router::<_, mdm_push::ApnsProvider<InMemoryStorage>>(store.clone(), None),
+        );
+        enroll(&app, "dev-1").await;
+        enroll(&app, "dev-2").await;
+
+        let command =
+            mdm_core::serialize_command(&mdm_core::new_command("DeviceInformation")).unwrap();
+        let (status, body) = send(&app, "/v1/enqueue/dev-1,dev-2", command.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        let response: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let uuid = response["command_uuid"].as_str().unwrap().to_string();
+
+        let (status, _) = send(&app, "/v1/enqueue/dev-1", command).await;
+        assert_eq!(status, StatusCode::CONFLICT);
+
+        // The device echoes the UUID it was sent, which answers its own row
+        assert_eq!(report(&app, "dev-1", "", "Idle").await, Some(uuid.clone()));
+        assert_eq!(report(&app, "dev-1", &uuid, "Acknowledged").await, None);
+        assert_eq!(report(&app, "dev-2", "", "Idle").await, Some(uuid));
+    }
+
+    #[tokio::test]
+    async fn test_enrollment_lookup() {
+        let store = InMemoryStorage::new();
+        let service = mdm_service::NanoMdm::new(store.clone());
+        let app = mdm_router(service).merge(
+            api_router::<_, mdm_push::ApnsProvider<InMemoryStorage>>(store.clone(), None),
+        );
+        enroll(&app, "dev-1").await;
+        enroll(&app, "dev-2").await;
+        let body = plist(&[
+            ("MessageType", "TokenUpdate".into()),
+            ("UDID", "dev-1".into()),
+            ("UserID", "user-1".into()),
+            ("UserShortName", "kim".into()),
+            ("Topic", "com.apple.mgmt.test".into()),
+            ("Token", plist::Value::Data(vec![0xcc, 0x01])),
+            ("PushMagic", "magic".into()),
+        ]);
+        assert_eq!(send(&app, "/mdm/checkin", body).await.0, StatusCode::OK);
+        let command =
+            mdm_core::serialize_command(&mdm_core::new_command("DeviceInformation")).unwrap();
+        assert_eq!(
+            send(&app, "/v1/enqueue/dev-1", command).await.0,
+            StatusCode::OK
+        );
+
+        let (status, body) = get(&app, "/v1/enrollments/dev-1").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["enroll_type"], "Device");
+        assert_eq!(body["disabled"], false);
+        assert_eq!(body["authenticate"]["serial_number"], "serial-dev-1");
+        assert_eq!(body["token_update"]["token"], "aa");
+        assert_eq!(body["queue_depth"], 1);
+        assert!(body["last_seen_at"].is_string());
+        assert_eq!(body["children"][0]["id"], "dev-1:user-1");
+
+        let (_, body) = get(&app, "/v1/enrollments/dev-1:user-1").await;
+        assert_eq!(body["parent_id"], "dev-1");
+        assert_eq!(body["token_update"]["token"], "cc01");
+        assert_eq!(body["token_update"]["user_short_name"], "kim");
+        assert!(body.get("authenticate").is_none());
+
+        let (status, _) = get(&app, "/v1/enrollments/missing").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (_, body) = get(&app, "/v1/enrollments?enroll_type=Device&limit=1").await;
+        assert_eq!(body["enrollments"][0]["id"], "dev-1");
+        assert_eq!(body["next_offset"], 1);
+        let (_, body) = get(&app, "/v1/enrollments?parent_id=dev-1&disabled=false").await;
+        assert_eq!(body["enrollments"].as_array().unwrap().len(), 1);
+        let (status, _) = get(&app, "/v1/enrollments?enroll_type=Bogus").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn test_declarative_management() {
+        const UDID: &str = "00008103-001A2B3C4D5E6F70";
+        const TOKENS: &[u8] = include_bytes!("../testdata/ddm_tokens.plist");
+        const ITEMS: &[u8] = include_bytes!("../testdata/ddm_declaration_items.plist");
+        const PASSCODE: &[u8] = include_bytes!("../testdata/ddm_declaration.plist");
+        const STATUS: &[u8] = include_bytes!("../testdata/ddm_status.plist");
+
+        let store = InMemoryStorage::new();
+        let service = mdm_service::NanoMdm::new(store.clone());
+        let app = mdm_router(service).merge(
+            api_router::<_, mdm_push::ApnsProvider<InMemoryStorage>>(store.clone(), None),
+        );
+        enroll(&app, UDID).await;
+        let checkin = |body: &'static [u8]| {
+            let app = app.clone();
+            async move {
+                let (status, body) = send(&app, "/mdm/checkin", body.to_vec()).await;
+                (status, serde_json::from_slice(&body).unwrap_or_default())
+            }
+        };
+        let (status, body): (_, serde_json::Value) = checkin(TOKENS).await;
+        assert_eq!(status, StatusCode::OK);
+        let empty_token = body["SyncTokens"]["DeclarationsToken"].clone();
+
+        let activation = r#"{
+            "Type": "com.apple.activation.simple",
+            "Identifier": "com.example.activation",
+            "Payload": {"StandardConfigurations": ["com.example.passcode"]}
+        }"#;
+        let passcode = r#"{
+            "Type": "com.apple.configuration.passcode.settings",
+            "Identifier": "com.example.passcode",
+            "Payload": {"MinimumLength": 6}
+        }"#;
+        assert_eq!(
+            call(&app, "PUT", "/v1/ddm/declarations", activation).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(&app, "PUT", "/v1/ddm/declarations", passcode).await,
+            StatusCode::OK
+        );
+        assert_eq!(
+            call(&app, "PUT", "/v1/ddm/declarations", r#"{"Type": "bogus"}"#).await,
+            StatusCode::BAD_REQUEST
+        );
+        let (_, body) = get(&app, "/v1/ddm/declarations/com.example.passcode").await;
+        let server_token = body["declaration"]["ServerToken"].clone();
+        assert!(server_token.is_string());
+
+        // Unassigned declarations aren't served
+        assert_eq!(checkin(PASSCODE).await.0, StatusCode::NOT_FOUND);
+
+        for identifier in ["com.example.activation", "com.example.passcode"] {
+            let uri = format!("/v1/ddm/enrollments/{UDID}/declarations/{identifier}");
+            assert_eq!(call(&app, "PUT", &uri, "").await, StatusCode::NO_CONTENT);
+        }
+        let uri = format!("/v1/ddm/enrollments/{UDID}/declarations/com.example.missing");
+        assert_eq!(call(&app, "PUT", &uri, "").await, StatusCode::NOT_FOUND);
+
+        let (_, body) = checkin(TOKENS).await;
+        let token = body["SyncTokens"]["DeclarationsToken"].clone();
+        assert_ne!(token, empty_token);
+
+        let (_, body) = checkin(ITEMS).await;
+        assert_eq!(body["DeclarationsToken"], token);
+        let items = &body["Declarations"];
+        assert_eq!(
+            items["Activations"][0]["Identifier"],
+            "com.example.activation"
+        );
+        assert_eq!(items["Configurations"][0]["ServerToken"], server_token);
+        assert_eq!(items["Assets"].as_array().unwrap().len(), 0);
+
+        let (status, body) = checkin(PASSCODE).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["Type"], "com.apple.configuration.passcode.settings");
+        assert_eq!(body["Payload"]["MinimumLength"], 6);
+        assert_eq!(body["ServerToken"], server_token);
+
+        // Nor are declarations asked for as the wrong kind
+        let as_asset = String::from_utf8(PASSCODE.to_vec())
+            .unwrap()
+            .replace("declaration/configuration/", "declaration/asset/");
+        let (status, _) = send(&app, "/mdm/checkin", as_asset.into_bytes()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        for body in [TOKENS, ITEMS, PASSCODE] {
+            let request = Request::post("/mdm/checkin")
+                .body(Body::from(body))
+                .unwrap();
+            let response = app.clone().oneshot(request).await.unwrap();
+            assert_eq!(response.headers()["content-type"], "application/json");
+        }
+
+        // Changing an assigned declaration changes the token
+        let changed = passcode.replace("6", "8");
+        assert_eq!(
+            call(&app, "PUT", "/v1/ddm/declarations", &changed).await,
+            StatusCode::OK
+        );
+        let (_, body) = checkin(TOKENS).await;
+        assert_ne!(body["SyncTokens"]["DeclarationsToken"], token);
+
+        let (status, _) = checkin(STATUS).await;
+        assert_eq!(status, StatusCode::OK);
+        let uri = format!("/v1/ddm/enrollments/{UDID}/status?prefix=softwareupdate");
+        let (_, body) = get(&app, &uri).await;
+        let items = body["items"].as_array().unwrap();
+        assert_eq!(items.len(), 3);
+        assert_eq!(items[1]["item"], "softwareupdate.install-state");
+        assert_eq!(items[1]["value"], "downloading");
+        assert_eq!(items[2]["value"]["os-version"], "14.5");
+        let uri = format!("/v1/ddm/enrollments/{UDID}/status/management.client-capabilities");
+        let (_, body) = get(&app, &uri).await;
+        assert_eq!(body["value"]["supported-versions"][0], "1.0.0");
+
+        // A later report that changes an item adds to its history
+        let report = serde_json::json!({
+            "StatusItems": {"device": {"operating-system": {"version": "14.5"}}},
+            "Errors": [],
+            "FullReport": false,
+        });
+        let body = plist(&[
+            ("MessageType", "DeclarativeManagement".into()),
+            ("UDID", UDID.into()),
+            ("Endpoint", "status".into()),
+            ("Data", plist::Value::Data(report.to_string().into_bytes())),
+        ]);
+        assert_eq!(send(&app, "/mdm/checkin", body).await.0, StatusCode::OK);
+        let uri = format!("/v1/ddm/enrollments/{UDID}/status/device.operating-system.version");
+        let (status, body) = get(&app, &uri).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["value"], "14.5");
+        assert_eq!(body["history"][0]["value"], "14.5");
+        assert_eq!(body["history"][1]["value"], "14.4.1");
+        let (_, body) = get(&app, &format!("{uri}?limit=1&offset=1")).await;
+        assert_eq!(body["history"][0]["value"], "14.4.1");
+        assert_eq!(body["next_offset"], 2);
+        let uri = format!("/v1/ddm/enrollments/{UDID}/status/device.missing");
+        assert_eq!(get(&app, &uri).await.0, StatusCode::NOT_FOUND);
+
+        let (_, body) = get(&app, &format!("/v1/ddm/enrollments/{UDID}/declarations")).await;
+        assert_eq!(body["declarations"].as_array().unwrap().len(), 2);
+        let statuses = &body["statuses"];
+        assert_eq!(statuses[0]["identifier"], "com.example.activation");
+        assert_eq!(statuses[0]["active"], true);
+        assert_eq!(statuses[1]["valid"], "invalid");
+        assert_eq!(
+            statuses[1]["reasons"][0]["code"],
+            "Error.ConfigurationCannotBeApplied"
+        );
+
+        let (status, body) = send(&app, &format!("/v1/ddm/sync/{UDID}"), Vec::new()).await;
+        assert_eq!(status, StatusCode::OK);
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        let uuid = body["command_uuids"][UDID].as_str().unwrap().to_string();
+        let (_, body) = get(&app, &format!("/v1/commands/{uuid}")).await;
+        assert_eq!(body["request_type"], "DeclarativeManagement");
+
+        // Deleting a declaration unassigns it
+        let uri = "/v1/ddm/declarations/com.example.passcode";
+        assert_eq!(call(&app, "DELETE", uri, "").await, StatusCode::NO_CONTENT);
+        assert_eq!(call(&app, "DELETE", uri, "").await, StatusCode::NOT_FOUND);
+        let (_, body) = checkin(ITEMS).await;
+        assert_eq!(
+            body["Declarations"]["Configurations"]
+                .as_array()
+                .unwrap()
+                .len(),
+            0
+        );
+    }
+
+    #[tokio::test]
+    async fn test_enroll_profile() {
+        const TOPIC: &str = "com.apple.mgmt.External.moonstone-test";
+        let store = InMemoryStorage::new();
+        let identity = mdm_core::IdentityPayload::Scep {
+            url: "https://mdm.example.com/scep".to_string(),
+            challenge: None,
+            subject: "moonstone".to_string(),
+        };
+        let config = EnrollConfig::new("https://mdm.example.com", identity);
+        let profile = |config: EnrollConfig| {
+            let app = enroll_router(store.clone(), config);
+            async move {
+                let response = app
+                    .oneshot(
+                        Request::get("/v1/enroll/profile")
+                            .body(Body::empty())
+                            .unwrap(),
+                    )
+                    .await
+                    .unwrap();
+                let status = response.status();
+                let content_type = response.headers().get("content-type").cloned();
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                (status, content_type, body.to_vec())
+            }
+        };
+
+        // The topic comes from the stored push certificate
+        let (status, _, _) = profile(config.clone()).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        store
+            .store_push_cert(
+                TOPIC,
+                include_str!("../../crypto/testdata/push.pem"),
+                include_str!("../../crypto/testdata/push.key"),
+            )
+            .await
+            .unwrap();
+
+        let (status, content_type, body) = profile(config.clone()).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), mdm_core::PROFILE_CONTENT_TYPE);
+        let xml: plist::Value = plist::from_bytes(&body).unwrap();
+        let payload = &xml.as_dictionary().unwrap()["PayloadContent"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find_map(|p| p.as_dictionary().filter(|p| p.contains_key("Topic")))
+            .unwrap();
+        assert_eq!(payload["Topic"].as_string(), Some(TOPIC));
+
+        // A signed profile wraps the XML in CMS SignedData
+        let signer = mdm_crypto::CmsSigner::from_pem(
+            include_str!("../../crypto/testdata/device-rsa.pem"),
+            include_str!("../../crypto/testdata/device-rsa.key"),
+        )
+        .unwrap();
+        let (status, content_type, signed) = profile(config.clone().with_signer(signer)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.unwrap(), mdm_core::PROFILE_CONTENT_TYPE);
+        assert_eq!(signed[0], 0x30, "expected DER");
+        let embeds = |needle: &[u8]| signed.windows(needle.len()).any(|w| w == needle);
+        assert!(embeds(b"<plist") && embeds(TOPIC.as_bytes()));
+
+        // Each profile carries a fresh challenge that only works once
+        let challenges = std::sync::Arc::new(mdm_crypto::OneTimeChallenges::new(
+            std::time::Duration::from_secs(60),
+        ));
+        let config = config.with_challenges(challenges.clone());
+        let challenge = |body: Vec<u8>| {
+            let xml: plist::Value = plist::from_bytes(&body).unwrap();
+            xml.as_dictionary().unwrap()["PayloadContent"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find_map(|p| p.as_dictionary()?.get("PayloadContent")?.as_dictionary())
+                .and_then(|scep| scep.get("Challenge")?.as_string().map(String::from))
+                .unwrap()
+        };
+        let first = challenge(profile(config.clone()).await.2);
+        let second = challenge(profile(config).await.2);
+        assert_ne!(first, second);
+        assert!(challenges.redeem(&first));
+        assert!(!challenges.redeem(&first));
+        assert!(challenges.redeem(&second));
+    }
+
+    #[tokio::test]
+    async fn test_push_cert() {
+        let store = InMemoryStorage::new();
+        let app = api_router::<_, mdm_push::ApnsProvider<InMemoryStorage>>(store, None);
+
+        // Nothing uploaded yet
+        let (status, body) = get(&app, "/v1/pushcert").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, serde_json::json!([]));
+
+        let pem = format!(
+            "{}{}",
+            include_str!("../../crypto/testdata/push.pem"),
+            include_str!("../../crypto/testdata/push.key")
+        );
+        assert_eq!(
+            call(&app, "PUT", "/v1/pushcert", &pem).await,
+            StatusCode::OK
+        );
+
+        let (status, body) = get(&app, "/v1/pushcert").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            serde_json::json!([{
+                "topic": "com.apple.mgmt.External.moonstone-test",
+                "not_after": "2035-01-01T00:00:00+00:00",
+            }])
+        );
+    }
 }
@@ -1,8 +1,24 @@
 //! HTTP middleware.
 
-use axum::extract::Request;
+use std::sync::Arc;
+
+use axum::body::Body;
+use axum::extract::{Request, State};
+use axum::http::StatusCode;
 use axum::middleware::Next;
-use axum::response::Response;
+use axum::response::{IntoResponse, Response};
+
+use mdm_crypto::SignatureVerifier;
+
+/// Largest MDM request body buffered for certificate verification.
+const MAX_MDM_BODY_SIZE: usize = 16 * 1024 * 1024;
+
+/// Identity certificate the verification middleware checked, in DER form.
+///
+/// Added to the request extensions so handlers don't verify the
+/// Mdm-Signature again.
+#[derive(Debug, Clone)]
+pub struct VerifiedCertificate(pub Vec<u8>);
 
 /// Logging middleware.
 pub async fn logging_middleware(request: Request, next: Next) -> Response {
@@ -22,3 +38,100 @@ pub async fn logging_middleware(request: Request, next: Next) -> Response {
 
     response
 }
+
+/// Certificate verification middleware.
+///
+/// Rejects MDM requests whose identity certificate is missing or doesn't
+/// chain to a CA trusted by the verifier, and passes the certificate on as a
+/// [`VerifiedCertificate`] extension. Apply it to the MDM router with
+/// `axum::middleware::from_fn_with_state(Arc::new(verifier), verify_cert_middleware)`.
+pub async fn verify_cert_middleware(
+    State(verifier): State<Arc<SignatureVerifier>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let (mut parts, body) = request.into_parts();
+
+    // The Mdm-Signature covers the body, so it has to be buffered here.
+    let body = match axum::body::to_bytes(body, MAX_MDM_BODY_SIZE).await {
+        Ok(body) => body,
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to read request body");
+            return StatusCode::BAD_REQUEST.into_response();
+        }
+    };
+
+    let cert = match crate::handlers::extract_certificate(&parts.headers, &body) {
+        Ok(Some(cert)) => cert,
+        Ok(None) => {
+            tracing::warn!(uri = %parts.uri, "request has no identity certificate");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+        Err(e) => {
+            tracing::warn!(uri = %parts.uri, error = %e, "failed to extract identity certificate");
+            return StatusCode::UNAUTHORIZED.into_response();
+        }
+    };
+
+    if let Err(e) = verifier.verify_cert(&cert) {
+        tracing::warn!(uri = %parts.uri, error = %e, "identity certificate rejected");
+        return StatusCode::FORBIDDEN.into_response();
+    }
+
+    parts.extensions.insert(VerifiedCertificate(cert));
+    next.run(Request::from_parts(parts, Body::from(body))).await
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::Extension;
+    use axum::routing::post;
+    use base64::Engine as _;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    const BODY: &[u8] = include_bytes!("../../crypto/testdata/checkin.plist");
+
+    /// Route that echoes the certificate the middleware verified.
+    fn app() -> axum::Router {
+        let verifier =
+            SignatureVerifier::new(include_bytes!("../../crypto/testdata/ca.der")).unwrap();
+        axum::Router::new()
+            .route(
+                "/mdm/checkin",
+                post(|Extension(cert): Extension<VerifiedCertificate>| async move { cert.0 }),
+            )
+            .layer(axum::middleware::from_fn_with_state(
+                Arc::new(verifier),
+                verify_cert_middleware,
+            ))
+    }
+
+    async fn checkin(signature: Option<&[u8]>) -> (StatusCode, Vec<u8>) {
+        let mut request = Request::post("/mdm/checkin");
+        if let Some(p7s) = signature {
+            let header = base64::engine::general_purpose::STANDARD.encode(p7s);
+            request = request.header("Mdm-Signature", header);
+        }
+        let response = app()
+            .oneshot(request.body(Body::from(BODY)).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, body.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_verified_certificate_extension() {
+        let p7s = include_bytes!("../../crypto/testdata/checkin-rsa.p7s");
+        let (status, cert) = checkin(Some(p7s)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(cert, include_bytes!("../../crypto/testdata/device-rsa.der"));
+
+        assert_eq!(checkin(None).await.0, StatusCode::UNAUTHORIZED);
+    }
+}
@@ -0,0 +1,60 @@
+//! Offset pagination for list endpoints.
+
+use serde::Deserialize;
+
+/// Page query parameters, extracted alongside each list endpoint's filters.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Page {
+    /// Most items to return; each endpoint has its own default and cap.
+    #[serde(default)]
+    pub limit: Option<u32>,
+    /// Skip this many items first.
+    #[serde(default)]
+    pub offset: u32,
+}
+
+impl Page {
+    /// Page size to ask the store for: the requested limit, or `default`,
+    /// clamped to `1..=max`.
+    pub fn limit(&self, default: u32, max: u32) -> u32 {
+        self.limit.unwrap_or(default).clamp(1, max)
+    }
+
+    /// Offset of the next page, if a page of `len` items fetched with `limit`
+    /// may be followed by another.
+    pub fn next_offset(&self, limit: u32, len: usize) -> Option<u32> {
+        (len == limit as usize).then(|| self.offset.saturating_add(limit))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_page() {
+        let page = Page::default();
+        assert_eq!(page.limit(100, 1000), 100);
+        let page = Page {
+            limit: Some(5000),
+            offset: 10,
+        };
+        assert_eq!(page.limit(100, 1000), 1000);
+        assert_eq!(
+            Page {
+                limit: Some(0),
+                ..page
+            }
+            .limit(100, 1000),
+            1
+        );
+
+        assert_eq!(page.next_offset(20, 20), Some(30));
+        assert_eq!(page.next_offset(20, 19), None);
+        let last = Page {
+            limit: None,
+            offset: u32::MAX - 1,
+        };
+        assert_eq!(last.next_offset(20, 20), Some(u32::MAX));
+    }
+}
@@ -0,0 +1,186 @@
+//! SCEP HTTP handler.
+
+use std::sync::Arc;
+
+use axum::body::Bytes;
+use axum::extract::{Query, State};
+use axum::http::{Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use color_eyre::eyre::WrapErr as _;
+use serde::Deserialize;
+
+use mdm_crypto::ScepServer;
+
+/// Content type for GetCACert with a single CA certificate.
+const CA_CERT_CONTENT_TYPE: &str = "application/x-x509-ca-cert";
+
+/// Content type for PKIOperation responses.
+const PKI_MESSAGE_CONTENT_TYPE: &str = "application/x-pki-message";
+
+/// SCEP query parameters.
+#[derive(Debug, Deserialize)]
+pub struct ScepQuery {
+    pub operation: String,
+    /// Base64 pkiMessage for GET PKIOperation (ignored otherwise).
+    pub message: Option<String>,
+}
+
+/// Handle SCEP GetCACaps, GetCACert, and PKIOperation requests.
+pub async fn scep_handler(
+    State(server): State<Arc<ScepServer>>,
+    method: Method,
+    Query(query): Query<ScepQuery>,
+    body: Bytes,
+) -> Response {
+    match query.operation.as_str() {
+        "GetCACaps" => ([(header::CONTENT_TYPE, "text/plain")], server.ca_caps()).into_response(),
+        "GetCACert" => (
+            [(header::CONTENT_TYPE, CA_CERT_CONTENT_TYPE)],
+            server.ca_cert().to_vec(),
+        )
+            .into_response(),
+        "PKIOperation" => {
+            let message = if method == Method::POST {
+                Ok(body.to_vec())
+            } else {
+                decode_get_message(query.message.as_deref())
+            };
+            let message = match message {
+                Ok(message) => message,
+                Err(e) => {
+                    tracing::warn!(error = %e, "invalid SCEP message");
+                    return StatusCode::BAD_REQUEST.into_response();
+                }
+            };
+
+            // RSA decryption and signing are CPU-bound.
+            let result = tokio::task::spawn_blocking(move || server.pki_operation(&message)).await;
+            match result {
+                Ok(Ok(response)) => {
+                    ([(header::CONTENT_TYPE, PKI_MESSAGE_CONTENT_TYPE)], response).into_response()
+                }
+                Ok(Err(e)) => {
+                    tracing::warn!(error = %e, "SCEP PKIOperation failed");
+                    StatusCode::BAD_REQUEST.into_response()
+                }
+                Err(e) => {
+                    tracing::error!(error = %e, "SCEP PKIOperation task failed");
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }
+        other => {
+            tracing::warn!(operation = %other, "unsupported SCEP operation");
+            StatusCode::BAD_REQUEST.into_response()
+        }
+    }
+}
+
+fn decode_get_message(message: Option<&str>) -> color_eyre::eyre::Result<Vec<u8>> {
+    use base64::Engine as _;
+
+    let message = message.ok_or_else(|| color_eyre::eyre::eyre!("missing message parameter"))?;
+    // Clients often leave '+' unescaped, which form decoding turns into ' '.
+    let message = message.replace(' ', "+");
+    base64::engine::general_purpose::STANDARD
+        .decode(message.trim())
+        .wrap_err("message is not valid base64")
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::Body;
+    use axum::http::Request;
+    use base64::Engine as _;
+    use tower::ServiceExt as _;
+
+    use super::*;
+
+    /// PKCSReq with challenge `secret`, encrypted to the test CA.
+    const PKCS_REQ: &[u8] = include_bytes!("../../crypto/testdata/scep-pkcsreq.der");
+
+    async fn scep(request: Request<Body>) -> (StatusCode, Option<String>, Vec<u8>) {
+        let ca = mdm_crypto::CertificateAuthority::from_pem(
+            include_str!("../../crypto/testdata/ca.pem"),
+            include_str!("../../crypto/testdata/ca.key"),
+        )
+        .unwrap();
+        let server = ScepServer::new(ca, mdm_crypto::StaticChallenge::new("secret"));
+        let response = crate::scep_router(Arc::new(server))
+            .oneshot(request)
+            .await
+            .unwrap();
+        let status = response.status();
+        let content_type = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .map(|v| v.to_str().unwrap().to_string());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (status, content_type, body.to_vec())
+    }
+
+    async fn get(query: &str) -> (StatusCode, Option<String>, Vec<u8>) {
+        scep(
+            Request::get(format!("/scep?{query}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+    }
+
+    async fn post(query: &str, body: &[u8]) -> (StatusCode, Option<String>, Vec<u8>) {
+        let request = Request::post(format!("/scep?{query}"))
+            .body(Body::from(body.to_vec()))
+            .unwrap();
+        scep(request).await
+    }
+
+    #[tokio::test]
+    async fn test_get_ca_caps() {
+        let (status, content_type, body) = get("operation=GetCACaps").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some("text/plain"));
+        assert_eq!(body, mdm_crypto::SCEP_CA_CAPS.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_get_ca_cert() {
+        let (status, content_type, body) = get("operation=GetCACert&message=moonstone").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some(CA_CERT_CONTENT_TYPE));
+        assert_eq!(body, include_bytes!("../../crypto/testdata/ca.der"));
+    }
+
+    #[tokio::test]
+    async fn test_pki_operation() {
+        let (status, content_type, body) = post("operation=PKIOperation", PKCS_REQ).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some(PKI_MESSAGE_CONTENT_TYPE));
+        assert_eq!(body[0], 0x30, "expected a DER CertRep");
+
+        // GET carries the message as base64, often with '+' left unescaped
+        let message = base64::engine::general_purpose::STANDARD.encode(PKCS_REQ);
+        let query = format!(
+            "operation=PKIOperation&message={}",
+            message.replace('/', "%2F")
+        );
+        let (status, content_type, _) = get(&query).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(content_type.as_deref(), Some(PKI_MESSAGE_CONTENT_TYPE));
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests() {
+        for query in [
+            "operation=PKIOperation",
+            "operation=PKIOperation&message=not-base64!",
+            "operation=GetNextCACert",
+        ] {
+            assert_eq!(get(query).await.0, StatusCode::BAD_REQUEST, "{query}");
+        }
+        let (status, _, _) = post("operation=PKIOperation", b"not cms").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+}
@@ -1,82 +1,256 @@
-//! APNs push implementation using the a2 crate.
+//! APNs push implementation over HTTP/2.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use a2::NotificationBuilder as _;
 use color_eyre::eyre::WrapErr as _;
-use mdm_core::{PushInfo, PushResult};
+use mdm_core::{EnrollId, EnrollType, PushErrorKind, PushInfo, PushResult};
+
+use crate::{PushProvider, Pusher};
+
+/// Error reported for enrollments without a push token.
+pub const NO_PUSH_INFO: &str = "no push info";
+
+/// Timeout for a single APNs request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Default number of in-flight APNs requests per client.
+///
+/// APNs allows a few hundred concurrent streams per HTTP/2 connection.
+pub const DEFAULT_PUSH_CONCURRENCY: usize = 100;
+
+/// APNs environment.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Endpoint {
+    /// api.push.apple.com
+    #[default]
+    Production,
+    /// api.sandbox.push.apple.com
+    Sandbox,
+    /// Any other base URL, such as a local APNs simulator. `http://` URLs
+    /// use cleartext HTTP/2.
+    Custom(String),
+}
+
+impl Endpoint {
+    /// Base URL of the APNs provider API.
+    pub fn base_url(&self) -> &str {
+        match self {
+            Self::Production => "https://api.push.apple.com",
+            Self::Sandbox => "https://api.sandbox.push.apple.com",
+            Self::Custom(url) => url.trim_end_matches('/'),
+        }
+    }
+}
 
-use crate::Pusher;
+impl std::str::FromStr for Endpoint {
+    type Err = color_eyre::Report;
+
+    /// Parse `production`, `sandbox`, or an `http(s)://` base URL.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "production" => Ok(Self::Production),
+            "sandbox" => Ok(Self::Sandbox),
+            url if url.starts_with("https://") || url.starts_with("http://") => {
+                Ok(Self::Custom(url.to_string()))
+            }
+            other => color_eyre::eyre::bail!(
+                "invalid APNs endpoint {other:?}: expected production, sandbox, or a URL"
+            ),
+        }
+    }
+}
 
 /// APNs pusher using certificate authentication.
 pub struct ApnsPusher {
-    client: a2::Client,
-    #[allow(dead_code)]
-    endpoint: a2::Endpoint,
+    client: reqwest::Client,
+    base_url: String,
+    concurrency: usize,
 }
 
 impl ApnsPusher {
     /// Create a new APNs pusher from PKCS12 certificate bytes and password.
     pub fn new(pkcs12_der: &[u8], password: &str) -> color_eyre::eyre::Result<Self> {
-        let mut cursor = std::io::Cursor::new(pkcs12_der);
-        let config = a2::ClientConfig::default();
-
-        let client = a2::Client::certificate(&mut cursor, password, config)
-            .wrap_err("failed to create APNs client")?;
-
-        Ok(Self {
-            client,
-            endpoint: a2::Endpoint::Production,
-        })
+        Self::from_pkcs12(pkcs12_der, password, Endpoint::Production)
     }
 
     /// Create a new APNs pusher for sandbox environment.
     pub fn sandbox(pkcs12_der: &[u8], password: &str) -> color_eyre::eyre::Result<Self> {
-        let mut cursor = std::io::Cursor::new(pkcs12_der);
-        let config = a2::ClientConfig::default();
+        Self::from_pkcs12(pkcs12_der, password, Endpoint::Sandbox)
+    }
 
-        let client = a2::Client::certificate(&mut cursor, password, config)
+    fn from_pkcs12(
+        pkcs12_der: &[u8],
+        password: &str,
+        endpoint: Endpoint,
+    ) -> color_eyre::eyre::Result<Self> {
+        let keystore = p12_keystore::KeyStore::from_pkcs12(pkcs12_der, password)
+            .wrap_err("invalid PKCS12 push certificate")?;
+        let Some((_, chain)) = keystore.private_key_chain() else {
+            color_eyre::eyre::bail!("PKCS12 push certificate is missing its certificate or key");
+        };
+        let Some(cert) = chain.chain().first() else {
+            color_eyre::eyre::bail!("PKCS12 push certificate is missing its certificate");
+        };
+
+        let key = pem::Pem::new("PRIVATE KEY", chain.key());
+        let cert = pem::Pem::new("CERTIFICATE", cert.as_der());
+        let identity_pem = pem::encode_many(&[key, cert]).into_bytes();
+        Ok(Self::from_identity_pem(&identity_pem)?.with_endpoint(&endpoint))
+    }
+
+    /// Create a new APNs pusher from a PEM certificate and private key, as
+    /// returned by [`PushCertStore::get_push_cert`](mdm_storage::PushCertStore::get_push_cert).
+    ///
+    /// Pushes go to production unless changed with [`Self::with_endpoint`].
+    pub fn from_pem(cert_pem: &str, key_pem: &str) -> color_eyre::eyre::Result<Self> {
+        let identity_pem = format!("{}\n{}\n", key_pem.trim(), cert_pem.trim());
+        Self::from_identity_pem(identity_pem.as_bytes())
+    }
+
+    /// Send pushes to `endpoint`.
+    pub fn with_endpoint(mut self, endpoint: &Endpoint) -> Self {
+        self.base_url = endpoint.base_url().to_string();
+        self
+    }
+
+    /// Limit the number of in-flight requests (at least one).
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Build the client from a PEM private key followed by its certificate.
+    fn from_identity_pem(identity_pem: &[u8]) -> color_eyre::eyre::Result<Self> {
+        let identity =
+            reqwest::Identity::from_pem(identity_pem).wrap_err("invalid push certificate")?;
+
+        let client = reqwest::Client::builder()
+            .use_rustls_tls()
+            .identity(identity)
+            .http2_prior_knowledge()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
             .wrap_err("failed to create APNs client")?;
 
         Ok(Self {
             client,
-            endpoint: a2::Endpoint::Sandbox,
+            base_url: Endpoint::Production.base_url().to_string(),
+            concurrency: DEFAULT_PUSH_CONCURRENCY,
         })
     }
 }
 
 impl Pusher for ApnsPusher {
     async fn push(&self, infos: &[&PushInfo]) -> Vec<PushResult> {
-        let mut results = Vec::with_capacity(infos.len());
-
-        for info in infos {
-            let result = self.push_single(info).await;
-            results.push(result);
-        }
+        use futures::StreamExt as _;
 
-        results
+        // Requests are multiplexed as streams over one HTTP/2 connection.
+        // Futures are created up front; mapping inside the stream would make
+        // the returned future not `Send`.
+        let pushes: Vec<_> = infos.iter().map(|info| self.push_single(info)).collect();
+        futures::stream::iter(pushes)
+            .buffer_unordered(self.concurrency)
+            .collect()
+            .await
     }
 }
 
+/// MDM push payload: `{"mdm": "<push_magic>"}`.
+#[derive(serde::Serialize)]
+struct MdmPayload<'a> {
+    mdm: &'a str,
+}
+
+/// APNs error response body.
+#[derive(serde::Deserialize)]
+struct ApnsError {
+    reason: String,
+}
+
 impl ApnsPusher {
     async fn push_single(&self, info: &PushInfo) -> PushResult {
-        // MDM push payload is just {"mdm": "<push_magic>"}
-        let body = format!(r#"{{"mdm":"{}"}}"#, info.push_magic);
-        let token = info.token_hex();
-
-        let payload = a2::DefaultNotificationBuilder::new().set_body(&body).build(
-            &token,
-            a2::NotificationOptions {
-                apns_topic: Some(&info.topic),
-                ..Default::default()
-            },
-        );
+        let url = format!("{}/3/device/{}", self.base_url, info.token_hex());
+
+        let response = self
+            .client
+            .post(url)
+            .header("apns-topic", &info.topic)
+            .json(&MdmPayload {
+                mdm: &info.push_magic,
+            })
+            .send()
+            .await;
 
-        match self.client.send(payload).await {
-            Ok(response) => {
-                let apns_id = response.apns_id.unwrap_or_default();
-                PushResult::success(token, apns_id)
+        let response = match response {
+            Ok(response) => response,
+            Err(e) => {
+                return PushResult::failure_kind(
+                    info.enrollment_id.clone(),
+                    PushErrorKind::Transport,
+                    e,
+                );
             }
-            Err(e) => PushResult::failure(token, e),
+        };
+
+        let status = response.status();
+        let apns_id = response
+            .headers()
+            .get("apns-id")
+            .and_then(|v| v.to_str().ok())
+            .map(String::from);
+
+        if status.is_success() {
+            return PushResult::success(info.enrollment_id.clone(), apns_id.unwrap_or_default());
         }
+
+        let reason = response
+            .json::<ApnsError>()
+            .await
+            .map(|body| body.reason)
+            .unwrap_or_default();
+        let kind = classify(status, &reason);
+        match kind {
+            PushErrorKind::Unregistered => {
+                tracing::info!(
+                    enrollment_id = %info.enrollment_id,
+                    %reason,
+                    "device token unregistered"
+                );
+            }
+            PushErrorKind::BadDeviceToken | PushErrorKind::DeviceTokenNotForTopic => {
+                tracing::warn!(
+                    enrollment_id = %info.enrollment_id,
+                    %reason,
+                    "APNs rejected device token; check the APNs environment and push topic"
+                );
+            }
+            _ => {}
+        }
+        PushResult::failure_kind(
+            info.enrollment_id.clone(),
+            kind,
+            format!("{status}: {reason}").trim_end_matches(": "),
+        )
+    }
+}
+
+/// Classify an APNs error response by its status and `reason`.
+fn classify(status: reqwest::StatusCode, reason: &str) -> PushErrorKind {
+    match reason {
+        "Unregistered" | "ExpiredToken" => PushErrorKind::Unregistered,
+        "BadDeviceToken" => PushErrorKind::BadDeviceToken,
+        "DeviceTokenNotForTopic" => PushErrorKind::DeviceTokenNotForTopic,
+        "BadCertificate" | "BadCertificateEnvironment" | "MissingProviderToken" => {
+            PushErrorKind::Certificate
+        }
+        "TooManyRequests" | "TooManyProviderTokenUpdates" => PushErrorKind::TooManyRequests,
+        _ if status == reqwest::StatusCode::GONE => PushErrorKind::Unregistered,
+        _ if status == reqwest::StatusCode::FORBIDDEN => PushErrorKind::Certificate,
+        _ if status == reqwest::StatusCode::TOO_MANY_REQUESTS => PushErrorKind::TooManyRequests,
+        _ if status.is_server_error() => PushErrorKind::ServerError,
+        _ => PushErrorKind::Other,
     }
 }
 
@@ -97,16 +271,502 @@ where
     }
 
     /// Push to enrollments by ID.
+    ///
+    /// IDs without push info get a [`NO_PUSH_INFO`] failure.
     pub async fn push_by_ids(
         &self,
         ids: &[&mdm_core::EnrollId],
     ) -> color_eyre::eyre::Result<Vec<PushResult>> {
-        let infos = self.store.get_push_infos(ids)?;
+        let infos = self.store.get_push_infos(ids).await?;
         let info_refs: Vec<&PushInfo> = infos.iter().collect();
 
-        Ok(self.pusher.push(&info_refs).await)
+        let mut results = self.pusher.push(&info_refs).await;
+        record_invalid_tokens(&self.store, &results).await;
+        for id in ids {
+            if !infos.iter().any(|info| info.enrollment_id == id.id) {
+                results.push(PushResult::failure_kind(
+                    id.id.clone(),
+                    PushErrorKind::NoPushInfo,
+                    NO_PUSH_INFO,
+                ));
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<S, P> PushProvider for PushService<S, P>
+where
+    S: mdm_storage::PushStore,
+    P: Pusher,
+{
+    async fn push_by_id(&self, ids: &[&str]) -> Vec<PushResult> {
+        let enroll_ids = device_ids(ids);
+        let id_refs: Vec<&EnrollId> = enroll_ids.iter().collect();
+
+        match self.push_by_ids(&id_refs).await {
+            Ok(results) => results,
+            Err(e) => lookup_failed(ids, &e),
+        }
+    }
+}
+
+/// Push provider with one APNs client per topic, built from stored push
+/// certificates.
+///
+/// Clients are created on first use. Each push compares the stored
+/// certificate with the one the cached client was built from, so a
+/// certificate replaced through `PUT /v1/pushcert` takes effect on the next
+/// push.
+pub struct ApnsProvider<S> {
+    store: S,
+    endpoint: Endpoint,
+    concurrency: usize,
+    /// Topic to the certificate PEM and the client built from it.
+    clients: Mutex<HashMap<String, (String, Arc<ApnsPusher>)>>,
+}
+
+impl<S> ApnsProvider<S>
+where
+    S: mdm_storage::PushStore + mdm_storage::PushCertStore,
+{
+    /// Create a provider that loads push certificates from `store`.
+    pub fn new(store: S) -> Self {
+        Self {
+            store,
+            endpoint: Endpoint::Production,
+            concurrency: DEFAULT_PUSH_CONCURRENCY,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Send pushes to `endpoint` instead of production.
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// Limit the number of in-flight requests per topic.
+    pub fn with_concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency;
+        self
+    }
+
+    /// Get the client for `topic`, building it if the stored certificate
+    /// is new or has changed.
+    async fn client(&self, topic: &str) -> color_eyre::eyre::Result<Arc<ApnsPusher>> {
+        let Some((cert_pem, key_pem)) = self.store.get_push_cert(topic).await? else {
+            color_eyre::eyre::bail!("no push certificate for topic {topic}");
+        };
+
+        let mut clients = self.clients.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((cached_pem, client)) = clients.get(topic)
+            && *cached_pem == cert_pem
+        {
+            return Ok(client.clone());
+        }
+
+        tracing::info!(topic = %topic, "loading APNs client");
+        let client = Arc::new(
+            ApnsPusher::from_pem(&cert_pem, &key_pem)?
+                .with_endpoint(&self.endpoint)
+                .with_concurrency(self.concurrency),
+        );
+        clients.insert(topic.to_string(), (cert_pem, client.clone()));
+        Ok(client)
+    }
+
+    /// Push to enrollments by ID, grouping them by topic.
+    ///
+    /// IDs without push info get a [`NO_PUSH_INFO`] failure.
+    pub async fn push_by_ids(
+        &self,
+        ids: &[&EnrollId],
+    ) -> color_eyre::eyre::Result<Vec<PushResult>> {
+        let infos = self.store.get_push_infos(ids).await?;
+
+        let mut by_topic: HashMap<&str, Vec<&PushInfo>> = HashMap::new();
+        for info in &infos {
+            by_topic.entry(info.topic.as_str()).or_default().push(info);
+        }
+
+        let pushes = by_topic.into_iter().map(|(topic, infos)| async move {
+            match self.client(topic).await {
+                Ok(client) => client.push(&infos).await,
+                Err(e) => {
+                    tracing::error!(topic = %topic, error = %e, "failed to load APNs client");
+                    infos
+                        .iter()
+                        .map(|info| {
+                            PushResult::failure_kind(
+                                info.enrollment_id.clone(),
+                                PushErrorKind::Certificate,
+                                format!("{e:#}"),
+                            )
+                        })
+                        .collect()
+                }
+            }
+        });
+        let mut results: Vec<PushResult> = futures::future::join_all(pushes)
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        record_invalid_tokens(&self.store, &results).await;
+
+        for id in ids {
+            if !infos.iter().any(|info| info.enrollment_id == id.id) {
+                results.push(PushResult::failure_kind(
+                    id.id.clone(),
+                    PushErrorKind::NoPushInfo,
+                    NO_PUSH_INFO,
+                ));
+            }
+        }
+
+        Ok(results)
+    }
+}
+
+impl<S> PushProvider for ApnsProvider<S>
+where
+    S: mdm_storage::PushStore + mdm_storage::PushCertStore,
+{
+    async fn push_by_id(&self, ids: &[&str]) -> Vec<PushResult> {
+        let enroll_ids = device_ids(ids);
+        let id_refs: Vec<&EnrollId> = enroll_ids.iter().collect();
+
+        match self.push_by_ids(&id_refs).await {
+            Ok(results) => results,
+            Err(e) => lookup_failed(ids, &e),
+        }
     }
 }
 
+/// Mark enrollments whose push token APNs rejected, so later fan-outs
+/// skip them until the device sends a new TokenUpdate.
+async fn record_invalid_tokens<S: mdm_storage::PushStore>(store: &S, results: &[PushResult]) {
+    for result in results {
+        if !result
+            .error_kind
+            .is_some_and(PushErrorKind::is_invalid_token)
+        {
+            continue;
+        }
+
+        let id = EnrollId {
+            enroll_type: EnrollType::Device,
+            id: result.enrollment_id.clone(),
+            parent_id: None,
+        };
+        if let Err(e) = store.mark_push_invalid(&id).await {
+            tracing::error!(
+                enrollment_id = %id.id,
+                error = %e,
+                "failed to mark push token invalid"
+            );
+        }
+    }
+}
+
+/// Device enrollment IDs for raw ID strings.
+fn device_ids(ids: &[&str]) -> Vec<EnrollId> {
+    ids.iter()
+        .map(|id| EnrollId {
+            enroll_type: EnrollType::Device,
+            id: id.to_string(),
+            parent_id: None,
+        })
+        .collect()
+}
+
+/// Fail every ID after push info could not be loaded.
+fn lookup_failed(ids: &[&str], e: &color_eyre::Report) -> Vec<PushResult> {
+    tracing::error!(error = %e, "failed to look up push info");
+    ids.iter()
+        .map(|id| PushResult::failure(id.to_string(), format!("{e:#}")))
+        .collect()
+}
+
 // Re-export for convenience
 pub use mdm_storage;
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use axum::extract::{Path, State};
+    use axum::http::{HeaderMap, StatusCode};
+    use axum::response::IntoResponse;
+    use mdm_storage::PushCertStore as _;
+
+    use super::*;
+
+    const TOPIC: &str = "com.apple.mgmt.External.moonstone-test";
+
+    /// Push store with fixed push info and one replaceable certificate for
+    /// [`TOPIC`].
+    struct MemoryStore {
+        infos: Vec<PushInfo>,
+        cert: Mutex<(String, String)>,
+        invalid: Mutex<Vec<String>>,
+    }
+
+    impl MemoryStore {
+        fn new(infos: Vec<PushInfo>) -> Self {
+            Self {
+                infos,
+                cert: Mutex::new((
+                    include_str!("../../crypto/testdata/push.pem").to_string(),
+                    include_str!("../../crypto/testdata/push.key").to_string(),
+                )),
+                invalid: Mutex::new(Vec::new()),
+            }
+        }
+    }
+
+    impl mdm_storage::PushStore for MemoryStore {
+        async fn get_push_info(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<PushInfo>> {
+            Ok(self
+                .infos
+                .iter()
+                .find(|i| i.enrollment_id == id.id)
+                .cloned())
+        }
+
+        async fn get_push_infos(
+            &self,
+            ids: &[&EnrollId],
+        ) -> color_eyre::eyre::Result<Vec<PushInfo>> {
+            Ok(self
+                .infos
+                .iter()
+                .filter(|i| ids.iter().any(|id| id.id == i.enrollment_id))
+                .cloned()
+                .collect())
+        }
+
+        async fn mark_push_invalid(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+            self.invalid.lock().unwrap().push(id.id.clone());
+            Ok(())
+        }
+    }
+
+    impl mdm_storage::PushCertStore for MemoryStore {
+        async fn store_push_cert(
+            &self,
+            _topic: &str,
+            cert_pem: &str,
+            key_pem: &str,
+        ) -> color_eyre::eyre::Result<()> {
+            *self.cert.lock().unwrap() = (cert_pem.to_string(), key_pem.to_string());
+            Ok(())
+        }
+
+        async fn get_push_cert(
+            &self,
+            topic: &str,
+        ) -> color_eyre::eyre::Result<Option<(String, String)>> {
+            Ok((topic == TOPIC).then(|| self.cert.lock().unwrap().clone()))
+        }
+
+        async fn list_push_topics(&self) -> color_eyre::eyre::Result<Vec<String>> {
+            Ok(vec![TOPIC.to_string()])
+        }
+
+        async fn list_push_certs(
+            &self,
+        ) -> color_eyre::eyre::Result<Vec<mdm_core::PushCertSummary>> {
+            Ok(vec![mdm_core::PushCertSummary {
+                topic: TOPIC.to_string(),
+                not_after: None,
+            }])
+        }
+    }
+
+    fn push_info(enrollment_id: &str, token: &[u8]) -> PushInfo {
+        PushInfo {
+            enrollment_id: enrollment_id.to_string(),
+            token: token.to_vec(),
+            push_magic: format!("magic-{enrollment_id}"),
+            topic: TOPIC.to_string(),
+        }
+    }
+
+    /// Current and peak number of requests the mock is handling.
+    #[derive(Default)]
+    struct InFlight {
+        current: AtomicUsize,
+        peak: AtomicUsize,
+    }
+
+    /// Mock APNs: token `dead` is unregistered, `bad` is malformed, and
+    /// everything else succeeds after a short delay.
+    async fn mock_device(
+        State(in_flight): State<Arc<InFlight>>,
+        Path(token): Path<String>,
+        headers: HeaderMap,
+        body: String,
+    ) -> axum::response::Response {
+        assert_eq!(headers["apns-topic"], TOPIC);
+        assert!(body.contains("\"mdm\":\"magic-"), "{body}");
+
+        let current = in_flight.current.fetch_add(1, Ordering::SeqCst) + 1;
+        in_flight.peak.fetch_max(current, Ordering::SeqCst);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        in_flight.current.fetch_sub(1, Ordering::SeqCst);
+
+        match token.as_str() {
+            "dead" => (StatusCode::GONE, r#"{"reason":"Unregistered"}"#).into_response(),
+            "0bad" => (StatusCode::BAD_REQUEST, r#"{"reason":"BadDeviceToken"}"#).into_response(),
+            _ => (StatusCode::OK, [("apns-id", format!("apns-{token}"))]).into_response(),
+        }
+    }
+
+    /// Start a cleartext HTTP/2 mock APNs server and return its endpoint.
+    async fn mock_apns() -> (Endpoint, Arc<InFlight>) {
+        let in_flight = Arc::new(InFlight::default());
+        let app = axum::Router::new()
+            .route("/3/device/{token}", axum::routing::post(mock_device))
+            .with_state(in_flight.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        (Endpoint::Custom(format!("http://{addr}/")), in_flight)
+    }
+
+    fn assert_results(results: &[PushResult]) {
+        let result = |id: &str| results.iter().find(|r| r.enrollment_id == id).unwrap();
+
+        assert_eq!(results.len(), 3);
+        assert_eq!(result("device-a").apns_id.as_deref(), Some("apns-aa"));
+        assert!(result("device-a").is_success());
+        assert!(
+            result("device-b")
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("Unregistered")
+        );
+        assert_eq!(
+            result("device-b").error_kind,
+            Some(PushErrorKind::Unregistered)
+        );
+        assert_eq!(result("device-c").error.as_deref(), Some(NO_PUSH_INFO));
+        assert_eq!(
+            result("device-c").error_kind,
+            Some(PushErrorKind::NoPushInfo)
+        );
+    }
+
+    fn devices() -> Vec<PushInfo> {
+        vec![
+            push_info("device-a", &[0xaa]),
+            push_info("device-b", &[0xde, 0xad]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_push_service() {
+        let store = MemoryStore::new(devices());
+        let (cert_pem, key_pem) = store.get_push_cert(TOPIC).await.unwrap().unwrap();
+        let pusher = ApnsPusher::from_pem(&cert_pem, &key_pem)
+            .unwrap()
+            .with_endpoint(&mock_apns().await.0);
+        let service = PushService::new(store, pusher);
+
+        let results = service
+            .push_by_id(&["device-a", "device-b", "device-c"])
+            .await;
+        assert_results(&results);
+        assert_eq!(*service.store.invalid.lock().unwrap(), ["device-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_provider_push() {
+        let provider =
+            ApnsProvider::new(MemoryStore::new(devices())).with_endpoint(mock_apns().await.0);
+
+        let results = provider
+            .push_by_id(&["device-a", "device-b", "device-c"])
+            .await;
+        assert_results(&results);
+        assert_eq!(*provider.store.invalid.lock().unwrap(), ["device-b"]);
+    }
+
+    #[tokio::test]
+    async fn test_bounded_fan_out() {
+        let mut infos: Vec<PushInfo> = (0..20u8)
+            .map(|i| push_info(&format!("device-{i}"), &[i]))
+            .collect();
+        infos.push(push_info("device-bad", &[0x0b, 0xad]));
+        let ids: Vec<String> = infos.iter().map(|i| i.enrollment_id.clone()).collect();
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+
+        let (endpoint, in_flight) = mock_apns().await;
+        let provider = ApnsProvider::new(MemoryStore::new(infos))
+            .with_endpoint(endpoint)
+            .with_concurrency(4);
+        let results = provider.push_by_id(&ids).await;
+
+        assert_eq!(results.len(), ids.len());
+        for id in &ids {
+            let result = results.iter().find(|r| r.enrollment_id == *id).unwrap();
+            if *id == "device-bad" {
+                assert_eq!(result.error_kind, Some(PushErrorKind::BadDeviceToken));
+            } else {
+                assert!(result.is_success(), "{result:?}");
+            }
+        }
+        assert!(in_flight.peak.load(Ordering::SeqCst) <= 4);
+        // A malformed token may mean the wrong environment, so it stays valid
+        assert!(provider.store.invalid.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_provider_rebuilds_client_on_cert_change() {
+        let provider = ApnsProvider::new(MemoryStore::new(Vec::new()));
+
+        let first = provider.client(TOPIC).await.unwrap();
+        assert!(Arc::ptr_eq(&first, &provider.client(TOPIC).await.unwrap()));
+        assert!(provider.client("com.apple.mgmt.other").await.is_err());
+
+        provider
+            .store
+            .store_push_cert(
+                TOPIC,
+                include_str!("../../crypto/testdata/device-rsa.pem"),
+                include_str!("../../crypto/testdata/device-rsa.key"),
+            )
+            .await
+            .unwrap();
+        assert!(!Arc::ptr_eq(&first, &provider.client(TOPIC).await.unwrap()));
+    }
+
+    #[test]
+    fn test_pkcs12() {
+        for p12 in [
+            include_bytes!("../../crypto/testdata/push.p12").as_slice(),
+            include_bytes!("../../crypto/testdata/push-legacy.p12"),
+        ] {
+            ApnsPusher::new(p12, "moonstone").unwrap();
+            assert!(ApnsPusher::sandbox(p12, "wrong").is_err());
+        }
+        assert!(ApnsPusher::new(b"not pkcs12", "moonstone").is_err());
+    }
+
+    #[test]
+    fn test_endpoint_from_str() {
+        assert_eq!("sandbox".parse::<Endpoint>().unwrap(), Endpoint::Sandbox);
+        assert_eq!(
+            "http://localhost:2197/"
+                .parse::<Endpoint>()
+                .unwrap()
+                .base_url(),
+            "http://localhost:2197"
+        );
+        assert!("staging".parse::<Endpoint>().is_err());
+    }
+}
@@ -0,0 +1,119 @@
+//! Push certificate expiry monitoring.
+
+use std::time::Duration;
+
+use mdm_core::PushCertSummary;
+use mdm_storage::PushCertStore;
+
+/// Push certificates that expire before `now + within`, including expired
+/// ones. Certificates with unknown expiry are skipped.
+pub async fn expiring_push_certs<S>(
+    store: &S,
+    within: Duration,
+    now: chrono::DateTime<chrono::Utc>,
+) -> color_eyre::eyre::Result<Vec<PushCertSummary>>
+where
+    S: PushCertStore,
+{
+    let deadline = now + within;
+    Ok(store
+        .list_push_certs()
+        .await?
+        .into_iter()
+        .filter(|cert| {
+            cert.not_after
+                .is_some_and(|not_after| not_after <= deadline)
+        })
+        .collect())
+}
+
+/// Periodically warn about push certificates that expire within `within`.
+///
+/// An expired push certificate makes every push fail, so this logs at
+/// `error` once a certificate has expired and `warn` before that. Runs
+/// forever; spawn it as a background task.
+pub async fn watch_push_cert_expiry<S>(store: S, within: Duration, interval: Duration)
+where
+    S: PushCertStore,
+{
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+
+        let now = chrono::Utc::now();
+        let certs = match expiring_push_certs(&store, within, now).await {
+            Ok(certs) => certs,
+            Err(e) => {
+                tracing::error!(error = %e, "failed to check push cert expiry");
+                continue;
+            }
+        };
+
+        for cert in certs {
+            let Some(not_after) = cert.not_after else {
+                continue;
+            };
+            if not_after <= now {
+                tracing::error!(topic = %cert.topic, %not_after, "push certificate has expired");
+            } else {
+                let days = (not_after - now).num_days();
+                tracing::warn!(
+                    topic = %cert.topic,
+                    %not_after,
+                    days,
+                    "push certificate expires soon"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Certificate summaries with fixed expiry dates; PEMs aren't kept.
+    struct FixedCerts(Vec<PushCertSummary>);
+
+    impl PushCertStore for FixedCerts {
+        async fn store_push_cert(&self, _: &str, _: &str, _: &str) -> color_eyre::eyre::Result<()> {
+            Ok(())
+        }
+
+        async fn get_push_cert(
+            &self,
+            _: &str,
+        ) -> color_eyre::eyre::Result<Option<(String, String)>> {
+            Ok(None)
+        }
+
+        async fn list_push_topics(&self) -> color_eyre::eyre::Result<Vec<String>> {
+            Ok(self.0.iter().map(|c| c.topic.clone()).collect())
+        }
+
+        async fn list_push_certs(&self) -> color_eyre::eyre::Result<Vec<PushCertSummary>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_expiring_push_certs() {
+        let now = chrono::Utc::now();
+        let cert = |topic: &str, days: Option<i64>| PushCertSummary {
+            topic: topic.to_string(),
+            not_after: days.map(|d| now + chrono::Duration::days(d)),
+        };
+        let store = FixedCerts(vec![
+            cert("expired", Some(-1)),
+            cert("soon", Some(10)),
+            cert("later", Some(100)),
+            cert("unknown", None),
+        ]);
+
+        let expiring = expiring_push_certs(&store, Duration::from_secs(30 * 24 * 60 * 60), now)
+            .await
+            .unwrap();
+        let topics: Vec<&str> = expiring.iter().map(|c| c.topic.as_str()).collect();
+        assert_eq!(topics, ["expired", "soon"]);
+    }
+}
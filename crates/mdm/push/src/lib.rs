@@ -3,7 +3,9 @@
 //! APNs push notification delivery for MDM.
 
 mod apns;
+mod expiry;
 mod traits;
 
 pub use apns::*;
+pub use expiry::*;
 pub use traits::*;
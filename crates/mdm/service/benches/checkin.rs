@@ -0,0 +1,124 @@
+//! Check-in throughput under concurrent load.
+//!
+//! Each check-in is Authenticate, TokenUpdate, and an Idle command poll for
+//! its own device, all driven through `NanoMdm` on a multi-threaded runtime.
+//!
+//! `checkin/sqlite` uses `SqliteStorage::new`'s single connection and is the
+//! baseline for `checkin/sqlite-pool{n}`, which shows what a larger pool
+//! (`SQLITE_POOL_SIZE`) buys at each concurrency level.
+
+use criterion::{BenchmarkId, Criterion, Throughput, criterion_group, criterion_main};
+use mdm_core::{
+    Authenticate, CommandResults, CommandStatus, EnrollId, EnrollType, Enrollment, Request,
+    TokenUpdate,
+};
+use mdm_service::{Checkin as _, CommandAndReportResults as _, NanoMdm};
+use mdm_storage::{AllStorage, InMemoryStorage, SqliteStorage};
+
+const CONCURRENCY: [usize; 3] = [1, 16, 64];
+
+async fn checkin<S: AllStorage>(service: &NanoMdm<S>, device: usize) {
+    let req = Request::new().with_enroll_id(EnrollId {
+        enroll_type: EnrollType::Device,
+        id: format!("device-{device}"),
+        parent_id: None,
+    });
+
+    let authenticate = Authenticate {
+        enrollment: Enrollment::default(),
+        topic: "com.apple.mgmt.bench".to_string(),
+        build_version: None,
+        os_version: None,
+        product_name: None,
+        serial_number: None,
+        device_name: None,
+        model: None,
+        model_name: None,
+        raw: Vec::new(),
+    };
+    service.authenticate(&req, &authenticate).await.unwrap();
+
+    let token_update = TokenUpdate {
+        enrollment: Enrollment::default(),
+        topic: "com.apple.mgmt.bench".to_string(),
+        token: vec![0xaa; 32],
+        push_magic: "magic".to_string(),
+        unlock_token: None,
+        awaiting_configuration: false,
+        raw: Vec::new(),
+    };
+    service.token_update(&req, &token_update).await.unwrap();
+
+    let idle = CommandResults {
+        enrollment: Enrollment::default(),
+        command_uuid: String::new(),
+        status: CommandStatus::Idle,
+        error_chain: Vec::new(),
+        raw: Vec::new(),
+    };
+    service
+        .command_and_report_results(&req, &idle)
+        .await
+        .unwrap();
+}
+
+fn bench_store<S: AllStorage + Clone + 'static>(c: &mut Criterion, name: &str, store: S) {
+    let rt = tokio::runtime::Builder::new_multi_thread()
+        .worker_threads(4)
+        .enable_all()
+        .build()
+        .unwrap();
+    let service = NanoMdm::new(store);
+
+    let mut group = c.benchmark_group(format!("checkin/{name}"));
+    for concurrency in CONCURRENCY {
+        group.throughput(Throughput::Elements(concurrency as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &concurrency,
+            |b, &concurrency| {
+                b.to_async(&rt).iter(|| {
+                    futures::future::join_all(
+                        (0..concurrency).map(|device| checkin(&service, device)),
+                    )
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Larger SQLite pool sizes compared against the single-connection baseline.
+const POOL_SIZES: [u32; 2] = [4, 16];
+
+fn bench_checkin(c: &mut Criterion) {
+    bench_store(c, "memory", InMemoryStorage::new());
+
+    bench_sqlite(c, "sqlite", SqliteStorage::new);
+    for pool_size in POOL_SIZES {
+        bench_sqlite(c, &format!("sqlite-pool{pool_size}"), |path| {
+            SqliteStorage::with_pool_size(path, pool_size)
+        });
+    }
+}
+
+/// Benchmark a fresh SQLite database from `open`, then delete it.
+fn bench_sqlite(
+    c: &mut Criterion,
+    name: &str,
+    open: impl FnOnce(&str) -> color_eyre::eyre::Result<SqliteStorage>,
+) {
+    let path = std::env::temp_dir().join(format!("mdm-bench-{}-{name}.db", std::process::id()));
+    let path = path.to_str().unwrap();
+    let sqlite = open(path).unwrap();
+    sqlite.run_migrations().unwrap();
+    bench_store(c, name, sqlite);
+
+    // WAL mode leaves the log and shared-memory files next to the database
+    for suffix in ["", "-wal", "-shm"] {
+        let _ = std::fs::remove_file(format!("{path}{suffix}"));
+    }
+}
+
+criterion_group!(benches, bench_checkin);
+criterion_main!(benches);
@@ -16,12 +16,27 @@ use crate::{Checkin, CommandAndReportResults};
 pub struct CertAuthService<S, I> {
     store: S,
     inner: I,
+    legacy_rehash: bool,
 }
 
 impl<S, I> CertAuthService<S, I> {
     /// Create a new cert auth service.
     pub fn new(store: S, inner: I) -> Self {
-        Self { store, inner }
+        Self {
+            store,
+            inner,
+            legacy_rehash: true,
+        }
+    }
+
+    /// Set whether certificates stored with the legacy XOR hash are accepted.
+    ///
+    /// Enabled by default so devices enrolled before the switch to SHA-256
+    /// keep working; matching rows are re-hashed on their next check-in.
+    /// Disable once every enrollment has checked in.
+    pub fn with_legacy_rehash(mut self, enabled: bool) -> Self {
+        self.legacy_rehash = enabled;
+        self
     }
 }
 
@@ -29,7 +44,7 @@ impl<S, I> CertAuthService<S, I>
 where
     S: CertAuthStore,
 {
-    fn validate_cert(&self, req: &Request) -> color_eyre::eyre::Result<()> {
+    async fn validate_cert(&self, req: &Request) -> color_eyre::eyre::Result<()> {
         let id = req.require_enroll_id()?;
 
         let cert = req
@@ -39,11 +54,24 @@ where
 
         let cert_hash = mdm_crypto::cert_hash(cert);
 
-        if !self.store.has_cert_auth(id, &cert_hash)? {
-            color_eyre::eyre::bail!("certificate not authorized for enrollment {}", id.id);
+        if self.store.has_cert_auth(id, &cert_hash).await? {
+            return Ok(());
         }
 
-        Ok(())
+        if self.legacy_rehash {
+            let legacy_hash = mdm_crypto::legacy_cert_hash(cert);
+            if self
+                .store
+                .rehash_legacy_cert(id, &legacy_hash, &cert_hash)
+                .await
+                .wrap_err("failed to rehash legacy certificate")?
+            {
+                tracing::info!(enrollment_id = %id.id, "re-hashed legacy certificate association");
+                return Ok(());
+            }
+        }
+
+        color_eyre::eyre::bail!("certificate not authorized for enrollment {}", id.id);
     }
 }
 
@@ -58,6 +86,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
             let cert_hash = mdm_crypto::cert_hash(cert);
             self.store
                 .associate_cert(id, &cert_hash)
+                .await
                 .wrap_err("failed to associate certificate")?;
         }
 
@@ -65,12 +94,12 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
     }
 
     async fn token_update(&self, req: &Request, msg: &TokenUpdate) -> color_eyre::eyre::Result<()> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.token_update(req, msg).await
     }
 
     async fn checkout(&self, req: &Request, msg: &CheckOut) -> color_eyre::eyre::Result<()> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.checkout(req, msg).await
     }
 
@@ -79,7 +108,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
         req: &Request,
         msg: &UserAuthenticate,
     ) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.user_authenticate(req, msg).await
     }
 
@@ -88,7 +117,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
         req: &Request,
         msg: &SetBootstrapToken,
     ) -> color_eyre::eyre::Result<()> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.set_bootstrap_token(req, msg).await
     }
 
@@ -97,7 +126,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
         req: &Request,
         msg: &GetBootstrapToken,
     ) -> color_eyre::eyre::Result<Option<BootstrapTokenResponse>> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.get_bootstrap_token(req, msg).await
     }
 
@@ -106,7 +135,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
         req: &Request,
         msg: &DeclarativeManagement,
     ) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.declarative_management(req, msg).await
     }
 
@@ -115,7 +144,7 @@ impl<S: CertAuthStore, I: Checkin> Checkin for CertAuthService<S, I> {
         req: &Request,
         msg: &GetToken,
     ) -> color_eyre::eyre::Result<Option<GetTokenResponse>> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.get_token(req, msg).await
     }
 }
@@ -128,7 +157,100 @@ impl<S: CertAuthStore, I: CommandAndReportResults> CommandAndReportResults
         req: &Request,
         results: &CommandResults,
     ) -> color_eyre::eyre::Result<Option<Command>> {
-        self.validate_cert(req)?;
+        self.validate_cert(req).await?;
         self.inner.command_and_report_results(req, results).await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use mdm_core::{EnrollId, EnrollType};
+
+    use super::*;
+
+    /// Cert auth rows as `(enrollment ID, hash, algorithm)`.
+    #[derive(Default)]
+    struct MemoryCertAuth(Mutex<Vec<(String, Vec<u8>, &'static str)>>);
+
+    impl CertAuthStore for MemoryCertAuth {
+        async fn associate_cert(
+            &self,
+            id: &EnrollId,
+            cert_hash: &[u8],
+        ) -> color_eyre::eyre::Result<()> {
+            let mut rows = self.0.lock().unwrap();
+            rows.push((id.id.clone(), cert_hash.to_vec(), "sha256"));
+            Ok(())
+        }
+
+        async fn has_cert_auth(
+            &self,
+            id: &EnrollId,
+            cert_hash: &[u8],
+        ) -> color_eyre::eyre::Result<bool> {
+            let rows = self.0.lock().unwrap();
+            Ok(rows
+                .iter()
+                .any(|(e, h, a)| *e == id.id && h == cert_hash && *a == "sha256"))
+        }
+
+        async fn rehash_legacy_cert(
+            &self,
+            id: &EnrollId,
+            legacy_hash: &[u8],
+            cert_hash: &[u8],
+        ) -> color_eyre::eyre::Result<bool> {
+            let mut rows = self.0.lock().unwrap();
+            let row = rows
+                .iter_mut()
+                .find(|(e, h, a)| *e == id.id && h == legacy_hash && *a == "xor");
+            Ok(row
+                .map(|row| *row = (id.id.clone(), cert_hash.to_vec(), "sha256"))
+                .is_some())
+        }
+    }
+
+    fn request(cert: &[u8]) -> Request {
+        Request::new()
+            .with_enroll_id(EnrollId {
+                enroll_type: EnrollType::Device,
+                id: "device-1".into(),
+                parent_id: None,
+            })
+            .with_certificate(cert.to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_legacy_hash_is_rehashed() {
+        let cert = b"device certificate";
+        let store = MemoryCertAuth::default();
+        store.0.lock().unwrap().push((
+            "device-1".into(),
+            mdm_crypto::legacy_cert_hash(cert).to_vec(),
+            "xor",
+        ));
+
+        let service = CertAuthService::new(store, ());
+        service.validate_cert(&request(cert)).await.unwrap();
+
+        let rows = service.store.0.lock().unwrap();
+        assert_eq!(rows[0].1, mdm_crypto::cert_hash(cert));
+        assert_eq!(rows[0].2, "sha256");
+    }
+
+    #[tokio::test]
+    async fn test_legacy_hash_rejected_without_transition() {
+        let cert = b"device certificate";
+        let store = MemoryCertAuth::default();
+        store.0.lock().unwrap().push((
+            "device-1".into(),
+            mdm_crypto::legacy_cert_hash(cert).to_vec(),
+            "xor",
+        ));
+
+        let service = CertAuthService::new(store, ()).with_legacy_rehash(false);
+        assert!(service.validate_cert(&request(cert)).await.is_err());
+    }
+}
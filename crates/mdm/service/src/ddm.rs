@@ -0,0 +1,106 @@
+//! Declarative Device Management endpoints.
+
+use color_eyre::eyre::WrapErr as _;
+use mdm_core::{
+    DdmEndpoint, DeclarationItemsResponse, DeclarativeManagement, EnrollId, StatusReport,
+    TokensResponse,
+};
+use mdm_storage::{DeclarationStore, StatusItemStore};
+
+/// The device asked for a declaration that isn't assigned to it, or isn't of
+/// the kind it asked for.
+///
+/// Returned by DeclarativeManagement check-ins inside the error report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeclarationNotFound {
+    pub enrollment_id: String,
+    pub identifier: String,
+}
+
+impl std::fmt::Display for DeclarationNotFound {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "no declaration {} for {}",
+            self.identifier, self.enrollment_id
+        )
+    }
+}
+
+impl std::error::Error for DeclarationNotFound {}
+
+/// Respond to a DeclarativeManagement check-in with the JSON the endpoint
+/// asks for.
+///
+/// Devices only get the declarations assigned to their enrollment. The
+/// `status` endpoint has no response body.
+pub(crate) async fn respond<S: DeclarationStore + StatusItemStore>(
+    store: &S,
+    id: &EnrollId,
+    msg: &DeclarativeManagement,
+) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+    let Some(endpoint) = msg.endpoint.as_deref() else {
+        color_eyre::eyre::bail!("DeclarativeManagement message has no Endpoint");
+    };
+
+    let body = match endpoint.parse::<DdmEndpoint>()? {
+        DdmEndpoint::Tokens => {
+            let assigned = store.assigned_declarations(id).await?;
+            serde_json::to_vec(&TokensResponse::new(&assigned))?
+        }
+        DdmEndpoint::DeclarationItems => {
+            let assigned = store.assigned_declarations(id).await?;
+            serde_json::to_vec(&DeclarationItemsResponse::new(&assigned))?
+        }
+        DdmEndpoint::Declaration { kind, identifier } => {
+            let assigned = store.assigned_declarations(id).await?;
+            let Some(record) = assigned
+                .into_iter()
+                .find(|r| r.declaration.identifier == identifier)
+                .filter(|r| r.declaration.kind() == Some(kind))
+            else {
+                return Err(DeclarationNotFound {
+                    enrollment_id: id.id.clone(),
+                    identifier,
+                }
+                .into());
+            };
+            serde_json::to_vec(&record.declaration)?
+        }
+        DdmEndpoint::Status => {
+            let Some(data) = msg.data.as_deref() else {
+                color_eyre::eyre::bail!("DDM status report has no Data");
+            };
+            store_status(store, id, &StatusReport::parse(data)?).await?;
+            return Ok(None);
+        }
+    };
+    Ok(Some(body))
+}
+
+async fn store_status<S: DeclarationStore + StatusItemStore>(
+    store: &S,
+    id: &EnrollId,
+    report: &StatusReport,
+) -> color_eyre::eyre::Result<()> {
+    for error in &report.errors {
+        tracing::warn!(
+            enrollment_id = %id.id,
+            status_item = %error.status_item,
+            reasons = ?error.reasons,
+            "device failed to report DDM status item"
+        );
+    }
+
+    store
+        .store_status_items(id, &report.items())
+        .await
+        .wrap_err("failed to store status items")?;
+    if let Some(statuses) = report.declarations()? {
+        store
+            .store_declaration_statuses(id, &statuses)
+            .await
+            .wrap_err("failed to store declaration statuses")?;
+    }
+    Ok(())
+}
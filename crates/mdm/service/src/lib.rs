@@ -3,11 +3,13 @@
 //! Business logic for handling MDM check-ins and commands.
 
 mod certauth;
+mod ddm;
 mod multi;
 mod nanomdm;
 mod traits;
 
 pub use certauth::CertAuthService;
+pub use ddm::DeclarationNotFound;
 pub use multi::MultiService;
 pub use nanomdm::NanoMdm;
 pub use traits::*;
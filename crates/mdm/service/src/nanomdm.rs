@@ -2,8 +2,9 @@
 
 use color_eyre::eyre::WrapErr as _;
 use mdm_core::{
-    Authenticate, BootstrapTokenResponse, CheckOut, Command, CommandResults, DeclarativeManagement,
-    GetBootstrapToken, GetToken, GetTokenResponse, Request, SetBootstrapToken, TokenUpdate,
+    Authenticate, BootstrapTokenResponse, CheckOut, Command, CommandResults, CommandStatus,
+    DeclarativeManagement, GetBootstrapToken, GetToken, GetTokenResponse, InventoryUpdate,
+    QueryResponses, QueuedCommand, Request, SecurityInfoReport, SetBootstrapToken, TokenUpdate,
     UserAuthenticate,
 };
 use mdm_storage::AllStorage;
@@ -23,6 +24,67 @@ impl<S> NanoMdm<S> {
     }
 }
 
+/// The parts of command results that feed the device inventory.
+///
+/// DeviceInformation and SecurityInfo results are told apart by their
+/// payload key, which saves looking up the command's request type.
+#[derive(serde::Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct InventoryResults {
+    #[serde(default)]
+    query_responses: Option<QueryResponses>,
+    #[serde(default)]
+    security_info: Option<SecurityInfoReport>,
+}
+
+/// Inventory reported by command results, plus the check-in itself.
+fn reported_inventory(results: &CommandResults) -> InventoryUpdate {
+    let mut update = InventoryUpdate::default();
+    if results.status == CommandStatus::Acknowledged && !results.raw.is_empty() {
+        match plist::from_bytes::<InventoryResults>(&results.raw) {
+            Ok(InventoryResults {
+                query_responses: Some(info),
+                ..
+            }) => update = InventoryUpdate::from(&info),
+            Ok(InventoryResults {
+                security_info: Some(info),
+                ..
+            }) => update = InventoryUpdate::from(&info),
+            Ok(_) => {}
+            Err(e) => tracing::warn!(
+                error = %e,
+                command_uuid = %results.command_uuid,
+                "unreadable inventory in command results"
+            ),
+        }
+    }
+    update.last_checkin_at = Some(chrono::Utc::now());
+    update
+}
+
+/// Parse a queued command for delivery.
+///
+/// Rows queued by earlier releases have a UUID different from the plist's,
+/// and under the `CommandUuid` key; results must match the row.
+fn delivered_command(queued: QueuedCommand) -> color_eyre::eyre::Result<Command> {
+    let mut cmd: Command =
+        plist::from_bytes(&queued.command).wrap_err("failed to parse stored command")?;
+    cmd.command_uuid = queued.uuid;
+    Ok(cmd)
+}
+
+impl<S: AllStorage> NanoMdm<S> {
+    /// Record inventory for a device, logging failures.
+    ///
+    /// Inventory is informational, so failing to store it mustn't fail the
+    /// check-in or hold back the next command.
+    async fn update_inventory(&self, device_id: &str, update: &InventoryUpdate) {
+        if let Err(e) = self.store.update_inventory(device_id, update).await {
+            tracing::error!(device_id = %device_id, error = %e, "failed to update inventory");
+        }
+    }
+}
+
 impl<S: AllStorage> Checkin for NanoMdm<S> {
     async fn authenticate(
         &self,
@@ -36,13 +98,21 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
         // Clear bootstrap token on re-enrollment
         self.store
             .delete_bootstrap_token(id)
+            .await
             .wrap_err("failed to delete bootstrap token")?;
 
         // Store authenticate and disable until TokenUpdate
         self.store
             .store_authenticate(id, msg)
+            .await
             .wrap_err("failed to store authenticate")?;
 
+        let update = InventoryUpdate {
+            last_checkin_at: Some(chrono::Utc::now()),
+            ..InventoryUpdate::from(msg)
+        };
+        self.update_inventory(id.device_id(), &update).await;
+
         Ok(())
     }
 
@@ -53,8 +123,15 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
 
         self.store
             .store_token_update(id, msg)
+            .await
             .wrap_err("failed to store token update")?;
 
+        self.update_inventory(
+            id.device_id(),
+            &InventoryUpdate::checkin(chrono::Utc::now()),
+        )
+        .await;
+
         Ok(())
     }
 
@@ -65,6 +142,7 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
 
         self.store
             .store_checkout(id, msg)
+            .await
             .wrap_err("failed to store checkout")?;
 
         Ok(())
@@ -94,6 +172,7 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
 
         self.store
             .store_bootstrap_token(id, &msg.bootstrap_token)
+            .await
             .wrap_err("failed to store bootstrap token")?;
 
         Ok(())
@@ -111,6 +190,7 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
         let token = self
             .store
             .get_bootstrap_token(id)
+            .await
             .wrap_err("failed to get bootstrap token")?;
 
         Ok(token.map(|t| BootstrapTokenResponse { bootstrap_token: t }))
@@ -129,9 +209,7 @@ impl<S: AllStorage> Checkin for NanoMdm<S> {
             "processing declarative management"
         );
 
-        // DDM handling would go here
-        // For now, return empty response
-        Ok(None)
+        crate::ddm::respond(&self.store, id, msg).await
     }
 
     async fn get_token(
@@ -171,13 +249,20 @@ impl<S: AllStorage> CommandAndReportResults for NanoMdm<S> {
 
             self.store
                 .store_result(id, results)
+                .await
                 .wrap_err("failed to store command results")?;
         }
 
-        // Get next pending command
+        self.update_inventory(id.device_id(), &reported_inventory(results))
+            .await;
+
+        // A device that answered NotNow (or anything else) is busy, so only
+        // retry NotNow commands once it reports Idle
+        let skip_not_now = results.status != CommandStatus::Idle;
         let next = self
             .store
-            .next_command(id)
+            .next_command(id, skip_not_now)
+            .await
             .wrap_err("failed to get next command")?;
 
         if let Some(queued) = next {
@@ -187,13 +272,162 @@ impl<S: AllStorage> CommandAndReportResults for NanoMdm<S> {
                 "sending next command"
             );
 
-            // Parse the stored command
-            let cmd: Command =
-                plist::from_bytes(&queued.command).wrap_err("failed to parse stored command")?;
-
-            return Ok(Some(cmd));
+            return delivered_command(queued).map(Some);
         }
 
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use mdm_core::{CommandStatus, EnrollId, EnrollType, Enrollment};
+    use mdm_storage::{CheckinStore as _, CommandStore as _, InMemoryStorage, InventoryStore as _};
+
+    use super::*;
+
+    fn results(command_uuid: &str, status: CommandStatus) -> CommandResults {
+        CommandResults {
+            enrollment: Enrollment::default(),
+            command_uuid: command_uuid.to_string(),
+            status,
+            error_chain: Vec::new(),
+            raw: Vec::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_enroll_and_deliver_command() {
+        let store = InMemoryStorage::new();
+        let service = NanoMdm::new(store.clone());
+        let id = EnrollId {
+            enroll_type: EnrollType::Device,
+            id: "device-1".to_string(),
+            parent_id: None,
+        };
+        let req = Request::new().with_enroll_id(id.clone());
+
+        let authenticate = Authenticate {
+            enrollment: Enrollment::default(),
+            topic: "com.apple.mgmt.test".to_string(),
+            build_version: None,
+            os_version: None,
+            product_name: None,
+            serial_number: None,
+            device_name: None,
+            model: None,
+            model_name: None,
+            raw: Vec::new(),
+        };
+        service.authenticate(&req, &authenticate).await.unwrap();
+        assert!(store.is_disabled(&id).await.unwrap());
+
+        let token_update = TokenUpdate {
+            enrollment: Enrollment::default(),
+            topic: "com.apple.mgmt.test".to_string(),
+            token: vec![0xaa],
+            push_magic: "magic".to_string(),
+            unlock_token: None,
+            awaiting_configuration: false,
+            raw: Vec::new(),
+        };
+        service.token_update(&req, &token_update).await.unwrap();
+        assert!(!store.is_disabled(&id).await.unwrap());
+
+        let command = mdm_core::new_command("DeviceInformation");
+        let uuid = store.enqueue_command(&id, &command, None).await.unwrap();
+
+        let next = service
+            .command_and_report_results(&req, &results("", CommandStatus::Idle))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(next.command.request_type, "DeviceInformation");
+
+        let next = service
+            .command_and_report_results(&req, &results(&uuid, CommandStatus::Acknowledged))
+            .await
+            .unwrap();
+        assert!(next.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_inventory_from_results() {
+        let store = InMemoryStorage::new();
+        let service = NanoMdm::new(store.clone());
+        let id = EnrollId {
+            enroll_type: EnrollType::Device,
+            id: "00008103-001A2B3C4D5E6F70".to_string(),
+            parent_id: None,
+        };
+        let req = Request::new().with_enroll_id(id.clone());
+
+        let authenticate = Authenticate {
+            enrollment: Enrollment::default(),
+            topic: "com.apple.mgmt.test".to_string(),
+            build_version: Some("23E214".to_string()),
+            os_version: Some("14.4".to_string()),
+            product_name: Some("MacBookPro18,3".to_string()),
+            serial_number: Some("C02G1234MD6T".to_string()),
+            device_name: None,
+            model: Some("MacBookPro18,3".to_string()),
+            model_name: Some("MacBook Pro".to_string()),
+            raw: Vec::new(),
+        };
+        service.authenticate(&req, &authenticate).await.unwrap();
+        let inventory = store.get_inventory(&id.id).await.unwrap().unwrap();
+        assert_eq!(inventory.os_version.as_deref(), Some("14.4"));
+        assert!(inventory.last_checkin_at.is_some());
+
+        for data in [
+            include_str!("../../core/testdata/device_information.plist"),
+            include_str!("../../core/testdata/security_info.plist"),
+        ] {
+            let results = mdm_core::parse_command_results(data.as_bytes()).unwrap();
+            service
+                .command_and_report_results(&req, &results)
+                .await
+                .unwrap();
+        }
+
+        let inventory = store.get_inventory(&id.id).await.unwrap().unwrap();
+        assert_eq!(inventory.os_version.as_deref(), Some("14.4.1"));
+        assert_eq!(inventory.build_version.as_deref(), Some("23E224"));
+        assert_eq!(inventory.device_name.as_deref(), Some("Kim’s MacBook Pro"));
+        assert_eq!(inventory.filevault_enabled, Some(true));
+        assert_eq!(inventory.sip_enabled, Some(true));
+    }
+
+    #[test]
+    fn test_deliver_legacy_command() {
+        // As stored by releases that used the CommandUuid key
+        let legacy = br#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN"
+    "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Command</key>
+	<dict>
+		<key>RequestType</key>
+		<string>DeviceInformation</string>
+	</dict>
+	<key>CommandUuid</key>
+	<string>plist-uuid</string>
+</dict>
+</plist>
+"#;
+        let queued = QueuedCommand {
+            uuid: "row-uuid".to_string(),
+            command: legacy.to_vec(),
+            created_at: chrono::Utc::now(),
+        };
+        let cmd = delivered_command(queued).unwrap();
+        assert_eq!(cmd.command_uuid, "row-uuid");
+        assert_eq!(cmd.command.request_type, "DeviceInformation");
+
+        // Devices are sent the Apple key
+        let sent = mdm_core::serialize_command(&cmd).unwrap();
+        let sent = String::from_utf8(sent).unwrap();
+        assert!(sent.contains("<key>CommandUUID</key>"));
+    }
+}
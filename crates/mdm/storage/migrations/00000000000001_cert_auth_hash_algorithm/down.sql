@@ -0,0 +1 @@
+ALTER TABLE cert_auth DROP COLUMN hash_algorithm;
@@ -0,0 +1,3 @@
+-- Existing rows were hashed with the legacy XOR fold and are re-hashed
+-- to SHA-256 on the device's next check-in.
+ALTER TABLE cert_auth ADD COLUMN hash_algorithm TEXT NOT NULL DEFAULT 'xor';
@@ -0,0 +1 @@
+ALTER TABLE enrollments DROP COLUMN push_invalid_at;
@@ -0,0 +1,3 @@
+-- Set when APNs reports the push token Unregistered;
+-- cleared by the next TokenUpdate.
+ALTER TABLE enrollments ADD COLUMN push_invalid_at TIMESTAMP;
@@ -0,0 +1,2 @@
+ALTER TABLE commands DROP COLUMN request_type;
+ALTER TABLE commands DROP COLUMN expires_at;
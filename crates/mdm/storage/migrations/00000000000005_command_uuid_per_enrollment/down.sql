@@ -0,0 +1,22 @@
+CREATE TABLE commands_old (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    enrollment_id TEXT NOT NULL REFERENCES enrollments(id),
+    uuid TEXT UNIQUE NOT NULL,
+    command BLOB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Pending',
+    result BLOB,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP,
+    request_type TEXT NOT NULL DEFAULT ''
+);
+
+INSERT INTO commands_old
+SELECT id, enrollment_id, uuid, command, status, result, created_at, expires_at, request_type
+FROM commands;
+
+DROP TABLE commands;
+ALTER TABLE commands_old RENAME TO commands;
+
+CREATE INDEX idx_commands_enrollment ON commands(enrollment_id);
+CREATE INDEX idx_commands_status ON commands(status);
+CREATE INDEX idx_commands_queue ON commands(enrollment_id, id);
@@ -0,0 +1,26 @@
+-- Commands are stored under the CommandUUID devices echo back, and one
+-- command may be queued for many enrollments, so the UUID is only unique per
+-- enrollment. SQLite can't change a constraint in place.
+CREATE TABLE commands_new (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    enrollment_id TEXT NOT NULL REFERENCES enrollments(id),
+    uuid TEXT NOT NULL,
+    command BLOB NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Pending',
+    result BLOB,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP,
+    request_type TEXT NOT NULL DEFAULT '',
+    UNIQUE (enrollment_id, uuid)
+);
+
+INSERT INTO commands_new
+SELECT id, enrollment_id, uuid, command, status, result, created_at, expires_at, request_type
+FROM commands;
+
+DROP TABLE commands;
+ALTER TABLE commands_new RENAME TO commands;
+
+CREATE INDEX idx_commands_enrollment ON commands(enrollment_id);
+CREATE INDEX idx_commands_status ON commands(status);
+CREATE INDEX idx_commands_queue ON commands(enrollment_id, id);
@@ -0,0 +1,3 @@
+DROP TABLE declaration_statuses;
+DROP TABLE declaration_assignments;
+DROP TABLE declarations;
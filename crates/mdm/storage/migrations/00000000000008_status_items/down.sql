@@ -0,0 +1,2 @@
+DROP TABLE status_item_history;
+DROP TABLE status_items;
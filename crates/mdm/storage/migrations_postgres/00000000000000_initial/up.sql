@@ -0,0 +1,60 @@
+-- Enrollments table
+CREATE TABLE enrollments (
+    id TEXT PRIMARY KEY NOT NULL,
+    enroll_type TEXT NOT NULL,
+    device_id TEXT,
+    parent_id TEXT,
+    topic TEXT NOT NULL,
+    push_magic TEXT,
+    push_token BYTEA,
+    disabled BOOLEAN NOT NULL DEFAULT FALSE,
+    authenticate_raw BYTEA,
+    token_update_raw BYTEA,
+    created_at TIMESTAMP NOT NULL,
+    updated_at TIMESTAMP NOT NULL,
+    -- Set when APNs reports the push token Unregistered;
+    -- cleared by the next TokenUpdate.
+    push_invalid_at TIMESTAMP
+);
+
+CREATE INDEX idx_enrollments_parent ON enrollments(parent_id);
+CREATE INDEX idx_enrollments_disabled ON enrollments(disabled);
+
+-- Commands queue
+CREATE TABLE commands (
+    id SERIAL PRIMARY KEY,
+    enrollment_id TEXT NOT NULL REFERENCES enrollments(id),
+    uuid TEXT UNIQUE NOT NULL,
+    command BYTEA NOT NULL,
+    status TEXT NOT NULL DEFAULT 'Pending',
+    result BYTEA,
+    created_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_commands_enrollment ON commands(enrollment_id);
+CREATE INDEX idx_commands_status ON commands(status);
+
+-- Push certificates
+CREATE TABLE push_certs (
+    topic TEXT PRIMARY KEY NOT NULL,
+    cert_pem TEXT NOT NULL,
+    key_pem TEXT NOT NULL,
+    not_after TIMESTAMP
+);
+
+-- Bootstrap tokens
+CREATE TABLE bootstrap_tokens (
+    enrollment_id TEXT PRIMARY KEY NOT NULL REFERENCES enrollments(id),
+    token BYTEA NOT NULL
+);
+
+-- Certificate authentication
+CREATE TABLE cert_auth (
+    id SERIAL PRIMARY KEY,
+    enrollment_id TEXT NOT NULL,
+    cert_hash BYTEA NOT NULL,
+    hash_algorithm TEXT NOT NULL DEFAULT 'sha256'
+);
+
+CREATE INDEX idx_cert_auth_enrollment ON cert_auth(enrollment_id);
+CREATE INDEX idx_cert_auth_hash ON cert_auth(cert_hash);
@@ -0,0 +1,4 @@
+DROP INDEX idx_commands_queue;
+
+-- Earlier releases only deliver Pending commands.
+UPDATE commands SET status = 'Pending' WHERE status = 'Sent';
@@ -0,0 +1,5 @@
+-- Statuses are now lifecycle states; format errors count as errors.
+UPDATE commands SET status = 'Error' WHERE status = 'CommandFormatError';
+
+-- The queue is FIFO on the insertion sequence.
+CREATE INDEX idx_commands_queue ON commands(enrollment_id, id);
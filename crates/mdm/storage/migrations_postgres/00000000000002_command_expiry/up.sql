@@ -0,0 +1,5 @@
+-- Commands still unanswered at this time are marked Expired, not sent.
+ALTER TABLE commands ADD COLUMN expires_at TIMESTAMP;
+-- Lets a new command supersede unanswered ones of the same type. Empty for
+-- commands queued by earlier releases.
+ALTER TABLE commands ADD COLUMN request_type TEXT NOT NULL DEFAULT '';
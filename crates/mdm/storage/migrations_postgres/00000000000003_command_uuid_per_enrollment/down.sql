@@ -0,0 +1,2 @@
+ALTER TABLE commands DROP CONSTRAINT commands_enrollment_uuid_key;
+ALTER TABLE commands ADD CONSTRAINT commands_uuid_key UNIQUE (uuid);
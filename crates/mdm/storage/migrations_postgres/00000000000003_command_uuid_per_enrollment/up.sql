@@ -0,0 +1,5 @@
+-- Commands are stored under the CommandUUID devices echo back, and one
+-- command may be queued for many enrollments, so the UUID is only unique per
+-- enrollment.
+ALTER TABLE commands DROP CONSTRAINT commands_uuid_key;
+ALTER TABLE commands ADD CONSTRAINT commands_enrollment_uuid_key UNIQUE (enrollment_id, uuid);
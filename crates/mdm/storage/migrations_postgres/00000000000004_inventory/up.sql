@@ -0,0 +1,17 @@
+-- Last reported hardware, OS and security state of each device, merged from
+-- Authenticate and DeviceInformation/SecurityInfo results.
+CREATE TABLE inventory (
+    device_id TEXT PRIMARY KEY NOT NULL,
+    serial_number TEXT,
+    device_name TEXT,
+    model TEXT,
+    model_name TEXT,
+    product_name TEXT,
+    os_version TEXT,
+    build_version TEXT,
+    filevault_enabled BOOLEAN,
+    sip_enabled BOOLEAN,
+    last_checkin_at TIMESTAMP
+);
+
+CREATE INDEX idx_inventory_serial_number ON inventory(serial_number);
@@ -0,0 +1,28 @@
+-- DDM declarations. Payload is the declaration's JSON Payload object.
+CREATE TABLE declarations (
+    identifier TEXT PRIMARY KEY NOT NULL,
+    declaration_type TEXT NOT NULL,
+    server_token TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    updated_at TIMESTAMP NOT NULL
+);
+
+-- Declarations served to each enrollment. Enrollments need not exist yet, so
+-- declarations can be assigned before a device enrolls.
+CREATE TABLE declaration_assignments (
+    enrollment_id TEXT NOT NULL,
+    identifier TEXT NOT NULL REFERENCES declarations(identifier),
+    PRIMARY KEY (enrollment_id, identifier)
+);
+
+-- Declaration states from each enrollment's latest DDM status report.
+CREATE TABLE declaration_statuses (
+    enrollment_id TEXT NOT NULL,
+    identifier TEXT NOT NULL,
+    active BOOLEAN NOT NULL,
+    valid TEXT NOT NULL,
+    server_token TEXT NOT NULL,
+    reasons TEXT,
+    updated_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (enrollment_id, identifier)
+);
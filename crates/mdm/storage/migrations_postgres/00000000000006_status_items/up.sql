@@ -0,0 +1,20 @@
+-- Latest value of each DDM status item per enrollment. Values are JSON.
+CREATE TABLE status_items (
+    enrollment_id TEXT NOT NULL,
+    item TEXT NOT NULL,
+    value TEXT NOT NULL,
+    changed_at TIMESTAMP NOT NULL,
+    reported_at TIMESTAMP NOT NULL,
+    PRIMARY KEY (enrollment_id, item)
+);
+
+-- Every value each status item changed to.
+CREATE TABLE status_item_history (
+    id SERIAL PRIMARY KEY,
+    enrollment_id TEXT NOT NULL,
+    item TEXT NOT NULL,
+    value TEXT NOT NULL,
+    reported_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX idx_status_item_history_item ON status_item_history(enrollment_id, item);
@@ -0,0 +1,2 @@
+ALTER TABLE commands ADD CONSTRAINT commands_enrollment_id_fkey
+    FOREIGN KEY (enrollment_id) REFERENCES enrollments(id);
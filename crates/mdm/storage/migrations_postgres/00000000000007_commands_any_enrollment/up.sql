@@ -0,0 +1,3 @@
+-- Commands may be queued before an enrollment exists, as with SQLite, which
+-- doesn't enforce foreign keys.
+ALTER TABLE commands DROP CONSTRAINT commands_enrollment_id_fkey;
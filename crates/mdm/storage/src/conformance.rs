@@ -0,0 +1,1164 @@
+//! Backend-agnostic conformance checks for [`AllStorage`] implementations.
+//!
+//! Every store is expected to behave the same way at the trait level. Enable
+//! the `conformance` feature and run the checks from a test, passing a
+//! constructor that returns an empty, migrated store:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn test_conformance() {
+//!     mdm_storage::conformance::run_conformance(|| MyStorage::new()).await;
+//! }
+//! ```
+
+use mdm_core::{
+    Authenticate, CheckOut, CommandResults, CommandState, CommandStatus, Declaration,
+    DeclarationStatus, EnrollId, EnrollType, Enrollment, InventoryUpdate, QueuedCommand,
+    StatusItem, TokenUpdate,
+};
+
+use crate::{
+    AllStorage, CommandFilter, DuplicateCommand, EnrollmentFilter, InventoryFilter,
+    StatusHistoryFilter, StatusItemFilter, UnknownDeclaration,
+};
+
+const TOPIC: &str = "com.apple.mgmt.External.moonstone-test";
+// Copies of mdm-crypto's fixtures, so the checks build outside the workspace
+const PUSH_CERT: &str = include_str!("../testdata/push.pem");
+const PUSH_KEY: &str = include_str!("../testdata/push.key");
+const OTHER_KEY: &str = include_str!("../testdata/other.key");
+
+/// Run every conformance check, each against a fresh store from `make`.
+///
+/// Panics on the first failed expectation.
+pub async fn run_conformance<S: AllStorage>(make: impl Fn() -> S) {
+    macro_rules! check {
+        ($($name:ident),* $(,)?) => {$(
+            tracing::debug!(check = stringify!($name), "running conformance check");
+            $name(&make()).await;
+        )*};
+    }
+
+    check!(
+        lifecycle,
+        not_now,
+        fifo,
+        expiry,
+        cancel,
+        history,
+        reauthenticate,
+        queue_isolation,
+        command_uuid,
+        batch_enqueue,
+        unknown_enrollment,
+        user_channel,
+        checkout,
+        bootstrap_token,
+        push_info,
+        push_cert,
+        cert_auth,
+        enrollments,
+        inventory,
+        declarations,
+        status_items,
+    );
+}
+
+fn device(id: &str) -> EnrollId {
+    EnrollId {
+        enroll_type: EnrollType::Device,
+        id: id.to_string(),
+        parent_id: None,
+    }
+}
+
+fn user(device_id: &str, user_id: &str) -> EnrollId {
+    EnrollId {
+        enroll_type: EnrollType::User,
+        id: format!("{device_id}:{user_id}"),
+        parent_id: Some(device_id.to_string()),
+    }
+}
+
+async fn authenticate(store: &impl AllStorage, id: &EnrollId) {
+    let msg = Authenticate {
+        enrollment: Enrollment::default(),
+        topic: TOPIC.to_string(),
+        build_version: None,
+        os_version: None,
+        product_name: None,
+        serial_number: None,
+        device_name: None,
+        model: None,
+        model_name: None,
+        raw: b"authenticate".to_vec(),
+    };
+    store.store_authenticate(id, &msg).await.unwrap();
+}
+
+async fn token_update(store: &impl AllStorage, id: &EnrollId, token: &[u8]) {
+    let msg = TokenUpdate {
+        enrollment: Enrollment::default(),
+        topic: TOPIC.to_string(),
+        token: token.to_vec(),
+        push_magic: format!("magic-{}", id.id),
+        unlock_token: None,
+        awaiting_configuration: false,
+        raw: b"token-update".to_vec(),
+    };
+    store.store_token_update(id, &msg).await.unwrap();
+}
+
+async fn enroll(store: &impl AllStorage, id: &EnrollId, token: &[u8]) {
+    authenticate(store, id).await;
+    token_update(store, id, token).await;
+}
+
+async fn check_out(store: &impl AllStorage, id: &EnrollId) {
+    let msg = CheckOut {
+        enrollment: Enrollment::default(),
+        topic: TOPIC.to_string(),
+        raw: b"checkout".to_vec(),
+    };
+    store.store_checkout(id, &msg).await.unwrap();
+}
+
+async fn report(store: &impl AllStorage, id: &EnrollId, uuid: &str, status: CommandStatus) {
+    let results = CommandResults {
+        enrollment: Enrollment::default(),
+        command_uuid: uuid.to_string(),
+        status,
+        error_chain: Vec::new(),
+        raw: b"results".to_vec(),
+    };
+    store.store_result(id, &results).await.unwrap();
+}
+
+async fn enqueue(store: &impl AllStorage, id: &EnrollId, request_type: &str) -> String {
+    let command = mdm_core::new_command(request_type);
+    store.enqueue_command(id, &command, None).await.unwrap()
+}
+
+fn request_type(cmd: &QueuedCommand) -> String {
+    let command: mdm_core::Command = plist::from_bytes(&cmd.command).unwrap();
+    command.command.request_type
+}
+
+async fn next_uuid(store: &impl AllStorage, id: &EnrollId) -> Option<String> {
+    store
+        .next_command(id, false)
+        .await
+        .unwrap()
+        .map(|cmd| cmd.uuid)
+}
+
+/// Like [`next_uuid`], for a device that didn't just report Idle.
+async fn busy_next_uuid(store: &impl AllStorage, id: &EnrollId) -> Option<String> {
+    store
+        .next_command(id, true)
+        .await
+        .unwrap()
+        .map(|cmd| cmd.uuid)
+}
+
+/// Authenticate → TokenUpdate → enqueue → next_command → store_result →
+/// CheckOut for one device.
+async fn lifecycle<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    assert!(store.is_disabled(&id).await.unwrap(), "unknown enrollment");
+
+    authenticate(store, &id).await;
+    assert!(
+        store.is_disabled(&id).await.unwrap(),
+        "disabled until TokenUpdate"
+    );
+    assert!(store.get_push_info(&id).await.unwrap().is_none());
+
+    token_update(store, &id, &[0xaa]).await;
+    assert!(!store.is_disabled(&id).await.unwrap());
+    let info = store.get_push_info(&id).await.unwrap().expect("push info");
+    assert_eq!(info.enrollment_id, id.id);
+    assert_eq!(info.token, [0xaa]);
+    assert_eq!(info.push_magic, "magic-device-1");
+    assert_eq!(info.topic, TOPIC);
+
+    assert!(store.next_command(&id, false).await.unwrap().is_none());
+    let first = enqueue(store, &id, "first").await;
+    let second = enqueue(store, &id, "second").await;
+    assert_ne!(first, second);
+
+    let cmd = store
+        .next_command(&id, false)
+        .await
+        .unwrap()
+        .expect("first command");
+    assert_eq!(cmd.uuid, first);
+    assert_eq!(request_type(&cmd), "first");
+    assert_eq!(
+        next_uuid(store, &id).await,
+        Some(first.clone()),
+        "not yet answered"
+    );
+
+    report(store, &id, &first, CommandStatus::Acknowledged).await;
+    let cmd = store
+        .next_command(&id, false)
+        .await
+        .unwrap()
+        .expect("second command");
+    assert_eq!(cmd.uuid, second);
+    assert_eq!(request_type(&cmd), "second");
+
+    report(store, &id, &second, CommandStatus::Error).await;
+    assert!(store.next_command(&id, false).await.unwrap().is_none());
+
+    check_out(store, &id).await;
+    assert!(store.is_disabled(&id).await.unwrap());
+    assert!(store.get_push_info(&id).await.unwrap().is_none());
+}
+
+/// NotNow commands wait until the device reports Idle, without holding up
+/// the rest of the queue.
+async fn not_now<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    enroll(store, &id, &[0xaa]).await;
+    let first = enqueue(store, &id, "first").await;
+    let second = enqueue(store, &id, "second").await;
+
+    assert_eq!(next_uuid(store, &id).await, Some(first.clone()));
+    report(store, &id, &first, CommandStatus::NotNow).await;
+    assert_eq!(
+        busy_next_uuid(store, &id).await,
+        Some(second.clone()),
+        "NotNow skipped while busy"
+    );
+    report(store, &id, &second, CommandStatus::Acknowledged).await;
+    assert!(busy_next_uuid(store, &id).await.is_none());
+
+    // Idle polls don't change state, and retry the NotNow command
+    report(store, &id, "", CommandStatus::Idle).await;
+    assert_eq!(next_uuid(store, &id).await, Some(first.clone()));
+    report(store, &id, &first, CommandStatus::Idle).await;
+    assert_eq!(next_uuid(store, &id).await, Some(first.clone()));
+    report(store, &id, &first, CommandStatus::Acknowledged).await;
+    assert!(next_uuid(store, &id).await.is_none());
+}
+
+/// Commands enqueued in quick succession come back in enqueue order.
+async fn fifo<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    enroll(store, &id, &[0xaa]).await;
+
+    let mut enqueued = Vec::new();
+    for i in 0..20 {
+        enqueued.push(enqueue(store, &id, &format!("command-{i}")).await);
+    }
+
+    let mut delivered = Vec::new();
+    while let Some(uuid) = next_uuid(store, &id).await {
+        report(store, &id, &uuid, CommandStatus::Acknowledged).await;
+        delivered.push(uuid);
+    }
+    assert_eq!(delivered, enqueued);
+}
+
+/// Commands unanswered by their expiry are skipped, even after being sent.
+async fn expiry<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    enroll(store, &id, &[0xaa]).await;
+    let now = chrono::Utc::now();
+
+    let stale = mdm_core::new_command("stale");
+    let stale = store
+        .enqueue_command(&id, &stale, Some(now - chrono::Duration::seconds(1)))
+        .await
+        .unwrap();
+    // Far enough out that a slow store still sends it first
+    let sent_expiry = now + chrono::Duration::seconds(2);
+    let sent = mdm_core::new_command("sent");
+    let sent = store
+        .enqueue_command(&id, &sent, Some(sent_expiry))
+        .await
+        .unwrap();
+    let fresh = mdm_core::new_command("fresh");
+    let fresh = store
+        .enqueue_command(&id, &fresh, Some(now + chrono::Duration::hours(1)))
+        .await
+        .unwrap();
+
+    assert_eq!(next_uuid(store, &id).await, Some(sent.clone()));
+    // Sleep until the expiry has certainly passed, however long that took
+    let remaining = (sent_expiry - chrono::Utc::now())
+        .to_std()
+        .unwrap_or_default();
+    tokio::time::sleep(remaining + std::time::Duration::from_millis(100)).await;
+    assert_eq!(next_uuid(store, &id).await, Some(fresh.clone()));
+
+    // Late results for an expired command are still recorded
+    report(store, &id, &stale, CommandStatus::Acknowledged).await;
+    report(store, &id, &fresh, CommandStatus::Acknowledged).await;
+    assert!(next_uuid(store, &id).await.is_none());
+    assert!(!store.cancel_command(&id, &sent).await.unwrap());
+}
+
+/// Cancelling stops delivery of unanswered commands only.
+async fn cancel<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    let other = device("device-2");
+    enroll(store, &id, &[0xaa]).await;
+    enroll(store, &other, &[0xbb]).await;
+
+    let answered = enqueue(store, &id, "policy").await;
+    let first = enqueue(store, &id, "first").await;
+    let old_policy = enqueue(store, &id, "policy").await;
+    let sent = enqueue(store, &id, "policy").await;
+    let for_other = enqueue(store, &other, "policy").await;
+
+    assert_eq!(next_uuid(store, &id).await, Some(answered.clone()));
+    report(store, &id, &answered, CommandStatus::Acknowledged).await;
+    assert!(!store.cancel_command(&id, &answered).await.unwrap());
+    assert!(!store.cancel_command(&other, &first).await.unwrap());
+    assert!(store.cancel_command(&id, &first).await.unwrap());
+    assert!(!store.cancel_command(&id, &first).await.unwrap());
+
+    // NotNow and Sent commands are still unanswered
+    assert_eq!(next_uuid(store, &id).await, Some(old_policy.clone()));
+    report(store, &id, &old_policy, CommandStatus::NotNow).await;
+    assert_eq!(busy_next_uuid(store, &id).await, Some(sent.clone()));
+
+    let latest = enqueue(store, &id, "policy").await;
+    let cancelled = store
+        .cancel_request_type(&id, "policy", Some(&latest))
+        .await
+        .unwrap();
+    assert_eq!(cancelled, [old_policy, sent]);
+    assert_eq!(next_uuid(store, &id).await, Some(latest.clone()));
+    let cancelled = store
+        .cancel_request_type(&id, "policy", None)
+        .await
+        .unwrap();
+    assert_eq!(cancelled, [latest]);
+    assert!(next_uuid(store, &id).await.is_none());
+    assert_eq!(next_uuid(store, &other).await, Some(for_other));
+}
+
+/// Commands keep their state and results after they leave the queue.
+async fn history<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    enroll(store, &a, &[0xaa]).await;
+    enroll(store, &b, &[0xbb]).await;
+
+    let info = mdm_core::new_command("DeviceInformation");
+    store.enqueue_command(&a, &info, None).await.unwrap();
+    store.enqueue_command(&b, &info, None).await.unwrap();
+    let profile = enqueue(store, &a, "InstallProfile").await;
+    let lock = enqueue(store, &a, "DeviceLock").await;
+
+    assert_eq!(next_uuid(store, &a).await, Some(info.command_uuid.clone()));
+    report(store, &a, &info.command_uuid, CommandStatus::Acknowledged).await;
+    assert_eq!(next_uuid(store, &a).await, Some(profile.clone()));
+    report(store, &a, &profile, CommandStatus::Error).await;
+    assert_eq!(next_uuid(store, &a).await, Some(lock.clone()));
+
+    let a = &a;
+    let list = |filter: CommandFilter| async move {
+        store
+            .list_commands(a, &filter)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|cmd| (cmd.uuid, cmd.state))
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        list(CommandFilter::default()).await,
+        [
+            (lock.clone(), CommandState::Sent),
+            (profile.clone(), CommandState::Error),
+            (info.command_uuid.clone(), CommandState::Acknowledged),
+        ]
+    );
+    let filter = CommandFilter {
+        state: Some(CommandState::Error),
+        ..Default::default()
+    };
+    assert_eq!(list(filter).await, [(profile.clone(), CommandState::Error)]);
+    let filter = CommandFilter {
+        request_type: Some("DeviceLock".to_string()),
+        ..Default::default()
+    };
+    assert_eq!(list(filter).await, [(lock.clone(), CommandState::Sent)]);
+    let filter = CommandFilter {
+        limit: Some(1),
+        offset: 1,
+        ..Default::default()
+    };
+    assert_eq!(list(filter).await, [(profile.clone(), CommandState::Error)]);
+
+    let copies = store.get_command(&info.command_uuid).await.unwrap();
+    assert_eq!(copies.len(), 2);
+    let copy_a = copies.iter().find(|cmd| cmd.enrollment_id == a.id).unwrap();
+    assert_eq!(copy_a.request_type, "DeviceInformation");
+    assert_eq!(copy_a.result.as_deref(), Some(&b"results"[..]));
+    let copy_b = copies.iter().find(|cmd| cmd.enrollment_id == b.id).unwrap();
+    assert_eq!(copy_b.state, CommandState::Pending);
+    assert!(copy_b.result.is_none());
+    assert!(store.get_command("missing").await.unwrap().is_empty());
+}
+
+/// Re-enrollment clears the queue and disables until the next TokenUpdate.
+async fn reauthenticate<S: AllStorage>(store: &S) {
+    let id = device("device-1");
+    enroll(store, &id, &[0xaa]).await;
+    enqueue(store, &id, "stale").await;
+
+    authenticate(store, &id).await;
+    assert!(store.is_disabled(&id).await.unwrap());
+    assert!(store.next_command(&id, false).await.unwrap().is_none());
+
+    token_update(store, &id, &[0xbb]).await;
+    assert!(!store.is_disabled(&id).await.unwrap());
+    assert_eq!(
+        store.get_push_info(&id).await.unwrap().unwrap().token,
+        [0xbb]
+    );
+}
+
+/// Each enrollment has its own queue.
+async fn queue_isolation<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    enroll(store, &a, &[0xaa]).await;
+    enroll(store, &b, &[0xbb]).await;
+
+    let uuid = enqueue(store, &a, "for-a").await;
+    assert!(store.next_command(&b, false).await.unwrap().is_none());
+
+    // Results reported by another enrollment don't touch the command
+    report(store, &b, &uuid, CommandStatus::Acknowledged).await;
+    assert_eq!(next_uuid(store, &a).await, Some(uuid));
+
+    enqueue(store, &b, "for-b").await;
+    store.clear_queue(&a).await.unwrap();
+    assert!(store.next_command(&a, false).await.unwrap().is_none());
+    assert!(store.next_command(&b, false).await.unwrap().is_some());
+}
+
+/// Commands are stored under their CommandUUID, unique per enrollment.
+async fn command_uuid<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    enroll(store, &a, &[0xaa]).await;
+    enroll(store, &b, &[0xbb]).await;
+
+    let command = mdm_core::new_command("DeviceInformation");
+    let uuid = store.enqueue_command(&a, &command, None).await.unwrap();
+    assert_eq!(uuid, command.command_uuid);
+    store.enqueue_command(&b, &command, None).await.unwrap();
+
+    let err = store.enqueue_command(&a, &command, None).await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<DuplicateCommand>(),
+        Some(&DuplicateCommand {
+            enrollment_id: a.id.clone(),
+            uuid: uuid.clone(),
+        })
+    );
+
+    // Results only answer the reporting enrollment's copy
+    assert_eq!(next_uuid(store, &a).await, Some(uuid.clone()));
+    report(store, &a, &uuid, CommandStatus::Acknowledged).await;
+    assert!(next_uuid(store, &a).await.is_none());
+    assert_eq!(next_uuid(store, &b).await, Some(uuid));
+}
+
+/// A batch enqueue queues the command for every enrollment or for none.
+async fn batch_enqueue<S: AllStorage>(store: &S) {
+    let [a, b, c] = ["device-a", "device-b", "device-c"].map(device);
+    for (id, token) in [(&a, 0xaa), (&b, 0xbb), (&c, 0xcc)] {
+        enroll(store, id, &[token]).await;
+    }
+
+    // A duplicate partway through leaves the earlier enrollments untouched
+    let command = mdm_core::new_command("DeviceInformation");
+    store.enqueue_command(&b, &command, None).await.unwrap();
+    let ids = [a.clone(), b.clone(), c.clone()];
+    let err = store
+        .enqueue_commands(&ids, &command, None)
+        .await
+        .unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<DuplicateCommand>(),
+        Some(&DuplicateCommand {
+            enrollment_id: b.id.clone(),
+            uuid: command.command_uuid.clone(),
+        })
+    );
+    let queued: Vec<String> = store
+        .get_command(&command.command_uuid)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|record| record.enrollment_id)
+        .collect();
+    assert_eq!(queued, [b.id.as_str()]);
+    assert!(next_uuid(store, &a).await.is_none());
+
+    // So does repeating an enrollment within the batch
+    let err = store
+        .enqueue_commands(&[c.clone(), c.clone()], &command, None)
+        .await
+        .unwrap_err();
+    assert!(err.downcast_ref::<DuplicateCommand>().is_some());
+    assert!(next_uuid(store, &c).await.is_none());
+
+    // Nothing was left behind to block a retry
+    let uuid = store
+        .enqueue_commands(&[a.clone(), c.clone()], &command, None)
+        .await
+        .unwrap();
+    assert_eq!(uuid, command.command_uuid);
+    assert_eq!(next_uuid(store, &a).await, Some(uuid.clone()));
+    assert_eq!(next_uuid(store, &c).await, Some(uuid));
+}
+
+/// Commands may be queued for enrollments the store hasn't seen.
+async fn unknown_enrollment<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let command = mdm_core::new_command("DeclarativeManagement");
+    store.enqueue_command(&a, &command, None).await.unwrap();
+    store
+        .enqueue_commands(&[device("device-b")], &command, None)
+        .await
+        .unwrap();
+
+    let records = store.get_command(&command.command_uuid).await.unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(records.iter().all(|r| r.state == CommandState::Pending));
+    assert!(store.get_enrollment(&a.id).await.unwrap().is_none());
+}
+
+/// A user channel enrolls with TokenUpdate alone and is addressed
+/// separately from its device.
+async fn user_channel<S: AllStorage>(store: &S) {
+    let dev = device("device-1");
+    let usr = user("device-1", "user-1");
+    enroll(store, &dev, &[0xaa]).await;
+
+    token_update(store, &usr, &[0xcc]).await;
+    assert!(!store.is_disabled(&usr).await.unwrap());
+
+    let infos = store.get_push_infos(&[&dev, &usr]).await.unwrap();
+    let token = |id: &EnrollId| {
+        infos
+            .iter()
+            .find(|info| info.enrollment_id == id.id)
+            .map(|info| info.token.clone())
+    };
+    assert_eq!(token(&dev), Some(vec![0xaa]));
+    assert_eq!(token(&usr), Some(vec![0xcc]));
+
+    let for_user = enqueue(store, &usr, "for-user").await;
+    assert!(store.next_command(&dev, false).await.unwrap().is_none());
+    assert_eq!(next_uuid(store, &usr).await, Some(for_user.clone()));
+    report(store, &usr, &for_user, CommandStatus::Acknowledged).await;
+    assert!(store.next_command(&usr, false).await.unwrap().is_none());
+
+    store
+        .store_bootstrap_token(&dev, b"device-token")
+        .await
+        .unwrap();
+    assert!(store.get_bootstrap_token(&usr).await.unwrap().is_none());
+}
+
+/// Checking out a device disables its user channels; checking out a user
+/// channel leaves the device alone.
+async fn checkout<S: AllStorage>(store: &S) {
+    let dev = device("device-1");
+    let alice = user("device-1", "alice");
+    let bob = user("device-1", "bob");
+    let other = device("device-2");
+    enroll(store, &dev, &[0xaa]).await;
+    token_update(store, &alice, &[0xa1]).await;
+    token_update(store, &bob, &[0xb1]).await;
+    enroll(store, &other, &[0xbb]).await;
+
+    check_out(store, &alice).await;
+    assert!(store.is_disabled(&alice).await.unwrap());
+    assert!(!store.is_disabled(&bob).await.unwrap());
+    assert!(!store.is_disabled(&dev).await.unwrap());
+
+    check_out(store, &dev).await;
+    assert!(store.is_disabled(&dev).await.unwrap());
+    assert!(store.is_disabled(&bob).await.unwrap());
+    assert!(!store.is_disabled(&other).await.unwrap());
+
+    // Re-enrolling brings the user channel back with its next TokenUpdate
+    enroll(store, &dev, &[0xaa]).await;
+    assert!(store.is_disabled(&bob).await.unwrap());
+    token_update(store, &bob, &[0xb2]).await;
+    assert!(!store.is_disabled(&bob).await.unwrap());
+}
+
+async fn bootstrap_token<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    enroll(store, &a, &[0xaa]).await;
+    enroll(store, &b, &[0xbb]).await;
+    assert!(store.get_bootstrap_token(&a).await.unwrap().is_none());
+
+    store.store_bootstrap_token(&a, b"first").await.unwrap();
+    assert_eq!(
+        store.get_bootstrap_token(&a).await.unwrap().unwrap(),
+        b"first"
+    );
+    assert!(store.get_bootstrap_token(&b).await.unwrap().is_none());
+
+    store.store_bootstrap_token(&a, b"second").await.unwrap();
+    assert_eq!(
+        store.get_bootstrap_token(&a).await.unwrap().unwrap(),
+        b"second"
+    );
+
+    store.delete_bootstrap_token(&a).await.unwrap();
+    assert!(store.get_bootstrap_token(&a).await.unwrap().is_none());
+    store.delete_bootstrap_token(&a).await.unwrap();
+}
+
+async fn push_info<S: AllStorage>(store: &S) {
+    let enrolled = device("enrolled");
+    let pending = device("pending");
+    let unknown = device("unknown");
+    enroll(store, &enrolled, &[0xaa]).await;
+    authenticate(store, &pending).await;
+
+    let infos = store
+        .get_push_infos(&[&enrolled, &pending, &unknown])
+        .await
+        .unwrap();
+    assert_eq!(infos.len(), 1);
+    assert_eq!(infos[0].enrollment_id, enrolled.id);
+
+    store.mark_push_invalid(&enrolled).await.unwrap();
+    store.mark_push_invalid(&enrolled).await.unwrap();
+    assert!(store.get_push_info(&enrolled).await.unwrap().is_none());
+    assert!(store.get_push_infos(&[&enrolled]).await.unwrap().is_empty());
+    store.mark_push_invalid(&unknown).await.unwrap();
+
+    token_update(store, &enrolled, &[0xbb]).await;
+    assert_eq!(
+        store.get_push_info(&enrolled).await.unwrap().unwrap().token,
+        [0xbb]
+    );
+}
+
+async fn push_cert<S: AllStorage>(store: &S) {
+    assert!(store.get_push_cert(TOPIC).await.unwrap().is_none());
+    assert!(store.list_push_certs().await.unwrap().is_empty());
+
+    assert!(
+        store
+            .store_push_cert("com.example.other", PUSH_CERT, PUSH_KEY)
+            .await
+            .is_err()
+    );
+    assert!(
+        store
+            .store_push_cert(TOPIC, PUSH_CERT, OTHER_KEY)
+            .await
+            .is_err()
+    );
+    assert!(
+        store
+            .store_push_cert(TOPIC, "not a cert", PUSH_KEY)
+            .await
+            .is_err()
+    );
+    assert!(store.list_push_topics().await.unwrap().is_empty());
+
+    store
+        .store_push_cert(TOPIC, PUSH_CERT, PUSH_KEY)
+        .await
+        .unwrap();
+    store
+        .store_push_cert(TOPIC, PUSH_CERT, PUSH_KEY)
+        .await
+        .unwrap();
+    let (cert, key) = store.get_push_cert(TOPIC).await.unwrap().unwrap();
+    assert_eq!(cert, PUSH_CERT);
+    assert_eq!(key, PUSH_KEY);
+    assert_eq!(store.list_push_topics().await.unwrap(), [TOPIC]);
+
+    let certs = store.list_push_certs().await.unwrap();
+    assert_eq!(certs.len(), 1);
+    assert_eq!(certs[0].topic, TOPIC);
+    let not_after = certs[0].not_after.expect("expiry");
+    assert_eq!(not_after.to_rfc3339(), "2035-01-01T00:00:00+00:00");
+}
+
+async fn cert_auth<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    let hash = [0x11; 32];
+    let other_hash = [0x22; 32];
+    assert!(!store.has_cert_auth(&a, &hash).await.unwrap());
+
+    store.associate_cert(&a, &hash).await.unwrap();
+    assert!(store.has_cert_auth(&a, &hash).await.unwrap());
+    assert!(!store.has_cert_auth(&a, &other_hash).await.unwrap());
+    assert!(!store.has_cert_auth(&b, &hash).await.unwrap());
+
+    // Only legacy rows are re-hashed
+    assert!(
+        !store
+            .rehash_legacy_cert(&a, &hash, &other_hash)
+            .await
+            .unwrap()
+    );
+    assert!(store.has_cert_auth(&a, &hash).await.unwrap());
+}
+
+/// Enrollment lookup, listing filters and queue depth.
+async fn enrollments<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    let alice = user("device-a", "alice");
+    assert!(store.get_enrollment(&a.id).await.unwrap().is_none());
+
+    enroll(store, &a, &[0xaa]).await;
+    authenticate(store, &b).await;
+    token_update(store, &alice, &[0xcc]).await;
+
+    let record = store.get_enrollment(&a.id).await.unwrap().unwrap();
+    assert_eq!(record.id, a.id);
+    assert_eq!(record.enroll_type, EnrollType::Device);
+    assert_eq!(record.topic, TOPIC);
+    assert!(!record.disabled);
+    assert_eq!(
+        record.authenticate_raw.as_deref(),
+        Some(&b"authenticate"[..])
+    );
+    assert_eq!(
+        record.token_update_raw.as_deref(),
+        Some(&b"token-update"[..])
+    );
+    assert!(record.push_invalid_at.is_none());
+    assert!(record.updated_at >= record.created_at);
+
+    let record = store.get_enrollment(&alice.id).await.unwrap().unwrap();
+    assert_eq!(record.enroll_type, EnrollType::User);
+    assert_eq!(record.parent_id.as_deref(), Some("device-a"));
+    assert!(record.authenticate_raw.is_none());
+
+    let ids = |records: Vec<mdm_core::EnrollmentRecord>| {
+        records
+            .into_iter()
+            .map(|record| record.id)
+            .collect::<Vec<_>>()
+    };
+    let all = store
+        .list_enrollments(&EnrollmentFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(ids(all), ["device-a", "device-a:alice", "device-b"]);
+
+    let filter = EnrollmentFilter {
+        enroll_type: Some(EnrollType::Device),
+        disabled: Some(false),
+        topic: Some(TOPIC.to_string()),
+        ..Default::default()
+    };
+    let listed = store.list_enrollments(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-a"]);
+
+    let filter = EnrollmentFilter {
+        parent_id: Some("device-a".to_string()),
+        ..Default::default()
+    };
+    let listed = store.list_enrollments(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-a:alice"]);
+
+    let filter = EnrollmentFilter {
+        topic: Some("com.apple.mgmt.other".to_string()),
+        ..Default::default()
+    };
+    assert!(store.list_enrollments(&filter).await.unwrap().is_empty());
+
+    let filter = EnrollmentFilter {
+        limit: Some(1),
+        offset: 2,
+        ..Default::default()
+    };
+    let listed = store.list_enrollments(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-b"]);
+
+    // Answered and expired commands aren't queued
+    assert_eq!(store.queue_depth(&a).await.unwrap(), 0);
+    let answered = enqueue(store, &a, "DeviceInformation").await;
+    enqueue(store, &a, "SecurityInfo").await;
+    let expired = mdm_core::new_command("ProfileList");
+    let past = chrono::Utc::now() - chrono::Duration::minutes(1);
+    store
+        .enqueue_command(&a, &expired, Some(past))
+        .await
+        .unwrap();
+    assert_eq!(store.queue_depth(&a).await.unwrap(), 2);
+    assert_eq!(next_uuid(store, &a).await, Some(answered.clone()));
+    report(store, &a, &answered, CommandStatus::Acknowledged).await;
+    assert_eq!(store.queue_depth(&a).await.unwrap(), 1);
+    assert_eq!(store.queue_depth(&alice).await.unwrap(), 0);
+}
+
+async fn inventory<S: AllStorage>(store: &S) {
+    assert!(store.get_inventory("device-a").await.unwrap().is_none());
+
+    // Whole seconds, which every backend stores exactly
+    let seen = chrono::DateTime::from_timestamp(1_700_000_000, 0).unwrap();
+    let update = InventoryUpdate {
+        serial_number: Some("C02A".to_string()),
+        model: Some("MacBookPro18,3".to_string()),
+        os_version: Some("14.4".to_string()),
+        last_checkin_at: Some(seen),
+        ..Default::default()
+    };
+    store.update_inventory("device-a", &update).await.unwrap();
+    let update = InventoryUpdate {
+        os_version: Some("14.4.1".to_string()),
+        filevault_enabled: Some(true),
+        sip_enabled: Some(true),
+        ..Default::default()
+    };
+    store.update_inventory("device-a", &update).await.unwrap();
+    store
+        .update_inventory("device-a", &InventoryUpdate::default())
+        .await
+        .unwrap();
+
+    // Unreported properties are kept
+    let a = store.get_inventory("device-a").await.unwrap().unwrap();
+    assert_eq!(a.device_id, "device-a");
+    assert_eq!(a.serial_number.as_deref(), Some("C02A"));
+    assert_eq!(a.model.as_deref(), Some("MacBookPro18,3"));
+    assert_eq!(a.os_version.as_deref(), Some("14.4.1"));
+    assert_eq!(a.filevault_enabled, Some(true));
+    assert_eq!(a.last_checkin_at, Some(seen));
+    assert!(a.build_version.is_none());
+
+    let update = InventoryUpdate {
+        os_version: Some("14.4.1".to_string()),
+        filevault_enabled: Some(false),
+        ..Default::default()
+    };
+    store.update_inventory("device-b", &update).await.unwrap();
+    store
+        .update_inventory("device-c", &InventoryUpdate::checkin(seen))
+        .await
+        .unwrap();
+
+    let ids = |devices: Vec<mdm_core::DeviceInventory>| {
+        devices
+            .into_iter()
+            .map(|device| device.device_id)
+            .collect::<Vec<_>>()
+    };
+    let all = store
+        .list_inventory(&InventoryFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(ids(all), ["device-a", "device-b", "device-c"]);
+
+    let filter = InventoryFilter {
+        os_version: Some("14.4.1".to_string()),
+        ..Default::default()
+    };
+    let listed = store.list_inventory(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-a", "device-b"]);
+
+    let filter = InventoryFilter {
+        filevault_enabled: Some(false),
+        ..Default::default()
+    };
+    let listed = store.list_inventory(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-b"]);
+
+    let filter = InventoryFilter {
+        model: Some("MacBookPro18,3".to_string()),
+        sip_enabled: Some(true),
+        serial_number: Some("C02A".to_string()),
+        ..Default::default()
+    };
+    let listed = store.list_inventory(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-a"]);
+
+    let filter = InventoryFilter {
+        limit: Some(1),
+        offset: 1,
+        ..Default::default()
+    };
+    let listed = store.list_inventory(&filter).await.unwrap();
+    assert_eq!(ids(listed), ["device-b"]);
+}
+
+fn declaration(
+    identifier: &str,
+    declaration_type: &str,
+    payload: serde_json::Value,
+) -> Declaration {
+    let mut declaration = Declaration {
+        declaration_type: declaration_type.to_string(),
+        identifier: identifier.to_string(),
+        server_token: String::new(),
+        payload: serde_json::from_value(payload).unwrap(),
+    };
+    declaration.server_token = declaration.content_token();
+    declaration
+}
+
+async fn declarations<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    let activation = declaration(
+        "activation",
+        "com.apple.activation.simple",
+        serde_json::json!({"StandardConfigurations": ["passcode"]}),
+    );
+    let passcode = declaration(
+        "passcode",
+        "com.apple.configuration.passcode.settings",
+        serde_json::json!({"MinimumLength": 6}),
+    );
+    store.store_declaration(&activation).await.unwrap();
+    store.store_declaration(&passcode).await.unwrap();
+
+    let stored = store.get_declaration("passcode").await.unwrap().unwrap();
+    assert_eq!(stored.declaration, passcode);
+    assert!(store.get_declaration("missing").await.unwrap().is_none());
+
+    // Storing the same content keeps the update time
+    store.store_declaration(&passcode).await.unwrap();
+    let again = store.get_declaration("passcode").await.unwrap().unwrap();
+    assert_eq!(again.updated_at, stored.updated_at);
+
+    let changed = declaration(
+        "passcode",
+        "com.apple.configuration.passcode.settings",
+        serde_json::json!({"MinimumLength": 8}),
+    );
+    store.store_declaration(&changed).await.unwrap();
+    let updated = store.get_declaration("passcode").await.unwrap().unwrap();
+    assert_eq!(updated.declaration.server_token, changed.server_token);
+    assert!(updated.updated_at >= stored.updated_at);
+
+    let listed = store.list_declarations().await.unwrap();
+    let identifiers: Vec<_> = listed
+        .iter()
+        .map(|r| r.declaration.identifier.as_str())
+        .collect();
+    assert_eq!(identifiers, ["activation", "passcode"]);
+
+    // Enrollments need not exist to be assigned declarations
+    assert!(store.assign_declaration(&a, "passcode").await.unwrap());
+    assert!(store.assign_declaration(&a, "activation").await.unwrap());
+    assert!(!store.assign_declaration(&a, "passcode").await.unwrap());
+    assert!(store.assign_declaration(&b, "passcode").await.unwrap());
+    let err = store.assign_declaration(&a, "missing").await.unwrap_err();
+    assert_eq!(
+        err.downcast_ref::<UnknownDeclaration>(),
+        Some(&UnknownDeclaration {
+            identifier: "missing".to_string(),
+        })
+    );
+
+    let assigned = store.assigned_declarations(&a).await.unwrap();
+    let identifiers: Vec<_> = assigned
+        .iter()
+        .map(|r| r.declaration.identifier.as_str())
+        .collect();
+    assert_eq!(identifiers, ["activation", "passcode"]);
+    assert_eq!(assigned[1].declaration, changed);
+
+    assert!(store.unassign_declaration(&a, "activation").await.unwrap());
+    assert!(!store.unassign_declaration(&a, "activation").await.unwrap());
+    assert_eq!(store.assigned_declarations(&a).await.unwrap().len(), 1);
+
+    // Deleting a declaration removes its assignments
+    assert!(store.delete_declaration("passcode").await.unwrap());
+    assert!(!store.delete_declaration("passcode").await.unwrap());
+    assert!(store.assigned_declarations(&a).await.unwrap().is_empty());
+    assert!(store.assigned_declarations(&b).await.unwrap().is_empty());
+    assert!(store.assign_declaration(&b, "activation").await.unwrap());
+
+    assert!(store.declaration_statuses(&a).await.unwrap().is_empty());
+    let status = |identifier: &str, valid: &str| DeclarationStatus {
+        identifier: identifier.to_string(),
+        active: valid == "valid",
+        valid: valid.to_string(),
+        server_token: "token".to_string(),
+        reasons: Vec::new(),
+    };
+    let mut invalid = status("passcode", "invalid");
+    invalid.reasons = vec![serde_json::json!({"code": "Error.ConfigurationCannotBeApplied"})];
+    store
+        .store_declaration_statuses(&a, &[invalid.clone(), status("activation", "valid")])
+        .await
+        .unwrap();
+    let statuses = store.declaration_statuses(&a).await.unwrap();
+    assert_eq!(statuses.len(), 2);
+    assert_eq!(statuses[0].status, status("activation", "valid"));
+    assert_eq!(statuses[1].status, invalid);
+    assert!(store.declaration_statuses(&b).await.unwrap().is_empty());
+
+    // Each report replaces the previous one
+    store
+        .store_declaration_statuses(&a, &[status("activation", "valid")])
+        .await
+        .unwrap();
+    assert_eq!(store.declaration_statuses(&a).await.unwrap().len(), 1);
+    store.store_declaration_statuses(&a, &[]).await.unwrap();
+    assert!(store.declaration_statuses(&a).await.unwrap().is_empty());
+}
+
+async fn status_items<S: AllStorage>(store: &S) {
+    let a = device("device-a");
+    let b = device("device-b");
+    let item = |item: &str, value: serde_json::Value| StatusItem {
+        item: item.to_string(),
+        value,
+    };
+    let names = |records: &[mdm_core::StatusItemRecord]| {
+        records
+            .iter()
+            .map(|record| record.item.clone())
+            .collect::<Vec<_>>()
+    };
+
+    let first = [
+        item("device.operating-system.version", serde_json::json!("14.4")),
+        item(
+            "softwareupdate.install-state",
+            serde_json::json!("downloading"),
+        ),
+        item(
+            "softwareupdate.pending-version",
+            serde_json::json!({"os-version": "14.4.1"}),
+        ),
+        // Neither an item under "softwareupdate" nor a LIKE wildcard match
+        item("software_update", serde_json::json!(true)),
+    ];
+    store.store_status_items(&a, &first).await.unwrap();
+    store
+        .store_status_items(
+            &b,
+            &[item(
+                "softwareupdate.install-state",
+                serde_json::json!("none"),
+            )],
+        )
+        .await
+        .unwrap();
+    let before = store
+        .status_items(&a, &StatusItemFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(
+        names(&before),
+        [
+            "device.operating-system.version",
+            "software_update",
+            "softwareupdate.install-state",
+            "softwareupdate.pending-version",
+        ]
+    );
+    assert_eq!(before[3].value, serde_json::json!({"os-version": "14.4.1"}));
+
+    store
+        .store_status_items(
+            &a,
+            &[
+                item(
+                    "device.operating-system.version",
+                    serde_json::json!("14.4.1"),
+                ),
+                item(
+                    "softwareupdate.install-state",
+                    serde_json::json!("downloading"),
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+    let filter = StatusItemFilter {
+        prefix: Some("softwareupdate".to_string()),
+    };
+    let updates = store.status_items(&a, &filter).await.unwrap();
+    assert_eq!(
+        names(&updates),
+        [
+            "softwareupdate.install-state",
+            "softwareupdate.pending-version"
+        ]
+    );
+    // Reporting the same value moves the report time but not the change
+    assert_eq!(updates[0].changed_at, before[2].changed_at);
+    assert!(updates[0].reported_at >= before[2].reported_at);
+
+    let filter = StatusItemFilter {
+        prefix: Some("device.operating-system.version".to_string()),
+    };
+    let version = store.status_items(&a, &filter).await.unwrap();
+    assert_eq!(version.len(), 1);
+    assert_eq!(version[0].value, serde_json::json!("14.4.1"));
+    assert!(version[0].changed_at >= before[0].changed_at);
+
+    let values = |changes: Vec<mdm_core::StatusItemChange>| {
+        changes
+            .into_iter()
+            .map(|change| change.value)
+            .collect::<Vec<_>>()
+    };
+    let history = store
+        .status_item_history(
+            &a,
+            "device.operating-system.version",
+            &StatusHistoryFilter::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(
+        values(history),
+        [serde_json::json!("14.4.1"), serde_json::json!("14.4")]
+    );
+    let history = store
+        .status_item_history(
+            &a,
+            "softwareupdate.install-state",
+            &StatusHistoryFilter::default(),
+        )
+        .await
+        .unwrap();
+    assert_eq!(values(history), [serde_json::json!("downloading")]);
+    let page = StatusHistoryFilter {
+        limit: Some(1),
+        offset: 1,
+    };
+    let history = store
+        .status_item_history(&a, "device.operating-system.version", &page)
+        .await
+        .unwrap();
+    assert_eq!(values(history), [serde_json::json!("14.4")]);
+
+    assert!(
+        store
+            .status_item_history(&b, "device.operating-system.version", &page)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    let other = store
+        .status_items(&b, &StatusItemFilter::default())
+        .await
+        .unwrap();
+    assert_eq!(other[0].value, serde_json::json!("none"));
+}
@@ -1,16 +1,31 @@
 //! MDM Storage Layer
 //!
-//! Diesel-based storage for MDM enrollments, commands, and push certificates.
+//! Diesel-based storage for MDM enrollments, commands, and push certificates,
+//! plus an in-memory store for tests and ephemeral servers.
 
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+mod memory;
 mod models;
+mod postgres;
 mod schema;
+mod shared;
 mod sqlite;
 mod traits;
 
+pub use memory::InMemoryStorage;
 pub use models::*;
+pub use postgres::PostgresStorage;
 pub use sqlite::SqliteStorage;
 pub use traits::*;
 
 use diesel_migrations::{EmbeddedMigrations, embed_migrations};
 
+/// SQLite migrations.
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations");
+
+/// PostgreSQL migrations.
+///
+/// Kept separate because the SQLite schema uses `AUTOINCREMENT` and `BLOB`.
+/// Schema changes need a migration in both directories.
+pub const POSTGRES_MIGRATIONS: EmbeddedMigrations = embed_migrations!("migrations_postgres");
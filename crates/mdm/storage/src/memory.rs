@@ -0,0 +1,869 @@
+//! In-memory storage implementation.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use mdm_core::{
+    CommandRecord, CommandResults, CommandState, Declaration, DeclarationRecord, DeclarationStatus,
+    DeclarationStatusRecord, DeviceInventory, EnrollId, EnrollType, EnrollmentRecord,
+    InventoryUpdate, PushCertSummary, PushInfo, QueuedCommand, StatusItem, StatusItemChange,
+    StatusItemRecord,
+};
+
+use crate::traits::*;
+
+/// Storage kept in process memory.
+///
+/// Nothing survives a restart, so this suits tests and ephemeral servers.
+/// Clones share the same data.
+#[derive(Clone, Default)]
+pub struct InMemoryStorage {
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    /// Enrollments by ID.
+    enrollments: BTreeMap<String, Enrollment>,
+    /// All commands, oldest first.
+    commands: Vec<StoredCommand>,
+    bootstrap_tokens: HashMap<String, Vec<u8>>,
+    push_certs: BTreeMap<String, PushCert>,
+    /// `(enrollment ID, SHA-256 cert hash)` pairs.
+    cert_auth: HashSet<(String, Vec<u8>)>,
+    /// Inventory by device ID.
+    inventory: BTreeMap<String, DeviceInventory>,
+    /// Declarations by identifier.
+    declarations: BTreeMap<String, DeclarationRecord>,
+    /// `(enrollment ID, declaration identifier)` pairs.
+    declaration_assignments: BTreeSet<(String, String)>,
+    /// Reported declaration states by enrollment ID, by identifier.
+    declaration_statuses: HashMap<String, Vec<DeclarationStatusRecord>>,
+    /// Latest status items by enrollment ID, by item.
+    status_items: HashMap<String, BTreeMap<String, StatusItemRecord>>,
+    /// Status item changes by `(enrollment ID, item)`, oldest first.
+    status_item_history: HashMap<(String, String), Vec<StatusItemChange>>,
+}
+
+struct Enrollment {
+    enroll_type: EnrollType,
+    parent_id: Option<String>,
+    topic: String,
+    push_magic: Option<String>,
+    push_token: Option<Vec<u8>>,
+    disabled: bool,
+    authenticate_raw: Option<Vec<u8>>,
+    token_update_raw: Option<Vec<u8>>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    updated_at: chrono::DateTime<chrono::Utc>,
+    push_invalid_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+struct StoredCommand {
+    enrollment_id: String,
+    uuid: String,
+    command: Vec<u8>,
+    request_type: String,
+    state: CommandState,
+    result: Option<Vec<u8>>,
+    created_at: chrono::DateTime<chrono::Utc>,
+    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+}
+
+impl StoredCommand {
+    fn record(&self) -> CommandRecord {
+        CommandRecord {
+            enrollment_id: self.enrollment_id.clone(),
+            uuid: self.uuid.clone(),
+            request_type: self.request_type.clone(),
+            state: self.state,
+            command: self.command.clone(),
+            result: self.result.clone(),
+            created_at: self.created_at,
+            expires_at: self.expires_at,
+        }
+    }
+
+    /// Whether the device has yet to answer the command.
+    fn is_unanswered(&self) -> bool {
+        matches!(
+            self.state,
+            CommandState::Pending | CommandState::Sent | CommandState::NotNow
+        )
+    }
+}
+
+struct PushCert {
+    cert_pem: String,
+    key_pem: String,
+    not_after: chrono::DateTime<chrono::Utc>,
+}
+
+impl InMemoryStorage {
+    /// Create an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        // A panic mid-update leaves the maps usable, so ignore poisoning
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Enrollment {
+    fn new(id: &EnrollId, topic: &str) -> Self {
+        let now = chrono::Utc::now();
+        Self {
+            enroll_type: id.enroll_type,
+            parent_id: id.parent_id.clone(),
+            topic: topic.to_string(),
+            push_magic: None,
+            push_token: None,
+            disabled: true,
+            authenticate_raw: None,
+            token_update_raw: None,
+            created_at: now,
+            updated_at: now,
+            push_invalid_at: None,
+        }
+    }
+
+    fn record(&self, id: &str) -> EnrollmentRecord {
+        EnrollmentRecord {
+            id: id.to_string(),
+            enroll_type: self.enroll_type,
+            parent_id: self.parent_id.clone(),
+            topic: self.topic.clone(),
+            disabled: self.disabled,
+            authenticate_raw: self.authenticate_raw.clone(),
+            token_update_raw: self.token_update_raw.clone(),
+            created_at: self.created_at,
+            updated_at: self.updated_at,
+            push_invalid_at: self.push_invalid_at,
+        }
+    }
+
+    fn push_info(&self, enrollment_id: &str) -> Option<PushInfo> {
+        if self.disabled || self.push_invalid_at.is_some() {
+            return None;
+        }
+        Some(PushInfo {
+            enrollment_id: enrollment_id.to_string(),
+            token: self.push_token.clone()?,
+            push_magic: self.push_magic.clone()?,
+            topic: self.topic.clone(),
+        })
+    }
+}
+
+impl CheckinStore for InMemoryStorage {
+    async fn store_authenticate(
+        &self,
+        id: &EnrollId,
+        msg: &mdm_core::Authenticate,
+    ) -> color_eyre::eyre::Result<()> {
+        let mut state = self.state();
+
+        state.commands.retain(|cmd| cmd.enrollment_id != id.id);
+
+        let enrollment = state
+            .enrollments
+            .entry(id.id.clone())
+            .or_insert_with(|| Enrollment::new(id, &msg.topic));
+        enrollment.topic = msg.topic.clone();
+        enrollment.disabled = true;
+        enrollment.authenticate_raw = Some(msg.raw.clone());
+        enrollment.updated_at = chrono::Utc::now();
+
+        Ok(())
+    }
+
+    async fn store_token_update(
+        &self,
+        id: &EnrollId,
+        msg: &mdm_core::TokenUpdate,
+    ) -> color_eyre::eyre::Result<()> {
+        let mut state = self.state();
+
+        // User channels send no Authenticate, so their first TokenUpdate
+        // creates the enrollment
+        let enrollment = state
+            .enrollments
+            .entry(id.id.clone())
+            .or_insert_with(|| Enrollment::new(id, &msg.topic));
+        enrollment.push_magic = Some(msg.push_magic.clone());
+        enrollment.push_token = Some(msg.token.clone());
+        enrollment.disabled = false;
+        enrollment.push_invalid_at = None;
+        enrollment.token_update_raw = Some(msg.raw.clone());
+        enrollment.updated_at = chrono::Utc::now();
+
+        Ok(())
+    }
+
+    async fn store_checkout(
+        &self,
+        id: &EnrollId,
+        _msg: &mdm_core::CheckOut,
+    ) -> color_eyre::eyre::Result<()> {
+        self.disable(id).await
+    }
+
+    async fn is_disabled(&self, id: &EnrollId) -> color_eyre::eyre::Result<bool> {
+        Ok(self
+            .state()
+            .enrollments
+            .get(&id.id)
+            .is_none_or(|enrollment| enrollment.disabled))
+    }
+
+    async fn disable(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+        for (enrollment_id, enrollment) in &mut self.state().enrollments {
+            if *enrollment_id == id.id || enrollment.parent_id.as_ref() == Some(&id.id) {
+                enrollment.disabled = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn get_enrollment(&self, id: &str) -> color_eyre::eyre::Result<Option<EnrollmentRecord>> {
+        Ok(self
+            .state()
+            .enrollments
+            .get(id)
+            .map(|enrollment| enrollment.record(id)))
+    }
+
+    async fn list_enrollments(
+        &self,
+        filter: &EnrollmentFilter,
+    ) -> color_eyre::eyre::Result<Vec<EnrollmentRecord>> {
+        Ok(self
+            .state()
+            .enrollments
+            .iter()
+            .filter(|(_, enrollment)| {
+                filter
+                    .enroll_type
+                    .is_none_or(|enroll_type| enrollment.enroll_type == enroll_type)
+                    && filter
+                        .disabled
+                        .is_none_or(|disabled| enrollment.disabled == disabled)
+                    && filter
+                        .topic
+                        .as_ref()
+                        .is_none_or(|topic| enrollment.topic == *topic)
+                    && filter
+                        .parent_id
+                        .as_ref()
+                        .is_none_or(|parent_id| enrollment.parent_id.as_ref() == Some(parent_id))
+            })
+            .skip(filter.offset as usize)
+            .take(filter.limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(|(id, enrollment)| enrollment.record(id))
+            .collect())
+    }
+}
+
+impl CommandStore for InMemoryStorage {
+    async fn enqueue_command(
+        &self,
+        id: &EnrollId,
+        command: &mdm_core::Command,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> color_eyre::eyre::Result<String> {
+        self.enqueue_commands(std::slice::from_ref(id), command, expires_at)
+            .await
+    }
+
+    async fn enqueue_commands(
+        &self,
+        ids: &[EnrollId],
+        command: &mdm_core::Command,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> color_eyre::eyre::Result<String> {
+        let mut state = self.state();
+
+        let uuid = &command.command_uuid;
+        let serialized = mdm_core::serialize_command(command)?;
+        let now = chrono::Utc::now();
+        let mut queued: Vec<StoredCommand> = Vec::with_capacity(ids.len());
+        for id in ids {
+            if state
+                .commands
+                .iter()
+                .chain(&queued)
+                .any(|cmd| cmd.enrollment_id == id.id && cmd.uuid == *uuid)
+            {
+                return Err(DuplicateCommand {
+                    enrollment_id: id.id.clone(),
+                    uuid: uuid.clone(),
+                }
+                .into());
+            }
+
+            queued.push(StoredCommand {
+                enrollment_id: id.id.clone(),
+                uuid: uuid.clone(),
+                command: serialized.clone(),
+                request_type: command.command.request_type.clone(),
+                state: CommandState::Pending,
+                result: None,
+                created_at: now,
+                expires_at,
+            });
+        }
+        state.commands.extend(queued);
+
+        Ok(uuid.clone())
+    }
+
+    async fn next_command(
+        &self,
+        id: &EnrollId,
+        skip_not_now: bool,
+    ) -> color_eyre::eyre::Result<Option<QueuedCommand>> {
+        let mut state = self.state();
+        let now = chrono::Utc::now();
+
+        for cmd in &mut state.commands {
+            if cmd.enrollment_id == id.id
+                && cmd.is_unanswered()
+                && cmd.expires_at.is_some_and(|t| t <= now)
+            {
+                cmd.state = CommandState::Expired;
+            }
+        }
+
+        let Some(cmd) = state.commands.iter_mut().find(|cmd| {
+            cmd.enrollment_id == id.id
+                && cmd.is_unanswered()
+                && !(skip_not_now && cmd.state == CommandState::NotNow)
+        }) else {
+            return Ok(None);
+        };
+        cmd.state = CommandState::Sent;
+
+        Ok(Some(QueuedCommand {
+            uuid: cmd.uuid.clone(),
+            command: cmd.command.clone(),
+            created_at: cmd.created_at,
+        }))
+    }
+
+    async fn store_result(
+        &self,
+        id: &EnrollId,
+        results: &CommandResults,
+    ) -> color_eyre::eyre::Result<()> {
+        let Some(new_state) = CommandState::from_status(results.status) else {
+            return Ok(());
+        };
+        let mut state = self.state();
+
+        if let Some(cmd) = state
+            .commands
+            .iter_mut()
+            .find(|cmd| cmd.enrollment_id == id.id && cmd.uuid == results.command_uuid)
+        {
+            cmd.state = new_state;
+            cmd.result = Some(results.raw.clone());
+        }
+
+        Ok(())
+    }
+
+    async fn cancel_command(&self, id: &EnrollId, uuid: &str) -> color_eyre::eyre::Result<bool> {
+        let mut state = self.state();
+
+        let Some(cmd) = state
+            .commands
+            .iter_mut()
+            .find(|cmd| cmd.enrollment_id == id.id && cmd.uuid == uuid && cmd.is_unanswered())
+        else {
+            return Ok(false);
+        };
+        cmd.state = CommandState::Cancelled;
+
+        Ok(true)
+    }
+
+    async fn cancel_request_type(
+        &self,
+        id: &EnrollId,
+        request_type: &str,
+        except: Option<&str>,
+    ) -> color_eyre::eyre::Result<Vec<String>> {
+        let mut cancelled = Vec::new();
+
+        for cmd in &mut self.state().commands {
+            if cmd.enrollment_id == id.id
+                && cmd.request_type == request_type
+                && except != Some(cmd.uuid.as_str())
+                && cmd.is_unanswered()
+            {
+                cmd.state = CommandState::Cancelled;
+                cancelled.push(cmd.uuid.clone());
+            }
+        }
+
+        Ok(cancelled)
+    }
+
+    async fn get_command(&self, uuid: &str) -> color_eyre::eyre::Result<Vec<CommandRecord>> {
+        Ok(self
+            .state()
+            .commands
+            .iter()
+            .filter(|cmd| cmd.uuid == uuid)
+            .map(StoredCommand::record)
+            .collect())
+    }
+
+    async fn list_commands(
+        &self,
+        id: &EnrollId,
+        filter: &CommandFilter,
+    ) -> color_eyre::eyre::Result<Vec<CommandRecord>> {
+        Ok(self
+            .state()
+            .commands
+            .iter()
+            .rev()
+            .filter(|cmd| cmd.enrollment_id == id.id)
+            .filter(|cmd| filter.state.is_none_or(|state| cmd.state == state))
+            .filter(|cmd| {
+                filter
+                    .request_type
+                    .as_ref()
+                    .is_none_or(|request_type| cmd.request_type == *request_type)
+            })
+            .skip(filter.offset as usize)
+            .take(filter.limit.map_or(usize::MAX, |limit| limit as usize))
+            .map(StoredCommand::record)
+            .collect())
+    }
+
+    async fn queue_depth(&self, id: &EnrollId) -> color_eyre::eyre::Result<u64> {
+        let now = chrono::Utc::now();
+        let count = self
+            .state()
+            .commands
+            .iter()
+            .filter(|cmd| cmd.enrollment_id == id.id && cmd.is_unanswered())
+            .filter(|cmd| cmd.expires_at.is_none_or(|expires_at| expires_at > now))
+            .count();
+
+        Ok(count as u64)
+    }
+
+    async fn clear_queue(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+        self.state()
+            .commands
+            .retain(|cmd| cmd.enrollment_id != id.id);
+
+        Ok(())
+    }
+}
+
+impl BootstrapTokenStore for InMemoryStorage {
+    async fn store_bootstrap_token(
+        &self,
+        id: &EnrollId,
+        token: &[u8],
+    ) -> color_eyre::eyre::Result<()> {
+        self.state()
+            .bootstrap_tokens
+            .insert(id.id.clone(), token.to_vec());
+
+        Ok(())
+    }
+
+    async fn get_bootstrap_token(
+        &self,
+        id: &EnrollId,
+    ) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+        Ok(self.state().bootstrap_tokens.get(&id.id).cloned())
+    }
+
+    async fn delete_bootstrap_token(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+        self.state().bootstrap_tokens.remove(&id.id);
+
+        Ok(())
+    }
+}
+
+impl PushStore for InMemoryStorage {
+    async fn get_push_info(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<PushInfo>> {
+        Ok(self
+            .state()
+            .enrollments
+            .get(&id.id)
+            .and_then(|enrollment| enrollment.push_info(&id.id)))
+    }
+
+    async fn get_push_infos(&self, ids: &[&EnrollId]) -> color_eyre::eyre::Result<Vec<PushInfo>> {
+        let state = self.state();
+
+        Ok(ids
+            .iter()
+            .filter_map(|id| state.enrollments.get(&id.id)?.push_info(&id.id))
+            .collect())
+    }
+
+    async fn mark_push_invalid(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+        if let Some(enrollment) = self.state().enrollments.get_mut(&id.id) {
+            enrollment
+                .push_invalid_at
+                .get_or_insert_with(chrono::Utc::now);
+        }
+
+        Ok(())
+    }
+}
+
+impl PushCertStore for InMemoryStorage {
+    async fn store_push_cert(
+        &self,
+        topic: &str,
+        cert_pem: &str,
+        key_pem: &str,
+    ) -> color_eyre::eyre::Result<()> {
+        let not_after = crate::shared::check_push_cert(topic, cert_pem, key_pem)?;
+
+        self.state().push_certs.insert(
+            topic.to_string(),
+            PushCert {
+                cert_pem: cert_pem.to_string(),
+                key_pem: key_pem.to_string(),
+                not_after,
+            },
+        );
+
+        Ok(())
+    }
+
+    async fn get_push_cert(
+        &self,
+        topic: &str,
+    ) -> color_eyre::eyre::Result<Option<(String, String)>> {
+        Ok(self
+            .state()
+            .push_certs
+            .get(topic)
+            .map(|cert| (cert.cert_pem.clone(), cert.key_pem.clone())))
+    }
+
+    async fn list_push_topics(&self) -> color_eyre::eyre::Result<Vec<String>> {
+        Ok(self.state().push_certs.keys().cloned().collect())
+    }
+
+    async fn list_push_certs(&self) -> color_eyre::eyre::Result<Vec<PushCertSummary>> {
+        Ok(self
+            .state()
+            .push_certs
+            .iter()
+            .map(|(topic, cert)| PushCertSummary {
+                topic: topic.clone(),
+                not_after: Some(cert.not_after),
+            })
+            .collect())
+    }
+}
+
+impl CertAuthStore for InMemoryStorage {
+    async fn associate_cert(
+        &self,
+        id: &EnrollId,
+        cert_hash: &[u8],
+    ) -> color_eyre::eyre::Result<()> {
+        self.state()
+            .cert_auth
+            .insert((id.id.clone(), cert_hash.to_vec()));
+
+        Ok(())
+    }
+
+    async fn has_cert_auth(
+        &self,
+        id: &EnrollId,
+        cert_hash: &[u8],
+    ) -> color_eyre::eyre::Result<bool> {
+        Ok(self
+            .state()
+            .cert_auth
+            .contains(&(id.id.clone(), cert_hash.to_vec())))
+    }
+
+    async fn rehash_legacy_cert(
+        &self,
+        _id: &EnrollId,
+        _legacy_hash: &[u8],
+        _cert_hash: &[u8],
+    ) -> color_eyre::eyre::Result<bool> {
+        // Certificates are only ever stored with SHA-256 hashes
+        Ok(false)
+    }
+}
+
+impl InventoryStore for InMemoryStorage {
+    async fn update_inventory(
+        &self,
+        device_id: &str,
+        update: &InventoryUpdate,
+    ) -> color_eyre::eyre::Result<()> {
+        self.state()
+            .inventory
+            .entry(device_id.to_string())
+            .or_insert_with(|| DeviceInventory {
+                device_id: device_id.to_string(),
+                ..Default::default()
+            })
+            .apply(update);
+
+        Ok(())
+    }
+
+    async fn get_inventory(
+        &self,
+        device_id: &str,
+    ) -> color_eyre::eyre::Result<Option<DeviceInventory>> {
+        Ok(self.state().inventory.get(device_id).cloned())
+    }
+
+    async fn list_inventory(
+        &self,
+        filter: &InventoryFilter,
+    ) -> color_eyre::eyre::Result<Vec<DeviceInventory>> {
+        fn matches<T: PartialEq + ?Sized>(wanted: Option<&T>, value: Option<&T>) -> bool {
+            wanted.is_none() || wanted == value
+        }
+
+        Ok(self
+            .state()
+            .inventory
+            .values()
+            .filter(|device| {
+                matches(
+                    filter.serial_number.as_deref(),
+                    device.serial_number.as_deref(),
+                ) && matches(filter.model.as_deref(), device.model.as_deref())
+                    && matches(filter.os_version.as_deref(), device.os_version.as_deref())
+                    && matches(
+                        filter.filevault_enabled.as_ref(),
+                        device.filevault_enabled.as_ref(),
+                    )
+                    && matches(filter.sip_enabled.as_ref(), device.sip_enabled.as_ref())
+            })
+            .skip(filter.offset as usize)
+            .take(filter.limit.map_or(usize::MAX, |limit| limit as usize))
+            .cloned()
+            .collect())
+    }
+}
+
+impl DeclarationStore for InMemoryStorage {
+    async fn store_declaration(&self, declaration: &Declaration) -> color_eyre::eyre::Result<()> {
+        let mut state = self.state();
+        let unchanged = state
+            .declarations
+            .get(&declaration.identifier)
+            .is_some_and(|record| record.declaration.server_token == declaration.server_token);
+        if !unchanged {
+            state.declarations.insert(
+                declaration.identifier.clone(),
+                DeclarationRecord {
+                    declaration: declaration.clone(),
+                    updated_at: chrono::Utc::now(),
+                },
+            );
+        }
+        Ok(())
+    }
+
+    async fn get_declaration(
+        &self,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<Option<DeclarationRecord>> {
+        Ok(self.state().declarations.get(identifier).cloned())
+    }
+
+    async fn list_declarations(&self) -> color_eyre::eyre::Result<Vec<DeclarationRecord>> {
+        Ok(self.state().declarations.values().cloned().collect())
+    }
+
+    async fn delete_declaration(&self, identifier: &str) -> color_eyre::eyre::Result<bool> {
+        let mut state = self.state();
+        state
+            .declaration_assignments
+            .retain(|(_, assigned)| assigned != identifier);
+        Ok(state.declarations.remove(identifier).is_some())
+    }
+
+    async fn assign_declaration(
+        &self,
+        id: &EnrollId,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<bool> {
+        let mut state = self.state();
+        if !state.declarations.contains_key(identifier) {
+            return Err(UnknownDeclaration {
+                identifier: identifier.to_string(),
+            }
+            .into());
+        }
+        Ok(state
+            .declaration_assignments
+            .insert((id.id.clone(), identifier.to_string())))
+    }
+
+    async fn unassign_declaration(
+        &self,
+        id: &EnrollId,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<bool> {
+        Ok(self
+            .state()
+            .declaration_assignments
+            .remove(&(id.id.clone(), identifier.to_string())))
+    }
+
+    async fn assigned_declarations(
+        &self,
+        id: &EnrollId,
+    ) -> color_eyre::eyre::Result<Vec<DeclarationRecord>> {
+        let state = self.state();
+        Ok(state
+            .declaration_assignments
+            .iter()
+            .filter(|(enrollment_id, _)| *enrollment_id == id.id)
+            .filter_map(|(_, identifier)| state.declarations.get(identifier).cloned())
+            .collect())
+    }
+
+    async fn store_declaration_statuses(
+        &self,
+        id: &EnrollId,
+        statuses: &[DeclarationStatus],
+    ) -> color_eyre::eyre::Result<()> {
+        let now = chrono::Utc::now();
+        let mut records: Vec<_> = statuses
+            .iter()
+            .map(|status| DeclarationStatusRecord {
+                status: status.clone(),
+                updated_at: now,
+            })
+            .collect();
+        records.sort_by(|a, b| a.status.identifier.cmp(&b.status.identifier));
+        self.state()
+            .declaration_statuses
+            .insert(id.id.clone(), records);
+        Ok(())
+    }
+
+    async fn declaration_statuses(
+        &self,
+        id: &EnrollId,
+    ) -> color_eyre::eyre::Result<Vec<DeclarationStatusRecord>> {
+        Ok(self
+            .state()
+            .declaration_statuses
+            .get(&id.id)
+            .cloned()
+            .unwrap_or_default())
+    }
+}
+
+impl StatusItemStore for InMemoryStorage {
+    async fn store_status_items(
+        &self,
+        id: &EnrollId,
+        items: &[StatusItem],
+    ) -> color_eyre::eyre::Result<()> {
+        let now = chrono::Utc::now();
+        let mut state = self.state();
+        let state = &mut *state;
+        let latest = state.status_items.entry(id.id.clone()).or_default();
+        for item in items {
+            match latest.get_mut(&item.item) {
+                Some(record) if record.value == item.value => record.reported_at = now,
+                _ => {
+                    latest.insert(
+                        item.item.clone(),
+                        StatusItemRecord {
+                            item: item.item.clone(),
+                            value: item.value.clone(),
+                            changed_at: now,
+                            reported_at: now,
+                        },
+                    );
+                    state
+                        .status_item_history
+                        .entry((id.id.clone(), item.item.clone()))
+                        .or_default()
+                        .push(StatusItemChange {
+                            value: item.value.clone(),
+                            reported_at: now,
+                        });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn status_items(
+        &self,
+        id: &EnrollId,
+        filter: &StatusItemFilter,
+    ) -> color_eyre::eyre::Result<Vec<StatusItemRecord>> {
+        let state = self.state();
+        let Some(latest) = state.status_items.get(&id.id) else {
+            return Ok(Vec::new());
+        };
+        Ok(latest
+            .values()
+            .filter(|record| match &filter.prefix {
+                Some(prefix) => record
+                    .item
+                    .strip_prefix(prefix.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('.')),
+                None => true,
+            })
+            .cloned()
+            .collect())
+    }
+
+    async fn status_item_history(
+        &self,
+        id: &EnrollId,
+        item: &str,
+        filter: &StatusHistoryFilter,
+    ) -> color_eyre::eyre::Result<Vec<StatusItemChange>> {
+        let state = self.state();
+        let Some(history) = state
+            .status_item_history
+            .get(&(id.id.clone(), item.to_string()))
+        else {
+            return Ok(Vec::new());
+        };
+        Ok(history
+            .iter()
+            .rev()
+            .skip(filter.offset as usize)
+            .take(filter.limit.map_or(usize::MAX, |limit| limit as usize))
+            .cloned()
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_conformance() {
+        crate::conformance::run_conformance(InMemoryStorage::new).await;
+    }
+}
@@ -2,7 +2,10 @@
 
 use diesel::prelude::*;
 
-use crate::schema::{bootstrap_tokens, cert_auth, commands, enrollments, push_certs};
+use crate::schema::{
+    bootstrap_tokens, cert_auth, commands, declaration_assignments, declaration_statuses,
+    declarations, enrollments, inventory, push_certs, status_item_history, status_items,
+};
 
 /// Enrollment record.
 #[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
@@ -20,6 +23,25 @@ pub struct EnrollmentRow {
     pub token_update_raw: Option<Vec<u8>>,
     pub created_at: chrono::NaiveDateTime,
     pub updated_at: chrono::NaiveDateTime,
+    pub push_invalid_at: Option<chrono::NaiveDateTime>,
+}
+
+impl EnrollmentRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_record(self) -> color_eyre::eyre::Result<mdm_core::EnrollmentRecord> {
+        Ok(mdm_core::EnrollmentRecord {
+            enroll_type: self.enroll_type.parse()?,
+            id: self.id,
+            parent_id: self.parent_id,
+            topic: self.topic,
+            disabled: self.disabled,
+            authenticate_raw: self.authenticate_raw,
+            token_update_raw: self.token_update_raw,
+            created_at: self.created_at.and_utc(),
+            updated_at: self.updated_at.and_utc(),
+            push_invalid_at: self.push_invalid_at.map(|t| t.and_utc()),
+        })
+    }
 }
 
 /// New enrollment for insertion.
@@ -51,6 +73,24 @@ pub struct CommandRow {
     pub status: String,
     pub result: Option<Vec<u8>>,
     pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub request_type: String,
+}
+
+impl CommandRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_record(self) -> color_eyre::eyre::Result<mdm_core::CommandRecord> {
+        Ok(mdm_core::CommandRecord {
+            state: self.status.parse()?,
+            enrollment_id: self.enrollment_id,
+            uuid: self.uuid,
+            request_type: self.request_type,
+            command: self.command,
+            result: self.result,
+            created_at: self.created_at.and_utc(),
+            expires_at: self.expires_at.map(|t| t.and_utc()),
+        })
+    }
 }
 
 /// New command for insertion.
@@ -62,6 +102,8 @@ pub struct NewCommand<'a> {
     pub command: &'a [u8],
     pub status: &'a str,
     pub created_at: chrono::NaiveDateTime,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub request_type: &'a str,
 }
 
 /// Push certificate record.
@@ -107,6 +149,7 @@ pub struct CertAuthRow {
     pub id: i32,
     pub enrollment_id: String,
     pub cert_hash: Vec<u8>,
+    pub hash_algorithm: String,
 }
 
 /// New certificate auth for insertion.
@@ -115,4 +158,272 @@ pub struct CertAuthRow {
 pub struct NewCertAuth<'a> {
     pub enrollment_id: &'a str,
     pub cert_hash: &'a [u8],
+    pub hash_algorithm: &'a str,
+}
+
+/// Device inventory record.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = inventory, primary_key(device_id))]
+pub struct InventoryRow {
+    pub device_id: String,
+    pub serial_number: Option<String>,
+    pub device_name: Option<String>,
+    pub model: Option<String>,
+    pub model_name: Option<String>,
+    pub product_name: Option<String>,
+    pub os_version: Option<String>,
+    pub build_version: Option<String>,
+    pub filevault_enabled: Option<bool>,
+    pub sip_enabled: Option<bool>,
+    pub last_checkin_at: Option<chrono::NaiveDateTime>,
+}
+
+impl From<InventoryRow> for mdm_core::DeviceInventory {
+    fn from(row: InventoryRow) -> Self {
+        Self {
+            device_id: row.device_id,
+            serial_number: row.serial_number,
+            device_name: row.device_name,
+            model: row.model,
+            model_name: row.model_name,
+            product_name: row.product_name,
+            os_version: row.os_version,
+            build_version: row.build_version,
+            filevault_enabled: row.filevault_enabled,
+            sip_enabled: row.sip_enabled,
+            last_checkin_at: row.last_checkin_at.map(|t| t.and_utc()),
+        }
+    }
+}
+
+/// Inventory update for upserting.
+///
+/// As a changeset, `None` fields are skipped, so stored values the update
+/// doesn't report are kept.
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = inventory, primary_key(device_id))]
+pub struct NewInventory<'a> {
+    pub device_id: &'a str,
+    pub serial_number: Option<&'a str>,
+    pub device_name: Option<&'a str>,
+    pub model: Option<&'a str>,
+    pub model_name: Option<&'a str>,
+    pub product_name: Option<&'a str>,
+    pub os_version: Option<&'a str>,
+    pub build_version: Option<&'a str>,
+    pub filevault_enabled: Option<bool>,
+    pub sip_enabled: Option<bool>,
+    pub last_checkin_at: Option<chrono::NaiveDateTime>,
+}
+
+impl<'a> NewInventory<'a> {
+    pub fn new(device_id: &'a str, update: &'a mdm_core::InventoryUpdate) -> Self {
+        Self {
+            device_id,
+            serial_number: update.serial_number.as_deref(),
+            device_name: update.device_name.as_deref(),
+            model: update.model.as_deref(),
+            model_name: update.model_name.as_deref(),
+            product_name: update.product_name.as_deref(),
+            os_version: update.os_version.as_deref(),
+            build_version: update.build_version.as_deref(),
+            filevault_enabled: update.filevault_enabled,
+            sip_enabled: update.sip_enabled,
+            last_checkin_at: update.last_checkin_at.map(|t| t.naive_utc()),
+        }
+    }
+}
+
+/// DDM declaration record.
+#[derive(Debug, Clone, Queryable, Selectable, Identifiable)]
+#[diesel(table_name = declarations, primary_key(identifier))]
+pub struct DeclarationRow {
+    pub identifier: String,
+    pub declaration_type: String,
+    pub server_token: String,
+    pub payload: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl DeclarationRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_record(self) -> color_eyre::eyre::Result<mdm_core::DeclarationRecord> {
+        Ok(mdm_core::DeclarationRecord {
+            declaration: mdm_core::Declaration {
+                payload: serde_json::from_str(&self.payload)?,
+                declaration_type: self.declaration_type,
+                identifier: self.identifier,
+                server_token: self.server_token,
+            },
+            updated_at: self.updated_at.and_utc(),
+        })
+    }
+}
+
+/// DDM declaration for upserting.
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = declarations, primary_key(identifier))]
+pub struct NewDeclaration<'a> {
+    pub identifier: &'a str,
+    pub declaration_type: &'a str,
+    pub server_token: &'a str,
+    pub payload: String,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl<'a> NewDeclaration<'a> {
+    pub fn new(
+        declaration: &'a mdm_core::Declaration,
+        updated_at: chrono::NaiveDateTime,
+    ) -> color_eyre::eyre::Result<Self> {
+        Ok(Self {
+            identifier: &declaration.identifier,
+            declaration_type: &declaration.declaration_type,
+            server_token: &declaration.server_token,
+            payload: serde_json::to_string(&declaration.payload)?,
+            updated_at,
+        })
+    }
+}
+
+/// Declaration assigned to an enrollment.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = declaration_assignments)]
+pub struct NewDeclarationAssignment<'a> {
+    pub enrollment_id: &'a str,
+    pub identifier: &'a str,
+}
+
+/// Reported declaration status record.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = declaration_statuses)]
+pub struct DeclarationStatusRow {
+    pub enrollment_id: String,
+    pub identifier: String,
+    pub active: bool,
+    pub valid: String,
+    pub server_token: String,
+    pub reasons: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl DeclarationStatusRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_record(self) -> color_eyre::eyre::Result<mdm_core::DeclarationStatusRecord> {
+        let reasons = match self.reasons {
+            Some(reasons) => serde_json::from_str(&reasons)?,
+            None => Vec::new(),
+        };
+        Ok(mdm_core::DeclarationStatusRecord {
+            status: mdm_core::DeclarationStatus {
+                identifier: self.identifier,
+                active: self.active,
+                valid: self.valid,
+                server_token: self.server_token,
+                reasons,
+            },
+            updated_at: self.updated_at.and_utc(),
+        })
+    }
+}
+
+/// Reported declaration status for insertion.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = declaration_statuses)]
+pub struct NewDeclarationStatus<'a> {
+    pub enrollment_id: &'a str,
+    pub identifier: &'a str,
+    pub active: bool,
+    pub valid: &'a str,
+    pub server_token: &'a str,
+    pub reasons: Option<String>,
+    pub updated_at: chrono::NaiveDateTime,
+}
+
+impl<'a> NewDeclarationStatus<'a> {
+    pub fn new(
+        enrollment_id: &'a str,
+        status: &'a mdm_core::DeclarationStatus,
+        updated_at: chrono::NaiveDateTime,
+    ) -> color_eyre::eyre::Result<Self> {
+        let reasons = if status.reasons.is_empty() {
+            None
+        } else {
+            Some(serde_json::to_string(&status.reasons)?)
+        };
+        Ok(Self {
+            enrollment_id,
+            identifier: &status.identifier,
+            active: status.active,
+            valid: &status.valid,
+            server_token: &status.server_token,
+            reasons,
+            updated_at,
+        })
+    }
+}
+
+/// Latest value of a DDM status item.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = status_items)]
+pub struct StatusItemRow {
+    pub enrollment_id: String,
+    pub item: String,
+    pub value: String,
+    pub changed_at: chrono::NaiveDateTime,
+    pub reported_at: chrono::NaiveDateTime,
+}
+
+impl StatusItemRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_record(self) -> color_eyre::eyre::Result<mdm_core::StatusItemRecord> {
+        Ok(mdm_core::StatusItemRecord {
+            value: serde_json::from_str(&self.value)?,
+            item: self.item,
+            changed_at: self.changed_at.and_utc(),
+            reported_at: self.reported_at.and_utc(),
+        })
+    }
+}
+
+/// Latest value of a DDM status item for upserting.
+#[derive(Debug, Clone, Insertable, AsChangeset)]
+#[diesel(table_name = status_items, primary_key(enrollment_id, item))]
+pub struct NewStatusItem<'a> {
+    pub enrollment_id: &'a str,
+    pub item: &'a str,
+    pub value: &'a str,
+    pub changed_at: chrono::NaiveDateTime,
+    pub reported_at: chrono::NaiveDateTime,
+}
+
+/// A value a DDM status item changed to.
+#[derive(Debug, Clone, Queryable, Selectable)]
+#[diesel(table_name = status_item_history)]
+pub struct StatusItemHistoryRow {
+    pub id: i32,
+    pub enrollment_id: String,
+    pub item: String,
+    pub value: String,
+    pub reported_at: chrono::NaiveDateTime,
+}
+
+impl StatusItemHistoryRow {
+    /// Convert to the storage-independent record.
+    pub(crate) fn into_change(self) -> color_eyre::eyre::Result<mdm_core::StatusItemChange> {
+        Ok(mdm_core::StatusItemChange {
+            value: serde_json::from_str(&self.value)?,
+            reported_at: self.reported_at.and_utc(),
+        })
+    }
+}
+
+/// A status item change for insertion.
+#[derive(Debug, Clone, Insertable)]
+#[diesel(table_name = status_item_history)]
+pub struct NewStatusItemHistory<'a> {
+    pub enrollment_id: &'a str,
+    pub item: &'a str,
+    pub value: &'a str,
+    pub reported_at: chrono::NaiveDateTime,
 }
@@ -0,0 +1,233 @@
+//! PostgreSQL storage implementation.
+
+use color_eyre::eyre::WrapErr as _;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::r2d2::{ConnectionManager, Pool};
+
+use crate::models::CommandRow;
+use crate::schema::commands;
+use mdm_core::EnrollId;
+
+type PgPool = Pool<ConnectionManager<PgConnection>>;
+
+/// PostgreSQL-based storage.
+///
+/// Safe to share one database between several server replicas.
+#[derive(Clone)]
+pub struct PostgresStorage {
+    pool: PgPool,
+}
+
+impl PostgresStorage {
+    /// Create a new PostgreSQL storage from a `postgres://` URL.
+    pub fn new(database_url: &str) -> color_eyre::eyre::Result<Self> {
+        let manager = ConnectionManager::<PgConnection>::new(database_url);
+        let pool = Pool::builder()
+            .max_size(10)
+            .build(manager)
+            .wrap_err("failed to create connection pool")?;
+
+        Ok(Self { pool })
+    }
+
+    /// Run migrations.
+    pub fn run_migrations(&self) -> color_eyre::eyre::Result<()> {
+        use diesel_migrations::MigrationHarness as _;
+
+        let mut conn = self
+            .pool
+            .get()
+            .wrap_err("failed to get connection for migrations")?;
+
+        conn.run_pending_migrations(crate::POSTGRES_MIGRATIONS)
+            .map_err(|e| color_eyre::eyre::eyre!("migration failed: {}", e))?;
+
+        Ok(())
+    }
+
+    fn conn(
+        &self,
+    ) -> color_eyre::eyre::Result<diesel::r2d2::PooledConnection<ConnectionManager<PgConnection>>>
+    {
+        self.pool
+            .get()
+            .wrap_err("failed to get database connection")
+    }
+
+    /// Oldest deliverable command for an enrollment, locked for update.
+    ///
+    /// Rows locked by another replica's transaction are skipped rather than
+    /// waited on, so concurrent check-ins never block each other.
+    fn next_pending_command(
+        conn: &mut PgConnection,
+        id: &EnrollId,
+        skip_not_now: bool,
+    ) -> diesel::QueryResult<Option<CommandRow>> {
+        commands::table
+            .filter(commands::enrollment_id.eq(&id.id))
+            .filter(commands::status.eq_any(crate::shared::deliverable_states(skip_not_now)))
+            .order(commands::id.asc())
+            .for_update()
+            .skip_locked()
+            .first(conn)
+            .optional()
+    }
+}
+
+crate::shared::impl_diesel_storage!(PostgresStorage, PgConnection);
+
+#[cfg(test)]
+pub(crate) mod testing {
+    use std::path::PathBuf;
+    use std::process::Command;
+
+    use diesel::prelude::*;
+
+    /// A PostgreSQL schema for one test.
+    ///
+    /// Uses the server at `MDM_TEST_POSTGRES_URL` if set, otherwise spawns a
+    /// throwaway server with `initdb` and `pg_ctl`. Each test gets its own
+    /// schema, dropped along with any spawned server. Tests using it are
+    /// `#[ignore]`d; run them with `cargo test -p mdm-storage -- --ignored`.
+    pub(crate) struct TestPostgres {
+        /// Admin URL of the server.
+        server_url: String,
+        schema: String,
+        /// Data directory of a server we spawned.
+        data_dir: Option<PathBuf>,
+    }
+
+    impl TestPostgres {
+        /// Panics if no server is available.
+        pub(crate) fn start() -> Self {
+            let (server_url, data_dir) = match std::env::var("MDM_TEST_POSTGRES_URL") {
+                Ok(url) => (url, None),
+                Err(_) => match spawn_server() {
+                    Ok((url, dir)) => (url, Some(dir)),
+                    Err(e) => panic!(
+                        "no PostgreSQL server; set MDM_TEST_POSTGRES_URL or put initdb and \
+                         pg_ctl on PATH: {e}"
+                    ),
+                },
+            };
+
+            let schema = format!("mdm_test_{}", uuid::Uuid::new_v4().simple());
+            let mut conn = PgConnection::establish(&server_url).expect("connect to PostgreSQL");
+            diesel::sql_query(format!("CREATE SCHEMA {schema}"))
+                .execute(&mut conn)
+                .expect("create test schema");
+
+            Self {
+                server_url,
+                schema,
+                data_dir,
+            }
+        }
+
+        /// URL whose `search_path` is the test schema.
+        pub(crate) fn url(&self) -> String {
+            let sep = if self.server_url.contains('?') {
+                '&'
+            } else {
+                '?'
+            };
+            format!(
+                "{}{sep}options=-csearch_path%3D{}",
+                self.server_url, self.schema
+            )
+        }
+    }
+
+    impl Drop for TestPostgres {
+        fn drop(&mut self) {
+            if let Some(dir) = &self.data_dir {
+                let _ = Command::new("pg_ctl")
+                    .args(["stop", "-m", "immediate", "-D"])
+                    .arg(dir)
+                    .output();
+                let _ = std::fs::remove_dir_all(dir);
+            } else if let Ok(mut conn) = PgConnection::establish(&self.server_url) {
+                let _ = diesel::sql_query(format!("DROP SCHEMA {} CASCADE", self.schema))
+                    .execute(&mut conn);
+            }
+        }
+    }
+
+    /// Start a trust-auth server on a free local port.
+    fn spawn_server() -> Result<(String, PathBuf), String> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .and_then(|l| l.local_addr())
+            .map_err(|e| e.to_string())?
+            .port();
+        let dir = std::env::temp_dir().join(format!("mdm-pg-{}", uuid::Uuid::new_v4().simple()));
+
+        let run = |cmd: &mut Command| -> Result<(), String> {
+            let output = cmd.output().map_err(|e| format!("{cmd:?}: {e}"))?;
+            if output.status.success() {
+                Ok(())
+            } else {
+                Err(format!(
+                    "{cmd:?}: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ))
+            }
+        };
+
+        let started = run(Command::new("initdb")
+            .args(["-U", "postgres", "--auth=trust", "-D"])
+            .arg(&dir))
+        .and_then(|()| {
+            run(Command::new("pg_ctl")
+                .args(["start", "-w", "-D"])
+                .arg(&dir)
+                .arg("-l")
+                .arg(dir.join("server.log"))
+                .arg("-o")
+                .arg(format!(
+                    "-p {port} -k {} -c listen_addresses=127.0.0.1",
+                    dir.display()
+                )))
+        });
+        if let Err(e) = started {
+            let _ = std::fs::remove_dir_all(&dir);
+            return Err(e);
+        }
+
+        Ok((
+            format!("postgres://postgres@127.0.0.1:{port}/postgres"),
+            dir,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::testing::TestPostgres;
+    use super::*;
+
+    /// Empty every migrated table, listed from the catalog so new
+    /// migrations are covered.
+    const TRUNCATE_ALL: &str = "DO $$ BEGIN EXECUTE (
+        SELECT 'TRUNCATE ' || string_agg(quote_ident(tablename), ', ')
+            || ' RESTART IDENTITY CASCADE'
+        FROM pg_tables
+        WHERE schemaname = current_schema()
+            AND tablename <> '__diesel_schema_migrations'
+    ); END $$";
+
+    #[tokio::test]
+    #[ignore = "needs PostgreSQL"]
+    async fn test_conformance() {
+        let pg = TestPostgres::start();
+        let storage = PostgresStorage::new(&pg.url()).unwrap();
+        storage.run_migrations().unwrap();
+
+        crate::conformance::run_conformance(|| {
+            let mut conn = storage.conn().unwrap();
+            diesel::sql_query(TRUNCATE_ALL).execute(&mut conn).unwrap();
+            storage.clone()
+        })
+        .await;
+    }
+}
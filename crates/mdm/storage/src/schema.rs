@@ -14,6 +14,7 @@ diesel::table! {
         token_update_raw -> Nullable<Binary>,
         created_at -> Timestamp,
         updated_at -> Timestamp,
+        push_invalid_at -> Nullable<Timestamp>,
     }
 }
 
@@ -26,6 +27,8 @@ diesel::table! {
         status -> Text,
         result -> Nullable<Binary>,
         created_at -> Timestamp,
+        expires_at -> Nullable<Timestamp>,
+        request_type -> Text,
     }
 }
 
@@ -50,11 +53,78 @@ diesel::table! {
         id -> Integer,
         enrollment_id -> Text,
         cert_hash -> Binary,
+        hash_algorithm -> Text,
+    }
+}
+
+diesel::table! {
+    inventory (device_id) {
+        device_id -> Text,
+        serial_number -> Nullable<Text>,
+        device_name -> Nullable<Text>,
+        model -> Nullable<Text>,
+        model_name -> Nullable<Text>,
+        product_name -> Nullable<Text>,
+        os_version -> Nullable<Text>,
+        build_version -> Nullable<Text>,
+        filevault_enabled -> Nullable<Bool>,
+        sip_enabled -> Nullable<Bool>,
+        last_checkin_at -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    declarations (identifier) {
+        identifier -> Text,
+        declaration_type -> Text,
+        server_token -> Text,
+        payload -> Text,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    declaration_assignments (enrollment_id, identifier) {
+        enrollment_id -> Text,
+        identifier -> Text,
+    }
+}
+
+diesel::table! {
+    declaration_statuses (enrollment_id, identifier) {
+        enrollment_id -> Text,
+        identifier -> Text,
+        active -> Bool,
+        valid -> Text,
+        server_token -> Text,
+        reasons -> Nullable<Text>,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    status_items (enrollment_id, item) {
+        enrollment_id -> Text,
+        item -> Text,
+        value -> Text,
+        changed_at -> Timestamp,
+        reported_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    status_item_history (id) {
+        id -> Integer,
+        enrollment_id -> Text,
+        item -> Text,
+        value -> Text,
+        reported_at -> Timestamp,
     }
 }
 
 diesel::joinable!(commands -> enrollments (enrollment_id));
 diesel::joinable!(bootstrap_tokens -> enrollments (enrollment_id));
+diesel::joinable!(declaration_assignments -> declarations (identifier));
 
 diesel::allow_tables_to_appear_in_same_query!(
     enrollments,
@@ -62,4 +132,10 @@ diesel::allow_tables_to_appear_in_same_query!(
     push_certs,
     bootstrap_tokens,
     cert_auth,
+    inventory,
+    declarations,
+    declaration_assignments,
+    declaration_statuses,
+    status_items,
+    status_item_history,
 );
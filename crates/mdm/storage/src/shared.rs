@@ -0,0 +1,1348 @@
+//! Storage trait implementations shared by the diesel backends.
+//!
+//! The diesel query DSL is the same for SQLite and PostgreSQL, so the trait
+//! impls are written once and expanded per backend. Each backend provides
+//! `conn()` as an inherent method and `next_pending_command()` as an
+//! associated function, which runs inside a transaction. Queries block, so
+//! they run on tokio's blocking pool.
+
+use mdm_core::CommandState;
+
+/// Enrollment ID, push magic, push token, and topic.
+pub(crate) type PushInfoColumns = (String, Option<String>, Option<Vec<u8>>, String);
+
+/// Check that a push certificate is for `topic` and matches its key.
+///
+/// Returns the certificate's expiry.
+pub(crate) fn check_push_cert(
+    topic: &str,
+    cert_pem: &str,
+    key_pem: &str,
+) -> color_eyre::eyre::Result<chrono::DateTime<chrono::Utc>> {
+    let cert_der = mdm_crypto::parse_pem_cert(cert_pem)?;
+    let info = mdm_crypto::parse_push_cert(&cert_der)?;
+    if info.topic != topic {
+        color_eyre::eyre::bail!(
+            "push certificate topic {} does not match {}",
+            info.topic,
+            topic
+        );
+    }
+    mdm_crypto::verify_cert_key(&cert_der, key_pem)?;
+
+    Ok(info.not_after)
+}
+
+/// States [`next_command`](crate::CommandStore::next_command) may deliver.
+pub(crate) fn deliverable_states(skip_not_now: bool) -> Vec<&'static str> {
+    let mut states = vec![CommandState::Pending.as_str(), CommandState::Sent.as_str()];
+    if !skip_not_now {
+        states.push(CommandState::NotNow.as_str());
+    }
+    states
+}
+
+/// Implement every [`AllStorage`](crate::AllStorage) trait for a diesel
+/// backend whose connections are `$conn`.
+macro_rules! impl_diesel_storage {
+    ($storage:ident, $conn:ty) => {
+        const _: () = {
+            use color_eyre::eyre::WrapErr as _;
+            use diesel::prelude::*;
+
+            use mdm_core::{CommandState, EnrollId, PushCertSummary, PushInfo, QueuedCommand};
+
+            use $crate::models::*;
+            use $crate::schema::*;
+            use $crate::shared::PushInfoColumns;
+            use $crate::traits::*;
+
+            impl $storage {
+                /// Run `f` with a pooled connection on the blocking pool.
+                async fn blocking<T, F>(&self, f: F) -> color_eyre::eyre::Result<T>
+                where
+                    T: Send + 'static,
+                    F: FnOnce(&mut $conn) -> color_eyre::eyre::Result<T> + Send + 'static,
+                {
+                    let storage = self.clone();
+                    tokio::task::spawn_blocking(move || f(&mut *storage.conn()?))
+                        .await
+                        .wrap_err("storage task failed")?
+                }
+            }
+
+            impl CheckinStore for $storage {
+                async fn store_authenticate(
+                    &self,
+                    id: &EnrollId,
+                    msg: &mdm_core::Authenticate,
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.clone();
+                    let topic = msg.topic.clone();
+                    let raw = msg.raw.clone();
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+
+                        // Clear command queue first
+                        diesel::delete(commands::table.filter(commands::enrollment_id.eq(&id.id)))
+                            .execute(conn)
+                            .wrap_err("failed to clear command queue")?;
+
+                        // Upsert enrollment (disabled until TokenUpdate)
+                        let new_enrollment = NewEnrollment {
+                            id: &id.id,
+                            enroll_type: id.enroll_type.as_str(),
+                            device_id: Some(&id.id),
+                            parent_id: id.parent_id.as_deref(),
+                            topic: &topic,
+                            push_magic: None,
+                            push_token: None,
+                            disabled: true,
+                            authenticate_raw: Some(&raw),
+                            token_update_raw: None,
+                            created_at: now,
+                            updated_at: now,
+                        };
+
+                        diesel::insert_into(enrollments::table)
+                            .values(&new_enrollment)
+                            .on_conflict(enrollments::id)
+                            .do_update()
+                            .set((
+                                enrollments::topic.eq(&topic),
+                                enrollments::disabled.eq(true),
+                                enrollments::authenticate_raw.eq(Some(&raw)),
+                                enrollments::updated_at.eq(now),
+                            ))
+                            .execute(conn)
+                            .wrap_err("failed to store authenticate")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn store_token_update(
+                    &self,
+                    id: &EnrollId,
+                    msg: &mdm_core::TokenUpdate,
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.clone();
+                    let msg = msg.clone();
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+
+                        // User channels send no Authenticate, so their first
+                        // TokenUpdate creates the enrollment
+                        let new_enrollment = NewEnrollment {
+                            id: &id.id,
+                            enroll_type: id.enroll_type.as_str(),
+                            device_id: Some(id.parent_id.as_deref().unwrap_or(&id.id)),
+                            parent_id: id.parent_id.as_deref(),
+                            topic: &msg.topic,
+                            push_magic: Some(&msg.push_magic),
+                            push_token: Some(&msg.token),
+                            disabled: false,
+                            authenticate_raw: None,
+                            token_update_raw: Some(&msg.raw),
+                            created_at: now,
+                            updated_at: now,
+                        };
+
+                        diesel::insert_into(enrollments::table)
+                            .values(&new_enrollment)
+                            .on_conflict(enrollments::id)
+                            .do_update()
+                            .set((
+                                enrollments::push_magic.eq(Some(&msg.push_magic)),
+                                enrollments::push_token.eq(Some(&msg.token)),
+                                enrollments::disabled.eq(false),
+                                enrollments::push_invalid_at.eq(None::<chrono::NaiveDateTime>),
+                                enrollments::token_update_raw.eq(Some(&msg.raw)),
+                                enrollments::updated_at.eq(now),
+                            ))
+                            .execute(conn)
+                            .wrap_err("failed to store token update")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn store_checkout(
+                    &self,
+                    id: &EnrollId,
+                    _msg: &mdm_core::CheckOut,
+                ) -> color_eyre::eyre::Result<()> {
+                    self.disable(id).await
+                }
+
+                async fn is_disabled(&self, id: &EnrollId) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        let result: Option<bool> = enrollments::table
+                            .filter(enrollments::id.eq(&id))
+                            .select(enrollments::disabled)
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to check disabled status")?;
+
+                        Ok(result.unwrap_or(true))
+                    })
+                    .await
+                }
+
+                async fn disable(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        diesel::update(
+                            enrollments::table
+                                .filter(enrollments::id.eq(&id).or(enrollments::parent_id.eq(&id))),
+                        )
+                        .set(enrollments::disabled.eq(true))
+                        .execute(conn)
+                        .wrap_err("failed to disable enrollment")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn get_enrollment(
+                    &self,
+                    id: &str,
+                ) -> color_eyre::eyre::Result<Option<mdm_core::EnrollmentRecord>> {
+                    let id = id.to_string();
+
+                    self.blocking(move |conn| {
+                        let row: Option<EnrollmentRow> = enrollments::table
+                            .find(&id)
+                            .select(EnrollmentRow::as_select())
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to get enrollment")?;
+
+                        row.map(EnrollmentRow::into_record).transpose()
+                    })
+                    .await
+                }
+
+                async fn list_enrollments(
+                    &self,
+                    filter: &EnrollmentFilter,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::EnrollmentRecord>> {
+                    let filter = filter.clone();
+
+                    self.blocking(move |conn| {
+                        let mut query = enrollments::table
+                            .select(EnrollmentRow::as_select())
+                            .order(enrollments::id.asc())
+                            .offset(i64::from(filter.offset))
+                            .into_boxed::<<$conn as diesel::Connection>::Backend>();
+                        if let Some(enroll_type) = filter.enroll_type {
+                            query = query.filter(enrollments::enroll_type.eq(enroll_type.as_str()));
+                        }
+                        if let Some(disabled) = filter.disabled {
+                            query = query.filter(enrollments::disabled.eq(disabled));
+                        }
+                        if let Some(topic) = filter.topic {
+                            query = query.filter(enrollments::topic.eq(topic));
+                        }
+                        if let Some(parent_id) = filter.parent_id {
+                            query = query.filter(enrollments::parent_id.eq(parent_id));
+                        }
+                        if let Some(limit) = filter.limit {
+                            query = query.limit(i64::from(limit));
+                        }
+
+                        query
+                            .load::<EnrollmentRow>(conn)
+                            .wrap_err("failed to list enrollments")?
+                            .into_iter()
+                            .map(EnrollmentRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+            }
+
+            impl CommandStore for $storage {
+                async fn enqueue_command(
+                    &self,
+                    id: &EnrollId,
+                    command: &mdm_core::Command,
+                    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+                ) -> color_eyre::eyre::Result<String> {
+                    self.enqueue_commands(std::slice::from_ref(id), command, expires_at)
+                        .await
+                }
+
+                async fn enqueue_commands(
+                    &self,
+                    ids: &[EnrollId],
+                    command: &mdm_core::Command,
+                    expires_at: Option<chrono::DateTime<chrono::Utc>>,
+                ) -> color_eyre::eyre::Result<String> {
+                    let ids: Vec<String> = ids.iter().map(|id| id.id.clone()).collect();
+                    let uuid = command.command_uuid.clone();
+                    let request_type = command.command.request_type.clone();
+                    let command = mdm_core::serialize_command(command)?;
+
+                    self.blocking(move |conn| {
+                        conn.transaction(|conn| {
+                            let now = chrono::Utc::now().naive_utc();
+
+                            for id in &ids {
+                                let new_command = NewCommand {
+                                    enrollment_id: id,
+                                    uuid: &uuid,
+                                    command: &command,
+                                    status: CommandState::Pending.as_str(),
+                                    created_at: now,
+                                    expires_at: expires_at.map(|t| t.naive_utc()),
+                                    request_type: &request_type,
+                                };
+
+                                match diesel::insert_into(commands::table)
+                                    .values(&new_command)
+                                    .execute(conn)
+                                {
+                                    Ok(_) => {}
+                                    Err(diesel::result::Error::DatabaseError(
+                                        diesel::result::DatabaseErrorKind::UniqueViolation,
+                                        _,
+                                    )) => {
+                                        return Err(DuplicateCommand {
+                                            enrollment_id: id.clone(),
+                                            uuid: uuid.clone(),
+                                        }
+                                        .into());
+                                    }
+                                    Err(e) => {
+                                        return Err(e).wrap_err("failed to enqueue command");
+                                    }
+                                }
+                            }
+
+                            Ok(uuid)
+                        })
+                    })
+                    .await
+                }
+
+                async fn next_command(
+                    &self,
+                    id: &EnrollId,
+                    skip_not_now: bool,
+                ) -> color_eyre::eyre::Result<Option<QueuedCommand>> {
+                    let id = id.clone();
+
+                    self.blocking(move |conn| {
+                        let result = conn
+                            .transaction(|conn| {
+                                diesel::update(
+                                    commands::table
+                                        .filter(commands::enrollment_id.eq(&id.id))
+                                        .filter(
+                                            commands::status
+                                                .eq_any($crate::shared::deliverable_states(false)),
+                                        )
+                                        .filter(
+                                            commands::expires_at.le(chrono::Utc::now().naive_utc()),
+                                        ),
+                                )
+                                .set(commands::status.eq(CommandState::Expired.as_str()))
+                                .execute(conn)?;
+
+                                let Some(row) =
+                                    Self::next_pending_command(conn, &id, skip_not_now)?
+                                else {
+                                    return Ok(None);
+                                };
+                                diesel::update(commands::table.find(row.id))
+                                    .set(commands::status.eq(CommandState::Sent.as_str()))
+                                    .execute(conn)?;
+                                diesel::QueryResult::Ok(Some(row))
+                            })
+                            .wrap_err("failed to get next command")?;
+
+                        Ok(result.map(|row| QueuedCommand {
+                            uuid: row.uuid,
+                            command: row.command,
+                            created_at: chrono::DateTime::from_naive_utc_and_offset(
+                                row.created_at,
+                                chrono::Utc,
+                            ),
+                        }))
+                    })
+                    .await
+                }
+
+                async fn store_result(
+                    &self,
+                    id: &EnrollId,
+                    results: &mdm_core::CommandResults,
+                ) -> color_eyre::eyre::Result<()> {
+                    let Some(state) = CommandState::from_status(results.status) else {
+                        return Ok(());
+                    };
+                    let id = id.id.clone();
+                    let command_uuid = results.command_uuid.clone();
+                    let raw = results.raw.clone();
+
+                    self.blocking(move |conn| {
+                        diesel::update(
+                            commands::table
+                                .filter(commands::enrollment_id.eq(&id))
+                                .filter(commands::uuid.eq(&command_uuid)),
+                        )
+                        .set((
+                            commands::status.eq(state.as_str()),
+                            commands::result.eq(Some(&raw)),
+                        ))
+                        .execute(conn)
+                        .wrap_err("failed to store command result")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn cancel_command(
+                    &self,
+                    id: &EnrollId,
+                    uuid: &str,
+                ) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+                    let uuid = uuid.to_string();
+
+                    self.blocking(move |conn| {
+                        let cancelled = diesel::update(
+                            commands::table
+                                .filter(commands::enrollment_id.eq(&id))
+                                .filter(commands::uuid.eq(&uuid))
+                                .filter(
+                                    commands::status
+                                        .eq_any($crate::shared::deliverable_states(false)),
+                                ),
+                        )
+                        .set(commands::status.eq(CommandState::Cancelled.as_str()))
+                        .execute(conn)
+                        .wrap_err("failed to cancel command")?;
+
+                        Ok(cancelled > 0)
+                    })
+                    .await
+                }
+
+                async fn cancel_request_type(
+                    &self,
+                    id: &EnrollId,
+                    request_type: &str,
+                    except: Option<&str>,
+                ) -> color_eyre::eyre::Result<Vec<String>> {
+                    let id = id.id.clone();
+                    let request_type = request_type.to_string();
+                    // Command UUIDs are never empty
+                    let except = except.unwrap_or_default().to_string();
+
+                    self.blocking(move |conn| {
+                        conn.transaction(|conn| {
+                            let unanswered = commands::table
+                                .filter(commands::enrollment_id.eq(&id))
+                                .filter(commands::request_type.eq(&request_type))
+                                .filter(commands::uuid.ne(&except))
+                                .filter(
+                                    commands::status
+                                        .eq_any($crate::shared::deliverable_states(false)),
+                                );
+                            let uuids: Vec<String> = unanswered
+                                .clone()
+                                .select(commands::uuid)
+                                .order(commands::id.asc())
+                                .load(conn)?;
+                            diesel::update(unanswered)
+                                .set(commands::status.eq(CommandState::Cancelled.as_str()))
+                                .execute(conn)?;
+                            diesel::QueryResult::Ok(uuids)
+                        })
+                        .wrap_err("failed to cancel commands")
+                    })
+                    .await
+                }
+
+                async fn get_command(
+                    &self,
+                    uuid: &str,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::CommandRecord>> {
+                    let uuid = uuid.to_string();
+
+                    self.blocking(move |conn| {
+                        commands::table
+                            .filter(commands::uuid.eq(&uuid))
+                            .order(commands::id.asc())
+                            .load::<CommandRow>(conn)
+                            .wrap_err("failed to get command")?
+                            .into_iter()
+                            .map(CommandRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+
+                async fn list_commands(
+                    &self,
+                    id: &EnrollId,
+                    filter: &CommandFilter,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::CommandRecord>> {
+                    let id = id.id.clone();
+                    let filter = filter.clone();
+
+                    self.blocking(move |conn| {
+                        let mut query = commands::table
+                            .filter(commands::enrollment_id.eq(id))
+                            .order(commands::id.desc())
+                            .offset(i64::from(filter.offset))
+                            .into_boxed::<<$conn as diesel::Connection>::Backend>();
+                        if let Some(state) = filter.state {
+                            query = query.filter(commands::status.eq(state.as_str()));
+                        }
+                        if let Some(request_type) = filter.request_type {
+                            query = query.filter(commands::request_type.eq(request_type));
+                        }
+                        if let Some(limit) = filter.limit {
+                            query = query.limit(i64::from(limit));
+                        }
+
+                        query
+                            .load::<CommandRow>(conn)
+                            .wrap_err("failed to list commands")?
+                            .into_iter()
+                            .map(CommandRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+
+                async fn queue_depth(&self, id: &EnrollId) -> color_eyre::eyre::Result<u64> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        let count: i64 = commands::table
+                            .filter(commands::enrollment_id.eq(&id))
+                            .filter(
+                                commands::status.eq_any($crate::shared::deliverable_states(false)),
+                            )
+                            .filter(
+                                commands::expires_at
+                                    .is_null()
+                                    .or(commands::expires_at.gt(chrono::Utc::now().naive_utc())),
+                            )
+                            .count()
+                            .get_result(conn)
+                            .wrap_err("failed to count queued commands")?;
+
+                        Ok(count.try_into()?)
+                    })
+                    .await
+                }
+
+                async fn clear_queue(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        diesel::delete(commands::table.filter(commands::enrollment_id.eq(&id)))
+                            .execute(conn)
+                            .wrap_err("failed to clear command queue")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+            }
+
+            impl BootstrapTokenStore for $storage {
+                async fn store_bootstrap_token(
+                    &self,
+                    id: &EnrollId,
+                    token: &[u8],
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+                    let token = token.to_vec();
+
+                    self.blocking(move |conn| {
+                        let new_token = NewBootstrapToken {
+                            enrollment_id: &id,
+                            token: &token,
+                        };
+
+                        diesel::insert_into(bootstrap_tokens::table)
+                            .values(&new_token)
+                            .on_conflict(bootstrap_tokens::enrollment_id)
+                            .do_update()
+                            .set(bootstrap_tokens::token.eq(&token))
+                            .execute(conn)
+                            .wrap_err("failed to store bootstrap token")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn get_bootstrap_token(
+                    &self,
+                    id: &EnrollId,
+                ) -> color_eyre::eyre::Result<Option<Vec<u8>>> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        let result: Option<Vec<u8>> = bootstrap_tokens::table
+                            .filter(bootstrap_tokens::enrollment_id.eq(&id))
+                            .select(bootstrap_tokens::token)
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to get bootstrap token")?;
+
+                        Ok(result)
+                    })
+                    .await
+                }
+
+                async fn delete_bootstrap_token(
+                    &self,
+                    id: &EnrollId,
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        diesel::delete(
+                            bootstrap_tokens::table.filter(bootstrap_tokens::enrollment_id.eq(&id)),
+                        )
+                        .execute(conn)
+                        .wrap_err("failed to delete bootstrap token")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+            }
+
+            impl PushStore for $storage {
+                async fn get_push_info(
+                    &self,
+                    id: &EnrollId,
+                ) -> color_eyre::eyre::Result<Option<PushInfo>> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        let result: Option<(Option<String>, Option<Vec<u8>>, String)> =
+                            enrollments::table
+                                .filter(enrollments::id.eq(&id))
+                                .filter(enrollments::disabled.eq(false))
+                                .filter(enrollments::push_invalid_at.is_null())
+                                .select((
+                                    enrollments::push_magic,
+                                    enrollments::push_token,
+                                    enrollments::topic,
+                                ))
+                                .first(conn)
+                                .optional()
+                                .wrap_err("failed to get push info")?;
+
+                        Ok(
+                            result.and_then(|(magic, token, topic)| match (magic, token) {
+                                (Some(push_magic), Some(token)) => Some(PushInfo {
+                                    enrollment_id: id,
+                                    token,
+                                    push_magic,
+                                    topic,
+                                }),
+                                _ => None,
+                            }),
+                        )
+                    })
+                    .await
+                }
+
+                async fn get_push_infos(
+                    &self,
+                    ids: &[&EnrollId],
+                ) -> color_eyre::eyre::Result<Vec<PushInfo>> {
+                    let id_strings: Vec<String> = ids.iter().map(|id| id.id.clone()).collect();
+
+                    self.blocking(move |conn| {
+                        let results: Vec<PushInfoColumns> = enrollments::table
+                            .filter(enrollments::id.eq_any(&id_strings))
+                            .filter(enrollments::disabled.eq(false))
+                            .filter(enrollments::push_invalid_at.is_null())
+                            .select((
+                                enrollments::id,
+                                enrollments::push_magic,
+                                enrollments::push_token,
+                                enrollments::topic,
+                            ))
+                            .load(conn)
+                            .wrap_err("failed to get push infos")?;
+
+                        Ok(results
+                            .into_iter()
+                            .filter_map(|(enrollment_id, magic, token, topic)| {
+                                match (magic, token) {
+                                    (Some(push_magic), Some(token)) => Some(PushInfo {
+                                        enrollment_id,
+                                        token,
+                                        push_magic,
+                                        topic,
+                                    }),
+                                    _ => None,
+                                }
+                            })
+                            .collect())
+                    })
+                    .await
+                }
+
+                async fn mark_push_invalid(&self, id: &EnrollId) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+
+                        diesel::update(
+                            enrollments::table
+                                .filter(enrollments::id.eq(&id))
+                                .filter(enrollments::push_invalid_at.is_null()),
+                        )
+                        .set(enrollments::push_invalid_at.eq(Some(now)))
+                        .execute(conn)
+                        .wrap_err("failed to mark push token invalid")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+            }
+
+            impl PushCertStore for $storage {
+                async fn store_push_cert(
+                    &self,
+                    topic: &str,
+                    cert_pem: &str,
+                    key_pem: &str,
+                ) -> color_eyre::eyre::Result<()> {
+                    let not_after = $crate::shared::check_push_cert(topic, cert_pem, key_pem)?;
+                    let topic = topic.to_string();
+                    let cert_pem = cert_pem.to_string();
+                    let key_pem = key_pem.to_string();
+
+                    self.blocking(move |conn| {
+                        let new_cert = NewPushCert {
+                            topic: &topic,
+                            cert_pem: &cert_pem,
+                            key_pem: &key_pem,
+                            not_after: Some(not_after.naive_utc()),
+                        };
+
+                        diesel::insert_into(push_certs::table)
+                            .values(&new_cert)
+                            .on_conflict(push_certs::topic)
+                            .do_update()
+                            .set((
+                                push_certs::cert_pem.eq(&cert_pem),
+                                push_certs::key_pem.eq(&key_pem),
+                                push_certs::not_after.eq(new_cert.not_after),
+                            ))
+                            .execute(conn)
+                            .wrap_err("failed to store push cert")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn get_push_cert(
+                    &self,
+                    topic: &str,
+                ) -> color_eyre::eyre::Result<Option<(String, String)>> {
+                    let topic = topic.to_string();
+
+                    self.blocking(move |conn| {
+                        let result: Option<(String, String)> = push_certs::table
+                            .filter(push_certs::topic.eq(&topic))
+                            .select((push_certs::cert_pem, push_certs::key_pem))
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to get push cert")?;
+
+                        Ok(result)
+                    })
+                    .await
+                }
+
+                async fn list_push_topics(&self) -> color_eyre::eyre::Result<Vec<String>> {
+                    self.blocking(|conn| {
+                        push_certs::table
+                            .select(push_certs::topic)
+                            .order(push_certs::topic.asc())
+                            .load(conn)
+                            .wrap_err("failed to list push topics")
+                    })
+                    .await
+                }
+
+                async fn list_push_certs(&self) -> color_eyre::eyre::Result<Vec<PushCertSummary>> {
+                    let rows: Vec<PushCertRow> = self
+                        .blocking(|conn| {
+                            push_certs::table
+                                .select(PushCertRow::as_select())
+                                .order(push_certs::topic.asc())
+                                .load(conn)
+                                .wrap_err("failed to list push certs")
+                        })
+                        .await?;
+
+                    Ok(rows
+                        .into_iter()
+                        .map(|row| {
+                            // Rows stored before expiry was recorded fall back to parsing the cert
+                            let not_after = match row.not_after {
+                                Some(not_after) => Some(not_after.and_utc()),
+                                None => mdm_crypto::parse_pem_cert(&row.cert_pem)
+                                    .and_then(|der| mdm_crypto::parse_push_cert(&der))
+                                    .map(|info| info.not_after)
+                                    .ok(),
+                            };
+                            PushCertSummary {
+                                topic: row.topic,
+                                not_after,
+                            }
+                        })
+                        .collect())
+                }
+            }
+
+            impl CertAuthStore for $storage {
+                async fn associate_cert(
+                    &self,
+                    id: &EnrollId,
+                    cert_hash: &[u8],
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+                    let cert_hash = cert_hash.to_vec();
+
+                    self.blocking(move |conn| {
+                        let new_auth = NewCertAuth {
+                            enrollment_id: &id,
+                            cert_hash: &cert_hash,
+                            hash_algorithm: "sha256",
+                        };
+
+                        diesel::insert_into(cert_auth::table)
+                            .values(&new_auth)
+                            .execute(conn)
+                            .wrap_err("failed to associate cert")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn has_cert_auth(
+                    &self,
+                    id: &EnrollId,
+                    cert_hash: &[u8],
+                ) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+                    let cert_hash = cert_hash.to_vec();
+
+                    self.blocking(move |conn| {
+                        let count: i64 = cert_auth::table
+                            .filter(cert_auth::enrollment_id.eq(&id))
+                            .filter(cert_auth::cert_hash.eq(&cert_hash))
+                            .filter(cert_auth::hash_algorithm.eq("sha256"))
+                            .count()
+                            .get_result(conn)
+                            .wrap_err("failed to check cert auth")?;
+
+                        Ok(count > 0)
+                    })
+                    .await
+                }
+
+                async fn rehash_legacy_cert(
+                    &self,
+                    id: &EnrollId,
+                    legacy_hash: &[u8],
+                    cert_hash: &[u8],
+                ) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+                    let legacy_hash = legacy_hash.to_vec();
+                    let cert_hash = cert_hash.to_vec();
+
+                    self.blocking(move |conn| {
+                        let updated = diesel::update(
+                            cert_auth::table
+                                .filter(cert_auth::enrollment_id.eq(&id))
+                                .filter(cert_auth::cert_hash.eq(&legacy_hash))
+                                .filter(cert_auth::hash_algorithm.eq("xor")),
+                        )
+                        .set((
+                            cert_auth::cert_hash.eq(&cert_hash),
+                            cert_auth::hash_algorithm.eq("sha256"),
+                        ))
+                        .execute(conn)
+                        .wrap_err("failed to rehash legacy cert auth")?;
+
+                        Ok(updated > 0)
+                    })
+                    .await
+                }
+            }
+
+            impl InventoryStore for $storage {
+                async fn update_inventory(
+                    &self,
+                    device_id: &str,
+                    update: &mdm_core::InventoryUpdate,
+                ) -> color_eyre::eyre::Result<()> {
+                    let device_id = device_id.to_string();
+                    let update = update.clone();
+
+                    self.blocking(move |conn| {
+                        let new_inventory = NewInventory::new(&device_id, &update);
+                        let insert = diesel::insert_into(inventory::table)
+                            .values(&new_inventory)
+                            .on_conflict(inventory::device_id);
+
+                        // An empty changeset isn't valid SQL
+                        if update.is_empty() {
+                            insert.do_nothing().execute(conn)
+                        } else {
+                            insert.do_update().set(&new_inventory).execute(conn)
+                        }
+                        .wrap_err("failed to update inventory")?;
+
+                        Ok(())
+                    })
+                    .await
+                }
+
+                async fn get_inventory(
+                    &self,
+                    device_id: &str,
+                ) -> color_eyre::eyre::Result<Option<mdm_core::DeviceInventory>> {
+                    let device_id = device_id.to_string();
+
+                    self.blocking(move |conn| {
+                        let row: Option<InventoryRow> = inventory::table
+                            .find(&device_id)
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to get inventory")?;
+
+                        Ok(row.map(Into::into))
+                    })
+                    .await
+                }
+
+                async fn list_inventory(
+                    &self,
+                    filter: &InventoryFilter,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::DeviceInventory>> {
+                    let filter = filter.clone();
+
+                    self.blocking(move |conn| {
+                        let mut query = inventory::table
+                            .order(inventory::device_id.asc())
+                            .offset(i64::from(filter.offset))
+                            .into_boxed::<<$conn as diesel::Connection>::Backend>();
+                        if let Some(serial_number) = filter.serial_number {
+                            query = query.filter(inventory::serial_number.eq(serial_number));
+                        }
+                        if let Some(model) = filter.model {
+                            query = query.filter(inventory::model.eq(model));
+                        }
+                        if let Some(os_version) = filter.os_version {
+                            query = query.filter(inventory::os_version.eq(os_version));
+                        }
+                        if let Some(enabled) = filter.filevault_enabled {
+                            query = query.filter(inventory::filevault_enabled.eq(enabled));
+                        }
+                        if let Some(enabled) = filter.sip_enabled {
+                            query = query.filter(inventory::sip_enabled.eq(enabled));
+                        }
+                        if let Some(limit) = filter.limit {
+                            query = query.limit(i64::from(limit));
+                        }
+
+                        let rows: Vec<InventoryRow> =
+                            query.load(conn).wrap_err("failed to list inventory")?;
+                        Ok(rows.into_iter().map(Into::into).collect())
+                    })
+                    .await
+                }
+            }
+
+            impl DeclarationStore for $storage {
+                async fn store_declaration(
+                    &self,
+                    declaration: &mdm_core::Declaration,
+                ) -> color_eyre::eyre::Result<()> {
+                    let declaration = declaration.clone();
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+                        let new_declaration = NewDeclaration::new(&declaration, now)?;
+
+                        conn.transaction(|conn| {
+                            let server_token: Option<String> = declarations::table
+                                .find(&declaration.identifier)
+                                .select(declarations::server_token)
+                                .first(conn)
+                                .optional()?;
+                            if server_token.as_deref() == Some(&declaration.server_token) {
+                                // Same content, keep the update time
+                                return diesel::QueryResult::Ok(());
+                            }
+                            diesel::insert_into(declarations::table)
+                                .values(&new_declaration)
+                                .on_conflict(declarations::identifier)
+                                .do_update()
+                                .set(&new_declaration)
+                                .execute(conn)?;
+                            Ok(())
+                        })
+                        .wrap_err("failed to store declaration")
+                    })
+                    .await
+                }
+
+                async fn get_declaration(
+                    &self,
+                    identifier: &str,
+                ) -> color_eyre::eyre::Result<Option<mdm_core::DeclarationRecord>> {
+                    let identifier = identifier.to_string();
+
+                    self.blocking(move |conn| {
+                        let row: Option<DeclarationRow> = declarations::table
+                            .find(&identifier)
+                            .first(conn)
+                            .optional()
+                            .wrap_err("failed to get declaration")?;
+
+                        row.map(DeclarationRow::into_record).transpose()
+                    })
+                    .await
+                }
+
+                async fn list_declarations(
+                    &self,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::DeclarationRecord>> {
+                    self.blocking(move |conn| {
+                        declarations::table
+                            .order(declarations::identifier.asc())
+                            .load::<DeclarationRow>(conn)
+                            .wrap_err("failed to list declarations")?
+                            .into_iter()
+                            .map(DeclarationRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+
+                async fn delete_declaration(
+                    &self,
+                    identifier: &str,
+                ) -> color_eyre::eyre::Result<bool> {
+                    let identifier = identifier.to_string();
+
+                    self.blocking(move |conn| {
+                        conn.transaction(|conn| {
+                            diesel::delete(
+                                declaration_assignments::table
+                                    .filter(declaration_assignments::identifier.eq(&identifier)),
+                            )
+                            .execute(conn)?;
+                            let deleted = diesel::delete(declarations::table.find(&identifier))
+                                .execute(conn)?;
+                            diesel::QueryResult::Ok(deleted > 0)
+                        })
+                        .wrap_err("failed to delete declaration")
+                    })
+                    .await
+                }
+
+                async fn assign_declaration(
+                    &self,
+                    id: &EnrollId,
+                    identifier: &str,
+                ) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+                    let identifier = identifier.to_string();
+
+                    self.blocking(move |conn| {
+                        conn.transaction(|conn| {
+                            let exists: i64 = declarations::table
+                                .filter(declarations::identifier.eq(&identifier))
+                                .count()
+                                .get_result(conn)
+                                .wrap_err("failed to get declaration")?;
+                            if exists == 0 {
+                                return Err(UnknownDeclaration { identifier }.into());
+                            }
+
+                            let inserted = diesel::insert_into(declaration_assignments::table)
+                                .values(&NewDeclarationAssignment {
+                                    enrollment_id: &id,
+                                    identifier: &identifier,
+                                })
+                                .on_conflict_do_nothing()
+                                .execute(conn)
+                                .wrap_err("failed to assign declaration")?;
+                            Ok(inserted > 0)
+                        })
+                    })
+                    .await
+                }
+
+                async fn unassign_declaration(
+                    &self,
+                    id: &EnrollId,
+                    identifier: &str,
+                ) -> color_eyre::eyre::Result<bool> {
+                    let id = id.id.clone();
+                    let identifier = identifier.to_string();
+
+                    self.blocking(move |conn| {
+                        let deleted = diesel::delete(
+                            declaration_assignments::table
+                                .filter(declaration_assignments::enrollment_id.eq(&id))
+                                .filter(declaration_assignments::identifier.eq(&identifier)),
+                        )
+                        .execute(conn)
+                        .wrap_err("failed to unassign declaration")?;
+
+                        Ok(deleted > 0)
+                    })
+                    .await
+                }
+
+                async fn assigned_declarations(
+                    &self,
+                    id: &EnrollId,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::DeclarationRecord>> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        declaration_assignments::table
+                            .inner_join(declarations::table)
+                            .filter(declaration_assignments::enrollment_id.eq(&id))
+                            .order(declarations::identifier.asc())
+                            .select(DeclarationRow::as_select())
+                            .load::<DeclarationRow>(conn)
+                            .wrap_err("failed to get assigned declarations")?
+                            .into_iter()
+                            .map(DeclarationRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+
+                async fn store_declaration_statuses(
+                    &self,
+                    id: &EnrollId,
+                    statuses: &[mdm_core::DeclarationStatus],
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+                    let statuses = statuses.to_vec();
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+                        let rows = statuses
+                            .iter()
+                            .map(|status| NewDeclarationStatus::new(&id, status, now))
+                            .collect::<color_eyre::eyre::Result<Vec<_>>>()?;
+
+                        conn.transaction(|conn| {
+                            diesel::delete(
+                                declaration_statuses::table
+                                    .filter(declaration_statuses::enrollment_id.eq(&id)),
+                            )
+                            .execute(conn)?;
+                            diesel::insert_into(declaration_statuses::table)
+                                .values(&rows)
+                                .execute(conn)?;
+                            diesel::QueryResult::Ok(())
+                        })
+                        .wrap_err("failed to store declaration statuses")
+                    })
+                    .await
+                }
+
+                async fn declaration_statuses(
+                    &self,
+                    id: &EnrollId,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::DeclarationStatusRecord>> {
+                    let id = id.id.clone();
+
+                    self.blocking(move |conn| {
+                        declaration_statuses::table
+                            .filter(declaration_statuses::enrollment_id.eq(&id))
+                            .order(declaration_statuses::identifier.asc())
+                            .load::<DeclarationStatusRow>(conn)
+                            .wrap_err("failed to get declaration statuses")?
+                            .into_iter()
+                            .map(DeclarationStatusRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+            }
+
+            impl StatusItemStore for $storage {
+                async fn store_status_items(
+                    &self,
+                    id: &EnrollId,
+                    items: &[mdm_core::StatusItem],
+                ) -> color_eyre::eyre::Result<()> {
+                    let id = id.id.clone();
+                    let items = items
+                        .iter()
+                        .map(|item| Ok((item.item.clone(), serde_json::to_string(&item.value)?)))
+                        .collect::<color_eyre::eyre::Result<Vec<_>>>()?;
+
+                    self.blocking(move |conn| {
+                        let now = chrono::Utc::now().naive_utc();
+
+                        conn.transaction(|conn| {
+                            let current: std::collections::HashMap<String, String> =
+                                status_items::table
+                                    .filter(status_items::enrollment_id.eq(&id))
+                                    .filter(
+                                        status_items::item
+                                            .eq_any(items.iter().map(|(item, _)| item)),
+                                    )
+                                    .select((status_items::item, status_items::value))
+                                    .load::<(String, String)>(conn)?
+                                    .into_iter()
+                                    .collect();
+
+                            let (unchanged, changed): (Vec<_>, Vec<_>) = items
+                                .iter()
+                                .partition(|(item, value)| current.get(item) == Some(value));
+
+                            diesel::update(
+                                status_items::table
+                                    .filter(status_items::enrollment_id.eq(&id))
+                                    .filter(
+                                        status_items::item
+                                            .eq_any(unchanged.iter().map(|(item, _)| item)),
+                                    ),
+                            )
+                            .set(status_items::reported_at.eq(now))
+                            .execute(conn)?;
+
+                            for (item, value) in changed {
+                                let latest = NewStatusItem {
+                                    enrollment_id: &id,
+                                    item,
+                                    value,
+                                    changed_at: now,
+                                    reported_at: now,
+                                };
+                                diesel::insert_into(status_items::table)
+                                    .values(&latest)
+                                    .on_conflict((status_items::enrollment_id, status_items::item))
+                                    .do_update()
+                                    .set(&latest)
+                                    .execute(conn)?;
+                                diesel::insert_into(status_item_history::table)
+                                    .values(&NewStatusItemHistory {
+                                        enrollment_id: &id,
+                                        item,
+                                        value,
+                                        reported_at: now,
+                                    })
+                                    .execute(conn)?;
+                            }
+                            diesel::QueryResult::Ok(())
+                        })
+                        .wrap_err("failed to store status items")
+                    })
+                    .await
+                }
+
+                async fn status_items(
+                    &self,
+                    id: &EnrollId,
+                    filter: &StatusItemFilter,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::StatusItemRecord>> {
+                    let id = id.id.clone();
+                    let filter = filter.clone();
+
+                    self.blocking(move |conn| {
+                        let mut query = status_items::table
+                            .filter(status_items::enrollment_id.eq(&id))
+                            .order(status_items::item.asc())
+                            .into_boxed::<<$conn as diesel::Connection>::Backend>();
+                        if let Some(prefix) = filter.prefix {
+                            let escaped = prefix
+                                .replace('\\', "\\\\")
+                                .replace('%', "\\%")
+                                .replace('_', "\\_");
+                            query =
+                                query.filter(status_items::item.eq(prefix).or(
+                                    status_items::item.like(format!("{escaped}.%")).escape('\\'),
+                                ));
+                        }
+
+                        query
+                            .load::<StatusItemRow>(conn)
+                            .wrap_err("failed to get status items")?
+                            .into_iter()
+                            .map(StatusItemRow::into_record)
+                            .collect()
+                    })
+                    .await
+                }
+
+                async fn status_item_history(
+                    &self,
+                    id: &EnrollId,
+                    item: &str,
+                    filter: &StatusHistoryFilter,
+                ) -> color_eyre::eyre::Result<Vec<mdm_core::StatusItemChange>> {
+                    let id = id.id.clone();
+                    let item = item.to_string();
+                    let filter = filter.clone();
+
+                    self.blocking(move |conn| {
+                        let mut query = status_item_history::table
+                            .filter(status_item_history::enrollment_id.eq(&id))
+                            .filter(status_item_history::item.eq(&item))
+                            .order(status_item_history::id.desc())
+                            .offset(i64::from(filter.offset))
+                            .into_boxed::<<$conn as diesel::Connection>::Backend>();
+                        if let Some(limit) = filter.limit {
+                            query = query.limit(i64::from(limit));
+                        }
+
+                        query
+                            .load::<StatusItemHistoryRow>(conn)
+                            .wrap_err("failed to get status item history")?
+                            .into_iter()
+                            .map(StatusItemHistoryRow::into_change)
+                            .collect()
+                    })
+                    .await
+                }
+            }
+        };
+    };
+}
+
+pub(crate) use impl_diesel_storage;
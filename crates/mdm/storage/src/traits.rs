@@ -1,80 +1,235 @@
 //! Storage traits.
+//!
+//! The methods are async so that blocking backends can move their work off
+//! the runtime's worker threads.
 
 use mdm_core::{
-    BootstrapTokenResponse, CheckinMessage, CommandResults, EnrollId, PushInfo, QueuedCommand,
+    Command, CommandRecord, CommandResults, CommandState, Declaration, DeclarationRecord,
+    DeclarationStatus, DeclarationStatusRecord, DeviceInventory, EnrollId, EnrollType,
+    EnrollmentRecord, InventoryUpdate, PushCertSummary, PushInfo, QueuedCommand, StatusItem,
+    StatusItemChange, StatusItemRecord,
 };
 
+/// Which enrollments to list. Fields left `None` match any enrollment.
+#[derive(Debug, Clone, Default)]
+pub struct EnrollmentFilter {
+    pub enroll_type: Option<EnrollType>,
+    pub disabled: Option<bool>,
+    pub topic: Option<String>,
+    /// Only user channels of this device.
+    pub parent_id: Option<String>,
+    /// Return at most this many enrollments.
+    pub limit: Option<u32>,
+    /// Skip this many enrollments first.
+    pub offset: u32,
+}
+
 /// Check-in storage operations.
+#[trait_variant::make(Send)]
 pub trait CheckinStore: Send + Sync {
     /// Store an Authenticate message and clear command queue.
-    fn store_authenticate(
+    async fn store_authenticate(
         &self,
         id: &EnrollId,
         msg: &mdm_core::Authenticate,
     ) -> color_eyre::eyre::Result<()>;
 
     /// Store a TokenUpdate message and enable enrollment.
-    fn store_token_update(
+    ///
+    /// Creates the enrollment if needed, since user channels send no
+    /// Authenticate.
+    async fn store_token_update(
         &self,
         id: &EnrollId,
         msg: &mdm_core::TokenUpdate,
     ) -> color_eyre::eyre::Result<()>;
 
     /// Store a CheckOut message and disable enrollment.
-    fn store_checkout(
+    async fn store_checkout(
         &self,
         id: &EnrollId,
         msg: &mdm_core::CheckOut,
     ) -> color_eyre::eyre::Result<()>;
 
     /// Check if an enrollment is disabled.
-    fn is_disabled(&self, id: &EnrollId) -> color_eyre::eyre::Result<bool>;
+    async fn is_disabled(&self, id: &EnrollId) -> color_eyre::eyre::Result<bool>;
+
+    /// Disable an enrollment and any user channels under it.
+    async fn disable(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
+
+    /// Get an enrollment by ID.
+    async fn get_enrollment(&self, id: &str) -> color_eyre::eyre::Result<Option<EnrollmentRecord>>;
+
+    /// List enrollments matching `filter`, by ID.
+    async fn list_enrollments(
+        &self,
+        filter: &EnrollmentFilter,
+    ) -> color_eyre::eyre::Result<Vec<EnrollmentRecord>>;
+}
 
-    /// Disable an enrollment.
-    fn disable(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
+/// The enrollment already has a command with this UUID.
+///
+/// Returned by [`CommandStore::enqueue_command`] and
+/// [`CommandStore::enqueue_commands`] inside the error report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateCommand {
+    pub enrollment_id: String,
+    pub uuid: String,
+}
+
+impl std::fmt::Display for DuplicateCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "command {} already queued for {}",
+            self.uuid, self.enrollment_id
+        )
+    }
+}
+
+impl std::error::Error for DuplicateCommand {}
+
+/// Which of an enrollment's commands to list.
+#[derive(Debug, Clone, Default)]
+pub struct CommandFilter {
+    /// Only commands in this state.
+    pub state: Option<CommandState>,
+    /// Only commands of this request type.
+    pub request_type: Option<String>,
+    /// Return at most this many commands.
+    pub limit: Option<u32>,
+    /// Skip this many commands first.
+    pub offset: u32,
 }
 
 /// Command storage operations.
+#[trait_variant::make(Send)]
 pub trait CommandStore: Send + Sync {
-    /// Enqueue a command for an enrollment.
-    fn enqueue_command(&self, id: &EnrollId, command: &[u8]) -> color_eyre::eyre::Result<String>;
+    /// Enqueue a command for an enrollment under its CommandUUID, which is
+    /// returned.
+    ///
+    /// Fails with [`DuplicateCommand`] if the enrollment already has a
+    /// command with that UUID. If the device hasn't answered the command by
+    /// `expires_at`, it is marked Expired and never sent again.
+    async fn enqueue_command(
+        &self,
+        id: &EnrollId,
+        command: &Command,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> color_eyre::eyre::Result<String>;
 
-    /// Get the next pending command for an enrollment.
-    fn next_command(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<QueuedCommand>>;
+    /// Enqueue a command for several enrollments, like
+    /// [`enqueue_command`](Self::enqueue_command), in one transaction.
+    ///
+    /// Either every enrollment gets the command or none does, so a
+    /// [`DuplicateCommand`] for one leaves nothing queued for the others.
+    async fn enqueue_commands(
+        &self,
+        ids: &[EnrollId],
+        command: &Command,
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+    ) -> color_eyre::eyre::Result<String>;
 
-    /// Store command results and remove from queue.
-    fn store_result(&self, id: &EnrollId, results: &CommandResults)
-    -> color_eyre::eyre::Result<()>;
+    /// Get the next command to send to an enrollment and mark it Sent.
+    ///
+    /// Commands come in the order they were enqueued. Sent commands that
+    /// never got results are sent again. NotNow commands are skipped if
+    /// `skip_not_now` is set, which callers do unless the device just
+    /// reported Idle.
+    async fn next_command(
+        &self,
+        id: &EnrollId,
+        skip_not_now: bool,
+    ) -> color_eyre::eyre::Result<Option<QueuedCommand>>;
+
+    /// Store command results and move the command to the reported state.
+    ///
+    /// Idle reports leave the command unchanged.
+    async fn store_result(
+        &self,
+        id: &EnrollId,
+        results: &CommandResults,
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Cancel a command the enrollment hasn't answered yet.
+    ///
+    /// Returns whether a command was cancelled.
+    async fn cancel_command(&self, id: &EnrollId, uuid: &str) -> color_eyre::eyre::Result<bool>;
+
+    /// Cancel every command of `request_type` the enrollment hasn't answered
+    /// yet, other than `except`.
+    ///
+    /// Returns the UUIDs of the cancelled commands.
+    async fn cancel_request_type(
+        &self,
+        id: &EnrollId,
+        request_type: &str,
+        except: Option<&str>,
+    ) -> color_eyre::eyre::Result<Vec<String>>;
+
+    /// Get every enrollment's copy of a command.
+    async fn get_command(&self, uuid: &str) -> color_eyre::eyre::Result<Vec<CommandRecord>>;
+
+    /// List an enrollment's commands matching `filter`, newest first.
+    async fn list_commands(
+        &self,
+        id: &EnrollId,
+        filter: &CommandFilter,
+    ) -> color_eyre::eyre::Result<Vec<CommandRecord>>;
+
+    /// Count the commands the enrollment has yet to answer, leaving out
+    /// expired ones.
+    async fn queue_depth(&self, id: &EnrollId) -> color_eyre::eyre::Result<u64>;
 
     /// Clear all pending commands for an enrollment.
-    fn clear_queue(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
+    async fn clear_queue(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
 }
 
 /// Bootstrap token storage.
+#[trait_variant::make(Send)]
 pub trait BootstrapTokenStore: Send + Sync {
     /// Store a bootstrap token.
-    fn store_bootstrap_token(&self, id: &EnrollId, token: &[u8]) -> color_eyre::eyre::Result<()>;
+    async fn store_bootstrap_token(
+        &self,
+        id: &EnrollId,
+        token: &[u8],
+    ) -> color_eyre::eyre::Result<()>;
 
     /// Get a bootstrap token.
-    fn get_bootstrap_token(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<Vec<u8>>>;
+    async fn get_bootstrap_token(&self, id: &EnrollId)
+    -> color_eyre::eyre::Result<Option<Vec<u8>>>;
 
     /// Delete a bootstrap token.
-    fn delete_bootstrap_token(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
+    async fn delete_bootstrap_token(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
 }
 
 /// Push info storage.
+#[trait_variant::make(Send)]
 pub trait PushStore: Send + Sync {
     /// Get push info for an enrollment.
-    fn get_push_info(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<PushInfo>>;
+    ///
+    /// Returns `None` if the push token was marked invalid.
+    async fn get_push_info(&self, id: &EnrollId) -> color_eyre::eyre::Result<Option<PushInfo>>;
 
     /// Get push info for multiple enrollments.
-    fn get_push_infos(&self, ids: &[&EnrollId]) -> color_eyre::eyre::Result<Vec<PushInfo>>;
+    ///
+    /// Enrollments whose push token was marked invalid are skipped.
+    async fn get_push_infos(&self, ids: &[&EnrollId]) -> color_eyre::eyre::Result<Vec<PushInfo>>;
+
+    /// Record that APNs rejected an enrollment's push token.
+    ///
+    /// Keeps the time of the first rejection; the next TokenUpdate clears it.
+    async fn mark_push_invalid(&self, id: &EnrollId) -> color_eyre::eyre::Result<()>;
 }
 
 /// Push certificate storage.
+#[trait_variant::make(Send)]
 pub trait PushCertStore: Send + Sync {
     /// Store a push certificate.
-    fn store_push_cert(
+    ///
+    /// Fails if the key does not match the certificate or the certificate's
+    /// topic is not `topic`.
+    async fn store_push_cert(
         &self,
         topic: &str,
         cert_pem: &str,
@@ -82,21 +237,226 @@ pub trait PushCertStore: Send + Sync {
     ) -> color_eyre::eyre::Result<()>;
 
     /// Get a push certificate by topic.
-    fn get_push_cert(&self, topic: &str) -> color_eyre::eyre::Result<Option<(String, String)>>;
+    async fn get_push_cert(
+        &self,
+        topic: &str,
+    ) -> color_eyre::eyre::Result<Option<(String, String)>>;
+
+    /// List the topics of all stored push certificates.
+    async fn list_push_topics(&self) -> color_eyre::eyre::Result<Vec<String>>;
+
+    /// List all stored push certificates with their expiry.
+    async fn list_push_certs(&self) -> color_eyre::eyre::Result<Vec<PushCertSummary>>;
 }
 
 /// Certificate authentication storage.
+///
+/// Certificate hashes are SHA-256 fingerprints. Rows written before the
+/// switch from the legacy XOR-folded hash are kept until re-hashed.
+#[trait_variant::make(Send)]
 pub trait CertAuthStore: Send + Sync {
     /// Associate a certificate hash with an enrollment.
-    fn associate_cert(&self, id: &EnrollId, cert_hash: &[u8]) -> color_eyre::eyre::Result<()>;
+    async fn associate_cert(&self, id: &EnrollId, cert_hash: &[u8])
+    -> color_eyre::eyre::Result<()>;
 
     /// Check if a certificate is associated with an enrollment.
-    fn has_cert_auth(&self, id: &EnrollId, cert_hash: &[u8]) -> color_eyre::eyre::Result<bool>;
+    async fn has_cert_auth(
+        &self,
+        id: &EnrollId,
+        cert_hash: &[u8],
+    ) -> color_eyre::eyre::Result<bool>;
+
+    /// Replace a legacy certificate hash with its SHA-256 equivalent.
+    ///
+    /// Returns `false` if no legacy row matched.
+    async fn rehash_legacy_cert(
+        &self,
+        id: &EnrollId,
+        legacy_hash: &[u8],
+        cert_hash: &[u8],
+    ) -> color_eyre::eyre::Result<bool>;
+}
+
+/// Which devices to list from the inventory. Fields left `None` match any
+/// device.
+#[derive(Debug, Clone, Default)]
+pub struct InventoryFilter {
+    pub serial_number: Option<String>,
+    /// Model identifier, e.g. "MacBookPro18,3".
+    pub model: Option<String>,
+    pub os_version: Option<String>,
+    pub filevault_enabled: Option<bool>,
+    pub sip_enabled: Option<bool>,
+    /// Return at most this many devices.
+    pub limit: Option<u32>,
+    /// Skip this many devices first.
+    pub offset: u32,
+}
+
+/// Device inventory storage.
+#[trait_variant::make(Send)]
+pub trait InventoryStore: Send + Sync {
+    /// Merge reported properties into a device's inventory, creating it if
+    /// needed.
+    async fn update_inventory(
+        &self,
+        device_id: &str,
+        update: &InventoryUpdate,
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Get a device's inventory.
+    async fn get_inventory(
+        &self,
+        device_id: &str,
+    ) -> color_eyre::eyre::Result<Option<DeviceInventory>>;
+
+    /// List the inventory of devices matching `filter`, by device ID.
+    async fn list_inventory(
+        &self,
+        filter: &InventoryFilter,
+    ) -> color_eyre::eyre::Result<Vec<DeviceInventory>>;
+}
+
+/// No declaration has this identifier.
+///
+/// Returned by [`DeclarationStore::assign_declaration`] inside the error
+/// report.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownDeclaration {
+    pub identifier: String,
+}
+
+impl std::fmt::Display for UnknownDeclaration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no declaration {}", self.identifier)
+    }
+}
+
+impl std::error::Error for UnknownDeclaration {}
+
+/// DDM declaration storage.
+///
+/// Assignments are by enrollment ID and may name enrollments that don't
+/// exist yet.
+#[trait_variant::make(Send)]
+pub trait DeclarationStore: Send + Sync {
+    /// Create or replace a declaration.
+    ///
+    /// The update time only moves if the server token changed.
+    async fn store_declaration(&self, declaration: &Declaration) -> color_eyre::eyre::Result<()>;
+
+    /// Get a declaration by identifier.
+    async fn get_declaration(
+        &self,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<Option<DeclarationRecord>>;
+
+    /// List all declarations, by identifier.
+    async fn list_declarations(&self) -> color_eyre::eyre::Result<Vec<DeclarationRecord>>;
+
+    /// Delete a declaration and its assignments.
+    ///
+    /// Returns whether the declaration existed.
+    async fn delete_declaration(&self, identifier: &str) -> color_eyre::eyre::Result<bool>;
+
+    /// Assign a declaration to an enrollment.
+    ///
+    /// Fails with [`UnknownDeclaration`] if there is no such declaration.
+    /// Returns whether it wasn't assigned already.
+    async fn assign_declaration(
+        &self,
+        id: &EnrollId,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<bool>;
+
+    /// Remove a declaration from an enrollment.
+    ///
+    /// Returns whether it was assigned.
+    async fn unassign_declaration(
+        &self,
+        id: &EnrollId,
+        identifier: &str,
+    ) -> color_eyre::eyre::Result<bool>;
+
+    /// Get the declarations assigned to an enrollment, by identifier.
+    async fn assigned_declarations(
+        &self,
+        id: &EnrollId,
+    ) -> color_eyre::eyre::Result<Vec<DeclarationRecord>>;
+
+    /// Replace an enrollment's reported declaration states.
+    async fn store_declaration_statuses(
+        &self,
+        id: &EnrollId,
+        statuses: &[DeclarationStatus],
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Get an enrollment's reported declaration states, by identifier.
+    async fn declaration_statuses(
+        &self,
+        id: &EnrollId,
+    ) -> color_eyre::eyre::Result<Vec<DeclarationStatusRecord>>;
+}
+
+/// Which of an enrollment's status items to list.
+#[derive(Debug, Clone, Default)]
+pub struct StatusItemFilter {
+    /// Only this item and the items under it, e.g. "softwareupdate".
+    pub prefix: Option<String>,
+}
+
+/// Which changes of a status item to list.
+#[derive(Debug, Clone, Default)]
+pub struct StatusHistoryFilter {
+    /// Return at most this many changes.
+    pub limit: Option<u32>,
+    /// Skip this many changes first.
+    pub offset: u32,
+}
+
+/// DDM status item storage.
+#[trait_variant::make(Send)]
+pub trait StatusItemStore: Send + Sync {
+    /// Record the items of an enrollment's status report.
+    ///
+    /// Items reported with their current value only have their report time
+    /// moved; the rest are added to their history. Items the report leaves
+    /// out are kept.
+    async fn store_status_items(
+        &self,
+        id: &EnrollId,
+        items: &[StatusItem],
+    ) -> color_eyre::eyre::Result<()>;
+
+    /// Get the latest value of an enrollment's status items matching
+    /// `filter`, by item.
+    async fn status_items(
+        &self,
+        id: &EnrollId,
+        filter: &StatusItemFilter,
+    ) -> color_eyre::eyre::Result<Vec<StatusItemRecord>>;
+
+    /// List the values one of an enrollment's status items changed to,
+    /// newest first.
+    async fn status_item_history(
+        &self,
+        id: &EnrollId,
+        item: &str,
+        filter: &StatusHistoryFilter,
+    ) -> color_eyre::eyre::Result<Vec<StatusItemChange>>;
 }
 
 /// Combined storage trait.
 pub trait AllStorage:
-    CheckinStore + CommandStore + BootstrapTokenStore + PushStore + PushCertStore + CertAuthStore
+    CheckinStore
+    + CommandStore
+    + BootstrapTokenStore
+    + PushStore
+    + PushCertStore
+    + CertAuthStore
+    + InventoryStore
+    + DeclarationStore
+    + StatusItemStore
 {
 }
 
@@ -107,5 +467,8 @@ impl<T> AllStorage for T where
         + PushStore
         + PushCertStore
         + CertAuthStore
+        + InventoryStore
+        + DeclarationStore
+        + StatusItemStore
 {
 }